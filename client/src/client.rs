@@ -1,37 +1,442 @@
+use crate::control_connection::ControlConnection;
+use shared::auth::Credentials;
 use shared::cluster::Cluster;
 use shared::connection_pool::ConnectionPool;
 use shared::consistent_hash_ring::Node;
-use shared::error::AppResult;
-use shared::protocol::types::{Request, Response};
+use shared::error::{AppResult, Error};
+use shared::paging::Pages;
+use shared::partitioner::{GlobalPartitioner, Murmur3Partitioner};
+use shared::protocol::types::{
+    Batch, BatchStatement, BatchType, CqlValue, CustomPayload, ErrorCode, Execute,
+    PreparedMetadata, ProtocolVersion, Query, Request, Response, TraceEvent, ValueCount,
+};
+use shared::routing::RoutingStrategy;
+use shared::tls::{TlsConnector, TlsSettings};
+use shared::token_range::TokenRange;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
 
 pub struct Client {
     connection_pool: ConnectionPool,
-    cluster: Cluster,
+    /// The ring, kept up to date by the control connection.
+    cluster: Arc<RwLock<Cluster>>,
+    /// Statements prepared so far, keyed by query string.
+    prepared: HashMap<String, PreparedStatement>,
+    /// Whether requests are traced.
+    tracing: bool,
+    /// The keyspace of unqualified table names, set by executing USE.
+    keyspace: Option<String>,
+}
+
+/// A statement prepared by the cluster.
+#[derive(Debug, Clone)]
+pub struct PreparedStatement {
+    pub query: String,
+    pub metadata: PreparedMetadata,
 }
 
 pub struct Settings {
     nodes: Vec<Node>,
+    protocol_version: ProtocolVersion,
+    credentials: Option<Credentials>,
+    tls: Option<TlsSettings>,
+    partitioner: GlobalPartitioner,
 }
 
 impl Settings {
     pub fn new(nodes: Vec<Node>) -> Self {
-        Settings { nodes }
+        Settings {
+            nodes,
+            protocol_version: ProtocolVersion::default(),
+            credentials: None,
+            tls: None,
+            partitioner: Arc::new(Murmur3Partitioner),
+        }
+    }
+
+    /// Sets the protocol version to speak; v5 opts into checksummed segment framing.
+    pub fn with_protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the credentials to log in with on nodes that require authentication.
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some(Credentials::new(username, password));
+        self
+    }
+
+    /// Connects to nodes over TLS, verifying them against the CA of the settings.
+    pub fn with_tls(mut self, tls: TlsSettings) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Sets the partitioner of the cluster, which routes requests to the nodes owning their
+    /// keys; the cluster uses the Murmur3 partitioner unless configured otherwise.
+    pub fn with_partitioner(mut self, partitioner: GlobalPartitioner) -> Self {
+        self.partitioner = partitioner;
+        self
     }
 }
 
 impl Client {
-    pub fn new(settings: Settings) -> Self {
-        Client {
-            connection_pool: ConnectionPool::new(),
-            cluster: Cluster::new(settings.nodes),
+    pub fn new(settings: Settings) -> AppResult<Self> {
+        let tls = settings
+            .tls
+            .map(TlsConnector::new)
+            .transpose()
+            .map_err(|e| Error::ConnectionError(Some(e)))?;
+
+        let cluster = Arc::new(RwLock::new(Cluster::new(
+            settings.nodes,
+            settings.partitioner,
+        )));
+        ControlConnection::new(
+            cluster.clone(),
+            settings.protocol_version,
+            settings.credentials.clone(),
+            tls.clone(),
+        )
+        .start();
+
+        Ok(Client {
+            connection_pool: ConnectionPool::new()
+                .with_protocol_version(settings.protocol_version)
+                .with_credentials(settings.credentials)
+                .with_tls(tls),
+            cluster,
+            prepared: HashMap::new(),
+            tracing: false,
+            keyspace: None,
+        })
+    }
+
+    /// Turns tracing of requests sent to a single node on or off.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+        self.connection_pool.set_tracing(tracing);
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.tracing
+    }
+
+    /// Returns the tracing session of the last traced request.
+    pub fn tracing_id(&self) -> Option<Uuid> {
+        self.connection_pool.tracing_id()
+    }
+
+    /// Returns the warnings the cluster attached to the response of the last request.
+    pub fn warnings(&self) -> &[String] {
+        self.connection_pool.warnings()
+    }
+
+    /// Sets the custom payload sent with every request, e.g. a tenant id or request tags.
+    pub fn set_custom_payload(&mut self, custom_payload: CustomPayload) {
+        self.connection_pool.set_custom_payload(custom_payload);
+    }
+
+    /// Returns the custom payload the cluster attached to the response of the last request.
+    pub fn response_payload(&self) -> &CustomPayload {
+        self.connection_pool.response_payload()
+    }
+
+    /// Reads the events of a tracing session from every node, ordered by time.
+    ///
+    /// Only superusers may read traces. Nodes that can't be reached are skipped, unless none
+    /// can. Replicas are written asynchronously, so their events may show up a little after the
+    /// response of the traced request.
+    pub fn trace(&mut self, session_id: Uuid) -> AppResult<Vec<TraceEvent>> {
+        let nodes = self.cluster.read().unwrap().get_nodes().to_vec();
+
+        // reading the trace must not start a session of its own
+        self.connection_pool.set_tracing(false);
+        let mut events = Vec::new();
+        let mut error = None;
+        let mut answered = false;
+        for node in nodes.iter() {
+            match self.connection_pool.execute(
+                RoutingStrategy::Direct(node),
+                Request::GetTrace(session_id),
+                None,
+            ) {
+                Ok(Response::Trace(node_events)) => {
+                    events.extend(node_events);
+                    answered = true;
+                }
+                Ok(response) => error = Some(Error::UnexpectedResponse(response)),
+                Err(e) => error = Some(e),
+            }
+        }
+        self.connection_pool.set_tracing(self.tracing);
+
+        if let Some(error) = error
+            && !answered
+        {
+            return Err(error);
+        }
+        events.sort_by_key(|event| event.timestamp);
+        Ok(events)
+    }
+
+    /// Reads the values every node stores in the given ranges, fetching `page_size` values at
+    /// a time as the iterator is consumed.
+    ///
+    /// Replicated values are returned once for every node storing them.
+    pub fn get_batch(&mut self, ranges: Vec<TokenRange>, page_size: usize) -> Pages<'_> {
+        let nodes = self.cluster.read().unwrap().get_nodes().to_vec();
+        Pages::new(&mut self.connection_pool, nodes, ranges, page_size)
+    }
+
+    /// Executes a CQL statement on any node, which coordinates it.
+    ///
+    /// The keyspace set by a USE is remembered and sent along with later queries, since they
+    /// may be coordinated by other nodes than the one that executed the USE.
+    pub fn query(&mut self, query: &str, values: Vec<CqlValue>) -> AppResult<Response> {
+        let request = Request::Query(Query {
+            query: query.to_string(),
+            values,
+            keyspace: self.keyspace.clone(),
+        });
+        let cluster = self.cluster.read().unwrap();
+        let routing_strategy = cluster.router().route_request(&request);
+
+        let response = self
+            .connection_pool
+            .execute(routing_strategy, request, None)?;
+        if let Response::SetKeyspace(keyspace) = &response {
+            self.keyspace = Some(keyspace.clone());
+        }
+        Ok(response)
+    }
+
+    /// Prepares a statement, reusing an earlier preparation of the same query.
+    pub fn prepare(&mut self, query: &str) -> AppResult<PreparedStatement> {
+        if let Some(statement) = self.prepared.get(query) {
+            return Ok(statement.clone());
+        }
+
+        let request = Request::Prepare(query.to_string());
+        let cluster = self.cluster.read().unwrap();
+        let routing_strategy = cluster.router().route_request(&request);
+
+        match self
+            .connection_pool
+            .execute(routing_strategy, request.clone(), None)?
+        {
+            Response::Prepared(metadata) => {
+                let statement = PreparedStatement {
+                    query: query.to_string(),
+                    metadata,
+                };
+                self.prepared.insert(query.to_string(), statement.clone());
+                Ok(statement)
+            }
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Executes a prepared statement on the node owning its partition key.
+    ///
+    /// Statements are prepared on a single node, so the owner may not know the statement
+    /// yet. On an UNPREPARED error it is prepared there and the execution is retried once.
+    pub fn execute(
+        &mut self,
+        statement: &PreparedStatement,
+        values: Vec<CqlValue>,
+    ) -> AppResult<Response> {
+        let execute = Execute {
+            id: statement.metadata.id,
+            values,
+        };
+        let cluster = self.cluster.read().unwrap();
+        let router = cluster.router();
+
+        let routing_strategy = router.route_execute(&execute, &statement.metadata);
+        let response = self.connection_pool.execute(
+            routing_strategy,
+            Request::Execute(execute.clone()),
+            None,
+        )?;
+
+        match response {
+            Response::Error(error) if error.code == ErrorCode::Unprepared => {
+                let routing_strategy = router.route_execute(&execute, &statement.metadata);
+                let prepare = Request::Prepare(statement.query.clone());
+                if let Response::Error(error) =
+                    self.connection_pool
+                        .execute(routing_strategy, prepare, None)?
+                {
+                    return Ok(Response::Error(error));
+                }
+
+                let routing_strategy = router.route_execute(&execute, &statement.metadata);
+                self.connection_pool
+                    .execute(routing_strategy, Request::Execute(execute), None)
+            }
+            response => Ok(response),
+        }
+    }
+
+    /// Adds a value, replicated to the given number of nodes or to the default number.
+    pub fn add(&mut self, value: &str, replication_factor: Option<i32>) -> AppResult<()> {
+        let mut values = vec![CqlValue::Text(value.to_string())];
+        let query = match replication_factor {
+            Some(replication_factor) => {
+                values.push(CqlValue::Int(replication_factor));
+                "ADD ? WITH REPLICATION ?"
+            }
+            None => "ADD ?",
+        };
+        let statement = self.prepare(query)?;
+
+        match self.execute(&statement, values)? {
+            Response::Void => Ok(()),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Checks whether a value is stored, answering `false` if it isn't.
+    pub fn check(&mut self, value: &str) -> AppResult<bool> {
+        let statement = self.prepare("CHECK ?")?;
+
+        match self.execute(&statement, vec![CqlValue::Text(value.to_string())])? {
+            Response::Rows(rows) => match rows.rows.first().and_then(|row| row.first()) {
+                Some(CqlValue::Boolean(exists)) => Ok(*exists),
+                _ => Err(Error::UnexpectedResponse(Response::Rows(rows))),
+            },
+            response => Err(Error::UnexpectedResponse(response)),
         }
     }
 
-    pub fn send(&mut self, request: Request) -> AppResult<Response> {
-        let router = self.cluster.router();
-        let routing_strategy = router.route_request(&request);
+    /// Counts the values stored on every node, along with the count of each node.
+    ///
+    /// Replicated values are counted once for every node storing them.
+    pub fn count(&mut self) -> AppResult<ValueCount> {
+        let request = Request::Count(Vec::new());
+        let cluster = self.cluster.read().unwrap();
+        let routing_strategy = cluster.router().route_request(&request);
+
+        match self
+            .connection_pool
+            .execute(routing_strategy, request, None)?
+        {
+            Response::Count(count) => Ok(count),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Creates a user that can log in with the given password.
+    ///
+    /// The logged in user must be a superuser. The user is created on every node.
+    pub fn create_user(
+        &mut self,
+        name: &str,
+        password: &str,
+        superuser: bool,
+    ) -> AppResult<Response> {
+        let query = match superuser {
+            true => "CREATE USER ? WITH PASSWORD ? SUPERUSER",
+            false => "CREATE USER ? WITH PASSWORD ? NOSUPERUSER",
+        };
+        let statement = self.prepare(query)?;
+
+        self.execute(
+            &statement,
+            vec![
+                CqlValue::Text(name.to_string()),
+                CqlValue::Text(password.to_string()),
+            ],
+        )
+    }
+
+    /// Changes the password of a user.
+    ///
+    /// The logged in user must be a superuser. The user is changed on every node.
+    pub fn alter_user(&mut self, name: &str, password: &str) -> AppResult<Response> {
+        let statement = self.prepare("ALTER USER ? WITH PASSWORD ?")?;
+
+        self.execute(
+            &statement,
+            vec![
+                CqlValue::Text(name.to_string()),
+                CqlValue::Text(password.to_string()),
+            ],
+        )
+    }
+
+    /// Drops a user, which can't be the logged in one.
+    ///
+    /// The logged in user must be a superuser. The user is dropped on every node.
+    pub fn drop_user(&mut self, name: &str) -> AppResult<Response> {
+        let statement = self.prepare("DROP USER ?")?;
+
+        self.execute(&statement, vec![CqlValue::Text(name.to_string())])
+    }
+
+    /// Executes prepared statements as one batch.
+    ///
+    /// The coordinator routes every statement to the node owning its partition. If it doesn't
+    /// know some of the statements, they are prepared on it and the batch is retried once.
+    pub fn batch(
+        &mut self,
+        batch_type: BatchType,
+        statements: Vec<(PreparedStatement, Vec<CqlValue>)>,
+    ) -> AppResult<Response> {
+        let request = Request::Batch(Batch {
+            batch_type,
+            statements: statements
+                .iter()
+                .map(|(statement, values)| {
+                    BatchStatement::Prepared(statement.metadata.id, values.clone())
+                })
+                .collect(),
+        });
+        let cluster = self.cluster.read().unwrap();
+        let router = cluster.router();
+
+        let response =
+            self.connection_pool
+                .execute(router.route_request(&request), request.clone(), None)?;
+
+        match response {
+            Response::Error(error) if error.code == ErrorCode::Unprepared => {
+                for (statement, _) in statements.iter() {
+                    let prepare = Request::Prepare(statement.query.clone());
+                    if let Response::Error(error) = self.connection_pool.execute(
+                        router.route_request(&request),
+                        prepare,
+                        None,
+                    )? {
+                        return Ok(Response::Error(error));
+                    }
+                }
+
+                self.connection_pool
+                    .execute(router.route_request(&request), request.clone(), None)
+            }
+            response => Ok(response),
+        }
+    }
 
-        println!("Sending request to: {:?}", routing_strategy);
+    /// Executes statements as one batch without preparing them, e.g. the CQL UPDATEs of a
+    /// counter batch, which can't be prepared.
+    pub fn batch_queries(
+        &mut self,
+        batch_type: BatchType,
+        statements: Vec<(String, Vec<CqlValue>)>,
+    ) -> AppResult<Response> {
+        let request = Request::Batch(Batch {
+            batch_type,
+            statements: statements
+                .into_iter()
+                .map(|(query, values)| BatchStatement::Query(query, values))
+                .collect(),
+        });
+        let cluster = self.cluster.read().unwrap();
+        let routing_strategy = cluster.router().route_request(&request);
 
         self.connection_pool
             .execute(routing_strategy, request, None)
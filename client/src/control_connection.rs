@@ -0,0 +1,142 @@
+//! Control connection of the client.
+//!
+//! The client keeps one extra connection to a node of the cluster, registered for cluster
+//! events. Topology changes pushed on it are applied to the client's ring, so requests are
+//! routed to nodes that joined after the client started, no longer to nodes that left, and to
+//! the new address of nodes that moved. The host ids and tokens of the nodes are read from the
+//! node the connection is made to, and those of a node that joined from the node itself.
+
+use crate::LOG_INFO;
+use shared::auth::Credentials;
+use shared::cluster::Cluster;
+use shared::connection::Connection;
+use shared::error::{AppResult, Error};
+use shared::protocol::types::{
+    Event, EventType, ProtocolVersion, Request, Response, TopologyChangeType,
+};
+use shared::stream::Stream;
+use shared::tls::TlsConnector;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before reconnecting once no node accepted the control connection.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ControlConnection {
+    cluster: Arc<RwLock<Cluster>>,
+    protocol_version: ProtocolVersion,
+    credentials: Option<Credentials>,
+    tls: Option<TlsConnector>,
+}
+
+impl ControlConnection {
+    pub fn new(
+        cluster: Arc<RwLock<Cluster>>,
+        protocol_version: ProtocolVersion,
+        credentials: Option<Credentials>,
+        tls: Option<TlsConnector>,
+    ) -> Self {
+        Self {
+            cluster,
+            protocol_version,
+            credentials,
+            tls,
+        }
+    }
+
+    /// Listens for events in a background thread, reconnecting to another node if the
+    /// current one goes away.
+    ///
+    /// The tokens of the nodes are read before returning, so that the first requests are
+    /// routed by them already.
+    pub fn start(self) {
+        for address in self.addresses() {
+            if self.learn_tokens(&address, None).is_ok() {
+                break;
+            }
+        }
+
+        thread::spawn(move || {
+            loop {
+                for address in self.addresses() {
+                    // only returns once the connection failed
+                    let _ = self.listen(&address);
+                }
+
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        });
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        self.cluster
+            .read()
+            .unwrap()
+            .get_nodes()
+            .iter()
+            .map(|node| node.address.clone())
+            .collect()
+    }
+
+    fn connect(&self, address: &str) -> AppResult<Connection> {
+        let stream = Stream::connect(address, self.tls.as_ref())
+            .map_err(|e| Error::ConnectionError(Some(e)))?;
+        let mut connection = Connection::new(stream)
+            .with_protocol_version(self.protocol_version)
+            .with_credentials(self.credentials.clone());
+        connection.startup()?;
+        Ok(connection)
+    }
+
+    /// Reads the host ids and tokens a node knows and places the nodes at them, or only the
+    /// node `of` if given.
+    fn learn_tokens(&self, address: &str, of: Option<&str>) -> AppResult<()> {
+        let mut connection = self.connect(address)?;
+        let node_tokens = match connection.send_request_with_response(&Request::Tokens)? {
+            Response::Tokens(node_tokens) => node_tokens,
+            response => return Err(Error::UnexpectedResponse(response)),
+        };
+
+        let mut cluster = self.cluster.write().unwrap();
+        for node_tokens in node_tokens {
+            if of.is_none_or(|of| of == node_tokens.address) {
+                cluster.learn_node(node_tokens);
+            }
+        }
+        Ok(())
+    }
+
+    fn listen(&self, address: &str) -> AppResult<()> {
+        self.learn_tokens(address, None)?;
+        let mut connection = self.connect(address)?;
+        connection.register(vec![
+            EventType::TopologyChange,
+            EventType::StatusChange,
+            EventType::SchemaChange,
+        ])?;
+
+        loop {
+            let event = connection.receive_event()?;
+            self.handle_event(event);
+        }
+    }
+
+    fn handle_event(&self, event: Event) {
+        if let Event::TopologyChange(change) = &event {
+            if !self.cluster.write().unwrap().apply_topology_change(change) {
+                return;
+            }
+            if change.change == TopologyChangeType::NewNode
+                && let Err(e) = self.learn_tokens(&change.address, Some(&change.address))
+            {
+                println!(
+                    "{}: Cannot learn the tokens of {}: {:?}",
+                    LOG_INFO, change.address, e
+                );
+            }
+        }
+
+        println!("{}: Event: {:?}", LOG_INFO, event);
+    }
+}
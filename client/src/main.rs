@@ -1,21 +1,53 @@
 mod client;
+mod control_connection;
 
 use crate::client::{Client, Settings};
 use shared::consistent_hash_ring::Node;
-use shared::protocol::types::{Entry, Request};
+use shared::error::{AppResult, Error};
+use shared::partitioner;
+use shared::partitioner::GlobalPartitioner;
+use shared::protocol::types::{
+    BatchType, CqlValue, CustomPayload, ProtocolVersion, Response, Rows,
+};
+use shared::tls::TlsSettings;
+use shared::token_range::TokenRange;
 use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use text_colorizer::*;
 
 const NODES_ARG_KEY: &str = "--nodes=";
+const PROTOCOL_VERSION_ARG_KEY: &str = "--protocol-version=";
+const USER_ARG_KEY: &str = "--user=";
+const PASSWORD_ARG_KEY: &str = "--password=";
+const TLS_CA_ARG_KEY: &str = "--tls-ca=";
+const TLS_CERT_ARG_KEY: &str = "--tls-cert=";
+const TLS_KEY_ARG_KEY: &str = "--tls-key=";
+const PARTITIONER_ARG_KEY: &str = "--partitioner=";
+
+/// How long to wait for the asynchronous replica writes of a traced request before reading
+/// its trace.
+const TRACE_DELAY: Duration = Duration::from_millis(200);
 
 const LOG_INFO: &str = "INFO";
 const LOG_ERROR: &str = "ERROR";
+const LOG_WARNING: &str = "WARNING";
 const LOG_VERBOSE: &str = "VERBOSE";
 
 fn main() {
     let nodes = initialize_cluster_config();
-    let settings = Settings::new(nodes);
-    let mut client = Client::new(settings);
+    let mut settings = Settings::new(nodes).with_protocol_version(initialize_protocol_version());
+    if let Some((user, password)) = initialize_credentials() {
+        settings = settings.with_credentials(&user, &password);
+    }
+    if let Some(tls) = initialize_tls() {
+        settings = settings.with_tls(tls);
+    }
+    if let Some(partitioner) = initialize_partitioner() {
+        settings = settings.with_partitioner(partitioner);
+    }
+    let mut client = Client::new(settings).unwrap_or_else(|e| panic!("{:?}", e));
 
     start_processing(&mut client);
     println!("Connected");
@@ -27,9 +59,7 @@ fn initialize_cluster_config() -> Vec<Node> {
         .expect("--nodes argument is required")
         .trim_start_matches(NODES_ARG_KEY)
         .split(",")
-        .map(|s| Node {
-            address: s.to_string(),
-        })
+        .map(Node::from)
         .collect::<Vec<Node>>();
 
     println!("Nodes: {:?}", nodes);
@@ -37,12 +67,58 @@ fn initialize_cluster_config() -> Vec<Node> {
     nodes
 }
 
+fn initialize_protocol_version() -> ProtocolVersion {
+    std::env::args()
+        .find(|arg| arg.starts_with(PROTOCOL_VERSION_ARG_KEY))
+        .map(|arg| {
+            let version = arg
+                .trim_start_matches(PROTOCOL_VERSION_ARG_KEY)
+                .parse::<u8>()
+                .expect("--protocol-version must be a number");
+            ProtocolVersion::try_from(version).unwrap_or_else(|e| panic!("{}", e))
+        })
+        .unwrap_or_default()
+}
+
+fn initialize_credentials() -> Option<(String, String)> {
+    match (find_arg(USER_ARG_KEY), find_arg(PASSWORD_ARG_KEY)) {
+        (Some(user), Some(password)) => Some((user, password)),
+        (None, None) => None,
+        _ => panic!("--user and --password must be given together"),
+    }
+}
+
+/// Enables TLS if a CA is given; a certificate and key are only needed for mutual TLS.
+fn initialize_tls() -> Option<TlsSettings> {
+    let ca = find_arg(TLS_CA_ARG_KEY)?;
+
+    Some(TlsSettings {
+        cert: find_arg(TLS_CERT_ARG_KEY).map(PathBuf::from),
+        key: find_arg(TLS_KEY_ARG_KEY).map(PathBuf::from),
+        ca: Some(PathBuf::from(ca)),
+        require_client_auth: false,
+    })
+}
+
+/// Uses the partitioner named by `--partitioner=`, which has to be the one of the cluster.
+fn initialize_partitioner() -> Option<GlobalPartitioner> {
+    let name = find_arg(PARTITIONER_ARG_KEY)?;
+
+    Some(partitioner::from_name(&name).unwrap_or_else(|| panic!("Unknown partitioner {}", name)))
+}
+
+fn find_arg(key: &str) -> Option<String> {
+    std::env::args()
+        .find(|arg| arg.starts_with(key))
+        .map(|arg| arg.trim_start_matches(key).to_string())
+}
+
 fn start_processing(client: &mut Client) {
     loop {
         println!(
             "{}: {}",
             LOG_INFO.bright_green(),
-            "Supported command: Add, Check (Enter command)"
+            "Supported command: Add, Check, Count, Batch, Counters, Scan, User, Password, DropUser, Query, Tracing, Payload (Enter command)"
                 .green()
                 .bold()
         );
@@ -59,7 +135,9 @@ fn start_processing(client: &mut Client) {
         println!(
             "{}: {}",
             LOG_INFO.bright_green(),
-            "Enter values (e.g. '123')".blue().bold()
+            "Enter values (e.g. '123', '1,2,3' for a batch, UPDATEs separated by ';' for counters, a page size for a scan, 'name:password' for a user or a new password, a name for dropping a user, a statement for a query, 'on'/'off' for tracing or 'key=value,...' for a payload)"
+                .blue()
+                .bold()
         );
         print!(
             "{}: {}",
@@ -71,40 +149,96 @@ fn start_processing(client: &mut Client) {
         let mut value = String::new();
         std::io::stdin().read_line(&mut value).unwrap();
         send(client, command_input.trim(), value.trim());
+        print_warnings(client);
+        print_response_payload(client);
+
+        if client.is_tracing() {
+            print_trace(client);
+        }
     }
 }
 
 fn send(client: &mut Client, operation: &str, value: &str) {
-    let request;
+    if operation == "batch" {
+        send_batch(client, value);
+        return;
+    }
+    if operation == "counters" {
+        send_counter_batch(client, value);
+        return;
+    }
+    if operation == "scan" {
+        scan(client, value);
+        return;
+    }
+    if operation == "user" {
+        create_user(client, value);
+        return;
+    }
+    if operation == "password" {
+        alter_user(client, value);
+        return;
+    }
+    if operation == "dropuser" {
+        print_response(client.drop_user(value));
+        return;
+    }
+    if operation == "tracing" {
+        match value {
+            "on" => client.set_tracing(true),
+            "off" => client.set_tracing(false),
+            _ => {
+                eprintln!(
+                    "{}: {}",
+                    LOG_ERROR.bright_red(),
+                    "Expected 'on' or 'off'".red().bold()
+                );
+                return;
+            }
+        }
+        println!("{}: Tracing is {}", LOG_INFO, value);
+        return;
+    }
+
+    if operation == "payload" {
+        set_custom_payload(client, value);
+        return;
+    }
+    if operation == "query" {
+        print_response(client.query(value, Vec::new()));
+        return;
+    }
+
     match operation {
         "add" => {
-            println!("{}: {}", LOG_INFO, "Replication factor: (Empty to skip)");
+            println!("{}: Replication factor: (Empty to skip)", LOG_INFO);
             let mut replication_factor_input = String::new();
             std::io::stdin()
                 .read_line(&mut replication_factor_input)
                 .unwrap();
-            request = match replication_factor_input.trim() {
-                "" => Request::Add(Entry {
-                    value: value.to_string(),
-                    replication_factor: None,
-                }),
-                _ => {
-                    let replication_factor = replication_factor_input
-                        .trim()
-                        .parse::<u32>()
-                        .ok()
-                        .or(Some(0))
-                        .unwrap();
-                    Request::Add(Entry {
-                        value: value.to_string(),
-                        replication_factor: Some(replication_factor as usize),
-                    })
-                }
+            let replication_factor = match replication_factor_input.trim() {
+                "" => None,
+                input => Some(input.parse::<i32>().ok().unwrap_or(0)),
             };
+            match client.add(value, replication_factor) {
+                Ok(()) => println!("{}: Added {}", LOG_VERBOSE, value),
+                Err(e) => print_error(e),
+            }
         }
-        "check" => {
-            request = Request::Check(value.to_string());
-        }
+        "check" => match client.check(value) {
+            Ok(true) => println!("{}: {} exists", LOG_VERBOSE, value),
+            Ok(false) => println!("{}: {} doesn't exist", LOG_VERBOSE, value),
+            Err(e) => print_error(e),
+        },
+        "count" => match client.count() {
+            Ok(count) => {
+                for (address, node_count) in count.nodes.iter() {
+                    println!("{}: [{}] {} values", LOG_VERBOSE, address, node_count.total);
+                }
+                println!("{}: {} values in total", LOG_VERBOSE, count.total);
+            }
+            Err(e) => print_error(e),
+        },
         _ => {
             eprintln!(
                 "{}: {}",
@@ -113,27 +247,214 @@ fn send(client: &mut Client, operation: &str, value: &str) {
                     .red()
                     .bold()
             );
+        }
+    }
+}
+
+/// Adds comma separated values in one logged batch.
+fn send_batch(client: &mut Client, values: &str) {
+    let response_result = client.prepare("ADD ?").and_then(|statement| {
+        let statements = values
+            .split(',')
+            .map(|value| {
+                (
+                    statement.clone(),
+                    vec![CqlValue::Text(value.trim().to_string())],
+                )
+            })
+            .collect();
+        client.batch(BatchType::Logged, statements)
+    });
+
+    print_response(response_result);
+}
+
+/// Applies UPDATEs of counters separated by semicolons in one counter batch.
+fn send_counter_batch(client: &mut Client, statements: &str) {
+    let statements = statements
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(|statement| (statement.to_string(), Vec::new()))
+        .collect();
+
+    print_response(client.batch_queries(BatchType::Counter, statements));
+}
+
+/// Prints every value stored in the cluster, reading the given number of values at a time
+/// from each node.
+fn scan(client: &mut Client, page_size: &str) {
+    let page_size = match page_size.parse::<usize>() {
+        Ok(page_size) if page_size > 0 => page_size,
+        _ => {
+            eprintln!(
+                "{}: {}",
+                LOG_ERROR.bright_red(),
+                "Expected a positive page size".red().bold()
+            );
             return;
         }
+    };
+
+    let all_values = vec![TokenRange::full()];
+    let mut count = 0;
+    for value in client.get_batch(all_values, page_size) {
+        match value {
+            Ok(value) => {
+                println!("{}: {}", LOG_VERBOSE, value);
+                count += 1;
+            }
+            Err(e) => {
+                eprintln!(
+                    "{}: {}",
+                    LOG_ERROR.bright_red(),
+                    format!("Failed to read: {:?}", e).red().bold()
+                );
+                return;
+            }
+        }
     }
+    println!("{}: ({} values)", LOG_VERBOSE, count);
+}
 
-    let response_result = client.send(request);
+/// Creates a user from a 'name:password' value.
+fn create_user(client: &mut Client, value: &str) {
+    let Some((name, password)) = value.split_once(':') else {
+        eprintln!(
+            "{}: {}",
+            LOG_ERROR.bright_red(),
+            "Expected 'name:password'".red().bold()
+        );
+        return;
+    };
 
-    let response;
-    match response_result {
-        Ok(value) => response = value,
-        Err(e) => {
+    print_response(client.create_user(name, password, false));
+}
+
+/// Changes the password of a user from a 'name:password' value.
+fn alter_user(client: &mut Client, value: &str) {
+    let Some((name, password)) = value.split_once(':') else {
+        eprintln!(
+            "{}: {}",
+            LOG_ERROR.bright_red(),
+            "Expected 'name:password'".red().bold()
+        );
+        return;
+    };
+
+    print_response(client.alter_user(name, password));
+}
+
+/// Sets the custom payload sent with every request from a 'key=value,...' value; an empty
+/// value clears it.
+fn set_custom_payload(client: &mut Client, value: &str) {
+    let mut custom_payload = CustomPayload::new();
+    for entry in value.split(',').filter(|entry| !entry.trim().is_empty()) {
+        let Some((key, value)) = entry.split_once('=') else {
             eprintln!(
                 "{}: {}",
                 LOG_ERROR.bright_red(),
-                format!("Error occurred while processing message: {:?}", e)
-                    .red()
-                    .bold()
+                "Expected 'key=value,...'".red().bold()
             );
             return;
+        };
+        custom_payload.insert(key.trim().to_string(), value.trim().as_bytes().to_vec());
+    }
+
+    println!(
+        "{}: Custom payload has {} entries",
+        LOG_INFO,
+        custom_payload.len()
+    );
+    client.set_custom_payload(custom_payload);
+}
+
+/// Prints the custom payload the cluster attached to the response of the last request.
+fn print_response_payload(client: &Client) {
+    let mut entries: Vec<String> = client
+        .response_payload()
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, String::from_utf8_lossy(value)))
+        .collect();
+    if entries.is_empty() {
+        return;
+    }
+
+    entries.sort();
+    println!("{}: Response payload: {}", LOG_VERBOSE, entries.join(","));
+}
+
+/// Prints the warnings the cluster attached to the response of the last request.
+fn print_warnings(client: &Client) {
+    for warning in client.warnings() {
+        println!("{}: {}", LOG_WARNING.bright_yellow(), warning.yellow());
+    }
+}
+
+/// Prints the trace of the last request, if it was traced.
+fn print_trace(client: &mut Client) {
+    let Some(session_id) = client.tracing_id() else {
+        return;
+    };
+
+    thread::sleep(TRACE_DELAY);
+    match client.trace(session_id) {
+        Ok(events) => {
+            println!("{}: Tracing session: {}", LOG_VERBOSE, session_id);
+            for event in events {
+                println!(
+                    "{}: {:>8}us | {:<16} | {}",
+                    LOG_VERBOSE, event.source_elapsed, event.source, event.activity
+                );
+            }
         }
+        Err(e) => eprintln!(
+            "{}: {}",
+            LOG_ERROR.bright_red(),
+            format!("Cannot read trace {}: {:?}", session_id, e)
+                .red()
+                .bold()
+        ),
     }
+}
+
+fn print_response(response_result: AppResult<Response>) {
+    let response = match response_result {
+        Ok(value) => value,
+        Err(e) => {
+            print_error(e);
+            return;
+        }
+    };
 
-    println!("{}: {}", LOG_VERBOSE, format!("Response: {:?}", response));
+    match response {
+        Response::Rows(rows) => print_rows(&rows),
+        response => println!("{}: Response: {:?}", LOG_VERBOSE, response),
+    }
     std::io::stdout().flush().unwrap();
 }
+
+fn print_error(error: Error) {
+    eprintln!(
+        "{}: {}",
+        LOG_ERROR.bright_red(),
+        format!("Error occurred while processing message: {:?}", error)
+            .red()
+            .bold()
+    );
+}
+
+/// Prints the rows of a SELECT as a table.
+fn print_rows(rows: &Rows) {
+    let header: Vec<&str> = rows
+        .columns
+        .iter()
+        .map(|column| column.name.as_str())
+        .collect();
+    println!("{}: {}", LOG_VERBOSE, header.join(" | "));
+    for row in rows.rows.iter() {
+        let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        println!("{}: {}", LOG_VERBOSE, values.join(" | "));
+    }
+    println!("{}: ({} rows)", LOG_VERBOSE, rows.rows.len());
+}
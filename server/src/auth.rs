@@ -0,0 +1,101 @@
+//! Authentication of client connections.
+//!
+//! The authenticator is chosen with the `authenticator=` argument. With the default
+//! `AllowAllAuthenticator` any connection is accepted right after STARTUP; with the
+//! `PasswordAuthenticator` the server answers STARTUP with AUTHENTICATE and only serves the
+//! connection once it sent valid SASL PLAIN credentials in an AUTH_RESPONSE.
+
+use crate::roles::{GlobalRoles, verify_password};
+use shared::auth::Credentials;
+use shared::protocol::types::{ErrorCode, ErrorResponse};
+use std::sync::Arc;
+
+/// A thread-safe, shared authenticator.
+pub(crate) type GlobalAuthenticator = Arc<dyn Authenticator + Send + Sync>;
+
+pub(crate) trait Authenticator {
+    /// Name announced to clients in the AUTHENTICATE message.
+    fn name(&self) -> &'static str;
+    /// Whether connections have to authenticate before sending queries.
+    fn requires_authentication(&self) -> bool;
+    /// Checks a SASL response, returning the name of the authenticated user.
+    fn authenticate(&self, token: &[u8]) -> Result<String, ErrorResponse>;
+}
+
+/// Creates the authenticator with the given name, or `None` if there is no such authenticator.
+pub(crate) fn from_name(name: &str, roles: &GlobalRoles) -> Option<GlobalAuthenticator> {
+    match name {
+        AllowAllAuthenticator::NAME => Some(Arc::new(AllowAllAuthenticator)),
+        PasswordAuthenticator::NAME => Some(Arc::new(PasswordAuthenticator {
+            roles: roles.clone(),
+        })),
+        _ => None,
+    }
+}
+
+/// Accepts every connection without authentication.
+pub(crate) struct AllowAllAuthenticator;
+
+impl AllowAllAuthenticator {
+    pub(crate) const NAME: &'static str = "AllowAllAuthenticator";
+}
+
+impl Authenticator for AllowAllAuthenticator {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn requires_authentication(&self) -> bool {
+        false
+    }
+
+    fn authenticate(&self, _token: &[u8]) -> Result<String, ErrorResponse> {
+        Err(ErrorResponse::new(
+            ErrorCode::ProtocolError,
+            "Authentication is not required by the AllowAllAuthenticator",
+        ))
+    }
+}
+
+/// Authenticates users of the roles table with SASL PLAIN.
+pub(crate) struct PasswordAuthenticator {
+    roles: GlobalRoles,
+}
+
+impl PasswordAuthenticator {
+    pub(crate) const NAME: &'static str = "PasswordAuthenticator";
+}
+
+impl Authenticator for PasswordAuthenticator {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn requires_authentication(&self) -> bool {
+        true
+    }
+
+    fn authenticate(&self, token: &[u8]) -> Result<String, ErrorResponse> {
+        let credentials = Credentials::from_sasl_plain(token).ok_or_else(|| {
+            ErrorResponse::new(ErrorCode::ProtocolError, "Invalid SASL PLAIN response")
+        })?;
+
+        let salted_hash = self
+            .roles
+            .lock()
+            .unwrap()
+            .salted_hash(&credentials.username);
+        match salted_hash {
+            Some(salted_hash) if verify_password(&credentials.password, &salted_hash) => {
+                Ok(credentials.username)
+            }
+            _ => Err(ErrorResponse::new(
+                ErrorCode::BadCredentials,
+                format!(
+                    "Provided username {} and/or password are incorrect",
+                    credentials.username
+                ),
+            )),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! Authorization of requests.
+//!
+//! When the authenticator requires connections to log in, every request is checked against
+//! the permissions of the logged in user before it is dispatched. Data requests need SELECT or
+//! MODIFY on the keyspace they touch, CQL statements are checked the same way once the keyspace
+//! of their table is known. Granting or revoking a permission needs AUTHORIZE on the resource
+//! as well as the permission itself, so users can't hand out more than they hold. Managing
+//! users, maintenance requests, such as the range transfers of the rebalancer, reading tracing
+//! sessions and requests nodes send each other are reserved for superusers.
+
+use crate::roles::Roles;
+use shared::protocol::types::{ErrorCode, ErrorResponse, Permission, Request, Resource};
+
+/// Keyspace holding the values of ADD, CHECK and COUNT.
+pub(crate) const DEFAULT_KEYSPACE: &str = "default";
+
+/// What the user of a connection needs to be allowed to send a request.
+enum Requirement {
+    Nothing,
+    Permission(Permission, Resource),
+    /// Every one of the permissions on the resource.
+    Permissions(Vec<Permission>, Resource),
+    Superuser,
+}
+
+/// Checks whether a user may perform a request.
+pub(crate) fn authorize(user: &str, request: &Request, roles: &Roles) -> Result<(), ErrorResponse> {
+    match requirement(request) {
+        Requirement::Nothing => Ok(()),
+        Requirement::Permission(permission, resource) => {
+            authorize_permission(user, permission, &resource, roles)
+        }
+        Requirement::Permissions(permissions, resource) => permissions
+            .into_iter()
+            .try_for_each(|permission| authorize_permission(user, permission, &resource, roles)),
+        Requirement::Superuser => {
+            if roles.is_superuser(user) {
+                return Ok(());
+            }
+            Err(ErrorResponse::new(
+                ErrorCode::Unauthorized,
+                format!(
+                    "Only superusers are allowed to perform {:?} requests",
+                    request.opcode()
+                ),
+            ))
+        }
+    }
+}
+
+/// Checks whether a user has a permission on a resource or any of its parents.
+pub(crate) fn authorize_permission(
+    user: &str,
+    permission: Permission,
+    resource: &Resource,
+    roles: &Roles,
+) -> Result<(), ErrorResponse> {
+    if roles.has_permission(user, permission, resource) {
+        return Ok(());
+    }
+    Err(ErrorResponse::new(
+        ErrorCode::Unauthorized,
+        format!(
+            "User {} has no {} permission on {} or any of its parents",
+            user, permission, resource
+        ),
+    ))
+}
+
+fn requirement(request: &Request) -> Requirement {
+    let default_keyspace = || Resource::Keyspace(DEFAULT_KEYSPACE.to_string());
+
+    match request {
+        // statements are authorized once their values are bound, CQL statements once the
+        // keyspace of their table is known
+        Request::Startup(_)
+        | Request::Options
+        | Request::AuthResponse(_)
+        | Request::Register(_)
+        | Request::Tokens
+        | Request::Prepare(_)
+        | Request::Execute(_)
+        | Request::Query(_)
+        | Request::Batch(_) => Requirement::Nothing,
+        Request::Check(_) | Request::Count(_) => {
+            Requirement::Permission(Permission::Select, default_keyspace())
+        }
+        Request::Add(_) => Requirement::Permission(Permission::Modify, default_keyspace()),
+        Request::Grant(change) | Request::Revoke(change) => Requirement::Permissions(
+            std::iter::once(Permission::Authorize)
+                .chain(change.permissions.iter().copied())
+                .collect(),
+            change.resource.clone(),
+        ),
+        Request::CreateUser(_)
+        | Request::AlterUser(_)
+        | Request::DropUser(_)
+        | Request::GetTrace(_)
+        | Request::GetBatch(_)
+        | Request::DropBatch(_)
+        | Request::AddBatch(_)
+        | Request::GetRows(_)
+        | Request::AddRows(_)
+        | Request::DropRows(_)
+        | Request::StoreBatchlog(_)
+        | Request::RemoveBatchlog(_)
+        | Request::ChangeTopology(_)
+        | Request::Mutate(_)
+        | Request::CounterUpdate(_)
+        | Request::Read(_)
+        | Request::ReadMutations(_)
+        | Request::PaxosPrepare(_)
+        | Request::PaxosPropose(_)
+        | Request::PaxosCommit(_)
+        | Request::CreateKeyspace(_)
+        | Request::CreateTable(_)
+        | Request::CreateIndex(_)
+        | Request::SchemaVersion
+        | Request::PullSchema
+        | Request::Load => Requirement::Superuser,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authorizer::authorize;
+    use crate::roles::{DEFAULT_SUPERUSER, Roles, hash_password};
+    use shared::protocol::types::{Permission, PermissionChange, Request, Resource};
+
+    #[test]
+    fn test_grant_requires_the_granted_permission() {
+        let mut roles = Roles::new();
+        let keyspace = Resource::Keyspace("default".to_string());
+        roles.create("alice", hash_password("pw"), false).unwrap();
+        roles
+            .grant("alice", &[Permission::Authorize], &keyspace)
+            .unwrap();
+        let grant = |permission| {
+            Request::Grant(PermissionChange {
+                permissions: vec![permission],
+                resource: keyspace.clone(),
+                role: "bob".to_string(),
+            })
+        };
+
+        assert!(authorize("alice", &grant(Permission::Modify), &roles).is_err());
+
+        roles
+            .grant("alice", &[Permission::Modify], &Resource::AllKeyspaces)
+            .unwrap();
+
+        assert!(authorize("alice", &grant(Permission::Modify), &roles).is_ok());
+        assert!(authorize("alice", &grant(Permission::Select), &roles).is_err());
+        assert!(authorize(DEFAULT_SUPERUSER, &grant(Permission::Select), &roles).is_ok());
+    }
+
+    #[test]
+    fn test_revoke_requires_the_revoked_permission() {
+        let mut roles = Roles::new();
+        let keyspace = Resource::Keyspace("default".to_string());
+        roles.create("alice", hash_password("pw"), false).unwrap();
+        roles
+            .grant(
+                "alice",
+                &[Permission::Authorize, Permission::Select],
+                &keyspace,
+            )
+            .unwrap();
+        let revoke = |permission| {
+            Request::Revoke(PermissionChange {
+                permissions: vec![permission],
+                resource: keyspace.clone(),
+                role: "bob".to_string(),
+            })
+        };
+
+        assert!(authorize("alice", &revoke(Permission::Select), &roles).is_ok());
+        assert!(authorize("alice", &revoke(Permission::Modify), &roles).is_err());
+    }
+}
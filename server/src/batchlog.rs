@@ -0,0 +1,146 @@
+//! Batchlog of logged batches.
+//!
+//! Before a coordinator applies a logged batch that spans several partitions, it stores the
+//! batch on two other nodes and removes it again once every mutation was applied. If the
+//! coordinator crashes in between, the batch stays in the batchlog and is replayed by the
+//! `BatchlogReplayer` of those nodes, so the batch is eventually applied completely.
+
+use crate::coordinator::Coordinator;
+use log::{info, warn};
+use shared::protocol::types::{BatchlogEntry, Entry, Mutation};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a batch may stay in the batchlog before its coordinator is assumed to have failed.
+const BATCHLOG_REPLAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the batchlog is checked for batches to replay.
+const BATCHLOG_REPLAY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A thread-safe, shared batchlog.
+pub type GlobalBatchlog = Arc<Mutex<Batchlog>>;
+
+struct StoredBatch {
+    mutations: Vec<Entry>,
+    row_mutations: Vec<Mutation>,
+    stored_at: Instant,
+}
+
+pub struct Batchlog {
+    batches: HashMap<Uuid, StoredBatch>,
+}
+
+impl Batchlog {
+    pub fn new() -> Self {
+        Self {
+            batches: HashMap::new(),
+        }
+    }
+
+    /// Stores a batch, restarting its replay timeout if it was already stored.
+    pub fn store(&mut self, entry: BatchlogEntry) {
+        self.batches.insert(
+            entry.id,
+            StoredBatch {
+                mutations: entry.mutations,
+                row_mutations: entry.row_mutations,
+                stored_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Removes a batch. Returns `true` if it was stored.
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        self.batches.remove(id).is_some()
+    }
+
+    /// Removes and returns the batches stored for longer than the timeout.
+    fn take_expired(&mut self, timeout: Duration) -> Vec<BatchlogEntry> {
+        let expired: Vec<Uuid> = self
+            .batches
+            .iter()
+            .filter(|(_, batch)| batch.stored_at.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|id| {
+                self.batches.remove(&id).map(|batch| BatchlogEntry {
+                    id,
+                    mutations: batch.mutations,
+                    row_mutations: batch.row_mutations,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Replays batches whose coordinator didn't remove them from the batchlog in time.
+pub(crate) struct BatchlogReplayer {
+    batchlog: GlobalBatchlog,
+    coordinator: Coordinator,
+}
+
+impl BatchlogReplayer {
+    pub(crate) fn new(batchlog: GlobalBatchlog, coordinator: Coordinator) -> Self {
+        Self {
+            batchlog,
+            coordinator,
+        }
+    }
+
+    pub(crate) fn run(&mut self) {
+        loop {
+            thread::sleep(BATCHLOG_REPLAY_INTERVAL);
+
+            let expired = self
+                .batchlog
+                .lock()
+                .unwrap()
+                .take_expired(BATCHLOG_REPLAY_TIMEOUT);
+
+            for entry in expired {
+                info!("Replaying batch {} from batchlog", entry.id);
+                let failed_nodes = self.coordinator.apply_mutations(&entry.mutations);
+                let written = self.coordinator.write_rows(&entry.row_mutations);
+                if !failed_nodes.is_empty() || written.is_err() {
+                    warn!(
+                        "Replay of batch {} failed on {:?} ({:?}), retrying later",
+                        entry.id, failed_nodes, written
+                    );
+                    self.batchlog.lock().unwrap().store(entry);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batchlog::Batchlog;
+    use shared::protocol::types::{BatchlogEntry, Entry};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_take_expired_returns_only_old_batches() {
+        let mut batchlog = Batchlog::new();
+        let entry = BatchlogEntry {
+            id: Uuid::new_v4(),
+            mutations: vec![Entry {
+                value: "value".to_string(),
+                replication_factor: None,
+            }],
+            row_mutations: Vec::new(),
+        };
+        batchlog.store(entry.clone());
+
+        assert!(batchlog.take_expired(Duration::from_secs(60)).is_empty());
+        assert_eq!(batchlog.take_expired(Duration::ZERO), vec![entry.clone()]);
+        assert!(!batchlog.remove(&entry.id));
+    }
+}
@@ -0,0 +1,949 @@
+//! Coordination of requests that span several nodes.
+//!
+//! The `Coordinator` sends mutations to the nodes owning their partitions and manages the
+//! batchlog copies of logged batches. For CQL tables it writes rows to and reads them from the
+//! replicas of their partitions, has one of those replicas lead every counter update, decides
+//! lightweight transactions with a round of Paxos among the replicas, and tells every node
+//! about new keyspaces and tables and about changed users. Values of the default keyspace are
+//! added to and checked on the node owning them, and counted on every node.
+
+use crate::batchlog::GlobalBatchlog;
+use crate::handlers::{
+    add_batch_handler, check_handler, get_count, mutate_handler, paxos_commit_handler,
+    paxos_prepare_handler, paxos_propose_handler, read_handler, read_mutations_handler,
+};
+use crate::internode;
+use crate::paxos::GlobalPaxos;
+use crate::replicator::ReplicationEntry;
+use crate::schema::GlobalSchema;
+use crate::server::NodeState;
+use crate::storage::{GlobalRowStorage, GlobalStorage, RowStorage};
+use crate::tracing;
+use log::{info, warn};
+use shared::cluster::{Cluster, Node};
+use shared::connection_pool::ConnectionPool;
+use shared::error::AppResult;
+use shared::partitioner::GlobalPartitioner;
+use shared::protocol::types::{
+    Ballot, BatchlogEntry, ClusteringBound, ClusteringSlice, CounterUpdate, CqlValue, Entry,
+    ErrorCode, ErrorResponse, Mutation, PaxosPrepare, Promise, Proposal, ReadCommand, Request,
+    Response, Rows,
+};
+use shared::routing::RoutingStrategy;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// A thread-safe, shared view of the cluster.
+pub type GlobalCluster = Arc<RwLock<Cluster>>;
+
+/// Number of nodes, other than the coordinator, a logged batch is written to before it is
+/// applied.
+const BATCHLOG_REPLICA_COUNT: usize = 2;
+
+/// Number of Paxos rounds a lightweight transaction starts before giving up on contention.
+const CAS_ATTEMPTS: u32 = 10;
+
+/// Longest pause before retrying a Paxos round lost to another coordinator.
+const CAS_MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The outcome of a lightweight transaction.
+pub(crate) struct CasResult {
+    pub(crate) applied: bool,
+    /// The row the condition was checked against, with every column of its table, or `None`
+    /// if it didn't exist.
+    pub(crate) current: Option<Vec<CqlValue>>,
+}
+
+pub(crate) struct Coordinator {
+    current_host: Node,
+    cluster: GlobalCluster,
+    storage: GlobalStorage,
+    batchlog: GlobalBatchlog,
+    schema: GlobalSchema,
+    rows: GlobalRowStorage,
+    paxos: GlobalPaxos,
+    sender: Sender<ReplicationEntry>,
+    connection_pool: ConnectionPool,
+}
+
+impl Coordinator {
+    pub(crate) fn new(
+        state: &NodeState,
+        sender: Sender<ReplicationEntry>,
+        connection_pool: ConnectionPool,
+    ) -> Self {
+        Self {
+            current_host: state.current_host.clone(),
+            cluster: state.cluster.clone(),
+            storage: state.storage.clone(),
+            batchlog: state.batchlog.clone(),
+            schema: state.schema.clone(),
+            rows: state.rows.clone(),
+            paxos: state.paxos.clone(),
+            sender,
+            connection_pool,
+        }
+    }
+
+    /// Returns the number of distinct partitions the mutations of values and rows touch.
+    pub(crate) fn partition_count(&self, mutations: &[Entry], row_mutations: &[Mutation]) -> usize {
+        let partitioner = self.partitioner();
+        let values = mutations
+            .iter()
+            .map(|entry| partitioner.token(entry.value.as_bytes()))
+            .collect::<HashSet<u64>>();
+        let rows = row_mutations
+            .iter()
+            .map(|mutation| {
+                (
+                    mutation.keyspace.as_str(),
+                    mutation.table.as_str(),
+                    partitioner.partition_key_token(&mutation.partition_key),
+                )
+            })
+            .collect::<HashSet<_>>();
+
+        values.len() + rows.len()
+    }
+
+    /// Applies mutations, sending each one to the node owning its partition.
+    ///
+    /// Mutations owned by this node are applied locally. Returns the addresses of the nodes
+    /// that failed to apply their mutations.
+    pub(crate) fn apply_mutations(&mut self, mutations: &[Entry]) -> Vec<String> {
+        let mut mutations_by_node: BTreeMap<String, (Node, Vec<Entry>)> = BTreeMap::new();
+        {
+            let cluster = self.cluster.read().unwrap();
+            let router = cluster.router();
+            for entry in mutations {
+                if let RoutingStrategy::Direct(node) =
+                    router.route_request(&Request::Add(entry.clone()))
+                {
+                    mutations_by_node
+                        .entry(node.address.clone())
+                        .or_insert_with(|| (node.clone(), Vec::new()))
+                        .1
+                        .push(entry.clone());
+                }
+            }
+        }
+
+        let mut failed_nodes = Vec::new();
+        for (node_address, (node, entries)) in mutations_by_node {
+            if node_address == self.current_host.address {
+                tracing::trace(|| format!("Applying {} mutations locally", entries.len()));
+                if let Err(e) = add_batch_handler::handle(&entries, &self.storage, &self.sender) {
+                    warn!("Failed to apply mutations locally: {:?}", e);
+                    failed_nodes.push(node_address);
+                }
+                continue;
+            }
+
+            tracing::trace(|| format!("Sending {} mutations to {}", entries.len(), node_address));
+            let result =
+                internode::execute(&mut self.connection_pool, &node, Request::AddBatch(entries));
+            match result {
+                Ok(Response::Error(error)) => {
+                    warn!("Node {} rejected mutations: {:?}", node_address, error);
+                    failed_nodes.push(node_address);
+                }
+                Err(e) => {
+                    warn!("Failed to send mutations to {}: {:?}", node_address, e);
+                    failed_nodes.push(node_address);
+                }
+                Ok(_) => {}
+            }
+        }
+
+        failed_nodes
+    }
+
+    /// Returns whether the node owning a value stores it.
+    pub(crate) fn check(&mut self, value: &str) -> Result<bool, ErrorResponse> {
+        let request = Request::Check(value.to_string());
+        let owner = match self
+            .cluster
+            .read()
+            .unwrap()
+            .router()
+            .route_request(&request)
+        {
+            RoutingStrategy::Direct(node) => node.clone(),
+            _ => unreachable!("values are routed to their owner"),
+        };
+
+        let result = if owner.address == self.current_host.address {
+            tracing::trace(|| format!("Checking {} locally", value));
+            check_handler::handle(&value.to_string(), &self.storage)
+        } else {
+            tracing::trace(|| format!("Checking {} on {}", value, owner.address));
+            internode::execute(&mut self.connection_pool, &owner, request)
+        };
+        match result {
+            Ok(Response::Bool(exists)) => Ok(exists),
+            Ok(Response::Error(error)) => Err(error),
+            result => Err(ErrorResponse::new(
+                ErrorCode::Unavailable,
+                format!("Cannot check {} on {}: {:?}", value, owner.address, result),
+            )),
+        }
+    }
+
+    /// Counts the values of every node, which counts a replicated value once per replica.
+    ///
+    /// Fails if any node can't be counted, since the total would be short otherwise.
+    pub(crate) fn count(&mut self) -> Result<u64, ErrorResponse> {
+        let nodes = self.cluster.read().unwrap().get_nodes().to_vec();
+
+        let mut total = 0;
+        for node in nodes.iter() {
+            let result = if node.address == self.current_host.address {
+                get_count::handle(&[], &self.storage)
+            } else {
+                tracing::trace(|| format!("Counting values on {}", node.address));
+                internode::execute(&mut self.connection_pool, node, Request::Count(Vec::new()))
+            };
+            match result {
+                Ok(Response::Count(count)) => total += count.total,
+                result => {
+                    return Err(ErrorResponse::new(
+                        ErrorCode::Unavailable,
+                        format!("Cannot count the values of {}: {:?}", node.address, result),
+                    ));
+                }
+            }
+        }
+        Ok(total)
+    }
+
+    /// Writes a logged batch to the batchlog replicas, returning the nodes it was stored on.
+    ///
+    /// The batch is stored on up to two nodes other than the coordinator; on a single node
+    /// cluster it is stored locally. Fails with `Unavailable` if any batchlog write fails, in
+    /// which case none of the batch's mutations must be applied.
+    pub(crate) fn store_batchlog(
+        &mut self,
+        entry: &BatchlogEntry,
+    ) -> Result<Vec<Node>, ErrorResponse> {
+        let endpoints = self.batchlog_endpoints(entry.id);
+        if endpoints.is_empty() {
+            self.batchlog.lock().unwrap().store(entry.clone());
+            return Ok(vec![self.current_host.clone()]);
+        }
+
+        for node in endpoints.iter() {
+            tracing::trace(|| {
+                format!("Storing batch {} in batchlog on {}", entry.id, node.address)
+            });
+            let result = internode::execute(
+                &mut self.connection_pool,
+                node,
+                Request::StoreBatchlog(entry.clone()),
+            );
+            if !matches!(result, Ok(Response::Bool(true))) {
+                warn!("Failed to store batchlog on {}: {:?}", node.address, result);
+                self.remove_batchlog(&endpoints, entry.id);
+                return Err(ErrorResponse::new(
+                    ErrorCode::Unavailable,
+                    format!(
+                        "Cannot write batch {} to batchlog on {}",
+                        entry.id, node.address
+                    ),
+                ));
+            }
+        }
+
+        info!("Stored batch {} in batchlog on {:?}", entry.id, endpoints);
+        Ok(endpoints)
+    }
+
+    /// Removes a batch from the batchlog once all of its mutations were applied.
+    pub(crate) fn remove_batchlog(&mut self, endpoints: &[Node], id: Uuid) {
+        for node in endpoints {
+            if node.address == self.current_host.address {
+                self.batchlog.lock().unwrap().remove(&id);
+                continue;
+            }
+
+            let result =
+                internode::execute(&mut self.connection_pool, node, Request::RemoveBatchlog(id));
+            if let Err(e) = result {
+                // the batch will be replayed, which is harmless since mutations are idempotent
+                warn!(
+                    "Failed to remove batch {} from {}: {:?}",
+                    id, node.address, e
+                );
+            }
+        }
+    }
+
+    /// Writes a row mutation to every replica of its partition.
+    ///
+    /// Succeeds once one replica applied it; replicas that missed the write are logged. Fails
+    /// with `Unavailable` if no replica applied it.
+    pub(crate) fn write(
+        &mut self,
+        mutation: &Mutation,
+        replication_factor: usize,
+    ) -> Result<(), ErrorResponse> {
+        let replicas = self.replicas(&mutation.partition_key, replication_factor);
+
+        let mut acknowledged = 0;
+        for node in replicas.iter() {
+            let result = if node.address == self.current_host.address {
+                tracing::trace(|| "Applying mutation locally".to_string());
+                mutate_handler::handle(mutation, &self.schema, &self.rows)
+            } else {
+                tracing::trace(|| format!("Sending mutation to {}", node.address));
+                internode::execute(
+                    &mut self.connection_pool,
+                    node,
+                    Request::Mutate(mutation.clone()),
+                )
+            };
+            match result {
+                Ok(Response::Void) => acknowledged += 1,
+                result => warn!("Replica {} missed a write: {:?}", node.address, result),
+            }
+        }
+
+        if acknowledged == 0 {
+            return Err(ErrorResponse::new(
+                ErrorCode::Unavailable,
+                format!("No replica of {:?} applied the write", replicas),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes row mutations, each to every replica of its partition with the replication factor
+    /// of its keyspace.
+    ///
+    /// Every mutation is written even if an earlier one failed; fails with the error of the
+    /// first mutation no replica applied.
+    pub(crate) fn write_rows(&mut self, mutations: &[Mutation]) -> Result<(), ErrorResponse> {
+        let mut result = Ok(());
+        for mutation in mutations {
+            let replication_factor = self
+                .schema
+                .lock()
+                .unwrap()
+                .keyspace(&mutation.keyspace)
+                .map(|keyspace| keyspace.replication_factor);
+            let written = match replication_factor {
+                Some(replication_factor) => self.write(mutation, replication_factor),
+                None => Err(ErrorResponse::new(
+                    ErrorCode::Invalid,
+                    format!("Keyspace '{}' does not exist", mutation.keyspace),
+                )),
+            };
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
+
+    /// Applies increments of counters through the replica of their partition leading them,
+    /// this node if it is a replica and the first replica otherwise.
+    ///
+    /// An update the leader fails on is not sent to another replica, since the leader may
+    /// have applied it already and counting it twice can't be undone.
+    pub(crate) fn write_counter(
+        &mut self,
+        update: &CounterUpdate,
+        replication_factor: usize,
+    ) -> Result<(), ErrorResponse> {
+        let replicas = self.replicas(&update.partition_key, replication_factor);
+        if replicas
+            .iter()
+            .any(|node| node.address == self.current_host.address)
+        {
+            return self.lead_counter_update(update, replication_factor);
+        }
+        let Some(leader) = replicas.first() else {
+            return Err(ErrorResponse::new(
+                ErrorCode::Unavailable,
+                "No replica to lead the counter update",
+            ));
+        };
+
+        tracing::trace(|| format!("Sending counter update to {}", leader.address));
+        let result = internode::execute(
+            &mut self.connection_pool,
+            leader,
+            Request::CounterUpdate(update.clone()),
+        );
+        match result {
+            Ok(Response::Void) => Ok(()),
+            Ok(Response::Error(error)) => Err(error),
+            result => {
+                warn!(
+                    "Leader {} missed a counter update: {:?}",
+                    leader.address, result
+                );
+                Err(ErrorResponse::new(
+                    ErrorCode::WriteTimeout,
+                    format!("Counter update was not applied by {}", leader.address),
+                ))
+            }
+        }
+    }
+
+    /// Adds increments of counters to this node's shards, then writes the new shards to every
+    /// replica of the partition.
+    pub(crate) fn lead_counter_update(
+        &mut self,
+        update: &CounterUpdate,
+        replication_factor: usize,
+    ) -> Result<(), ErrorResponse> {
+        let Some(table) = self
+            .schema
+            .lock()
+            .unwrap()
+            .table(&update.keyspace, &update.table)
+            .cloned()
+        else {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!("unconfigured table {}.{}", update.keyspace, update.table),
+            ));
+        };
+
+        tracing::trace(|| "Applying counter update locally as its leader".to_string());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64;
+        // shards belong to the host id, so a node that moved keeps leading its own shards
+        let replica = self
+            .current_host
+            .host_id
+            .expect("the host id is set when the node starts")
+            .to_string();
+        let mutation = self
+            .rows
+            .lock()
+            .unwrap()
+            .increment(&table, update, &replica, timestamp);
+        self.write(&mutation, replication_factor)
+    }
+
+    /// Applies a mutation of a single row if the row satisfies a condition, which gets the row
+    /// with every column of its table, or `None` if it doesn't exist.
+    ///
+    /// The replicas of the partition decide with a round of Paxos, so concurrent transactions
+    /// on the partition see each other's writes: of two transactions inserting a row if it
+    /// doesn't exist, only one applies. A majority of the replicas has to take part, and the
+    /// condition is checked against the row merged from a majority of them. Rounds lost to
+    /// other coordinators are retried with a higher ballot after a random pause.
+    pub(crate) fn cas(
+        &mut self,
+        mutation: &Mutation,
+        replication_factor: usize,
+        condition: impl Fn(Option<&[CqlValue]>) -> bool,
+    ) -> Result<CasResult, ErrorResponse> {
+        let replicas = self.replicas(&mutation.partition_key, replication_factor);
+        let quorum = replicas.len() / 2 + 1;
+
+        for attempt in 0..CAS_ATTEMPTS {
+            if attempt > 0 {
+                let backoff = Uuid::new_v4().as_u128() % CAS_MAX_BACKOFF.as_micros();
+                thread::sleep(Duration::from_micros(backoff as u64));
+            }
+            let ballot = Ballot {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_micros() as i64,
+                id: Uuid::new_v4(),
+            };
+            let prepare = PaxosPrepare {
+                keyspace: mutation.keyspace.clone(),
+                table: mutation.table.clone(),
+                partition_key: mutation.partition_key.clone(),
+                ballot,
+            };
+
+            let mut responded = 0;
+            let mut promises = Vec::new();
+            for node in replicas.iter() {
+                match self.paxos_request(node, Request::PaxosPrepare(prepare.clone())) {
+                    Ok(Response::Promise(promise)) => {
+                        responded += 1;
+                        if promise.promised {
+                            promises.push((node.clone(), *promise));
+                        }
+                    }
+                    result => warn!(
+                        "Replica {} missed a Paxos prepare: {:?}",
+                        node.address, result
+                    ),
+                }
+            }
+            if responded < quorum {
+                return Err(ErrorResponse::new(
+                    ErrorCode::Unavailable,
+                    format!(
+                        "Lightweight transaction requires {} of {} replicas, only {} responded",
+                        quorum,
+                        replicas.len(),
+                        responded
+                    ),
+                ));
+            }
+            if promises.len() < quorum {
+                tracing::trace(|| "Paxos ballot was rejected, retrying".to_string());
+                continue;
+            }
+
+            // replicas that missed the last commit get it now, so that a quorum of them has
+            // it when the row is read
+            let committed = promises
+                .iter()
+                .filter_map(|(_, promise)| promise.committed.clone())
+                .max_by_key(|proposal| proposal.ballot);
+            if let Some(committed) = &committed {
+                self.repair_commit(&promises, committed, quorum)?;
+            }
+
+            // a proposal accepted after the last commit may have been decided already, so it
+            // is finished before anything else is proposed
+            let in_progress = promises
+                .iter()
+                .filter_map(|(_, promise)| promise.accepted.clone())
+                .max_by_key(|proposal| proposal.ballot)
+                .filter(|accepted| {
+                    committed
+                        .as_ref()
+                        .is_none_or(|committed| committed.ballot < accepted.ballot)
+                });
+            if let Some(in_progress) = in_progress {
+                tracing::trace(|| "Finishing an in-progress Paxos proposal".to_string());
+                let proposal = Proposal {
+                    ballot,
+                    mutation: in_progress.mutation,
+                };
+                if self.propose(&replicas, &proposal, quorum) {
+                    self.commit(&replicas, &proposal, quorum)?;
+                }
+                continue;
+            }
+
+            let promised: Vec<Node> = promises.into_iter().map(|(node, _)| node).collect();
+            let current = self.read_row(&promised, quorum, mutation)?;
+            if !condition(current.as_deref()) {
+                tracing::trace(|| "Lightweight transaction condition is not met".to_string());
+                return Ok(CasResult {
+                    applied: false,
+                    current,
+                });
+            }
+
+            let proposal = Proposal {
+                ballot,
+                mutation: Mutation {
+                    timestamp: ballot.timestamp,
+                    ..mutation.clone()
+                },
+            };
+            if !self.propose(&replicas, &proposal, quorum) {
+                tracing::trace(|| "Paxos proposal was rejected, retrying".to_string());
+                continue;
+            }
+            self.commit(&replicas, &proposal, quorum)?;
+            return Ok(CasResult {
+                applied: true,
+                current,
+            });
+        }
+
+        Err(ErrorResponse::new(
+            ErrorCode::WriteTimeout,
+            format!(
+                "Lightweight transaction on {}.{} gave up after {} contended rounds",
+                mutation.keyspace, mutation.table, CAS_ATTEMPTS
+            ),
+        ))
+    }
+
+    /// Reads a partition from the first of its replicas that answers, trying this node first.
+    pub(crate) fn read(
+        &mut self,
+        command: &ReadCommand,
+        replication_factor: usize,
+    ) -> Result<Rows, ErrorResponse> {
+        let partition_key = command.partition_key.clone().unwrap_or_default();
+        let mut replicas = self.replicas(&partition_key, replication_factor);
+        replicas.sort_by_key(|node| node.address != self.current_host.address);
+
+        let mut last_error = None;
+        for node in replicas.iter() {
+            match self.read_from(node, command) {
+                Ok(rows) => return Ok(rows),
+                Err(error) => last_error = Some(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ErrorResponse::new(ErrorCode::Unavailable, "No replica to read from")
+        }))
+    }
+
+    /// Reads all rows of a table from every node.
+    ///
+    /// Partitions are returned in token order, with the copies of replicated partitions dropped,
+    /// and their rows in clustering order. Fails if any node can't be read, since its partitions
+    /// might be missing otherwise.
+    pub(crate) fn scan(&mut self, command: &ReadCommand) -> Result<Rows, ErrorResponse> {
+        let nodes = self.cluster.read().unwrap().get_nodes().to_vec();
+        let (keyspace, table) = (command.keyspace.clone(), command.table.clone());
+        let partition_key_indexes: Vec<usize> =
+            match self.schema.lock().unwrap().table(&keyspace, &table) {
+                Some(table) => table
+                    .partition_key
+                    .iter()
+                    .filter_map(|key| table.columns.iter().position(|column| column.name == *key))
+                    .collect(),
+                None => Vec::new(),
+            };
+
+        let partitioner = self.partitioner();
+        let mut scanned = Rows::default();
+        let mut partitions: BTreeMap<(u64, Vec<String>), Vec<Vec<CqlValue>>> = BTreeMap::new();
+        for node in nodes.iter() {
+            let rows = self.read_from(node, command)?;
+            // rows arrive sorted by clustering key within each partition
+            let mut node_partitions: BTreeMap<(u64, Vec<String>), Vec<Vec<CqlValue>>> =
+                BTreeMap::new();
+            for row in rows.rows {
+                let partition_key: Vec<CqlValue> = partition_key_indexes
+                    .iter()
+                    .map(|index| row[*index].clone())
+                    .collect();
+                let position = (
+                    partitioner.partition_key_token(&partition_key),
+                    partition_key
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect(),
+                );
+                node_partitions.entry(position).or_default().push(row);
+            }
+            for (position, rows) in node_partitions {
+                // a replica may have cut a partition short when reaching the limit
+                let known = partitions.entry(position).or_default();
+                if rows.len() > known.len() {
+                    *known = rows;
+                }
+            }
+            scanned.columns = rows.columns;
+        }
+
+        scanned.rows = partitions.into_values().flatten().collect();
+        if let Some(limit) = command.limit {
+            scanned.rows.truncate(limit);
+        }
+        Ok(scanned)
+    }
+
+    /// Tells every other node about a new keyspace, table or index, or about a change of the
+    /// users and their permissions, which every node keeps its own copy of.
+    ///
+    /// Nodes that can't be told are logged; they don't know about the change until they are
+    /// told again.
+    pub(crate) fn announce(&mut self, request: Request) {
+        let nodes = self.cluster.read().unwrap().get_nodes().to_vec();
+
+        for node in nodes
+            .iter()
+            .filter(|node| node.address != self.current_host.address)
+        {
+            tracing::trace(|| format!("Sending {:?} to {}", request, node.address));
+            let result = internode::execute(&mut self.connection_pool, node, request.clone());
+            if !matches!(result, Ok(Response::Bool(_))) {
+                warn!(
+                    "Failed to send {:?} to {}: {:?}",
+                    request, node.address, result
+                );
+            }
+        }
+    }
+
+    /// Asks the replicas to accept a proposal, returning whether a majority did.
+    fn propose(&mut self, replicas: &[Node], proposal: &Proposal, quorum: usize) -> bool {
+        let mut accepted = 0;
+        for node in replicas.iter() {
+            match self.paxos_request(node, Request::PaxosPropose(proposal.clone())) {
+                Ok(Response::Bool(true)) => accepted += 1,
+                Ok(Response::Bool(false)) => {}
+                result => warn!(
+                    "Replica {} missed a Paxos proposal: {:?}",
+                    node.address, result
+                ),
+            }
+        }
+        accepted >= quorum
+    }
+
+    /// Tells the replicas to apply an accepted proposal, failing unless a majority did.
+    fn commit(
+        &mut self,
+        replicas: &[Node],
+        proposal: &Proposal,
+        quorum: usize,
+    ) -> Result<(), ErrorResponse> {
+        let mut acknowledged = 0;
+        for node in replicas.iter() {
+            match self.paxos_request(node, Request::PaxosCommit(proposal.clone())) {
+                Ok(Response::Void) => acknowledged += 1,
+                result => warn!(
+                    "Replica {} missed a Paxos commit: {:?}",
+                    node.address, result
+                ),
+            }
+        }
+
+        if acknowledged < quorum {
+            return Err(ErrorResponse::new(
+                ErrorCode::WriteTimeout,
+                format!(
+                    "Lightweight transaction was committed by {} of {} replicas",
+                    acknowledged,
+                    replicas.len()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Applies the last commit of a partition on the promising replicas that missed it,
+    /// failing unless a majority of the replicas has it afterwards.
+    fn repair_commit(
+        &mut self,
+        promises: &[(Node, Promise)],
+        committed: &Proposal,
+        quorum: usize,
+    ) -> Result<(), ErrorResponse> {
+        let mut up_to_date = 0;
+        for (node, promise) in promises.iter() {
+            if promise.committed.as_ref().map(|proposal| proposal.ballot) == Some(committed.ballot)
+            {
+                up_to_date += 1;
+                continue;
+            }
+            tracing::trace(|| format!("Repairing Paxos commit on {}", node.address));
+            match self.paxos_request(node, Request::PaxosCommit(committed.clone())) {
+                Ok(Response::Void) => up_to_date += 1,
+                result => warn!(
+                    "Replica {} missed a Paxos repair commit: {:?}",
+                    node.address, result
+                ),
+            }
+        }
+
+        if up_to_date < quorum {
+            return Err(ErrorResponse::new(
+                ErrorCode::WriteTimeout,
+                format!(
+                    "Last Paxos commit was repaired on {} replicas, {} required",
+                    up_to_date, quorum
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn paxos_request(&mut self, node: &Node, request: Request) -> AppResult<Response> {
+        if node.address != self.current_host.address {
+            return internode::execute(&mut self.connection_pool, node, request);
+        }
+
+        match request {
+            Request::PaxosPrepare(prepare) => paxos_prepare_handler::handle(&prepare, &self.paxos),
+            Request::PaxosPropose(proposal) => {
+                paxos_propose_handler::handle(&proposal, &self.paxos)
+            }
+            Request::PaxosCommit(proposal) => {
+                paxos_commit_handler::handle(&proposal, &self.paxos, &self.schema, &self.rows)
+            }
+            request => unreachable!("{:?} is not a Paxos request", request),
+        }
+    }
+
+    /// Reads the row a mutation writes, with every column of its table, from a majority of the
+    /// given replicas.
+    ///
+    /// The copies of the row are merged by timestamp, deletions included, so the latest write
+    /// any of the replicas read has wins.
+    fn read_row(
+        &mut self,
+        replicas: &[Node],
+        quorum: usize,
+        mutation: &Mutation,
+    ) -> Result<Option<Vec<CqlValue>>, ErrorResponse> {
+        let table = self
+            .schema
+            .lock()
+            .unwrap()
+            .table(&mutation.keyspace, &mutation.table)
+            .cloned();
+        let Some(table) = table else {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!(
+                    "unconfigured table {}.{}",
+                    mutation.keyspace, mutation.table
+                ),
+            ));
+        };
+        let bound = Some(ClusteringBound {
+            prefix: mutation.clustering_key.clone(),
+            inclusive: true,
+        })
+        .filter(|bound| !bound.prefix.is_empty());
+        let command = ReadCommand {
+            keyspace: mutation.keyspace.clone(),
+            table: mutation.table.clone(),
+            partition_key: Some(mutation.partition_key.clone()),
+            slice: ClusteringSlice {
+                start: bound.clone(),
+                end: bound,
+                reversed: false,
+            },
+            limit: Some(1),
+            index: None,
+        };
+
+        let mut replicas = replicas.to_vec();
+        replicas.sort_by_key(|node| node.address != self.current_host.address);
+        let mut reconciled = RowStorage::new(self.partitioner());
+        let mut responded = 0;
+        for node in replicas.iter() {
+            if responded == quorum {
+                break;
+            }
+            match self.read_mutations_from(node, &command) {
+                Ok(mutations) => {
+                    responded += 1;
+                    for mutation in mutations.iter() {
+                        reconciled.apply(&table, mutation);
+                    }
+                }
+                Err(error) => warn!("Failed to read from {}: {:?}", node.address, error),
+            }
+        }
+        if responded < quorum {
+            return Err(ErrorResponse::new(
+                ErrorCode::Unavailable,
+                format!(
+                    "Lightweight transaction requires reading {} replicas, only {} responded",
+                    quorum, responded
+                ),
+            ));
+        }
+
+        let (rows, _) = reconciled.read(&table, &command);
+        Ok(rows
+            .into_iter()
+            .next()
+            .map(|row| read_handler::column_values(&table, row)))
+    }
+
+    fn read_mutations_from(
+        &mut self,
+        node: &Node,
+        command: &ReadCommand,
+    ) -> Result<Vec<Mutation>, ErrorResponse> {
+        let result = if node.address == self.current_host.address {
+            tracing::trace(|| "Reading mutations locally".to_string());
+            read_mutations_handler::handle(command, &self.schema, &self.rows)
+        } else {
+            tracing::trace(|| format!("Sending mutations read to {}", node.address));
+            internode::execute(
+                &mut self.connection_pool,
+                node,
+                Request::ReadMutations(command.clone()),
+            )
+        };
+
+        match result {
+            Ok(Response::Mutations(mutations)) => Ok(mutations),
+            Ok(Response::Error(error)) => Err(error),
+            result => Err(ErrorResponse::new(
+                ErrorCode::Unavailable,
+                format!("Cannot read from {}: {:?}", node.address, result),
+            )),
+        }
+    }
+
+    fn read_from(&mut self, node: &Node, command: &ReadCommand) -> Result<Rows, ErrorResponse> {
+        let result = if node.address == self.current_host.address {
+            tracing::trace(|| "Reading locally".to_string());
+            read_handler::handle(command, &self.schema, &self.rows)
+        } else {
+            tracing::trace(|| format!("Sending read to {}", node.address));
+            internode::execute(
+                &mut self.connection_pool,
+                node,
+                Request::Read(command.clone()),
+            )
+        };
+
+        match result {
+            Ok(Response::Rows(rows)) => Ok(rows),
+            Ok(Response::Error(error)) => Err(error),
+            result => {
+                warn!("Failed to read from {}: {:?}", node.address, result);
+                Err(ErrorResponse::new(
+                    ErrorCode::Unavailable,
+                    format!("Cannot read from {}", node.address),
+                ))
+            }
+        }
+    }
+
+    /// Returns the nodes storing a partition: the node owning its token, followed by the next
+    /// distinct nodes on the ring up to the replication factor. None if the ring is empty.
+    fn replicas(&self, partition_key: &[CqlValue], replication_factor: usize) -> Vec<Node> {
+        let cluster = self.cluster.read().unwrap();
+        let hash = cluster.partitioner().partition_key_token(partition_key);
+        let Some(owner) = cluster.router().owner(hash) else {
+            return Vec::new();
+        };
+
+        let mut replicas: Vec<Node> = cluster
+            .replication_strategy()
+            .get_replica_nodes(hash, owner, replication_factor.saturating_sub(1))
+            .into_iter()
+            .filter_map(|address| cluster.node(address).cloned())
+            .collect();
+        replicas.insert(0, owner.clone());
+        replicas
+    }
+
+    fn partitioner(&self) -> GlobalPartitioner {
+        self.cluster.read().unwrap().partitioner().clone()
+    }
+
+    fn batchlog_endpoints(&self, id: Uuid) -> Vec<Node> {
+        let cluster = self.cluster.read().unwrap();
+
+        let mut endpoints: Vec<Node> = cluster
+            .replication_strategy()
+            .get_key_replica_nodes(
+                id.to_string().as_str(),
+                &self.current_host,
+                BATCHLOG_REPLICA_COUNT,
+            )
+            .into_iter()
+            .filter_map(|address| cluster.node(address).cloned())
+            .collect();
+        endpoints.sort_by(|a, b| a.address.cmp(&b.address));
+        endpoints
+    }
+}
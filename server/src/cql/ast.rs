@@ -0,0 +1,207 @@
+//! The syntax tree of parsed CQL statements.
+//!
+//! Names of keyspaces, tables and columns are lower-cased unless they were quoted. Values are
+//! kept as written until the statement is executed against the schema, which gives them their
+//! column's type. Besides CQL, statements cover the values of the default keyspace and the
+//! management of users and their permissions.
+
+use shared::protocol::types::{ClusteringOrder, DataType, PermissionChange};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CqlStatement {
+    CreateKeyspace(CreateKeyspace),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    Insert(Insert),
+    Select(Select),
+    Update(Update),
+    Delete(Delete),
+    Use(String),
+    AddValue(AddValue),
+    /// `CHECK <term>`
+    CheckValue(Term),
+    /// `COUNT`
+    CountValues,
+    CreateUser(CreateUser),
+    AlterUser(AlterUser),
+    DropUser(DropUser),
+    /// `GRANT <permission> ON <resource> TO <name>`
+    Grant(PermissionChange),
+    /// `REVOKE <permission> ON <resource> FROM <name>`
+    Revoke(PermissionChange),
+}
+
+/// `ADD <term> [WITH REPLICATION <term>]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AddValue {
+    pub(crate) value: Term,
+    pub(crate) replication_factor: Option<Term>,
+}
+
+/// `CREATE USER <term> WITH PASSWORD <term> [SUPERUSER | NOSUPERUSER]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CreateUser {
+    pub(crate) name: Term,
+    pub(crate) password: Term,
+    pub(crate) superuser: bool,
+}
+
+/// `ALTER USER <term> [WITH PASSWORD <term>] [SUPERUSER | NOSUPERUSER]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AlterUser {
+    pub(crate) name: Term,
+    pub(crate) password: Option<Term>,
+    pub(crate) superuser: Option<bool>,
+}
+
+/// `DROP USER [IF EXISTS] <term>`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DropUser {
+    pub(crate) name: Term,
+    pub(crate) if_exists: bool,
+}
+
+/// `CREATE KEYSPACE [IF NOT EXISTS] <name> WITH replication = {...}`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CreateKeyspace {
+    pub(crate) name: String,
+    pub(crate) if_not_exists: bool,
+    /// The `replication` map, keyed by lower-cased option name.
+    pub(crate) replication: Vec<(String, Literal)>,
+}
+
+/// `CREATE TABLE [IF NOT EXISTS] <table> (<column> <type>, ..., PRIMARY KEY (...))
+/// [WITH CLUSTERING ORDER BY (<column> ASC|DESC, ...)]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CreateTable {
+    pub(crate) table: TableName,
+    pub(crate) if_not_exists: bool,
+    pub(crate) columns: Vec<(String, DataType)>,
+    /// The partition key columns, in key order.
+    pub(crate) partition_key: Vec<String>,
+    /// The clustering columns, in key order.
+    pub(crate) clustering_key: Vec<String>,
+    /// The order of the clustering columns given with CLUSTERING ORDER BY.
+    pub(crate) clustering_order: Vec<(String, ClusteringOrder)>,
+}
+
+/// `CREATE INDEX [IF NOT EXISTS] [<name>] ON <table> (<column>)`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CreateIndex {
+    /// The name of the index; `<table>_<column>_idx` if not given.
+    pub(crate) name: Option<String>,
+    pub(crate) if_not_exists: bool,
+    pub(crate) table: TableName,
+    pub(crate) column: String,
+}
+
+/// `INSERT INTO <table> (<column>, ...) VALUES (<term>, ...) [IF NOT EXISTS]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Insert {
+    pub(crate) table: TableName,
+    pub(crate) values: Vec<(String, Term)>,
+    pub(crate) if_not_exists: bool,
+}
+
+/// `SELECT <selection> FROM <table> [WHERE ...] [ORDER BY <column> ASC|DESC, ...] [LIMIT <n>]
+/// [ALLOW FILTERING]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Select {
+    pub(crate) table: TableName,
+    pub(crate) selection: Selection,
+    pub(crate) relations: Vec<Relation>,
+    pub(crate) ordering: Vec<(String, ClusteringOrder)>,
+    pub(crate) limit: Option<usize>,
+    pub(crate) allow_filtering: bool,
+}
+
+/// `UPDATE <table> SET <column> = <assignment>, ... WHERE ...
+/// [IF EXISTS | IF <condition> AND ...]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Update {
+    pub(crate) table: TableName,
+    pub(crate) assignments: Vec<(String, Assignment)>,
+    pub(crate) relations: Vec<Relation>,
+    pub(crate) if_exists: bool,
+    /// Relations the current values of the row must satisfy for the update to apply.
+    pub(crate) conditions: Vec<Relation>,
+}
+
+/// `DELETE [<column>, ...] FROM <table> WHERE ...`; without columns the whole row is deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Delete {
+    pub(crate) table: TableName,
+    pub(crate) columns: Vec<String>,
+    pub(crate) relations: Vec<Relation>,
+}
+
+/// A table name, qualified with its keyspace or relative to the keyspace of the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TableName {
+    pub(crate) keyspace: Option<String>,
+    pub(crate) name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Selection {
+    /// `*`
+    All,
+    Columns(Vec<String>),
+    /// `COUNT(*)`
+    Count,
+}
+
+/// A restriction of a WHERE clause: `<column> <operator> <term>`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Relation {
+    pub(crate) column: String,
+    pub(crate) operator: Operator,
+    pub(crate) term: Term,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Equal,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+}
+
+/// The new value an UPDATE gives a column.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Assignment {
+    /// `<column> = <term>`
+    Value(Term),
+    /// `<column> = <column> + <term>`, which increments a counter.
+    Add(Term),
+    /// `<column> = <column> - <term>`, which decrements a counter.
+    Subtract(Term),
+}
+
+/// A value of a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Term {
+    Literal(Literal),
+    /// A bind marker, with its position among the statement's markers.
+    Marker(usize),
+}
+
+/// A constant as written in a statement.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Literal {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Uuid(Uuid),
+    Blob(Vec<u8>),
+    Boolean(bool),
+    Null,
+    /// `[<literal>, ...]`
+    List(Vec<Literal>),
+    /// `{<literal>, ...}`; `{}` is an empty map, which is an empty set as well.
+    Set(Vec<Literal>),
+    /// `{<literal>: <literal>, ...}`
+    Map(Vec<(Literal, Literal)>),
+}
@@ -0,0 +1,1555 @@
+//! Execution of parsed CQL statements.
+//!
+//! Statements are validated against the schema of this node, which also gives the values of
+//! the statement their column's type. Writes become a `Mutation` sent to the replicas of the
+//! row's partition; SELECT reads one partition from its replicas, or with ALLOW FILTERING
+//! scans the whole table on every node, and filters, limits and projects the rows here.
+//! Restrictions of the clustering columns of a single partition query select a slice of the
+//! partition, which the replica reads directly in the requested order. Without a partition key,
+//! an EQ restriction of an indexed column is looked up in the secondary index of every node
+//! instead of scanning the table. INSERT IF NOT EXISTS and UPDATE with IF conditions are
+//! lightweight transactions, decided with Paxos among the replicas of the row's partition.
+//! Tables with counter columns only take UPDATEs incrementing or decrementing the counters,
+//! which a replica of the row's partition leads.
+//!
+//! ADD and CHECK go to the node owning the value, COUNT sums the counts of every node. Changes
+//! of users and permissions are applied on this node and then sent to every other node, since
+//! each node keeps its own roles table.
+
+use crate::authorizer;
+use crate::coordinator::Coordinator;
+use crate::cql::ast::{
+    AddValue, AlterUser, Assignment, CqlStatement, CreateIndex, CreateKeyspace, CreateTable,
+    CreateUser, Delete, DropUser, Insert, Literal, Operator, Relation, Select, Selection,
+    TableName, Term, Update,
+};
+use crate::cql::invalid;
+use crate::handlers::{
+    alter_user_handler, create_index_handler, create_keyspace_handler, create_table_handler,
+    create_user_handler, drop_user_handler, grant_handler, revoke_handler,
+};
+use crate::server::NodeState;
+use crate::tracing;
+use shared::protocol::types::{
+    self, ClusteringBound, ClusteringOrder, ClusteringSlice, ColumnSpec, CounterUpdate, CqlValue,
+    DataType, Entry, ErrorCode, ErrorResponse, IndexExpression, IndexMetadata, KeyspaceMetadata,
+    Mutation, Permission, ReadCommand, Request, Resource, Response, Rows, SchemaChange,
+    SchemaChangeType, TableMetadata,
+};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Replication strategy of keyspaces: replicas are the next distinct nodes on the ring.
+const SIMPLE_STRATEGY: &str = "SimpleStrategy";
+
+/// Runs the CQL statements of a connection.
+pub(crate) struct Executor<'a> {
+    state: &'a NodeState,
+    coordinator: &'a mut Coordinator,
+    /// The user the connection authenticated as, if the authenticator requires one.
+    user: Option<&'a str>,
+    /// Keyspace of unqualified table names.
+    keyspace: Option<&'a str>,
+    /// Values of the statement's bind markers.
+    values: &'a [CqlValue],
+}
+
+/// A restriction of a WHERE clause with its value bound and typed.
+struct Restriction {
+    column: usize,
+    operator: Operator,
+    value: CqlValue,
+}
+
+impl<'a> Executor<'a> {
+    pub(crate) fn new(
+        state: &'a NodeState,
+        coordinator: &'a mut Coordinator,
+        user: Option<&'a str>,
+        keyspace: Option<&'a str>,
+        values: &'a [CqlValue],
+    ) -> Self {
+        Self {
+            state,
+            coordinator,
+            user,
+            keyspace,
+            values,
+        }
+    }
+
+    pub(crate) fn execute(&mut self, statement: &CqlStatement) -> Result<Response, ErrorResponse> {
+        match statement {
+            CqlStatement::CreateKeyspace(create) => self.create_keyspace(create),
+            CqlStatement::CreateTable(create) => self.create_table(create),
+            CqlStatement::CreateIndex(create) => self.create_index(create),
+            CqlStatement::Insert(insert) => self.insert(insert),
+            CqlStatement::Select(select) => self.select(select),
+            CqlStatement::Update(update) => self.update(update),
+            CqlStatement::Delete(delete) => self.delete(delete),
+            CqlStatement::Use(keyspace) => self.use_keyspace(keyspace),
+            CqlStatement::AddValue(add) => self.add_value(add),
+            CqlStatement::CheckValue(value) => self.check_value(value),
+            CqlStatement::CountValues => self.count_values(),
+            CqlStatement::CreateUser(create) => {
+                let request = self.bind_create_user(create)?;
+                self.change_roles(request)
+            }
+            CqlStatement::AlterUser(alter) => {
+                let request = self.bind_alter_user(alter)?;
+                self.change_roles(request)
+            }
+            CqlStatement::DropUser(drop) => {
+                let request = self.bind_drop_user(drop)?;
+                self.change_roles(request)
+            }
+            CqlStatement::Grant(change) => self.change_roles(Request::Grant(change.clone())),
+            CqlStatement::Revoke(change) => self.change_roles(Request::Revoke(change.clone())),
+        }
+    }
+
+    fn create_keyspace(&mut self, create: &CreateKeyspace) -> Result<Response, ErrorResponse> {
+        self.authorize(Permission::Create, Resource::AllKeyspaces)?;
+
+        let mut class = None;
+        let mut replication_factor = None;
+        for (option, value) in create.replication.iter() {
+            match (option.as_str(), value) {
+                ("class", Literal::String(value)) => class = Some(value.as_str()),
+                ("replication_factor", Literal::Integer(factor)) => {
+                    replication_factor = Some(*factor)
+                }
+                ("replication_factor", Literal::String(factor)) => {
+                    replication_factor = factor.parse().ok()
+                }
+                _ => {
+                    return Err(invalid(format!(
+                        "Unknown replication option {} = {:?}",
+                        option, value
+                    )));
+                }
+            }
+        }
+        if class.is_none_or(|class| !class.ends_with(SIMPLE_STRATEGY)) {
+            return Err(invalid(format!(
+                "Unsupported replication class {:?}, expected {}",
+                class, SIMPLE_STRATEGY
+            )));
+        }
+        let replication_factor = match replication_factor {
+            Some(factor) if factor > 0 => factor as usize,
+            _ => {
+                return Err(invalid(
+                    "SimpleStrategy requires a positive replication_factor",
+                ));
+            }
+        };
+
+        let keyspace = KeyspaceMetadata {
+            name: create.name.clone(),
+            replication_factor,
+        };
+        let created = matches!(
+            create_keyspace_handler::handle(&keyspace, &self.state.schema, &self.state.events),
+            Ok(Response::Bool(true))
+        );
+        if !created {
+            return Self::already_exists(create.if_not_exists, &create.name);
+        }
+
+        self.coordinator.announce(Request::CreateKeyspace(keyspace));
+        Ok(Response::SchemaChange(SchemaChange {
+            change: SchemaChangeType::Created,
+            keyspace: create.name.clone(),
+            table: None,
+        }))
+    }
+
+    fn create_table(&mut self, create: &CreateTable) -> Result<Response, ErrorResponse> {
+        let keyspace = self.keyspace_of(&create.table)?;
+        self.authorize(Permission::Create, Resource::Keyspace(keyspace.clone()))?;
+
+        let mut names = HashSet::new();
+        for (column, _) in create.columns.iter() {
+            if !names.insert(column) {
+                return Err(invalid(format!(
+                    "Multiple definition of identifier {}",
+                    column
+                )));
+            }
+        }
+        if create.partition_key.is_empty() {
+            return Err(invalid("No PRIMARY KEY specified (exactly one required)"));
+        }
+        let mut keys = HashSet::new();
+        for key in create
+            .partition_key
+            .iter()
+            .chain(create.clustering_key.iter())
+        {
+            let Some((_, data_type)) = create.columns.iter().find(|(name, _)| name == key) else {
+                return Err(invalid(format!(
+                    "Unknown definition {} referenced in PRIMARY KEY",
+                    key
+                )));
+            };
+            if !keys.insert(key) {
+                return Err(invalid(format!(
+                    "{} is referenced more than once in PRIMARY KEY",
+                    key
+                )));
+            }
+            if data_type.is_collection() {
+                return Err(invalid(format!(
+                    "Invalid collection type {} for PRIMARY KEY component {}",
+                    data_type, key
+                )));
+            }
+            if *data_type == DataType::Counter {
+                return Err(invalid(format!(
+                    "counter type is not supported for PRIMARY KEY column '{}'",
+                    key
+                )));
+            }
+        }
+        let counters = create
+            .columns
+            .iter()
+            .filter(|(_, data_type)| *data_type == DataType::Counter)
+            .count();
+        if counters > 0 && counters < create.columns.len() - keys.len() {
+            return Err(invalid(
+                "Cannot mix counter and non counter columns in the same table",
+            ));
+        }
+        let mut clustering_order = vec![ClusteringOrder::Asc; create.clustering_key.len()];
+        for (index, (column, order)) in create.clustering_order.iter().enumerate() {
+            if !create.clustering_key.contains(column) {
+                return Err(invalid(format!(
+                    "Only clustering key columns can be defined in CLUSTERING ORDER directive, \
+                     got {}",
+                    column
+                )));
+            }
+            if create.clustering_key[index] != *column {
+                return Err(invalid(
+                    "The order of columns in the CLUSTERING ORDER directive must match that of \
+                     the clustering columns",
+                ));
+            }
+            clustering_order[index] = *order;
+        }
+
+        let table = TableMetadata {
+            keyspace: keyspace.clone(),
+            name: create.table.name.clone(),
+            columns: create
+                .columns
+                .iter()
+                .map(|(name, data_type)| ColumnSpec::new(name.clone(), data_type.clone()))
+                .collect(),
+            partition_key: create.partition_key.clone(),
+            clustering_key: create.clustering_key.clone(),
+            clustering_order,
+        };
+        let created =
+            match create_table_handler::handle(&table, &self.state.schema, &self.state.events) {
+                Ok(Response::Bool(created)) => created,
+                Ok(Response::Error(error)) => return Err(error),
+                result => return Err(invalid(format!("Cannot create table: {:?}", result))),
+            };
+        if !created {
+            return Self::already_exists(create.if_not_exists, &create.table.name);
+        }
+
+        self.coordinator.announce(Request::CreateTable(table));
+        Ok(Response::SchemaChange(SchemaChange {
+            change: SchemaChangeType::Created,
+            keyspace,
+            table: Some(create.table.name.clone()),
+        }))
+    }
+
+    fn create_index(&mut self, create: &CreateIndex) -> Result<Response, ErrorResponse> {
+        let table = self.table(&create.table, Permission::Create)?;
+        let spec = Self::column(&table, &create.column)?;
+        if table.is_partition_key(&create.column) {
+            return Err(invalid(format!(
+                "Cannot create secondary index on partition key column {}",
+                create.column
+            )));
+        }
+        if spec.data_type.is_collection() {
+            return Err(invalid(format!(
+                "Cannot create secondary index on collection column {}",
+                create.column
+            )));
+        }
+        if table.is_counter_table() {
+            return Err(invalid(
+                "Secondary indexes on counter tables are not supported",
+            ));
+        }
+
+        let index = IndexMetadata {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            name: create
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{}_{}_idx", table.name, create.column)),
+            column: create.column.clone(),
+        };
+        if let Some(existing) =
+            self.state
+                .schema
+                .lock()
+                .unwrap()
+                .index_on(&table.keyspace, &table.name, &index.column)
+            && existing.name != index.name
+        {
+            if create.if_not_exists {
+                return Ok(Response::Void);
+            }
+            return Err(invalid(format!(
+                "Index {} is a duplicate of existing index {}",
+                index.name, existing.name
+            )));
+        }
+        let created = match create_index_handler::handle(
+            &index,
+            &self.state.schema,
+            &self.state.rows,
+            &self.state.events,
+        ) {
+            Ok(Response::Bool(created)) => created,
+            Ok(Response::Error(error)) => return Err(error),
+            result => return Err(invalid(format!("Cannot create index: {:?}", result))),
+        };
+        if !created {
+            return Self::already_exists(create.if_not_exists, &index.name);
+        }
+
+        self.coordinator.announce(Request::CreateIndex(index));
+        Ok(Response::SchemaChange(SchemaChange {
+            change: SchemaChangeType::Updated,
+            keyspace: table.keyspace.clone(),
+            table: Some(table.name.clone()),
+        }))
+    }
+
+    fn insert(&mut self, insert: &Insert) -> Result<Response, ErrorResponse> {
+        let table = self.table(&insert.table, Permission::Modify)?;
+        let mutation = self.bind_insert(&table, insert)?;
+        if insert.if_not_exists {
+            return self.write_if(&table, mutation, |row| row.is_none());
+        }
+        self.write(&table, mutation)
+    }
+
+    /// Binds a statement of a batch that writes a row of a table without counters into its
+    /// mutation. Returns `None` for any other statement.
+    pub(crate) fn row_mutation(
+        &self,
+        statement: &CqlStatement,
+    ) -> Result<Option<Mutation>, ErrorResponse> {
+        let conditional = || invalid("Conditional statements are not supported inside a batch");
+
+        match statement {
+            CqlStatement::Insert(insert) => {
+                let table = self.table(&insert.table, Permission::Modify)?;
+                if insert.if_not_exists {
+                    return Err(conditional());
+                }
+                self.bind_insert(&table, insert).map(Some)
+            }
+            CqlStatement::Update(update) => {
+                let table = self.table(&update.table, Permission::Modify)?;
+                if table.is_counter_table() {
+                    return Ok(None);
+                }
+                if update.if_exists || !update.conditions.is_empty() {
+                    return Err(conditional());
+                }
+                let (mutation, _) = self.bind_update(&table, update)?;
+                Ok(Some(mutation))
+            }
+            CqlStatement::Delete(delete) => {
+                let table = self.table(&delete.table, Permission::Modify)?;
+                self.bind_delete(&table, delete).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn bind_insert(
+        &self,
+        table: &TableMetadata,
+        insert: &Insert,
+    ) -> Result<Mutation, ErrorResponse> {
+        if table.is_counter_table() {
+            return Err(invalid(
+                "INSERT statements are not allowed on counter tables, use UPDATE instead",
+            ));
+        }
+
+        let mut partition_key = vec![None; table.partition_key.len()];
+        let mut clustering_key = vec![None; table.clustering_key.len()];
+        let mut cells = Vec::new();
+        for (column, term) in insert.values.iter() {
+            let spec = Self::column(table, column)?;
+            let value = self.bind(term, spec)?;
+            if insert
+                .values
+                .iter()
+                .filter(|(name, _)| name == column)
+                .count()
+                > 1
+            {
+                return Err(invalid(format!(
+                    "Multiple definitions found for column {}",
+                    column
+                )));
+            }
+            if let Some(index) = table.partition_key.iter().position(|key| key == column) {
+                partition_key[index] = Some(value);
+            } else if let Some(index) = table.clustering_key.iter().position(|key| key == column) {
+                clustering_key[index] = Some(value);
+            } else {
+                cells.push((column.clone(), value));
+            }
+        }
+
+        Ok(Mutation {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key: Self::complete_key(
+                "partition key",
+                &table.partition_key,
+                partition_key,
+            )?,
+            clustering_key: Self::complete_key(
+                "clustering key",
+                &table.clustering_key,
+                clustering_key,
+            )?,
+            cells,
+            row_marker: true,
+            row_deletion: false,
+            counters: Vec::new(),
+            timestamp: Self::timestamp(),
+        })
+    }
+
+    fn add_value(&mut self, add: &AddValue) -> Result<Response, ErrorResponse> {
+        let entry = self.bind_add_value(add)?;
+        self.authorize_request(&Request::Add(entry.clone()))?;
+
+        let failed_nodes = self
+            .coordinator
+            .apply_mutations(std::slice::from_ref(&entry));
+        if !failed_nodes.is_empty() {
+            return Err(ErrorResponse::new(
+                ErrorCode::WriteTimeout,
+                format!("{} was not added on {:?}", entry.value, failed_nodes),
+            ));
+        }
+        Ok(Response::Void)
+    }
+
+    /// Binds a statement of a batch that adds a value into the value to add. Returns `None`
+    /// for any other statement.
+    pub(crate) fn added_value(
+        &self,
+        statement: &CqlStatement,
+    ) -> Result<Option<Entry>, ErrorResponse> {
+        let CqlStatement::AddValue(add) = statement else {
+            return Ok(None);
+        };
+        let entry = self.bind_add_value(add)?;
+        self.authorize_request(&Request::Add(entry.clone()))?;
+        Ok(Some(entry))
+    }
+
+    fn bind_add_value(&self, add: &AddValue) -> Result<Entry, ErrorResponse> {
+        let value = match self.bind(&add.value, &ColumnSpec::new("value", DataType::Text))? {
+            CqlValue::Text(value) => value,
+            other => return Err(invalid(format!("Invalid value {}", other))),
+        };
+        let replication_factor = match &add.replication_factor {
+            Some(term) => {
+                match self.bind(term, &ColumnSpec::new("replication_factor", DataType::Int))? {
+                    CqlValue::Null => None,
+                    CqlValue::Int(factor) if factor >= 0 => Some(factor as usize),
+                    other => {
+                        return Err(invalid(format!("Invalid replication factor {}", other)));
+                    }
+                }
+            }
+            None => None,
+        };
+        Ok(Entry {
+            value,
+            replication_factor,
+        })
+    }
+
+    fn check_value(&mut self, term: &Term) -> Result<Response, ErrorResponse> {
+        let column = ColumnSpec::new("value", DataType::Text);
+        let value = match self.bind(term, &column)? {
+            CqlValue::Text(value) => value,
+            other => return Err(invalid(format!("Invalid value {}", other))),
+        };
+        self.authorize_request(&Request::Check(value.clone()))?;
+
+        let exists = self.coordinator.check(&value)?;
+        Ok(Response::Rows(Rows {
+            columns: vec![ColumnSpec::new("exists", DataType::Boolean)],
+            rows: vec![vec![CqlValue::Boolean(exists)]],
+        }))
+    }
+
+    fn count_values(&mut self) -> Result<Response, ErrorResponse> {
+        self.authorize_request(&Request::Count(Vec::new()))?;
+
+        let count = self.coordinator.count()?;
+        Ok(Response::Rows(Rows {
+            columns: vec![ColumnSpec::new("count", DataType::BigInt)],
+            rows: vec![vec![CqlValue::BigInt(count as i64)]],
+        }))
+    }
+
+    fn bind_create_user(&self, create: &CreateUser) -> Result<Request, ErrorResponse> {
+        Ok(Request::CreateUser(types::CreateUser {
+            name: self.bind_text(&create.name, "name")?,
+            password: self.bind_text(&create.password, "password")?,
+            superuser: create.superuser,
+        }))
+    }
+
+    fn bind_alter_user(&self, alter: &AlterUser) -> Result<Request, ErrorResponse> {
+        let password = match &alter.password {
+            Some(password) => Some(self.bind_text(password, "password")?),
+            None => None,
+        };
+        Ok(Request::AlterUser(types::AlterUser {
+            name: self.bind_text(&alter.name, "name")?,
+            password,
+            superuser: alter.superuser,
+        }))
+    }
+
+    fn bind_drop_user(&self, drop: &DropUser) -> Result<Request, ErrorResponse> {
+        Ok(Request::DropUser(types::DropUser {
+            name: self.bind_text(&drop.name, "name")?,
+            if_exists: drop.if_exists,
+        }))
+    }
+
+    /// Applies a change of the users or their permissions on this node, then sends it to
+    /// every other node.
+    fn change_roles(&mut self, request: Request) -> Result<Response, ErrorResponse> {
+        self.authorize_request(&request)?;
+
+        let state = self.state;
+        let result = match &request {
+            Request::CreateUser(create) => {
+                create_user_handler::handle(create, &state.authenticator, &state.roles)
+            }
+            Request::AlterUser(alter) => {
+                alter_user_handler::handle(alter, self.user, &state.authenticator, &state.roles)
+            }
+            Request::DropUser(drop) => {
+                drop_user_handler::handle(drop, self.user, &state.authenticator, &state.roles)
+            }
+            Request::Grant(change) => {
+                grant_handler::handle(change, &state.authenticator, &state.roles)
+            }
+            Request::Revoke(change) => {
+                revoke_handler::handle(change, &state.authenticator, &state.roles)
+            }
+            request => unreachable!("{:?} doesn't change roles", request),
+        };
+        match result {
+            Ok(Response::Bool(true)) => {
+                self.coordinator.announce(request);
+                Ok(Response::Void)
+            }
+            Ok(Response::Error(error)) => Err(error),
+            result => Err(invalid(format!("Cannot change roles: {:?}", result))),
+        }
+    }
+
+    /// Binds a statement of a batch that updates the counters of a row into the increments a
+    /// replica of the row leads, along with the replication factor of its keyspace. Returns
+    /// `None` for any other statement.
+    pub(crate) fn counter_update(
+        &mut self,
+        statement: &CqlStatement,
+    ) -> Result<Option<(CounterUpdate, usize)>, ErrorResponse> {
+        let CqlStatement::Update(update) = statement else {
+            return Ok(None);
+        };
+        let table = self.table(&update.table, Permission::Modify)?;
+        match table.is_counter_table() {
+            true => self.bind_counter_update(&table, update).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn update(&mut self, update: &Update) -> Result<Response, ErrorResponse> {
+        let table = self.table(&update.table, Permission::Modify)?;
+        if table.is_counter_table() {
+            let (update, replication_factor) = self.bind_counter_update(&table, update)?;
+            tracing::trace(|| format!("Updating counters of {}.{}", table.keyspace, table.name));
+            self.coordinator
+                .write_counter(&update, replication_factor)?;
+            return Ok(Response::Void);
+        }
+
+        let (mutation, conditions) = self.bind_update(&table, update)?;
+        if update.if_exists || !conditions.is_empty() {
+            return self.write_if(&table, mutation, |row| {
+                row.is_some_and(|row| conditions.iter().all(|condition| condition.matches(row)))
+            });
+        }
+        self.write(&table, mutation)
+    }
+
+    /// Binds an UPDATE of a table without counters into its mutation, along with the
+    /// conditions of an IF clause.
+    fn bind_update(
+        &self,
+        table: &TableMetadata,
+        update: &Update,
+    ) -> Result<(Mutation, Vec<Restriction>), ErrorResponse> {
+        let (partition_key, clustering_key) = self.modified_row(table, &update.relations)?;
+
+        let mut cells = Vec::new();
+        for (column, assignment) in update.assignments.iter() {
+            let spec = Self::column(table, column)?;
+            if table.is_primary_key(column) {
+                return Err(invalid(format!(
+                    "PRIMARY KEY part {} found in SET part",
+                    column
+                )));
+            }
+            match assignment {
+                Assignment::Value(term) => cells.push((column.clone(), self.bind(term, spec)?)),
+                Assignment::Add(_) | Assignment::Subtract(_) => {
+                    return Err(invalid(format!(
+                        "Invalid operation ({0} = {0} + ?) for non counter column {0}",
+                        column
+                    )));
+                }
+            }
+        }
+
+        let conditions = self.restrictions(table, &update.conditions)?;
+        if let Some(condition) = conditions
+            .iter()
+            .find(|condition| table.is_primary_key(&table.columns[condition.column].name))
+        {
+            return Err(invalid(format!(
+                "PRIMARY KEY column '{}' cannot have IF conditions",
+                table.columns[condition.column].name
+            )));
+        }
+
+        let mutation = Mutation {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key,
+            clustering_key,
+            cells,
+            row_marker: false,
+            row_deletion: false,
+            counters: Vec::new(),
+            timestamp: Self::timestamp(),
+        };
+        Ok((mutation, conditions))
+    }
+
+    /// Binds an UPDATE of a counter table, whose columns besides the primary key are all
+    /// counters, into the increments of its counters.
+    fn bind_counter_update(
+        &self,
+        table: &TableMetadata,
+        update: &Update,
+    ) -> Result<(CounterUpdate, usize), ErrorResponse> {
+        let (partition_key, clustering_key) = self.modified_row(table, &update.relations)?;
+
+        let mut deltas = Vec::new();
+        for (column, assignment) in update.assignments.iter() {
+            let spec = Self::column(table, column)?;
+            if table.is_primary_key(column) {
+                return Err(invalid(format!(
+                    "PRIMARY KEY part {} found in SET part",
+                    column
+                )));
+            }
+            match assignment {
+                Assignment::Value(_) => {
+                    return Err(invalid(format!(
+                        "Cannot set the value of counter column {} (counters can only be \
+                         incremented/decremented, not set)",
+                        column
+                    )));
+                }
+                Assignment::Add(term) => {
+                    deltas.push((column.clone(), self.counter_delta(term, spec)?))
+                }
+                Assignment::Subtract(term) => {
+                    let delta = self.counter_delta(term, spec)?.checked_neg();
+                    let delta = delta.ok_or_else(|| {
+                        invalid(format!("Counter decrement of {} overflows", column))
+                    })?;
+                    deltas.push((column.clone(), delta));
+                }
+            }
+        }
+        if update.if_exists || !update.conditions.is_empty() {
+            return Err(invalid(
+                "Conditional updates are not supported on counter tables",
+            ));
+        }
+
+        let update = CounterUpdate {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key,
+            clustering_key,
+            deltas,
+        };
+        Ok((update, self.replication_factor(&table.keyspace)?))
+    }
+
+    fn delete(&mut self, delete: &Delete) -> Result<Response, ErrorResponse> {
+        let table = self.table(&delete.table, Permission::Modify)?;
+        let mutation = self.bind_delete(&table, delete)?;
+        self.write(&table, mutation)
+    }
+
+    fn bind_delete(
+        &self,
+        table: &TableMetadata,
+        delete: &Delete,
+    ) -> Result<Mutation, ErrorResponse> {
+        if table.is_counter_table() {
+            return Err(invalid(
+                "DELETE statements are not supported on counter tables",
+            ));
+        }
+        let (partition_key, clustering_key) = self.modified_row(table, &delete.relations)?;
+
+        let mut cells = Vec::new();
+        for column in delete.columns.iter() {
+            Self::column(table, column)?;
+            if table.is_primary_key(column) {
+                return Err(invalid(format!(
+                    "Invalid identifier {} for deletion (should not be a PRIMARY KEY part)",
+                    column
+                )));
+            }
+            cells.push((column.clone(), CqlValue::Null));
+        }
+
+        Ok(Mutation {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key,
+            clustering_key,
+            row_deletion: cells.is_empty(),
+            counters: Vec::new(),
+            cells,
+            row_marker: false,
+            timestamp: Self::timestamp(),
+        })
+    }
+
+    fn select(&mut self, select: &Select) -> Result<Response, ErrorResponse> {
+        let table = self.table(&select.table, Permission::Select)?;
+
+        let selected: Vec<usize> = match &select.selection {
+            Selection::All | Selection::Count => (0..table.columns.len()).collect(),
+            Selection::Columns(columns) => columns
+                .iter()
+                .map(|column| {
+                    Self::column(&table, column)?;
+                    Ok(Self::column_index(&table, column))
+                })
+                .collect::<Result<_, ErrorResponse>>()?,
+        };
+
+        let restrictions = self.restrictions(&table, &select.relations)?;
+        let partition_key = Self::restricted_partition_key(&table, &restrictions);
+        // restrictions the read already applies, which don't need to be checked again
+        let mut applied = vec![false; restrictions.len()];
+        let mut slice = ClusteringSlice::default();
+        let mut index = None;
+        if partition_key.is_some() {
+            for (index, restriction) in restrictions.iter().enumerate() {
+                applied[index] = restriction.operator == Operator::Equal
+                    && table.is_partition_key(&table.columns[restriction.column].name);
+            }
+            slice = Self::slice(&table, &restrictions, &mut applied);
+            slice.reversed = Self::reversed(&table, &select.ordering)?;
+        } else if !select.ordering.is_empty() {
+            return Err(invalid(
+                "ORDER BY is only supported when the partition key is restricted by an EQ",
+            ));
+        } else {
+            index = self.index_expression(&table, &restrictions, &mut applied);
+        }
+
+        let filters: Vec<&Restriction> = restrictions
+            .iter()
+            .zip(applied)
+            .filter(|(_, applied)| !applied)
+            .map(|(restriction, _)| restriction)
+            .collect();
+        if !filters.is_empty() && !select.allow_filtering {
+            return Err(invalid(
+                "Cannot execute this query as it might involve data filtering and thus may \
+                 have unpredictable performance. If you want to execute this query despite \
+                 the performance unpredictability, use ALLOW FILTERING",
+            ));
+        }
+
+        let command = ReadCommand {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key: partition_key.clone(),
+            slice,
+            // with filters, rows the replicas return may still be dropped here
+            limit: select.limit.filter(|_| filters.is_empty()),
+            index,
+        };
+        let rows = match partition_key {
+            None if command.index.is_some() => {
+                tracing::trace(|| format!("Executing secondary index query on {}", table.name));
+                self.coordinator.scan(&command)?
+            }
+            Some(_) => {
+                tracing::trace(|| format!("Executing single-partition query on {}", table.name));
+                let replication_factor = self.replication_factor(&table.keyspace)?;
+                self.coordinator.read(&command, replication_factor)?
+            }
+            None => {
+                tracing::trace(|| format!("Scanning all partitions of {}", table.name));
+                self.coordinator.scan(&command)?
+            }
+        };
+
+        let mut rows: Vec<Vec<CqlValue>> = rows
+            .rows
+            .into_iter()
+            .filter(|row| filters.iter().all(|filter| filter.matches(row)))
+            .collect();
+        if let Some(limit) = select.limit {
+            rows.truncate(limit);
+        }
+
+        if select.selection == Selection::Count {
+            return Ok(Response::Rows(Rows {
+                columns: vec![ColumnSpec::new("count", DataType::BigInt)],
+                rows: vec![vec![CqlValue::BigInt(rows.len() as i64)]],
+            }));
+        }
+        Ok(Response::Rows(Rows {
+            columns: selected
+                .iter()
+                .map(|index| table.columns[*index].clone())
+                .collect(),
+            rows: rows
+                .into_iter()
+                .map(|row| selected.iter().map(|index| row[*index].clone()).collect())
+                .collect(),
+        }))
+    }
+
+    fn use_keyspace(&mut self, keyspace: &str) -> Result<Response, ErrorResponse> {
+        if self
+            .state
+            .schema
+            .lock()
+            .unwrap()
+            .keyspace(keyspace)
+            .is_none()
+        {
+            return Err(invalid(format!("Keyspace '{}' does not exist", keyspace)));
+        }
+        Ok(Response::SetKeyspace(keyspace.to_string()))
+    }
+
+    /// Sends a mutation to the replicas of its partition.
+    fn write(
+        &mut self,
+        table: &TableMetadata,
+        mutation: Mutation,
+    ) -> Result<Response, ErrorResponse> {
+        let replication_factor = self.replication_factor(&table.keyspace)?;
+        tracing::trace(|| {
+            format!(
+                "Writing {}.{} to {} replicas",
+                table.keyspace, table.name, replication_factor
+            )
+        });
+
+        self.coordinator.write(&mutation, replication_factor)?;
+        Ok(Response::Void)
+    }
+
+    /// Applies a mutation as a lightweight transaction if the row it writes, or `None` if
+    /// there is none, satisfies a condition.
+    ///
+    /// The result has an `[applied]` column, followed by the columns of the table holding the
+    /// row the condition was checked against if the mutation wasn't applied.
+    fn write_if(
+        &mut self,
+        table: &TableMetadata,
+        mutation: Mutation,
+        condition: impl Fn(Option<&[CqlValue]>) -> bool,
+    ) -> Result<Response, ErrorResponse> {
+        let replication_factor = self.replication_factor(&table.keyspace)?;
+        tracing::trace(|| {
+            format!(
+                "Executing lightweight transaction on {}.{}",
+                table.keyspace, table.name
+            )
+        });
+
+        let result = self
+            .coordinator
+            .cas(&mutation, replication_factor, condition)?;
+        let mut columns = vec![ColumnSpec::new("[applied]", DataType::Boolean)];
+        let mut row = vec![CqlValue::Boolean(result.applied)];
+        if !result.applied
+            && let Some(current) = result.current
+        {
+            columns.extend(table.columns.iter().cloned());
+            row.extend(current);
+        }
+        Ok(Response::Rows(Rows {
+            columns,
+            rows: vec![row],
+        }))
+    }
+
+    /// Returns the partition and clustering key of the row modified by an UPDATE or DELETE,
+    /// whose WHERE clause must restrict every primary key column, and nothing else, to a
+    /// single value.
+    fn modified_row(
+        &self,
+        table: &TableMetadata,
+        relations: &[Relation],
+    ) -> Result<(Vec<CqlValue>, Vec<CqlValue>), ErrorResponse> {
+        let mut partition_key = vec![None; table.partition_key.len()];
+        let mut clustering_key = vec![None; table.clustering_key.len()];
+        for restriction in self.restrictions(table, relations)? {
+            let column = &table.columns[restriction.column].name;
+            let key = match (
+                table.partition_key.iter().position(|key| key == column),
+                table.clustering_key.iter().position(|key| key == column),
+            ) {
+                (Some(index), _) => &mut partition_key[index],
+                (_, Some(index)) => &mut clustering_key[index],
+                _ => {
+                    return Err(invalid(format!(
+                        "Non PRIMARY KEY columns found in where clause: {}",
+                        column
+                    )));
+                }
+            };
+            if restriction.operator != Operator::Equal {
+                return Err(invalid(format!(
+                    "Only EQ relations are supported on the PRIMARY KEY part {}",
+                    column
+                )));
+            }
+            *key = Some(restriction.value);
+        }
+
+        Ok((
+            Self::complete_key("partition key", &table.partition_key, partition_key)?,
+            Self::complete_key("clustering key", &table.clustering_key, clustering_key)?,
+        ))
+    }
+
+    /// Returns the values of a partition or clustering key if every part of it has a value,
+    /// and fails otherwise.
+    fn complete_key(
+        kind: &str,
+        names: &[String],
+        values: Vec<Option<CqlValue>>,
+    ) -> Result<Vec<CqlValue>, ErrorResponse> {
+        let missing: Vec<&str> = names
+            .iter()
+            .zip(values.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(invalid(format!(
+                "Some {} parts are missing: {}",
+                kind,
+                missing.join(", ")
+            )));
+        }
+
+        let values: Vec<CqlValue> = values.into_iter().flatten().collect();
+        if let Some(index) = values.iter().position(|value| *value == CqlValue::Null) {
+            return Err(invalid(format!(
+                "Invalid null value for {} part {}",
+                kind, names[index]
+            )));
+        }
+        Ok(values)
+    }
+
+    /// Returns the first EQ restriction of a column with a secondary index, marking it as
+    /// applied, since the index returns only the rows matching it.
+    fn index_expression(
+        &self,
+        table: &TableMetadata,
+        restrictions: &[Restriction],
+        applied: &mut [bool],
+    ) -> Option<IndexExpression> {
+        let schema = self.state.schema.lock().unwrap();
+        let position = restrictions.iter().position(|restriction| {
+            restriction.operator == Operator::Equal
+                && schema
+                    .index_on(
+                        &table.keyspace,
+                        &table.name,
+                        &table.columns[restriction.column].name,
+                    )
+                    .is_some()
+        })?;
+
+        applied[position] = true;
+        Some(IndexExpression {
+            column: table.columns[restrictions[position].column].name.clone(),
+            value: restrictions[position].value.clone(),
+        })
+    }
+
+    /// Returns the slice of a partition selected by the restrictions of its clustering
+    /// columns, marking the restrictions it applies.
+    ///
+    /// The slice starts with the values of the first clustering columns restricted to a single
+    /// value, and ends with a range of the next one. Restrictions of later columns aren't part
+    /// of the slice.
+    fn slice(
+        table: &TableMetadata,
+        restrictions: &[Restriction],
+        applied: &mut [bool],
+    ) -> ClusteringSlice {
+        let mut prefix = Vec::new();
+        for (position, column) in table.clustering_key.iter().enumerate() {
+            let column = Self::column_index(table, column);
+            let mut find = |operators: &[Operator]| {
+                let index = restrictions
+                    .iter()
+                    .enumerate()
+                    .position(|(index, restriction)| {
+                        !applied[index]
+                            && restriction.column == column
+                            && operators.contains(&restriction.operator)
+                    })?;
+                applied[index] = true;
+                Some(&restrictions[index])
+            };
+
+            if let Some(equal) = find(&[Operator::Equal]) {
+                prefix.push(equal.value.clone());
+                continue;
+            }
+            let bound = |restriction: Option<&Restriction>| {
+                restriction.map(|restriction| {
+                    let mut bound_prefix = prefix.clone();
+                    bound_prefix.push(restriction.value.clone());
+                    ClusteringBound {
+                        prefix: bound_prefix,
+                        inclusive: matches!(
+                            restriction.operator,
+                            Operator::GreaterThanOrEqual | Operator::LessThanOrEqual
+                        ),
+                    }
+                })
+            };
+            let lower = bound(find(&[Operator::GreaterThan, Operator::GreaterThanOrEqual]));
+            let upper = bound(find(&[Operator::LessThan, Operator::LessThanOrEqual]));
+            if lower.is_none() && upper.is_none() {
+                break;
+            }
+
+            let prefix_bound = || {
+                Some(ClusteringBound {
+                    prefix: prefix.clone(),
+                    inclusive: true,
+                })
+                .filter(|bound| !bound.prefix.is_empty())
+            };
+            let (start, end) = match table.clustering_order[position] {
+                ClusteringOrder::Asc => (lower, upper),
+                ClusteringOrder::Desc => (upper, lower),
+            };
+            return ClusteringSlice {
+                start: start.or_else(prefix_bound),
+                end: end.or_else(prefix_bound),
+                reversed: false,
+            };
+        }
+
+        let bound = Some(ClusteringBound {
+            prefix,
+            inclusive: true,
+        })
+        .filter(|bound| !bound.prefix.is_empty());
+        ClusteringSlice {
+            start: bound.clone(),
+            end: bound,
+            reversed: false,
+        }
+    }
+
+    /// Returns whether ORDER BY asks for the reverse of the clustering order of the table.
+    fn reversed(
+        table: &TableMetadata,
+        ordering: &[(String, ClusteringOrder)],
+    ) -> Result<bool, ErrorResponse> {
+        let mut reversed = None;
+        for (index, (column, order)) in ordering.iter().enumerate() {
+            if !table.is_clustering_key(column) {
+                return Err(invalid(format!(
+                    "Order by is currently only supported on the clustered columns of the \
+                     PRIMARY KEY, got {}",
+                    column
+                )));
+            }
+            if table.clustering_key[index] != *column {
+                return Err(invalid(
+                    "Order by currently only supports the ordering of columns following their \
+                     declared order in the PRIMARY KEY",
+                ));
+            }
+
+            let column_reversed = *order != table.clustering_order[index];
+            if reversed.is_some_and(|reversed| reversed != column_reversed) {
+                return Err(invalid("Unsupported order by relation"));
+            }
+            reversed = Some(column_reversed);
+        }
+        Ok(reversed.unwrap_or_default())
+    }
+
+    /// Returns the partition key if every part of it is restricted to a single value.
+    fn restricted_partition_key(
+        table: &TableMetadata,
+        restrictions: &[Restriction],
+    ) -> Option<Vec<CqlValue>> {
+        table
+            .partition_key
+            .iter()
+            .map(|key| {
+                let index = Self::column_index(table, key);
+                let mut equal = restrictions.iter().filter(|restriction| {
+                    restriction.column == index && restriction.operator == Operator::Equal
+                });
+                match (equal.next(), equal.next()) {
+                    (Some(restriction), None) => Some(restriction.value.clone()),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn restrictions(
+        &self,
+        table: &TableMetadata,
+        relations: &[Relation],
+    ) -> Result<Vec<Restriction>, ErrorResponse> {
+        relations
+            .iter()
+            .map(|relation| {
+                let spec = Self::column(table, &relation.column)?;
+                Ok(Restriction {
+                    column: Self::column_index(table, &relation.column),
+                    operator: relation.operator,
+                    value: self.bind(&relation.term, spec)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the amount a term increments a counter column by.
+    fn counter_delta(&self, term: &Term, column: &ColumnSpec) -> Result<i64, ErrorResponse> {
+        match self.bind(term, column)? {
+            CqlValue::BigInt(delta) => Ok(delta),
+            _ => Err(invalid(format!(
+                "Invalid null value for counter increment of {}",
+                column.name
+            ))),
+        }
+    }
+
+    /// Returns the value of a term as a value of the column's type.
+    fn bind(&self, term: &Term, column: &ColumnSpec) -> Result<CqlValue, ErrorResponse> {
+        let value = match term {
+            Term::Marker(index) => {
+                let value = self.values.get(*index).cloned().ok_or_else(|| {
+                    invalid(format!(
+                        "No value bound for marker {} of {}",
+                        index, column.name
+                    ))
+                })?;
+                if !value.is_of_type(&column.data_type) {
+                    return Err(invalid(format!(
+                        "Invalid value {} for {} of type {}",
+                        value, column.name, column.data_type
+                    )));
+                }
+                return Ok(normalize(value));
+            }
+            Term::Literal(literal) => literal,
+        };
+
+        literal_value(value, &column.data_type)
+            .map_err(|message| invalid(format!("{} for {}", message, column.name)))
+    }
+
+    /// Returns the value of a term that must be a string.
+    fn bind_text(&self, term: &Term, name: &str) -> Result<String, ErrorResponse> {
+        match self.bind(term, &ColumnSpec::new(name, DataType::Text))? {
+            CqlValue::Text(text) => Ok(text),
+            other => Err(invalid(format!("Invalid {} {}", name, other))),
+        }
+    }
+
+    /// Looks up the table of a statement, checking that the user has the permission on its
+    /// keyspace.
+    fn table(
+        &self,
+        table: &TableName,
+        permission: Permission,
+    ) -> Result<TableMetadata, ErrorResponse> {
+        let keyspace = self.keyspace_of(table)?;
+        self.authorize(permission, Resource::Keyspace(keyspace.clone()))?;
+
+        self.state
+            .schema
+            .lock()
+            .unwrap()
+            .table(&keyspace, &table.name)
+            .cloned()
+            .ok_or_else(|| invalid(format!("unconfigured table {}", table.name)))
+    }
+
+    fn keyspace_of(&self, table: &TableName) -> Result<String, ErrorResponse> {
+        table
+            .keyspace
+            .as_deref()
+            .or(self.keyspace)
+            .map(|keyspace| keyspace.to_string())
+            .ok_or_else(|| {
+                invalid(
+                    "No keyspace has been specified. USE a keyspace, or explicitly specify \
+                     keyspace.tablename",
+                )
+            })
+    }
+
+    fn replication_factor(&self, keyspace: &str) -> Result<usize, ErrorResponse> {
+        self.state
+            .schema
+            .lock()
+            .unwrap()
+            .keyspace(keyspace)
+            .map(|keyspace| keyspace.replication_factor)
+            .ok_or_else(|| invalid(format!("Keyspace '{}' does not exist", keyspace)))
+    }
+
+    fn authorize(&self, permission: Permission, resource: Resource) -> Result<(), ErrorResponse> {
+        let Some(user) = self.user else {
+            return Ok(());
+        };
+        authorizer::authorize_permission(
+            user,
+            permission,
+            &resource,
+            &self.state.roles.lock().unwrap(),
+        )
+    }
+
+    /// Checks that the user may send the request a statement is bound into.
+    fn authorize_request(&self, request: &Request) -> Result<(), ErrorResponse> {
+        let Some(user) = self.user else {
+            return Ok(());
+        };
+        authorizer::authorize(user, request, &self.state.roles.lock().unwrap())
+    }
+
+    fn column<'t>(table: &'t TableMetadata, name: &str) -> Result<&'t ColumnSpec, ErrorResponse> {
+        table
+            .column(name)
+            .ok_or_else(|| invalid(format!("Undefined column name {}", name)))
+    }
+
+    fn column_index(table: &TableMetadata, name: &str) -> usize {
+        table
+            .columns
+            .iter()
+            .position(|column| column.name == name)
+            .unwrap_or_default()
+    }
+
+    fn already_exists(if_not_exists: bool, name: &str) -> Result<Response, ErrorResponse> {
+        if if_not_exists {
+            return Ok(Response::Void);
+        }
+        Err(ErrorResponse::new(
+            ErrorCode::AlreadyExists,
+            format!("{} already exists", name),
+        ))
+    }
+
+    /// Returns the write time of a mutation, in microseconds since the epoch.
+    fn timestamp() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as i64
+    }
+}
+
+/// Returns a literal as a value of a type, or why it isn't one.
+fn literal_value(literal: &Literal, data_type: &DataType) -> Result<CqlValue, String> {
+    let element_value = |literal: &Literal, data_type: &DataType| match literal {
+        Literal::Null => Err("null is not supported inside collections".to_string()),
+        literal => literal_value(literal, data_type),
+    };
+
+    let value = match (literal, data_type) {
+        (Literal::Null, _) => CqlValue::Null,
+        (Literal::String(value), DataType::Text) => CqlValue::Text(value.clone()),
+        (Literal::Integer(value), DataType::Int) => i32::try_from(*value)
+            .map(CqlValue::Int)
+            .map_err(|_| format!("Value {} is out of range for int", value))?,
+        (Literal::Integer(value), DataType::BigInt | DataType::Counter) => CqlValue::BigInt(*value),
+        (Literal::Uuid(value), DataType::Uuid) => CqlValue::Uuid(*value),
+        (Literal::Integer(millis), DataType::Timestamp) => CqlValue::Timestamp(*millis),
+        (Literal::String(value), DataType::Timestamp) => parse_timestamp(value)
+            .map(CqlValue::Timestamp)
+            .ok_or_else(|| format!("Unable to parse a date from '{}'", value))?,
+        (Literal::Blob(value), DataType::Blob) => CqlValue::Blob(value.clone()),
+        (Literal::Boolean(value), DataType::Boolean) => CqlValue::Boolean(*value),
+        (Literal::Integer(value), DataType::Double) => CqlValue::Double(*value as f64),
+        (Literal::Float(value), DataType::Double) => CqlValue::Double(*value),
+        (Literal::List(elements), DataType::List(element_type)) => CqlValue::List(
+            elements
+                .iter()
+                .map(|element| element_value(element, element_type))
+                .collect::<Result<_, _>>()?,
+        ),
+        (Literal::Set(elements), DataType::Set(element_type)) => CqlValue::Set(
+            elements
+                .iter()
+                .map(|element| element_value(element, element_type))
+                .collect::<Result<_, _>>()?,
+        ),
+        (Literal::Map(entries), DataType::Set(_)) if entries.is_empty() => {
+            CqlValue::Set(Vec::new())
+        }
+        (Literal::Map(entries), DataType::Map(key_type, value_type)) => CqlValue::Map(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    Ok((
+                        element_value(key, key_type)?,
+                        element_value(value, value_type)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        (literal, data_type) => {
+            return Err(format!(
+                "Invalid {:?} constant of type {}",
+                literal, data_type
+            ));
+        }
+    };
+    Ok(normalize(value))
+}
+
+/// Sorts the elements of a set and the entries of a map and removes duplicates, keeping the
+/// last value of a key written twice.
+fn normalize(value: CqlValue) -> CqlValue {
+    let order =
+        |left: &CqlValue, right: &CqlValue| left.partial_cmp(right).unwrap_or(Ordering::Equal);
+
+    match value {
+        CqlValue::Set(mut elements) => {
+            elements.sort_by(order);
+            elements.dedup();
+            CqlValue::Set(elements)
+        }
+        CqlValue::Map(mut entries) => {
+            entries.reverse();
+            // the sort is stable, so the last value of a key comes first
+            entries.sort_by(|(left, _), (right, _)| order(left, right));
+            entries.dedup_by(|(key, _), (previous_key, _)| key == previous_key);
+            CqlValue::Map(entries)
+        }
+        value => value,
+    }
+}
+
+/// Parses a UTC date with an optional time, e.g. `2024-03-01`, `2024-03-01 12:30` or
+/// `2024-03-01T12:30:00.250Z`, into milliseconds since the epoch.
+fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim().trim_end_matches('Z').trim_end_matches("+0000");
+    let (date, time) = match text.split_once([' ', 'T']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (text, None),
+    };
+
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [year, month, day] = date[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let millis_of_day = match time {
+        Some(time) => {
+            let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+            let time: Vec<i64> = time
+                .split(':')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            let (hours, minutes, seconds) = match time[..] {
+                [hours, minutes] => (hours, minutes, 0),
+                [hours, minutes, seconds] => (hours, minutes, seconds),
+                _ => return None,
+            };
+            if !(0..24).contains(&hours)
+                || !(0..60).contains(&minutes)
+                || !(0..60).contains(&seconds)
+            {
+                return None;
+            }
+            if !fraction.chars().all(|digit| digit.is_ascii_digit()) {
+                return None;
+            }
+            let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)])
+                .parse::<i64>()
+                .ok()?;
+            ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis
+        }
+        None => 0,
+    };
+
+    // days since the epoch of a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    Some(days * 86_400_000 + millis_of_day)
+}
+
+impl Restriction {
+    /// Returns whether a row with the columns of its table satisfies the restriction.
+    fn matches(&self, row: &[CqlValue]) -> bool {
+        let Some(ordering) = row[self.column].partial_cmp(&self.value) else {
+            return false;
+        };
+        match self.operator {
+            Operator::Equal => ordering == Ordering::Equal,
+            Operator::LessThan => ordering == Ordering::Less,
+            Operator::LessThanOrEqual => ordering != Ordering::Greater,
+            Operator::GreaterThan => ordering == Ordering::Greater,
+            Operator::GreaterThanOrEqual => ordering != Ordering::Less,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cql::ast::{Literal, Operator};
+    use crate::cql::executor::{Restriction, literal_value, parse_timestamp};
+    use shared::protocol::types::{CqlValue, DataType};
+
+    #[test]
+    fn test_restriction_matches_same_type_only() {
+        let restriction = Restriction {
+            column: 1,
+            operator: Operator::GreaterThanOrEqual,
+            value: CqlValue::Int(30),
+        };
+
+        let row = |age| vec![CqlValue::Text("alice".to_string()), age];
+        assert!(restriction.matches(&row(CqlValue::Int(30))));
+        assert!(!restriction.matches(&row(CqlValue::Int(29))));
+        assert!(!restriction.matches(&row(CqlValue::Null)));
+        assert!(!restriction.matches(&row(CqlValue::BigInt(31))));
+    }
+
+    #[test]
+    fn test_literal_value_normalizes_collections() {
+        let set = literal_value(
+            &Literal::Set(vec![
+                Literal::Integer(3),
+                Literal::Integer(1),
+                Literal::Integer(3),
+            ]),
+            &DataType::Set(Box::new(DataType::Int)),
+        );
+        assert_eq!(
+            set,
+            Ok(CqlValue::Set(vec![CqlValue::Int(1), CqlValue::Int(3)]))
+        );
+
+        let map = literal_value(
+            &Literal::Map(vec![
+                (Literal::String("b".to_string()), Literal::Integer(1)),
+                (Literal::String("a".to_string()), Literal::Integer(2)),
+                (Literal::String("b".to_string()), Literal::Integer(3)),
+            ]),
+            &DataType::Map(Box::new(DataType::Text), Box::new(DataType::BigInt)),
+        );
+        assert_eq!(
+            map,
+            Ok(CqlValue::Map(vec![
+                (CqlValue::Text("a".to_string()), CqlValue::BigInt(2)),
+                (CqlValue::Text("b".to_string()), CqlValue::BigInt(3)),
+            ]))
+        );
+
+        let with_null = literal_value(
+            &Literal::List(vec![Literal::Null]),
+            &DataType::List(Box::new(DataType::Int)),
+        );
+        assert!(with_null.is_err());
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1970-01-01"), Some(0));
+        assert_eq!(
+            parse_timestamp("2024-03-01 12:30:05.25Z"),
+            Some(1_709_296_205_250)
+        );
+        assert_eq!(parse_timestamp("1969-12-31T23:59:59"), Some(-1000));
+        assert_eq!(parse_timestamp("2024-13-01"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Splits a CQL query string into tokens.
+
+use crate::cql::syntax_error;
+use shared::protocol::types::ErrorResponse;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    /// A keyword or unquoted name; kept as written, since only names are case-sensitive.
+    Word(String),
+    /// A `"quoted name"`, whose case is kept.
+    QuotedName(String),
+    /// A `'string literal'`.
+    String(String),
+    Integer(i64),
+    /// A number with a fraction or exponent, e.g. `1.5` or `2e3`.
+    Float(f64),
+    Uuid(Uuid),
+    /// A `0x` prefixed hex string.
+    Blob(Vec<u8>),
+    /// A `?` bind marker.
+    Marker,
+    /// `(`, `)`, `{`, `}`, `[`, `]`, `,`, `.`, `:`, `*`, `=`, `<`, `>`, `<=` or `>=`.
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::QuotedName(name) => write!(f, "\"{}\"", name),
+            Token::String(value) => write!(f, "'{}'", value),
+            Token::Integer(value) => write!(f, "{}", value),
+            Token::Float(value) => write!(f, "{}", value),
+            Token::Uuid(value) => write!(f, "{}", value),
+            Token::Blob(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            Token::Marker => write!(f, "?"),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 17] = [
+    "<=", ">=", "(", ")", "{", "}", "[", "]", ",", ".", ":", "*", "=", "<", ">", "+", "-",
+];
+
+/// Returns the tokens of a query, without the trailing `;`.
+pub(crate) fn tokenize(query: &str) -> Result<Vec<Token>, ErrorResponse> {
+    let mut tokens = Vec::new();
+    let mut chars = query.trim().trim_end_matches(';').chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '?' => {
+                chars.next();
+                tokens.push(Token::Marker);
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut literal = String::new();
+                loop {
+                    match chars.next() {
+                        // a doubled quote is an escaped quote inside the literal
+                        Some(quote) if quote == c && chars.peek() == Some(&c) => {
+                            chars.next();
+                            literal.push(c);
+                        }
+                        Some(quote) if quote == c => break,
+                        Some(other) => literal.push(other),
+                        None => return Err(syntax_error("Unterminated quoted literal")),
+                    }
+                }
+                tokens.push(match c {
+                    '\'' => Token::String(literal),
+                    _ => Token::QuotedName(literal),
+                });
+            }
+            c if c.is_ascii_digit()
+                || (c == '-'
+                    && chars
+                        .clone()
+                        .nth(1)
+                        .is_some_and(|next| next.is_ascii_digit())) =>
+            {
+                let mut constant = String::from(c);
+                chars.next();
+                while let Some(&next) = chars.peek()
+                    && (next.is_ascii_alphanumeric() || next == '-' || next == '.')
+                {
+                    constant.push(next);
+                    chars.next();
+                }
+                tokens.push(constant_token(&constant)?);
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&letter) = chars.peek()
+                    && (letter.is_alphanumeric() || letter == '_')
+                {
+                    word.push(letter);
+                    chars.next();
+                }
+                // a UUID may start with a letter, e.g. a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11
+                if chars.peek() == Some(&'-')
+                    && word.len() == 8
+                    && word.chars().all(|letter| letter.is_ascii_hexdigit())
+                {
+                    while let Some(&next) = chars.peek()
+                        && (next.is_ascii_alphanumeric() || next == '-')
+                    {
+                        word.push(next);
+                        chars.next();
+                    }
+                    tokens.push(constant_token(&word)?);
+                    continue;
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => {
+                let rest: String = chars.clone().take(2).collect();
+                let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) else {
+                    return Err(syntax_error(format!("Unexpected character '{}'", c)));
+                };
+                for _ in 0..symbol.len() {
+                    chars.next();
+                }
+                tokens.push(Token::Symbol(symbol));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Returns the token of a constant starting with a digit or `-`: an integer, a float, a UUID
+/// or a blob.
+fn constant_token(constant: &str) -> Result<Token, ErrorResponse> {
+    let invalid = || syntax_error(format!("Invalid constant {}", constant));
+
+    if let Some(hex) = constant
+        .strip_prefix("0x")
+        .or_else(|| constant.strip_prefix("0X"))
+    {
+        if hex.len() % 2 != 0 {
+            return Err(invalid());
+        }
+        return (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<_, _>>()
+            .map(Token::Blob);
+    }
+    if constant.len() == 36 && constant[1..].contains('-') {
+        return Uuid::try_parse(constant)
+            .map(Token::Uuid)
+            .map_err(|_| invalid());
+    }
+    if let Ok(value) = constant.parse::<i64>() {
+        return Ok(Token::Integer(value));
+    }
+    constant
+        .parse::<f64>()
+        .map(Token::Float)
+        .map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cql::lexer::{Token, tokenize};
+    use shared::protocol::types::ErrorCode;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_tokenize_statement() {
+        let tokens = tokenize("SELECT \"Name\" FROM t WHERE age >= -3 AND id = 'it''s';").unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("SELECT".to_string()),
+                Token::QuotedName("Name".to_string()),
+                Token::Word("FROM".to_string()),
+                Token::Word("t".to_string()),
+                Token::Word("WHERE".to_string()),
+                Token::Word("age".to_string()),
+                Token::Symbol(">="),
+                Token::Integer(-3),
+                Token::Word("AND".to_string()),
+                Token::Word("id".to_string()),
+                Token::Symbol("="),
+                Token::String("it's".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_constants() {
+        let tokens = tokenize(
+            "[1.5, -2e3, 0xCAFE, 123e4567-e89b-12d3-a456-426614174000, \
+             a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11]",
+        )
+        .unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Symbol("["),
+                Token::Float(1.5),
+                Token::Symbol(","),
+                Token::Float(-2000.0),
+                Token::Symbol(","),
+                Token::Blob(vec![0xca, 0xfe]),
+                Token::Symbol(","),
+                Token::Uuid(Uuid::parse_str("123e4567-e89b-12d3-a456-426614174000").unwrap()),
+                Token::Symbol(","),
+                Token::Uuid(Uuid::parse_str("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11").unwrap()),
+                Token::Symbol("]"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_counter_update() {
+        let tokens = tokenize("views = views - 2").unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("views".to_string()),
+                Token::Symbol("="),
+                Token::Word("views".to_string()),
+                Token::Symbol("-"),
+                Token::Integer(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_rejects_unterminated_string() {
+        let error = tokenize("SELECT * FROM t WHERE id = 'abc").unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::SyntaxError);
+    }
+}
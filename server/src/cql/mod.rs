@@ -0,0 +1,20 @@
+//! The CQL subset understood by QUERY messages.
+//!
+//! A query is split into tokens by the `lexer`, parsed into a `CqlStatement` by the `parser`
+//! and run by the `executor`, which validates it against the schema and reads or writes the
+//! rows of its table on the table's replicas.
+
+pub(crate) mod ast;
+pub(crate) mod executor;
+mod lexer;
+pub(crate) mod parser;
+
+use shared::protocol::types::{ErrorCode, ErrorResponse};
+
+fn syntax_error(message: impl Into<String>) -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::SyntaxError, message)
+}
+
+fn invalid(message: impl Into<String>) -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::Invalid, message)
+}
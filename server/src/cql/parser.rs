@@ -0,0 +1,1171 @@
+//! Parses the tokens of a CQL query into a statement.
+//!
+//! ```text
+//! CREATE KEYSPACE [IF NOT EXISTS] <name> WITH replication = { '<option>' : <literal>, ... }
+//! CREATE TABLE [IF NOT EXISTS] <table> ( <column> <type> [PRIMARY KEY], ...
+//!     [, PRIMARY KEY ( <column> | ( <column>, ... ) [, <clustering column>, ...] )] )
+//!     [WITH CLUSTERING ORDER BY ( <clustering column> [ASC | DESC], ... )]
+//! CREATE INDEX [IF NOT EXISTS] [<name>] ON <table> ( <column> )
+//! INSERT INTO <table> ( <column>, ... ) VALUES ( <term>, ... ) [IF NOT EXISTS]
+//! SELECT * | COUNT(*) | <column>, ... FROM <table> [WHERE <relation> AND ...]
+//!     [ORDER BY <clustering column> [ASC | DESC], ...] [LIMIT <n>] [ALLOW FILTERING]
+//! UPDATE <table> SET <column> = <term> | <column> (+ | -) <term>, ... WHERE <relation> AND ...
+//!     [IF EXISTS | IF <relation> AND ...]
+//! DELETE [<column>, ...] FROM <table> WHERE <relation> AND ...
+//! USE <keyspace>
+//! ADD <term> [WITH REPLICATION <term>]
+//! CHECK <term>
+//! COUNT
+//! CREATE USER <term> WITH PASSWORD <term> [SUPERUSER | NOSUPERUSER]
+//! ALTER USER <term> [WITH PASSWORD <term>] [SUPERUSER | NOSUPERUSER]
+//! DROP USER [IF EXISTS] <term>
+//! GRANT <permission> ON <resource> TO <name>
+//! REVOKE <permission> ON <resource> FROM <name>
+//! ```
+//!
+//! A table is `[<keyspace>.]<name>`, a relation is `<column> <op> <term>` with one of
+//! `= < <= > >=`, and a term is a `?` bind marker or a literal: a `'string'`, an integer, a
+//! float, a UUID, a `0x` blob, `true`, `false`, `null`, a `[list]`, a `{set}` or a
+//! `{key: value}` map. Keywords are case-insensitive; types are `text`, `varchar`, `int`,
+//! `bigint`, `uuid`, `timestamp`, `blob`, `boolean`, `double`, `counter`, `list<type>`,
+//! `set<type>` and `map<type, type>`.
+//!
+//! ADD, CHECK and COUNT work on the values of the default keyspace. A permission is `CREATE`,
+//! `SELECT`, `MODIFY`, `AUTHORIZE` or `ALL`, optionally followed by `PERMISSION(S)`, and a
+//! resource is `ALL KEYSPACES` or `KEYSPACE <name>`; user names may be `'string'`s as well.
+
+use crate::cql::ast::{
+    AddValue, AlterUser, Assignment, CqlStatement, CreateIndex, CreateKeyspace, CreateTable,
+    CreateUser, Delete, DropUser, Insert, Literal, Operator, Relation, Select, Selection,
+    TableName, Term, Update,
+};
+use crate::cql::lexer::{Token, tokenize};
+use crate::cql::syntax_error;
+use shared::protocol::types::{
+    ClusteringOrder, DataType, ErrorResponse, Permission, PermissionChange, Resource,
+};
+
+/// Parses a CQL query.
+pub(crate) fn parse(query: &str) -> Result<CqlStatement, ErrorResponse> {
+    let mut parser = Parser {
+        tokens: tokenize(query)?,
+        position: 0,
+        markers: 0,
+    };
+
+    let statement = parser.statement()?;
+    if let Some(token) = parser.peek() {
+        return Err(syntax_error(format!("Unexpected input '{}'", token)));
+    }
+    Ok(statement)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Number of bind markers seen so far.
+    markers: usize,
+}
+
+impl Parser {
+    fn statement(&mut self) -> Result<CqlStatement, ErrorResponse> {
+        let keyword = self.next_keyword()?;
+        match keyword.as_str() {
+            "CREATE" => match self.next_keyword()?.as_str() {
+                "KEYSPACE" => self.create_keyspace().map(CqlStatement::CreateKeyspace),
+                "TABLE" => self.create_table().map(CqlStatement::CreateTable),
+                "INDEX" => self.create_index().map(CqlStatement::CreateIndex),
+                "USER" => self.create_user().map(CqlStatement::CreateUser),
+                keyword => Err(syntax_error(format!("Cannot create {}", keyword))),
+            },
+            "ALTER" => {
+                self.expect_keyword("USER")?;
+                self.alter_user().map(CqlStatement::AlterUser)
+            }
+            "DROP" => {
+                self.expect_keyword("USER")?;
+                self.drop_user().map(CqlStatement::DropUser)
+            }
+            "INSERT" => self.insert().map(CqlStatement::Insert),
+            "SELECT" => self.select().map(CqlStatement::Select),
+            "UPDATE" => self.update().map(CqlStatement::Update),
+            "DELETE" => self.delete().map(CqlStatement::Delete),
+            "USE" => self.name().map(CqlStatement::Use),
+            "ADD" => self.add_value().map(CqlStatement::AddValue),
+            "CHECK" => self.term().map(CqlStatement::CheckValue),
+            "COUNT" => Ok(CqlStatement::CountValues),
+            "GRANT" => self.permission_change("TO").map(CqlStatement::Grant),
+            "REVOKE" => self.permission_change("FROM").map(CqlStatement::Revoke),
+            _ => Err(syntax_error(format!("Unknown statement {}", keyword))),
+        }
+    }
+
+    fn create_keyspace(&mut self) -> Result<CreateKeyspace, ErrorResponse> {
+        let if_not_exists = self.if_not_exists()?;
+        let name = self.name()?;
+        self.expect_keyword("WITH")?;
+        self.expect_keyword("REPLICATION")?;
+        self.expect_symbol("=")?;
+        self.expect_symbol("{")?;
+
+        let mut replication = Vec::new();
+        loop {
+            let option = match self.next()? {
+                Token::String(option) => option.to_lowercase(),
+                token => {
+                    return Err(syntax_error(format!(
+                        "Expected a replication option but found {}",
+                        token
+                    )));
+                }
+            };
+            self.expect_symbol(":")?;
+            replication.push((option, self.literal()?));
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol("}")?;
+
+        Ok(CreateKeyspace {
+            name,
+            if_not_exists,
+            replication,
+        })
+    }
+
+    fn create_table(&mut self) -> Result<CreateTable, ErrorResponse> {
+        let if_not_exists = self.if_not_exists()?;
+        let table = self.table_name()?;
+        self.expect_symbol("(")?;
+
+        let mut columns = Vec::new();
+        let mut partition_key = Vec::new();
+        let mut clustering_key = Vec::new();
+        loop {
+            if self.accept_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                if !partition_key.is_empty() {
+                    return Err(syntax_error("Multiple PRIMARY KEY definitions"));
+                }
+                (partition_key, clustering_key) = self.primary_key()?;
+            } else {
+                let column = self.name()?;
+                let data_type = self.data_type()?;
+                if self.accept_keyword("PRIMARY") {
+                    self.expect_keyword("KEY")?;
+                    if !partition_key.is_empty() {
+                        return Err(syntax_error("Multiple PRIMARY KEY definitions"));
+                    }
+                    partition_key = vec![column.clone()];
+                }
+                columns.push((column, data_type));
+            }
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        self.expect_symbol(")")?;
+
+        let clustering_order = match self.accept_keyword("WITH") {
+            true => {
+                self.expect_keyword("CLUSTERING")?;
+                self.expect_keyword("ORDER")?;
+                self.expect_keyword("BY")?;
+                self.expect_symbol("(")?;
+                let clustering_order = self.orderings()?;
+                self.expect_symbol(")")?;
+                clustering_order
+            }
+            false => Vec::new(),
+        };
+
+        Ok(CreateTable {
+            table,
+            if_not_exists,
+            columns,
+            partition_key,
+            clustering_key,
+            clustering_order,
+        })
+    }
+
+    /// Parses `( <column> | ( <column>, ... ) [, <column>, ...] )` after PRIMARY KEY into
+    /// the partition key and the clustering columns.
+    fn primary_key(&mut self) -> Result<(Vec<String>, Vec<String>), ErrorResponse> {
+        self.expect_symbol("(")?;
+        let partition_key = match self.accept_symbol("(") {
+            true => {
+                let columns = self.names()?;
+                self.expect_symbol(")")?;
+                columns
+            }
+            false => vec![self.name()?],
+        };
+        let clustering_key = match self.accept_symbol(",") {
+            true => self.names()?,
+            false => Vec::new(),
+        };
+        self.expect_symbol(")")?;
+        Ok((partition_key, clustering_key))
+    }
+
+    fn create_index(&mut self) -> Result<CreateIndex, ErrorResponse> {
+        let if_not_exists = self.if_not_exists()?;
+        let name = match self.accept_keyword("ON") {
+            true => None,
+            false => {
+                let name = self.name()?;
+                self.expect_keyword("ON")?;
+                Some(name)
+            }
+        };
+        let table = self.table_name()?;
+        self.expect_symbol("(")?;
+        let column = self.name()?;
+        self.expect_symbol(")")?;
+
+        Ok(CreateIndex {
+            name,
+            if_not_exists,
+            table,
+            column,
+        })
+    }
+
+    fn create_user(&mut self) -> Result<CreateUser, ErrorResponse> {
+        let name = self.term()?;
+        self.expect_keyword("WITH")?;
+        self.expect_keyword("PASSWORD")?;
+        Ok(CreateUser {
+            name,
+            password: self.term()?,
+            superuser: self.superuser()?.unwrap_or(false),
+        })
+    }
+
+    fn alter_user(&mut self) -> Result<AlterUser, ErrorResponse> {
+        let name = self.term()?;
+        let password = match self.accept_keyword("WITH") {
+            true => {
+                self.expect_keyword("PASSWORD")?;
+                Some(self.term()?)
+            }
+            false => None,
+        };
+        let superuser = self.superuser()?;
+        if password.is_none() && superuser.is_none() {
+            return Err(syntax_error(
+                "Expected WITH PASSWORD, SUPERUSER or NOSUPERUSER",
+            ));
+        }
+        Ok(AlterUser {
+            name,
+            password,
+            superuser,
+        })
+    }
+
+    fn drop_user(&mut self) -> Result<DropUser, ErrorResponse> {
+        let if_exists = match self.accept_keyword("IF") {
+            true => {
+                self.expect_keyword("EXISTS")?;
+                true
+            }
+            false => false,
+        };
+        Ok(DropUser {
+            name: self.term()?,
+            if_exists,
+        })
+    }
+
+    /// Parses the optional `SUPERUSER` or `NOSUPERUSER` ending CREATE USER and ALTER USER.
+    fn superuser(&mut self) -> Result<Option<bool>, ErrorResponse> {
+        if self.accept_keyword("SUPERUSER") {
+            return Ok(Some(true));
+        }
+        if self.accept_keyword("NOSUPERUSER") {
+            return Ok(Some(false));
+        }
+        Ok(None)
+    }
+
+    /// Parses `<permission> ON <resource> <preposition> <name>` of GRANT and REVOKE.
+    fn permission_change(&mut self, preposition: &str) -> Result<PermissionChange, ErrorResponse> {
+        let permissions = match self.next_keyword()?.as_str() {
+            "CREATE" => vec![Permission::Create],
+            "SELECT" => vec![Permission::Select],
+            "MODIFY" => vec![Permission::Modify],
+            "AUTHORIZE" => vec![Permission::Authorize],
+            "ALL" => Permission::ALL.to_vec(),
+            keyword => return Err(syntax_error(format!("Unknown permission {}", keyword))),
+        };
+        if !self.accept_keyword("PERMISSION") {
+            self.accept_keyword("PERMISSIONS");
+        }
+
+        self.expect_keyword("ON")?;
+        let resource = match self.next_keyword()?.as_str() {
+            "ALL" => {
+                self.expect_keyword("KEYSPACES")?;
+                Resource::AllKeyspaces
+            }
+            "KEYSPACE" => Resource::Keyspace(self.role_or_name()?),
+            keyword => return Err(syntax_error(format!("Unknown resource {}", keyword))),
+        };
+
+        self.expect_keyword(preposition)?;
+        Ok(PermissionChange {
+            permissions,
+            resource,
+            role: self.role_or_name()?,
+        })
+    }
+
+    fn add_value(&mut self) -> Result<AddValue, ErrorResponse> {
+        let value = self.term()?;
+        let replication_factor = match self.accept_keyword("WITH") {
+            true => {
+                self.expect_keyword("REPLICATION")?;
+                Some(self.term()?)
+            }
+            false => None,
+        };
+        Ok(AddValue {
+            value,
+            replication_factor,
+        })
+    }
+
+    fn insert(&mut self) -> Result<Insert, ErrorResponse> {
+        self.expect_keyword("INTO")?;
+        let table = self.table_name()?;
+
+        self.expect_symbol("(")?;
+        let columns = self.names()?;
+        self.expect_symbol(")")?;
+        self.expect_keyword("VALUES")?;
+        self.expect_symbol("(")?;
+        let mut terms = vec![self.term()?];
+        while self.accept_symbol(",") {
+            terms.push(self.term()?);
+        }
+        self.expect_symbol(")")?;
+        let if_not_exists = self.if_not_exists()?;
+
+        if columns.len() != terms.len() {
+            return Err(syntax_error(format!(
+                "Unmatched column names/values: {} columns but {} values",
+                columns.len(),
+                terms.len()
+            )));
+        }
+        Ok(Insert {
+            table,
+            values: columns.into_iter().zip(terms).collect(),
+            if_not_exists,
+        })
+    }
+
+    fn select(&mut self) -> Result<Select, ErrorResponse> {
+        let selection = if self.accept_symbol("*") {
+            Selection::All
+        } else if self.peek_keyword("COUNT")
+            && self
+                .tokens
+                .get(self.position + 1)
+                .is_some_and(|token| *token == Token::Symbol("("))
+        {
+            self.next()?;
+            self.expect_symbol("(")?;
+            self.expect_symbol("*")?;
+            self.expect_symbol(")")?;
+            Selection::Count
+        } else {
+            Selection::Columns(self.names()?)
+        };
+
+        self.expect_keyword("FROM")?;
+        let table = self.table_name()?;
+        let relations = self.where_clause(false)?;
+        let ordering = match self.accept_keyword("ORDER") {
+            true => {
+                self.expect_keyword("BY")?;
+                self.orderings()?
+            }
+            false => Vec::new(),
+        };
+
+        let limit = match self.accept_keyword("LIMIT") {
+            true => match self.next()? {
+                Token::Integer(limit) if limit > 0 => Some(limit as usize),
+                token => {
+                    return Err(syntax_error(format!(
+                        "LIMIT must be a positive integer but found {}",
+                        token
+                    )));
+                }
+            },
+            false => None,
+        };
+        let allow_filtering = match self.accept_keyword("ALLOW") {
+            true => {
+                self.expect_keyword("FILTERING")?;
+                true
+            }
+            false => false,
+        };
+
+        Ok(Select {
+            table,
+            selection,
+            relations,
+            ordering,
+            limit,
+            allow_filtering,
+        })
+    }
+
+    fn update(&mut self) -> Result<Update, ErrorResponse> {
+        let table = self.table_name()?;
+        self.expect_keyword("SET")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.name()?;
+            self.expect_symbol("=")?;
+            let assignment = self.assignment(&column)?;
+            assignments.push((column, assignment));
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+
+        let relations = self.where_clause(true)?;
+
+        let mut if_exists = false;
+        let mut conditions = Vec::new();
+        if self.accept_keyword("IF") {
+            if self.accept_keyword("EXISTS") {
+                if_exists = true;
+            } else {
+                conditions.push(self.relation()?);
+                while self.accept_keyword("AND") {
+                    conditions.push(self.relation()?);
+                }
+            }
+        }
+
+        Ok(Update {
+            table,
+            assignments,
+            relations,
+            if_exists,
+            conditions,
+        })
+    }
+
+    fn delete(&mut self) -> Result<Delete, ErrorResponse> {
+        let columns = match self.peek_keyword("FROM") {
+            true => Vec::new(),
+            false => self.names()?,
+        };
+        self.expect_keyword("FROM")?;
+
+        Ok(Delete {
+            table: self.table_name()?,
+            columns,
+            relations: self.where_clause(true)?,
+        })
+    }
+
+    /// Parses `WHERE <relation> AND ...`, which modifications must have.
+    fn where_clause(&mut self, required: bool) -> Result<Vec<Relation>, ErrorResponse> {
+        if !self.accept_keyword("WHERE") {
+            if required {
+                return Err(syntax_error("Expected WHERE"));
+            }
+            return Ok(Vec::new());
+        }
+
+        let mut relations = vec![self.relation()?];
+        while self.accept_keyword("AND") {
+            relations.push(self.relation()?);
+        }
+        Ok(relations)
+    }
+
+    fn relation(&mut self) -> Result<Relation, ErrorResponse> {
+        let column = self.name()?;
+        let operator = match self.next()? {
+            Token::Symbol("=") => Operator::Equal,
+            Token::Symbol("<") => Operator::LessThan,
+            Token::Symbol("<=") => Operator::LessThanOrEqual,
+            Token::Symbol(">") => Operator::GreaterThan,
+            Token::Symbol(">=") => Operator::GreaterThanOrEqual,
+            token => {
+                return Err(syntax_error(format!(
+                    "Expected an operator but found {}",
+                    token
+                )));
+            }
+        };
+
+        Ok(Relation {
+            column,
+            operator,
+            term: self.term()?,
+        })
+    }
+
+    /// Parses the value assigned to a column: a term, or the column itself plus or minus a
+    /// term.
+    fn assignment(&mut self, column: &str) -> Result<Assignment, ErrorResponse> {
+        let references_column = match self.peek() {
+            Some(Token::Word(word)) => word.to_lowercase() == column,
+            Some(Token::QuotedName(name)) => name == column,
+            _ => false,
+        };
+        if !references_column {
+            return Ok(Assignment::Value(self.term()?));
+        }
+
+        self.next()?;
+        match self.next()? {
+            Token::Symbol("+") => Ok(Assignment::Add(self.term()?)),
+            Token::Symbol("-") => Ok(Assignment::Subtract(self.term()?)),
+            // `c -1` is lexed as `c` followed by the constant -1
+            Token::Integer(value) if value < 0 => {
+                Ok(Assignment::Add(Term::Literal(Literal::Integer(value))))
+            }
+            token => Err(syntax_error(format!(
+                "Expected '+' or '-' after {} but found {}",
+                column, token
+            ))),
+        }
+    }
+
+    fn if_not_exists(&mut self) -> Result<bool, ErrorResponse> {
+        if !self.accept_keyword("IF") {
+            return Ok(false);
+        }
+        self.expect_keyword("NOT")?;
+        self.expect_keyword("EXISTS")?;
+        Ok(true)
+    }
+
+    fn table_name(&mut self) -> Result<TableName, ErrorResponse> {
+        let name = self.name()?;
+        if self.accept_symbol(".") {
+            return Ok(TableName {
+                keyspace: Some(name),
+                name: self.name()?,
+            });
+        }
+        Ok(TableName {
+            keyspace: None,
+            name,
+        })
+    }
+
+    fn data_type(&mut self) -> Result<DataType, ErrorResponse> {
+        let name = self.next_keyword()?;
+        match name.as_str() {
+            "TEXT" | "VARCHAR" => Ok(DataType::Text),
+            "INT" => Ok(DataType::Int),
+            "BIGINT" => Ok(DataType::BigInt),
+            "UUID" => Ok(DataType::Uuid),
+            "TIMESTAMP" => Ok(DataType::Timestamp),
+            "BLOB" => Ok(DataType::Blob),
+            "BOOLEAN" => Ok(DataType::Boolean),
+            "DOUBLE" => Ok(DataType::Double),
+            "COUNTER" => Ok(DataType::Counter),
+            "LIST" | "SET" => {
+                self.expect_symbol("<")?;
+                let element = Box::new(self.element_type()?);
+                self.expect_symbol(">")?;
+                match name.as_str() {
+                    "LIST" => Ok(DataType::List(element)),
+                    _ => Ok(DataType::Set(element)),
+                }
+            }
+            "MAP" => {
+                self.expect_symbol("<")?;
+                let key = Box::new(self.element_type()?);
+                self.expect_symbol(",")?;
+                let value = Box::new(self.element_type()?);
+                self.expect_symbol(">")?;
+                Ok(DataType::Map(key, value))
+            }
+            _ => Err(syntax_error(format!(
+                "Unknown type {}",
+                name.to_lowercase()
+            ))),
+        }
+    }
+
+    /// Parses the type of the elements of a collection, which can't be a collection itself.
+    fn element_type(&mut self) -> Result<DataType, ErrorResponse> {
+        let data_type = self.data_type()?;
+        if data_type.is_collection() {
+            return Err(syntax_error(format!(
+                "Non-frozen collections are not allowed inside collections: {}",
+                data_type
+            )));
+        }
+        if data_type == DataType::Counter {
+            return Err(syntax_error("Counters are not allowed inside collections"));
+        }
+        Ok(data_type)
+    }
+
+    fn term(&mut self) -> Result<Term, ErrorResponse> {
+        if self.peek() == Some(&Token::Marker) {
+            self.next()?;
+            self.markers += 1;
+            return Ok(Term::Marker(self.markers - 1));
+        }
+        self.literal().map(Term::Literal)
+    }
+
+    fn literal(&mut self) -> Result<Literal, ErrorResponse> {
+        match self.next()? {
+            Token::String(value) => Ok(Literal::String(value)),
+            Token::Integer(value) => Ok(Literal::Integer(value)),
+            Token::Float(value) => Ok(Literal::Float(value)),
+            Token::Uuid(value) => Ok(Literal::Uuid(value)),
+            Token::Blob(value) => Ok(Literal::Blob(value)),
+            Token::Symbol("[") => {
+                let mut elements = Vec::new();
+                if !self.accept_symbol("]") {
+                    elements = self.literals()?;
+                    self.expect_symbol("]")?;
+                }
+                Ok(Literal::List(elements))
+            }
+            Token::Symbol("{") => self.set_or_map(),
+            Token::Word(word) => match word.to_uppercase().as_str() {
+                "TRUE" => Ok(Literal::Boolean(true)),
+                "FALSE" => Ok(Literal::Boolean(false)),
+                "NULL" => Ok(Literal::Null),
+                _ => Err(syntax_error(format!("Invalid term {}", word))),
+            },
+            token => Err(syntax_error(format!("Invalid term {}", token))),
+        }
+    }
+
+    /// Parses a comma separated list of columns, each optionally followed by ASC or DESC.
+    fn orderings(&mut self) -> Result<Vec<(String, ClusteringOrder)>, ErrorResponse> {
+        let mut orderings = Vec::new();
+        loop {
+            let column = self.name()?;
+            let order = match self.accept_keyword("DESC") {
+                true => ClusteringOrder::Desc,
+                false => {
+                    self.accept_keyword("ASC");
+                    ClusteringOrder::Asc
+                }
+            };
+            orderings.push((column, order));
+            if !self.accept_symbol(",") {
+                return Ok(orderings);
+            }
+        }
+    }
+
+    /// Parses a comma separated list of literals.
+    fn literals(&mut self) -> Result<Vec<Literal>, ErrorResponse> {
+        let mut literals = vec![self.literal()?];
+        while self.accept_symbol(",") {
+            literals.push(self.literal()?);
+        }
+        Ok(literals)
+    }
+
+    /// Parses the rest of a `{set}` or `{key: value}` map after its `{`.
+    fn set_or_map(&mut self) -> Result<Literal, ErrorResponse> {
+        if self.accept_symbol("}") {
+            return Ok(Literal::Map(Vec::new()));
+        }
+
+        let first = self.literal()?;
+        if !self.accept_symbol(":") {
+            let mut elements = vec![first];
+            while self.accept_symbol(",") {
+                elements.push(self.literal()?);
+            }
+            self.expect_symbol("}")?;
+            return Ok(Literal::Set(elements));
+        }
+
+        let mut entries = vec![(first, self.literal()?)];
+        while self.accept_symbol(",") {
+            let key = self.literal()?;
+            self.expect_symbol(":")?;
+            entries.push((key, self.literal()?));
+        }
+        self.expect_symbol("}")?;
+        Ok(Literal::Map(entries))
+    }
+
+    /// Parses a comma separated list of names.
+    fn names(&mut self) -> Result<Vec<String>, ErrorResponse> {
+        let mut names = vec![self.name()?];
+        while self.accept_symbol(",") {
+            names.push(self.name()?);
+        }
+        Ok(names)
+    }
+
+    /// Parses a name, or a user name given as a `'string'`, which keeps its case.
+    fn role_or_name(&mut self) -> Result<String, ErrorResponse> {
+        if let Some(Token::String(name)) = self.peek() {
+            let name = name.clone();
+            self.position += 1;
+            return Ok(name);
+        }
+        self.name()
+    }
+
+    /// Parses a name, lower-casing it unless it is quoted.
+    fn name(&mut self) -> Result<String, ErrorResponse> {
+        match self.next()? {
+            Token::Word(word) => Ok(word.to_lowercase()),
+            Token::QuotedName(name) => Ok(name),
+            token => Err(syntax_error(format!("Expected a name but found {}", token))),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, ErrorResponse> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| syntax_error("Unexpected end of statement"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_keyword(&mut self) -> Result<String, ErrorResponse> {
+        match self.next()? {
+            Token::Word(word) => Ok(word.to_uppercase()),
+            token => Err(syntax_error(format!(
+                "Expected a keyword but found {}",
+                token
+            ))),
+        }
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let accepted = self.peek_keyword(keyword);
+        if accepted {
+            self.position += 1;
+        }
+        accepted
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ErrorResponse> {
+        match self.next()? {
+            Token::Word(word) if word.eq_ignore_ascii_case(keyword) => Ok(()),
+            token => Err(syntax_error(format!(
+                "Expected {} but found {}",
+                keyword, token
+            ))),
+        }
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let accepted = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if accepted {
+            self.position += 1;
+        }
+        accepted
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), ErrorResponse> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            token => Err(syntax_error(format!(
+                "Expected '{}' but found {}",
+                symbol, token
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cql::ast::{
+        AddValue, AlterUser, Assignment, CqlStatement, CreateIndex, CreateTable, CreateUser,
+        Delete, DropUser, Literal, Operator, Relation, Select, Selection, TableName, Term, Update,
+    };
+    use crate::cql::parser::parse;
+    use shared::protocol::types::{
+        ClusteringOrder, DataType, ErrorCode, Permission, PermissionChange, Resource,
+    };
+
+    fn table(keyspace: Option<&str>, name: &str) -> TableName {
+        TableName {
+            keyspace: keyspace.map(|keyspace| keyspace.to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_create_keyspace() {
+        let statement = parse(
+            "CREATE KEYSPACE IF NOT EXISTS Shop WITH replication = \
+             {'class': 'SimpleStrategy', 'replication_factor': 2}",
+        )
+        .unwrap();
+
+        let CqlStatement::CreateKeyspace(create) = statement else {
+            panic!("Expected CREATE KEYSPACE, got {:?}", statement);
+        };
+        assert_eq!(create.name, "shop");
+        assert!(create.if_not_exists);
+        assert_eq!(
+            create.replication,
+            vec![
+                (
+                    "class".to_string(),
+                    Literal::String("SimpleStrategy".to_string())
+                ),
+                ("replication_factor".to_string(), Literal::Integer(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_create_table_with_composite_partition_key() {
+        let statement = parse(
+            "create table shop.orders (user text, day int, total bigint, \
+             PRIMARY KEY ((user, day)))",
+        )
+        .unwrap();
+
+        assert_eq!(
+            statement,
+            CqlStatement::CreateTable(CreateTable {
+                table: table(Some("shop"), "orders"),
+                if_not_exists: false,
+                columns: vec![
+                    ("user".to_string(), DataType::Text),
+                    ("day".to_string(), DataType::Int),
+                    ("total".to_string(), DataType::BigInt),
+                ],
+                partition_key: vec!["user".to_string(), "day".to_string()],
+                clustering_key: Vec::new(),
+                clustering_order: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_create_table_with_clustering_columns_and_collections() {
+        let statement = parse(
+            "CREATE TABLE readings (sensor uuid, at timestamp, seq int, value double, \
+             tags set<text>, attributes map<text, blob>, PRIMARY KEY (sensor, at, seq)) \
+             WITH CLUSTERING ORDER BY (at DESC, seq ASC)",
+        )
+        .unwrap();
+
+        let CqlStatement::CreateTable(create) = statement else {
+            panic!("Expected CREATE TABLE, got {:?}", statement);
+        };
+        assert_eq!(create.partition_key, vec!["sensor".to_string()]);
+        assert_eq!(
+            create.clustering_key,
+            vec!["at".to_string(), "seq".to_string()]
+        );
+        assert_eq!(
+            create.clustering_order,
+            vec![
+                ("at".to_string(), ClusteringOrder::Desc),
+                ("seq".to_string(), ClusteringOrder::Asc)
+            ]
+        );
+        assert_eq!(
+            create.columns[5],
+            (
+                "attributes".to_string(),
+                DataType::Map(Box::new(DataType::Text), Box::new(DataType::Blob))
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_nested_collections() {
+        let error = parse("CREATE TABLE t (id int PRIMARY KEY, tags list<set<int>>)").unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::SyntaxError);
+    }
+
+    #[test]
+    fn test_parse_collection_literals() {
+        let statement =
+            parse("INSERT INTO t (id, scores, tags, owners) VALUES (1, [1.5, 2], {'a', 'b'}, {})")
+                .unwrap();
+
+        let CqlStatement::Insert(insert) = statement else {
+            panic!("Expected INSERT, got {:?}", statement);
+        };
+        let literals: Vec<&Term> = insert.values.iter().map(|(_, term)| term).collect();
+        assert_eq!(
+            literals[1..],
+            [
+                &Term::Literal(Literal::List(vec![
+                    Literal::Float(1.5),
+                    Literal::Integer(2)
+                ])),
+                &Term::Literal(Literal::Set(vec![
+                    Literal::String("a".to_string()),
+                    Literal::String("b".to_string())
+                ])),
+                &Term::Literal(Literal::Map(Vec::new())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_select_with_where_and_limit() {
+        let statement =
+            parse("SELECT name, age FROM users WHERE id = ? AND age > 30 LIMIT 10 ALLOW FILTERING")
+                .unwrap();
+
+        assert_eq!(
+            statement,
+            CqlStatement::Select(Select {
+                table: table(None, "users"),
+                selection: Selection::Columns(vec!["name".to_string(), "age".to_string()]),
+                relations: vec![
+                    Relation {
+                        column: "id".to_string(),
+                        operator: Operator::Equal,
+                        term: Term::Marker(0),
+                    },
+                    Relation {
+                        column: "age".to_string(),
+                        operator: Operator::GreaterThan,
+                        term: Term::Literal(Literal::Integer(30)),
+                    },
+                ],
+                ordering: Vec::new(),
+                limit: Some(10),
+                allow_filtering: true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_select_with_order_by() {
+        let statement = parse(
+            "SELECT * FROM readings WHERE sensor = ? AND at > ? ORDER BY at DESC, seq LIMIT 5",
+        )
+        .unwrap();
+
+        let CqlStatement::Select(select) = statement else {
+            panic!("Expected SELECT, got {:?}", statement);
+        };
+        assert_eq!(
+            select.ordering,
+            vec![
+                ("at".to_string(), ClusteringOrder::Desc),
+                ("seq".to_string(), ClusteringOrder::Asc)
+            ]
+        );
+        assert_eq!(select.limit, Some(5));
+    }
+
+    #[test]
+    fn test_parse_delete_columns() {
+        let statement = parse("DELETE age FROM users WHERE id = 'alice'").unwrap();
+
+        assert_eq!(
+            statement,
+            CqlStatement::Delete(Delete {
+                table: table(None, "users"),
+                columns: vec!["age".to_string()],
+                relations: vec![Relation {
+                    column: "id".to_string(),
+                    operator: Operator::Equal,
+                    term: Term::Literal(Literal::String("alice".to_string())),
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_create_index() {
+        assert_eq!(
+            parse("CREATE INDEX IF NOT EXISTS by_email ON shop.users (Email)").unwrap(),
+            CqlStatement::CreateIndex(CreateIndex {
+                name: Some("by_email".to_string()),
+                if_not_exists: true,
+                table: table(Some("shop"), "users"),
+                column: "email".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("CREATE INDEX ON users (email)").unwrap(),
+            CqlStatement::CreateIndex(CreateIndex {
+                name: None,
+                if_not_exists: false,
+                table: table(None, "users"),
+                column: "email".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_conditional_modifications() {
+        let statement =
+            parse("INSERT INTO users (id, age) VALUES ('alice', 30) IF NOT EXISTS").unwrap();
+        let CqlStatement::Insert(insert) = statement else {
+            panic!("Expected INSERT, got {:?}", statement);
+        };
+        assert!(insert.if_not_exists);
+
+        assert_eq!(
+            parse("UPDATE users SET age = ? WHERE id = 'alice' IF age = 30 AND name = ?").unwrap(),
+            CqlStatement::Update(Update {
+                table: table(None, "users"),
+                assignments: vec![("age".to_string(), Assignment::Value(Term::Marker(0)))],
+                relations: vec![Relation {
+                    column: "id".to_string(),
+                    operator: Operator::Equal,
+                    term: Term::Literal(Literal::String("alice".to_string())),
+                }],
+                if_exists: false,
+                conditions: vec![
+                    Relation {
+                        column: "age".to_string(),
+                        operator: Operator::Equal,
+                        term: Term::Literal(Literal::Integer(30)),
+                    },
+                    Relation {
+                        column: "name".to_string(),
+                        operator: Operator::Equal,
+                        term: Term::Marker(1),
+                    },
+                ],
+            })
+        );
+
+        let statement = parse("UPDATE users SET age = 31 WHERE id = 'alice' IF EXISTS").unwrap();
+        let CqlStatement::Update(update) = statement else {
+            panic!("Expected UPDATE, got {:?}", statement);
+        };
+        assert!(update.if_exists);
+        assert!(update.conditions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_counter_update() {
+        let statement =
+            parse("UPDATE page_views SET views = views + ?, likes = likes -1 WHERE page = 'home'")
+                .unwrap();
+
+        let CqlStatement::Update(update) = statement else {
+            panic!("Expected UPDATE, got {:?}", statement);
+        };
+        assert_eq!(
+            update.assignments,
+            vec![
+                ("views".to_string(), Assignment::Add(Term::Marker(0))),
+                (
+                    "likes".to_string(),
+                    Assignment::Add(Term::Literal(Literal::Integer(-1)))
+                ),
+            ]
+        );
+        assert!(parse("UPDATE page_views SET views = views * 2 WHERE page = 'home'").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_update_without_where() {
+        let error = parse("UPDATE users SET age = 3").unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::SyntaxError);
+    }
+
+    #[test]
+    fn test_parse_value_statements() {
+        assert_eq!(
+            parse("add ? with replication ?;").unwrap(),
+            CqlStatement::AddValue(AddValue {
+                value: Term::Marker(0),
+                replication_factor: Some(Term::Marker(1)),
+            })
+        );
+        assert_eq!(
+            parse("CHECK 'it''s'").unwrap(),
+            CqlStatement::CheckValue(Term::Literal(Literal::String("it's".to_string())))
+        );
+        assert_eq!(parse("COUNT").unwrap(), CqlStatement::CountValues);
+    }
+
+    #[test]
+    fn test_parse_user_statements() {
+        assert_eq!(
+            parse("CREATE USER 'alice' WITH PASSWORD ? SUPERUSER").unwrap(),
+            CqlStatement::CreateUser(CreateUser {
+                name: Term::Literal(Literal::String("alice".to_string())),
+                password: Term::Marker(0),
+                superuser: true,
+            })
+        );
+        assert_eq!(
+            parse("alter user 'alice' nosuperuser").unwrap(),
+            CqlStatement::AlterUser(AlterUser {
+                name: Term::Literal(Literal::String("alice".to_string())),
+                password: None,
+                superuser: Some(false),
+            })
+        );
+        assert_eq!(
+            parse("DROP USER IF EXISTS ?").unwrap(),
+            CqlStatement::DropUser(DropUser {
+                name: Term::Marker(0),
+                if_exists: true,
+            })
+        );
+        assert_eq!(
+            parse("ALTER USER 'alice'").unwrap_err().code,
+            ErrorCode::SyntaxError
+        );
+    }
+
+    #[test]
+    fn test_parse_grant_and_revoke() {
+        let grant = parse("GRANT SELECT PERMISSION ON KEYSPACE Default TO 'Alice'").unwrap();
+        let revoke = parse("revoke all permissions on all keyspaces from alice").unwrap();
+
+        assert_eq!(
+            grant,
+            CqlStatement::Grant(PermissionChange {
+                permissions: vec![Permission::Select],
+                resource: Resource::Keyspace("default".to_string()),
+                role: "Alice".to_string(),
+            })
+        );
+        assert_eq!(
+            revoke,
+            CqlStatement::Revoke(PermissionChange {
+                permissions: Permission::ALL.to_vec(),
+                resource: Resource::AllKeyspaces,
+                role: "alice".to_string(),
+            })
+        );
+        assert!(parse("GRANT EVERYTHING ON ALL KEYSPACES TO alice").is_err());
+    }
+}
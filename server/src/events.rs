@@ -0,0 +1,49 @@
+//! Distribution of events to registered connections.
+//!
+//! Connections that sent a REGISTER subscribe to the `EventBus`; every event published on the
+//! bus is forwarded to the subscribers registered for its type, which push it to their client.
+
+use shared::protocol::types::{Event, EventType};
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, mpsc};
+
+/// A thread-safe, shared event bus.
+pub type GlobalEventBus = Arc<Mutex<EventBus>>;
+
+struct Subscriber {
+    event_types: HashSet<EventType>,
+    sender: Sender<Event>,
+}
+
+pub struct EventBus {
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Subscribes to events of the given types, returning the receiving end of the
+    /// subscription.
+    pub(crate) fn subscribe(&mut self, event_types: &[EventType]) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(Subscriber {
+            event_types: event_types.iter().copied().collect(),
+            sender,
+        });
+        receiver
+    }
+
+    /// Sends an event to its subscribers, dropping those whose receiver is gone.
+    pub(crate) fn publish(&mut self, event: Event) {
+        let event_type = event.event_type();
+        self.subscribers.retain(|subscriber| {
+            !subscriber.event_types.contains(&event_type)
+                || subscriber.sender.send(event.clone()).is_ok()
+        });
+    }
+}
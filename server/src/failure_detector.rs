@@ -0,0 +1,147 @@
+//! Detection of unreachable nodes.
+//!
+//! Every node periodically sends a TOKENS message to the other nodes of the cluster. When a
+//! node stops answering, or answers again, a STATUS_CHANGE event is published. The answers
+//! tell the host ids of the nodes and the tokens they picked, which replace the default tokens
+//! they are placed at until then.
+//!
+//! The answers also tell where a node knows this node to be. A node still knowing it at an
+//! address it moved away from is told about the move, so that it follows this node to its new
+//! address.
+
+use crate::coordinator::GlobalCluster;
+use crate::events::GlobalEventBus;
+use log::{info, warn};
+use shared::cluster::Node;
+use shared::connection_pool::ConnectionPool;
+use shared::protocol::types::{
+    Event, NodeTokens, Request, Response, StatusChange, StatusChangeType, TopologyChange,
+    TopologyChangeType,
+};
+use shared::routing::RoutingStrategy;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+/// How often the other nodes are checked.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+pub(crate) struct FailureDetector {
+    current_host: Node,
+    cluster: GlobalCluster,
+    events: GlobalEventBus,
+    connection_pool: ConnectionPool,
+    /// Last known status of every other node; nodes are assumed up until a heartbeat fails.
+    statuses: HashMap<String, StatusChangeType>,
+}
+
+impl FailureDetector {
+    pub(crate) fn new(
+        current_host: Node,
+        cluster: GlobalCluster,
+        events: GlobalEventBus,
+        connection_pool: ConnectionPool,
+    ) -> Self {
+        Self {
+            current_host,
+            cluster,
+            events,
+            connection_pool,
+            statuses: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn run(&mut self) {
+        loop {
+            thread::sleep(HEARTBEAT_INTERVAL);
+
+            let nodes: Vec<Node> = self
+                .cluster
+                .read()
+                .unwrap()
+                .get_nodes()
+                .iter()
+                .filter(|node| node.address != self.current_host.address)
+                .cloned()
+                .collect();
+
+            // forget nodes that left the cluster
+            self.statuses
+                .retain(|address, _| nodes.iter().any(|node| &node.address == address));
+
+            for node in nodes {
+                let status = self.heartbeat(&node);
+                let previous_status = self
+                    .statuses
+                    .insert(node.address.clone(), status)
+                    .unwrap_or(StatusChangeType::Up);
+
+                if previous_status != status {
+                    info!("Node {} is now {:?}", node.address, status);
+                    self.events
+                        .lock()
+                        .unwrap()
+                        .publish(Event::StatusChange(StatusChange {
+                            change: status,
+                            address: node.address,
+                        }));
+                }
+            }
+        }
+    }
+
+    fn heartbeat(&mut self, node: &Node) -> StatusChangeType {
+        let result =
+            self.connection_pool
+                .execute(RoutingStrategy::Direct(node), Request::Tokens, None);
+
+        match result {
+            Ok(Response::Tokens(node_tokens)) => {
+                if node_tokens.iter().any(|known| self.is_moved_away(known)) {
+                    self.announce_move(node);
+                }
+                // only a node itself knows for sure which host id and tokens it has
+                for node_tokens in node_tokens {
+                    let address = node_tokens.address.clone();
+                    if address == node.address
+                        && self.cluster.write().unwrap().learn_node(node_tokens)
+                    {
+                        info!("Learned the host id and tokens of {}", address);
+                    }
+                }
+                StatusChangeType::Up
+            }
+            Ok(_) => StatusChangeType::Up,
+            Err(e) => {
+                warn!("Heartbeat to {} failed: {:?}", node.address, e);
+                StatusChangeType::Down
+            }
+        }
+    }
+
+    /// Returns whether a node is known to be this node, at an address it moved away from.
+    fn is_moved_away(&self, known: &NodeTokens) -> bool {
+        known.host_id.is_some()
+            && known.host_id == self.current_host.host_id
+            && known.address != self.current_host.address
+    }
+
+    /// Tells a node that this node moved to its current address.
+    fn announce_move(&mut self, node: &Node) {
+        let change = TopologyChange {
+            change: TopologyChangeType::MovedNode,
+            address: self.current_host.address.clone(),
+            host_id: self.current_host.host_id,
+        };
+        info!("Telling {} that this node moved", node.address);
+
+        let result = self.connection_pool.execute(
+            RoutingStrategy::Direct(node),
+            Request::ChangeTopology(change),
+            None,
+        );
+        if let Err(e) = result {
+            warn!("Cannot tell {} that this node moved: {:?}", node.address, e);
+        }
+    }
+}
@@ -1,32 +1,368 @@
+use crate::authorizer;
+use crate::coordinator::Coordinator;
 use crate::handlers::{
-    add_batch_handler, add_handler, check_handler, drop_batch_handler, get_batch_handler, get_count,
+    add_batch_handler, add_handler, add_rows_handler, alter_user_handler, batch_handler,
+    change_topology_handler, check_handler, counter_update_handler, cql_handler,
+    create_index_handler, create_keyspace_handler, create_table_handler, create_user_handler,
+    drop_batch_handler, drop_rows_handler, drop_user_handler, get_batch_handler, get_count,
+    get_rows_handler, get_trace_handler, grant_handler, load_handler, mutate_handler,
+    options_handler, paxos_commit_handler, paxos_prepare_handler, paxos_propose_handler,
+    prepare_handler, pull_schema_handler, read_handler, read_mutations_handler, register_handler,
+    remove_batchlog_handler, revoke_handler, schema_version_handler, startup_handler,
+    store_batchlog_handler, tokens_handler,
 };
+use crate::internode;
+use crate::internode::RequestContext;
 use crate::replicator::ReplicationEntry;
-use crate::storage::GlobalStorage;
+use crate::server::NodeState;
+use crate::tracing;
+use crate::tracing::TraceState;
+use crate::warnings;
 use shared::error::AppResult;
-use shared::protocol::types::{Request, Response};
+use shared::protocol::frame::{RequestExtras, ResponseExtras, Tracing};
+use shared::protocol::types::{ErrorCode, ErrorResponse, Request, Response};
 use std::sync::mpsc::Sender;
+use uuid::Uuid;
 
 pub(crate) struct HandlerManager {
-    storage: GlobalStorage,
+    state: NodeState,
     sender: Sender<ReplicationEntry>,
+    coordinator: Coordinator,
+    /// The user the connection authenticated as, if the authenticator requires one.
+    authenticated_user: Option<String>,
+    /// The keyspace the connection set with USE.
+    keyspace: Option<String>,
 }
 
 impl HandlerManager {
-    pub(crate) fn new(storage: GlobalStorage, sender: Sender<ReplicationEntry>) -> Self {
-        Self { storage, sender }
+    pub(crate) fn new(
+        state: NodeState,
+        sender: Sender<ReplicationEntry>,
+        coordinator: Coordinator,
+    ) -> Self {
+        Self {
+            state,
+            sender,
+            coordinator,
+            authenticated_user: None,
+            keyspace: None,
+        }
+    }
+
+    /// Handles a request of the connection, tracing it if it asks to be and collecting the
+    /// warnings for its client.
+    ///
+    /// The query handler sees the custom payload of the request first and may reject it. The
+    /// response is returned together with the warnings, the payload the query handler returned
+    /// and, if this node started the tracing session, its id, which the client has to be told
+    /// about. Requests forwarded by other nodes are traced in the session of the forwarding
+    /// node and carry its payload.
+    pub(crate) fn handle_with_extras(
+        &mut self,
+        request: &Request,
+        extras: RequestExtras,
+    ) -> AppResult<(Response, ResponseExtras)> {
+        let session_id = match extras.tracing {
+            Tracing::Disabled => None,
+            Tracing::NewSession => Some(Uuid::new_v4()),
+            Tracing::Session(session_id) => Some(session_id),
+        };
+        let trace_state = session_id.map(|session_id| {
+            TraceState::new(
+                session_id,
+                self.state.current_host.address.clone(),
+                self.state.traces.clone(),
+            )
+        });
+        let query_handler = self.state.query_handler.clone();
+
+        RequestContext::new(trace_state, extras.custom_payload.clone()).enter();
+        warnings::begin();
+        tracing::trace(|| format!("Received {:?} request", request.opcode()));
+        let result = match query_handler.before_request(
+            request,
+            self.authenticated_user.as_deref(),
+            &extras.custom_payload,
+        ) {
+            Ok(()) => self.handle(request),
+            Err(error) => Ok(Response::Error(error)),
+        };
+        tracing::trace(|| "Request complete".to_string());
+        internode::exit();
+
+        let warnings = warnings::take();
+        result.map(|response| {
+            let custom_payload =
+                query_handler.after_request(request, &response, &extras.custom_payload);
+            let extras = ResponseExtras {
+                tracing_id: session_id.filter(|_| extras.tracing == Tracing::NewSession),
+                warnings,
+                custom_payload,
+            };
+            (response, extras)
+        })
     }
 
-    pub(crate) fn handle(&self, request: &Request) -> AppResult<Response> {
+    /// Handles a request of the connection.
+    ///
+    /// Before dispatching, it checks that the connection authenticated if required, and that
+    /// the authenticated user is allowed to perform the request.
+    pub(crate) fn handle(&mut self, request: &Request) -> AppResult<Response> {
+        if !self.is_allowed(request) {
+            return Ok(Response::Error(ErrorResponse::new(
+                ErrorCode::ProtocolError,
+                format!(
+                    "Unexpected message {:?}, expecting AUTH_RESPONSE",
+                    request.opcode()
+                ),
+            )));
+        }
+
+        if let Some(user) = &self.authenticated_user
+            && let Err(error) =
+                authorizer::authorize(user, request, &self.state.roles.lock().unwrap())
+        {
+            return Ok(Response::Error(error));
+        }
+
+        let state = &self.state;
         match request {
-            Request::Check(value) => check_handler::handle(value, &self.storage),
+            Request::Startup(options) => startup_handler::handle(options, &state.authenticator),
+            Request::Options => options_handler::handle(),
+            Request::AuthResponse(token) => match state.authenticator.authenticate(token) {
+                Ok(user) => {
+                    self.authenticated_user = Some(user);
+                    Ok(Response::AuthSuccess(None))
+                }
+                Err(error) => Ok(Response::Error(error)),
+            },
+            Request::Query(query) => {
+                let response = cql_handler::handle(
+                    query,
+                    self.keyspace.as_deref(),
+                    self.authenticated_user.as_deref(),
+                    state,
+                    &mut self.coordinator,
+                )?;
+                if let Response::SetKeyspace(keyspace) = &response {
+                    self.keyspace = Some(keyspace.clone());
+                }
+                Ok(response)
+            }
+            Request::Prepare(query) => prepare_handler::handle(
+                query,
+                self.keyspace.as_deref(),
+                &state.schema,
+                &state.prepared_cache,
+            ),
+            Request::Execute(execute) => {
+                let statement = state.prepared_cache.lock().unwrap().statement(execute);
+                let statement = match statement {
+                    Ok(statement) => statement,
+                    Err(error) => return Ok(Response::Error(error)),
+                };
+                tracing::trace(|| format!("Executing prepared statement {:?}", statement));
+                let response = cql_handler::execute(
+                    &statement,
+                    &execute.values,
+                    self.keyspace.as_deref(),
+                    self.authenticated_user.as_deref(),
+                    state,
+                    &mut self.coordinator,
+                )?;
+                if let Response::SetKeyspace(keyspace) = &response {
+                    self.keyspace = Some(keyspace.clone());
+                }
+                Ok(response)
+            }
+            Request::Batch(batch) => batch_handler::handle(
+                batch,
+                self.keyspace.as_deref(),
+                self.authenticated_user.as_deref(),
+                state,
+                &mut self.coordinator,
+            ),
+            Request::StoreBatchlog(entry) => store_batchlog_handler::handle(entry, &state.batchlog),
+            Request::RemoveBatchlog(id) => remove_batchlog_handler::handle(id, &state.batchlog),
+            Request::Register(event_types) => register_handler::handle(event_types),
+            Request::ChangeTopology(change) => {
+                change_topology_handler::handle(change, &state.cluster, &state.events)
+            }
+            Request::CreateUser(create_user) => {
+                create_user_handler::handle(create_user, &state.authenticator, &state.roles)
+            }
+            Request::AlterUser(alter_user) => alter_user_handler::handle(
+                alter_user,
+                self.authenticated_user.as_deref(),
+                &state.authenticator,
+                &state.roles,
+            ),
+            Request::DropUser(drop_user) => drop_user_handler::handle(
+                drop_user,
+                self.authenticated_user.as_deref(),
+                &state.authenticator,
+                &state.roles,
+            ),
+            Request::Grant(change) => {
+                grant_handler::handle(change, &state.authenticator, &state.roles)
+            }
+            Request::Revoke(change) => {
+                revoke_handler::handle(change, &state.authenticator, &state.roles)
+            }
+            Request::Check(value) => check_handler::handle(value, &state.storage),
             Request::AddBatch(items) => {
-                add_batch_handler::handle(items, &self.storage, &self.sender)
+                add_batch_handler::handle(items, &state.storage, &self.sender)
+            }
+            Request::Add(entry) => add_handler::handle(entry, &state.storage, &self.sender),
+            Request::Count(ranges) => get_count::handle(ranges, &state.storage),
+            Request::GetTrace(session_id) => get_trace_handler::handle(session_id, &state.traces),
+            Request::Mutate(mutation) => {
+                mutate_handler::handle(mutation, &state.schema, &state.rows)
+            }
+            Request::CounterUpdate(update) => {
+                counter_update_handler::handle(update, &state.schema, &mut self.coordinator)
+            }
+            Request::Read(command) => read_handler::handle(command, &state.schema, &state.rows),
+            Request::ReadMutations(command) => {
+                read_mutations_handler::handle(command, &state.schema, &state.rows)
+            }
+            Request::PaxosPrepare(prepare) => paxos_prepare_handler::handle(prepare, &state.paxos),
+            Request::PaxosPropose(proposal) => {
+                paxos_propose_handler::handle(proposal, &state.paxos)
+            }
+            Request::PaxosCommit(proposal) => {
+                paxos_commit_handler::handle(proposal, &state.paxos, &state.schema, &state.rows)
+            }
+            Request::CreateKeyspace(keyspace) => {
+                create_keyspace_handler::handle(keyspace, &state.schema, &state.events)
+            }
+            Request::CreateTable(table) => {
+                create_table_handler::handle(table, &state.schema, &state.events)
+            }
+            Request::CreateIndex(index) => {
+                create_index_handler::handle(index, &state.schema, &state.rows, &state.events)
+            }
+            Request::SchemaVersion => schema_version_handler::handle(&state.schema),
+            Request::Tokens => tokens_handler::handle(&state.cluster),
+            Request::Load => load_handler::handle(&state.storage, &state.rows),
+            Request::PullSchema => pull_schema_handler::handle(&state.schema),
+            Request::DropBatch(ranges) => drop_batch_handler::handle(ranges, &state.storage),
+            Request::GetBatch(get_batch) => get_batch_handler::handle(get_batch, &state.storage),
+            Request::GetRows(row_ranges) => {
+                get_rows_handler::handle(row_ranges, &state.schema, &state.rows)
+            }
+            Request::AddRows(mutations) => {
+                add_rows_handler::handle(mutations, &state.schema, &state.rows)
+            }
+            Request::DropRows(row_ranges) => drop_rows_handler::handle(row_ranges, &state.rows),
+        }
+    }
+
+    /// Returns whether the request may be handled, which before authentication is only the
+    /// case for the messages of the startup exchange.
+    fn is_allowed(&self, request: &Request) -> bool {
+        !self.state.authenticator.requires_authentication()
+            || self.authenticated_user.is_some()
+            || matches!(
+                request,
+                Request::Startup(_) | Request::Options | Request::AuthResponse(_)
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coordinator::Coordinator;
+    use crate::handler_manager::HandlerManager;
+    use crate::query_handler::QueryHandler;
+    use crate::server::NodeState;
+    use shared::connection_pool::ConnectionPool;
+    use shared::protocol::frame::RequestExtras;
+    use shared::protocol::types::{CustomPayload, ErrorCode, ErrorResponse, Request, Response};
+    use std::sync::{Arc, mpsc};
+
+    /// Rejects requests without a tenant and returns the tenant of the others.
+    struct TenantQueryHandler;
+
+    impl QueryHandler for TenantQueryHandler {
+        fn name(&self) -> &'static str {
+            "TenantQueryHandler"
+        }
+
+        fn before_request(
+            &self,
+            _request: &Request,
+            _user: Option<&str>,
+            custom_payload: &CustomPayload,
+        ) -> Result<(), ErrorResponse> {
+            match custom_payload.contains_key("tenant") {
+                true => Ok(()),
+                false => Err(ErrorResponse::new(ErrorCode::Invalid, "No tenant")),
             }
-            Request::Add(entry) => add_handler::handle(entry, &self.storage, &self.sender),
-            Request::Count => get_count::handle(&self.storage),
-            Request::DropBatch(ranges) => drop_batch_handler::handle(ranges, &self.storage),
-            Request::GetBatch(ranges) => get_batch_handler::handle(ranges, &self.storage),
         }
+
+        fn after_request(
+            &self,
+            _request: &Request,
+            _response: &Response,
+            custom_payload: &CustomPayload,
+        ) -> CustomPayload {
+            custom_payload
+                .iter()
+                .filter(|(key, _)| *key == "tenant")
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        }
+    }
+
+    fn handler_manager() -> HandlerManager {
+        let mut state = NodeState::single_node();
+        state.query_handler = Arc::new(TenantQueryHandler);
+        let (sender, _receiver) = mpsc::channel();
+        let coordinator = Coordinator::new(&state, sender.clone(), ConnectionPool::new());
+        HandlerManager::new(state, sender, coordinator)
+    }
+
+    #[test]
+    fn test_query_handler_rejects_requests_before_they_are_handled() {
+        let mut handler_manager = handler_manager();
+
+        let (response, extras) = handler_manager
+            .handle_with_extras(&Request::Options, RequestExtras::default())
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            Response::Error(ErrorResponse {
+                code: ErrorCode::Invalid,
+                ..
+            })
+        ));
+        assert!(extras.custom_payload.is_empty());
+    }
+
+    #[test]
+    fn test_query_handler_returns_the_payload_of_the_response() {
+        let mut handler_manager = handler_manager();
+        let custom_payload = CustomPayload::from([
+            ("tenant".to_string(), b"acme".to_vec()),
+            ("tag".to_string(), b"checkout".to_vec()),
+        ]);
+
+        let (response, extras) = handler_manager
+            .handle_with_extras(
+                &Request::Options,
+                RequestExtras {
+                    custom_payload,
+                    ..RequestExtras::default()
+                },
+            )
+            .unwrap();
+
+        assert!(matches!(response, Response::Supported(_)));
+        assert_eq!(
+            extras.custom_payload,
+            CustomPayload::from([("tenant".to_string(), b"acme".to_vec())])
+        );
     }
 }
@@ -1,7 +1,9 @@
 //! Handler for the "add batch" command.
 
+use crate::internode::RequestContext;
 use crate::replicator::ReplicationEntry;
 use crate::storage::{GlobalStorage, Storage};
+use crate::tracing;
 use shared::error::AppResult;
 use shared::protocol::types::{Entry, Response};
 use std::sync::mpsc::Sender;
@@ -15,16 +17,17 @@ pub(crate) fn handle(
     let mut storage_guard = storage.lock().unwrap();
 
     let items_count = items.len();
+    tracing::trace(|| format!("Writing {} values to storage", items_count));
     for item in items.iter() {
         storage_guard.add(item.value.clone());
     }
 
     sender
-        .send(ReplicationEntry::Batch(items.to_vec()))
+        .send(ReplicationEntry::Batch(
+            items.to_vec(),
+            RequestContext::current(),
+        ))
         .unwrap();
 
-    Ok(Response::String(format!(
-        "Inserted batch of {}",
-        items_count
-    )))
+    Ok(Response::Void)
 }
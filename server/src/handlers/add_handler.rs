@@ -1,7 +1,9 @@
 //! Handler for the "add" command.
 
+use crate::internode::RequestContext;
 use crate::replicator::ReplicationEntry;
 use crate::storage::{GlobalStorage, Storage};
+use crate::tracing;
 use shared::error::AppResult;
 use shared::protocol::types::{Entry, Response};
 use std::sync::mpsc::Sender;
@@ -14,15 +16,18 @@ pub(crate) fn handle(
 ) -> AppResult<Response> {
     let mut storage_guard = storage.lock().unwrap();
 
+    tracing::trace(|| format!("Writing {} to storage", entry.value));
     storage_guard.add(entry.value.clone());
 
+    if let Some(replication_factor) = entry.replication_factor {
+        tracing::trace(|| format!("Enqueuing replication to {} replicas", replication_factor));
+    }
     sender
-        .send(ReplicationEntry::Single(entry.clone()))
+        .send(ReplicationEntry::Single(
+            entry.clone(),
+            RequestContext::current(),
+        ))
         .unwrap();
 
-    Ok(Response::String(format!(
-        "Added {}, there are currently {}",
-        entry.value,
-        storage_guard.get_count()
-    )))
+    Ok(Response::Void)
 }
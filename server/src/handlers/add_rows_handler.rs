@@ -0,0 +1,31 @@
+//! Handler for the "add rows" command.
+
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use shared::error::AppResult;
+use shared::protocol::types::{ErrorCode, ErrorResponse, Mutation, Response};
+
+/// Applies the rows another node streamed to this one, which updates the indexes of their
+/// tables as well.
+pub(crate) fn handle(
+    mutations: &[Mutation],
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    let schema = schema.lock().unwrap();
+    let mut rows = rows.lock().unwrap();
+    for mutation in mutations {
+        let Some(table) = schema.table(&mutation.keyspace, &mutation.table) else {
+            return Ok(Response::Error(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!(
+                    "unconfigured table {}.{}",
+                    mutation.keyspace, mutation.table
+                ),
+            )));
+        };
+        rows.apply(table, mutation);
+    }
+
+    Ok(Response::Void)
+}
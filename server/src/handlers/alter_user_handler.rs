@@ -0,0 +1,44 @@
+//! Handler for the "alter user" command.
+
+use crate::auth::GlobalAuthenticator;
+use crate::roles::{GlobalRoles, hash_password};
+use shared::error::AppResult;
+use shared::protocol::types::{AlterUser, ErrorCode, ErrorResponse, Response};
+
+/// Changes the password or superuser status of a user in the roles table.
+///
+/// The authorizer already made sure the logged in user is a superuser, who may not take away
+/// its own superuser status.
+pub(crate) fn handle(
+    alter_user: &AlterUser,
+    user: Option<&str>,
+    authenticator: &GlobalAuthenticator,
+    roles: &GlobalRoles,
+) -> AppResult<Response> {
+    if !authenticator.requires_authentication() {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!(
+                "ALTER USER is not supported by the {}",
+                authenticator.name()
+            ),
+        )));
+    }
+    if alter_user.superuser.is_some() && user == Some(alter_user.name.as_str()) {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Unauthorized,
+            "You aren't allowed to alter your own superuser status",
+        )));
+    }
+
+    let salted_hash = alter_user.password.as_deref().map(hash_password);
+    let result = roles
+        .lock()
+        .unwrap()
+        .alter(&alter_user.name, salted_hash, alter_user.superuser);
+
+    match result {
+        Ok(()) => Ok(Response::Bool(true)),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
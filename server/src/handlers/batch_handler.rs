@@ -0,0 +1,365 @@
+//! Handler for the "batch" message.
+
+use crate::coordinator::Coordinator;
+use crate::cql::executor::Executor;
+use crate::cql::parser;
+use crate::server::NodeState;
+use crate::tracing;
+use crate::warnings;
+use shared::error::AppResult;
+use shared::protocol::types::{
+    Batch, BatchStatement, BatchType, BatchlogEntry, CounterUpdate, Entry, ErrorCode,
+    ErrorResponse, Execute, Mutation, Response,
+};
+use uuid::Uuid;
+
+/// Size in bytes of the values of a batch above which its client is warned.
+const BATCH_SIZE_WARN_THRESHOLD: usize = 5 * 1024;
+
+/// Number of partitions an unlogged batch may span before its client is warned.
+const UNLOGGED_BATCH_PARTITIONS_WARN_THRESHOLD: usize = 10;
+
+/// A statement of a batch, once its values are bound.
+enum BoundStatement {
+    Add(Entry),
+    /// A write of a row of a table without counters.
+    Row(Mutation),
+    /// Increments of the counters of a row, with the replication factor of its keyspace.
+    Counter(CounterUpdate, usize),
+}
+
+/// Coordinates a batch, routing each mutation to the node owning its partition.
+///
+/// A logged batch spanning several partitions is written to the batchlog first and removed
+/// from it once applied, so it is completed by a batchlog replay if this node fails midway.
+/// A counter batch only holds CQL UPDATEs of counter tables, each led by a replica of its row;
+/// other batches hold ADDs and CQL INSERTs, UPDATEs and DELETEs of tables without counters.
+pub(crate) fn handle(
+    batch: &Batch,
+    keyspace: Option<&str>,
+    user: Option<&str>,
+    state: &NodeState,
+    coordinator: &mut Coordinator,
+) -> AppResult<Response> {
+    tracing::trace(|| format!("Binding {} batch statements", batch.statements.len()));
+    let statements = match bind_statements(batch, keyspace, user, state, coordinator) {
+        Ok(statements) => statements,
+        Err(error) => return Ok(Response::Error(error)),
+    };
+
+    if let Err(error) = check_statements(batch.batch_type, &statements) {
+        return Ok(Response::Error(error));
+    }
+
+    let mut mutations = Vec::new();
+    let mut row_mutations = Vec::new();
+    for statement in statements {
+        match statement {
+            BoundStatement::Add(entry) => mutations.push(entry),
+            BoundStatement::Row(mutation) => row_mutations.push(mutation),
+            BoundStatement::Counter(update, replication_factor) => {
+                if let Err(error) = coordinator.write_counter(&update, replication_factor) {
+                    return Ok(Response::Error(error));
+                }
+            }
+        }
+    }
+    if batch.batch_type == BatchType::Counter {
+        return Ok(Response::Void);
+    }
+
+    let partition_count = coordinator.partition_count(&mutations, &row_mutations);
+    warn_about_access_pattern(
+        batch.batch_type,
+        &mutations,
+        &row_mutations,
+        partition_count,
+    );
+
+    let is_logged = batch.batch_type == BatchType::Logged && partition_count > 1;
+
+    if is_logged {
+        let entry = BatchlogEntry {
+            id: Uuid::new_v4(),
+            mutations,
+            row_mutations,
+        };
+        let endpoints = match coordinator.store_batchlog(&entry) {
+            Ok(endpoints) => endpoints,
+            Err(error) => return Ok(Response::Error(error)),
+        };
+
+        if let Err(failure) = apply(coordinator, &entry.mutations, &entry.row_mutations) {
+            return Ok(Response::Error(ErrorResponse::new(
+                ErrorCode::WriteTimeout,
+                format!(
+                    "Batch {} {}; it will be replayed from the batchlog",
+                    entry.id, failure
+                ),
+            )));
+        }
+        coordinator.remove_batchlog(&endpoints, entry.id);
+
+        return Ok(Response::Void);
+    }
+
+    if let Err(failure) = apply(coordinator, &mutations, &row_mutations) {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::WriteTimeout,
+            format!("Batch {}", failure),
+        )));
+    }
+
+    Ok(Response::Void)
+}
+
+/// Applies the values and rows a batch writes, describing what failed if it wasn't applied
+/// completely.
+fn apply(
+    coordinator: &mut Coordinator,
+    mutations: &[Entry],
+    row_mutations: &[Mutation],
+) -> Result<(), String> {
+    let failed_nodes = coordinator.apply_mutations(mutations);
+    let written = coordinator.write_rows(row_mutations);
+    if !failed_nodes.is_empty() {
+        return Err(format!("was not applied on {:?}", failed_nodes));
+    }
+    written.map_err(|error| format!("was not applied: {}", error.message))
+}
+
+/// Warns the client about batches that are too big, or that span many partitions without
+/// the atomicity of a logged batch that would make it worth it.
+fn warn_about_access_pattern(
+    batch_type: BatchType,
+    mutations: &[Entry],
+    row_mutations: &[Mutation],
+    partition_count: usize,
+) {
+    let size: usize = mutations
+        .iter()
+        .map(|entry| entry.value.len())
+        .chain(row_mutations.iter().flat_map(|mutation| {
+            mutation
+                .cells
+                .iter()
+                .map(|(_, value)| value.to_string().len())
+        }))
+        .sum();
+    if size > BATCH_SIZE_WARN_THRESHOLD {
+        warnings::warn(format!(
+            "Batch for {} partitions is of size {} bytes, exceeding specified threshold of {} by {}",
+            partition_count,
+            size,
+            BATCH_SIZE_WARN_THRESHOLD,
+            size - BATCH_SIZE_WARN_THRESHOLD
+        ));
+    }
+
+    if batch_type == BatchType::Unlogged
+        && partition_count > UNLOGGED_BATCH_PARTITIONS_WARN_THRESHOLD
+    {
+        warnings::warn(format!(
+            "Unlogged batch covering {} partitions detected. You should use a logged batch for \
+             atomicity, or asynchronous writes for performance",
+            partition_count
+        ));
+    }
+}
+
+/// Binds every statement of the batch, which must all be ADDs or CQL modifications.
+fn bind_statements(
+    batch: &Batch,
+    keyspace: Option<&str>,
+    user: Option<&str>,
+    state: &NodeState,
+    coordinator: &mut Coordinator,
+) -> Result<Vec<BoundStatement>, ErrorResponse> {
+    let only_modifications = || {
+        ErrorResponse::new(
+            ErrorCode::Invalid,
+            "Only ADD, INSERT, UPDATE and DELETE statements are allowed inside a batch",
+        )
+    };
+
+    batch
+        .statements
+        .iter()
+        .map(|statement| {
+            let (statement, values) = match statement {
+                BatchStatement::Query(query, values) => (parser::parse(query)?, values),
+                BatchStatement::Prepared(id, values) => {
+                    let statement = state.prepared_cache.lock().unwrap().statement(&Execute {
+                        id: *id,
+                        values: values.clone(),
+                    })?;
+                    (statement, values)
+                }
+            };
+
+            let mut executor = Executor::new(state, coordinator, user, keyspace, values);
+            if let Some(entry) = executor.added_value(&statement)? {
+                return Ok(BoundStatement::Add(entry));
+            }
+            if let Some(mutation) = executor.row_mutation(&statement)? {
+                return Ok(BoundStatement::Row(mutation));
+            }
+            match executor.counter_update(&statement)? {
+                Some((update, replication_factor)) => {
+                    Ok(BoundStatement::Counter(update, replication_factor))
+                }
+                None => Err(only_modifications()),
+            }
+        })
+        .collect()
+}
+
+/// Checks that a counter batch only updates counters, and that other batches don't.
+fn check_statements(
+    batch_type: BatchType,
+    statements: &[BoundStatement],
+) -> Result<(), ErrorResponse> {
+    let is_counter = |statement: &BoundStatement| matches!(statement, BoundStatement::Counter(..));
+
+    if batch_type == BatchType::Counter && !statements.iter().all(is_counter) {
+        return Err(ErrorResponse::new(
+            ErrorCode::Invalid,
+            "Cannot include non-counter statement in a counter batch",
+        ));
+    }
+    if batch_type != BatchType::Counter && statements.iter().any(is_counter) {
+        return Err(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!(
+                "Cannot include a counter statement in a {} batch",
+                batch_type.to_string().to_lowercase()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coordinator::Coordinator;
+    use crate::cql::executor::Executor;
+    use crate::cql::parser;
+    use crate::handlers::batch_handler::{BoundStatement, check_statements, handle};
+    use crate::server::NodeState;
+    use shared::connection_pool::ConnectionPool;
+    use shared::protocol::types::{
+        Batch, BatchStatement, BatchType, CounterUpdate, CqlValue, Entry, ErrorCode, Response,
+    };
+    use std::sync::mpsc;
+
+    fn execute(state: &NodeState, coordinator: &mut Coordinator, query: &str) -> Response {
+        let statement = parser::parse(query).unwrap();
+        Executor::new(state, coordinator, None, None, &[])
+            .execute(&statement)
+            .unwrap()
+    }
+
+    fn statements() -> (BoundStatement, BoundStatement) {
+        let add = BoundStatement::Add(Entry {
+            value: "a".to_string(),
+            replication_factor: None,
+        });
+        let counter = BoundStatement::Counter(
+            CounterUpdate {
+                keyspace: "shop".to_string(),
+                table: "visits".to_string(),
+                partition_key: vec![CqlValue::Text("home".to_string())],
+                clustering_key: Vec::new(),
+                deltas: vec![("count".to_string(), 1)],
+            },
+            1,
+        );
+        (add, counter)
+    }
+
+    #[test]
+    fn test_counter_batches_only_take_counter_updates() {
+        let (add, counter) = statements();
+        assert!(check_statements(BatchType::Counter, &[counter]).is_ok());
+
+        let (_, counter) = statements();
+        let error = check_statements(BatchType::Counter, &[counter, add]).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Invalid);
+    }
+
+    #[test]
+    fn test_logged_and_unlogged_batches_reject_counter_updates() {
+        for batch_type in [BatchType::Logged, BatchType::Unlogged] {
+            let (add, counter) = statements();
+            assert!(check_statements(batch_type, &[add]).is_ok());
+
+            let (add, _) = statements();
+            let error = check_statements(batch_type, &[add, counter]).unwrap_err();
+            assert_eq!(error.code, ErrorCode::Invalid);
+        }
+    }
+
+    #[test]
+    fn test_logged_batch_writes_rows_of_different_partitions() {
+        let state = NodeState::single_node();
+        let (sender, _receiver) = mpsc::channel();
+        let mut coordinator = Coordinator::new(&state, sender, ConnectionPool::new());
+        execute(
+            &state,
+            &mut coordinator,
+            "CREATE KEYSPACE shop WITH replication = \
+             {'class': 'SimpleStrategy', 'replication_factor': 1}",
+        );
+        execute(
+            &state,
+            &mut coordinator,
+            "CREATE TABLE shop.orders (customer text, id int, total int, \
+             PRIMARY KEY (customer, id))",
+        );
+
+        let batch = Batch {
+            batch_type: BatchType::Logged,
+            statements: vec![
+                BatchStatement::Query(
+                    "INSERT INTO orders (customer, id, total) VALUES ('alice', 1, 10)".to_string(),
+                    Vec::new(),
+                ),
+                BatchStatement::Query(
+                    "INSERT INTO orders (customer, id, total) VALUES (?, ?, ?)".to_string(),
+                    vec![
+                        CqlValue::Text("bob".to_string()),
+                        CqlValue::Int(2),
+                        CqlValue::Int(20),
+                    ],
+                ),
+            ],
+        };
+        let response = handle(&batch, Some("shop"), None, &state, &mut coordinator).unwrap();
+        assert_eq!(response, Response::Void);
+
+        let Response::Rows(rows) = execute(
+            &state,
+            &mut coordinator,
+            "SELECT customer, id, total FROM shop.orders",
+        ) else {
+            panic!("Expected rows");
+        };
+        let mut rows = rows.rows;
+        rows.sort_by_key(|row| row[1].to_string());
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    CqlValue::Text("alice".to_string()),
+                    CqlValue::Int(1),
+                    CqlValue::Int(10),
+                ],
+                vec![
+                    CqlValue::Text("bob".to_string()),
+                    CqlValue::Int(2),
+                    CqlValue::Int(20),
+                ],
+            ]
+        );
+    }
+}
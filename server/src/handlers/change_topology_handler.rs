@@ -0,0 +1,70 @@
+//! Handler for the "change topology" command.
+
+use crate::coordinator::GlobalCluster;
+use crate::events::GlobalEventBus;
+use log::info;
+use shared::error::AppResult;
+use shared::protocol::types::{Event, Response, TopologyChange};
+
+/// Applies a node joining or leaving the cluster and notifies the registered clients.
+///
+/// Returns `false` if the node already knew about the change.
+pub(crate) fn handle(
+    change: &TopologyChange,
+    cluster: &GlobalCluster,
+    events: &GlobalEventBus,
+) -> AppResult<Response> {
+    let applied = cluster.write().unwrap().apply_topology_change(change);
+
+    if applied {
+        info!("Topology changed: {:?}", change);
+        events
+            .lock()
+            .unwrap()
+            .publish(Event::TopologyChange(change.clone()));
+    }
+
+    Ok(Response::Bool(applied))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::change_topology_handler::handle;
+    use crate::server::NodeState;
+    use shared::protocol::types::{Event, EventType, Response, TopologyChange, TopologyChangeType};
+
+    #[test]
+    fn test_registered_connections_are_told_about_topology_changes() {
+        let state = NodeState::single_node();
+        let topology_events = state
+            .events
+            .lock()
+            .unwrap()
+            .subscribe(&[EventType::TopologyChange]);
+        let schema_events = state
+            .events
+            .lock()
+            .unwrap()
+            .subscribe(&[EventType::SchemaChange]);
+        let change = TopologyChange {
+            change: TopologyChangeType::NewNode,
+            address: "localhost:5001".to_string(),
+            host_id: None,
+        };
+
+        let response = handle(&change, &state.cluster, &state.events).unwrap();
+
+        assert_eq!(response, Response::Bool(true));
+        assert_eq!(
+            topology_events.try_recv().unwrap(),
+            Event::TopologyChange(change.clone())
+        );
+        assert!(schema_events.try_recv().is_err());
+
+        // a change the node already knew about isn't announced again
+        let response = handle(&change, &state.cluster, &state.events).unwrap();
+
+        assert_eq!(response, Response::Bool(false));
+        assert!(topology_events.try_recv().is_err());
+    }
+}
@@ -1,17 +1,14 @@
 //! Handler for the "check" command.
 
 use crate::storage::{GlobalStorage, Storage};
+use crate::tracing;
 use shared::error::AppResult;
 use shared::protocol::types::Response;
 
-/// Checks if a value exists in the global storage.
+/// Checks if a value exists in the global storage, answering `false` if it doesn't.
 pub(crate) fn handle(value: &String, storage: &GlobalStorage) -> AppResult<Response> {
     let storage_guard = storage.lock().unwrap();
 
-    let exists = storage_guard.check(value);
-    if exists {
-        return Ok(Response::Bool(true));
-    }
-
-    Ok(Response::String(format!("Value: {} doesn't exist", value)))
+    tracing::trace(|| format!("Reading {} from storage", value));
+    Ok(Response::Bool(storage_guard.check(value)))
 }
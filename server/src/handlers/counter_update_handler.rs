@@ -0,0 +1,31 @@
+//! Handler for the "counter update" command.
+
+use crate::coordinator::Coordinator;
+use crate::schema::GlobalSchema;
+use shared::error::AppResult;
+use shared::protocol::types::{CounterUpdate, ErrorCode, ErrorResponse, Response};
+
+/// Leads an increment of counters of a partition this node is a replica of, sent by a
+/// coordinator that isn't one.
+pub(crate) fn handle(
+    update: &CounterUpdate,
+    schema: &GlobalSchema,
+    coordinator: &mut Coordinator,
+) -> AppResult<Response> {
+    let Some(replication_factor) = schema
+        .lock()
+        .unwrap()
+        .keyspace(&update.keyspace)
+        .map(|keyspace| keyspace.replication_factor)
+    else {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("Keyspace '{}' does not exist", update.keyspace),
+        )));
+    };
+
+    match coordinator.lead_counter_update(update, replication_factor) {
+        Ok(()) => Ok(Response::Void),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
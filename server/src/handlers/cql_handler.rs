@@ -0,0 +1,54 @@
+//! Handler for the "query" and "execute" messages.
+
+use crate::coordinator::Coordinator;
+use crate::cql::ast::CqlStatement;
+use crate::cql::executor::Executor;
+use crate::cql::parser;
+use crate::server::NodeState;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::{CqlValue, Query, Response};
+
+/// Parses and executes a statement, coordinating its reads and writes.
+///
+/// Unqualified table names refer to the keyspace of the query or else to the keyspace the
+/// connection set with USE.
+pub(crate) fn handle(
+    query: &Query,
+    keyspace: Option<&str>,
+    user: Option<&str>,
+    state: &NodeState,
+    coordinator: &mut Coordinator,
+) -> AppResult<Response> {
+    tracing::trace(|| format!("Parsing {}", query.query));
+    let statement = match parser::parse(&query.query) {
+        Ok(statement) => statement,
+        Err(error) => return Ok(Response::Error(error)),
+    };
+
+    let keyspace = query.keyspace.as_deref().or(keyspace);
+    execute(
+        &statement,
+        &query.values,
+        keyspace,
+        user,
+        state,
+        coordinator,
+    )
+}
+
+/// Executes a parsed or prepared statement with the values of its bind markers.
+pub(crate) fn execute(
+    statement: &CqlStatement,
+    values: &[CqlValue],
+    keyspace: Option<&str>,
+    user: Option<&str>,
+    state: &NodeState,
+    coordinator: &mut Coordinator,
+) -> AppResult<Response> {
+    let mut executor = Executor::new(state, coordinator, user, keyspace, values);
+    match executor.execute(statement) {
+        Ok(response) => Ok(response),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
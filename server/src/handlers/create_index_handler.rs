@@ -0,0 +1,43 @@
+//! Handler for the "create index" command.
+
+use crate::events::GlobalEventBus;
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use log::info;
+use shared::error::AppResult;
+use shared::protocol::types::{Event, IndexMetadata, Response, SchemaChange, SchemaChangeType};
+
+/// Adds a secondary index to the schema of this node, builds it from the rows this node
+/// stores and notifies the registered clients that its table was updated.
+///
+/// Returns `false` if an index of the same name already existed, and fails if its table
+/// doesn't exist.
+pub(crate) fn handle(
+    index: &IndexMetadata,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+    events: &GlobalEventBus,
+) -> AppResult<Response> {
+    let mut schema = schema.lock().unwrap();
+    let created = match schema.create_index(index.clone()) {
+        Ok(created) => created,
+        Err(error) => return Ok(Response::Error(error)),
+    };
+
+    if created {
+        if let Some(table) = schema.table(&index.keyspace, &index.table) {
+            rows.lock().unwrap().create_index(table, &index.column);
+        }
+        info!("Created index {:?}", index);
+        events
+            .lock()
+            .unwrap()
+            .publish(Event::SchemaChange(SchemaChange {
+                change: SchemaChangeType::Updated,
+                keyspace: index.keyspace.clone(),
+                table: Some(index.table.clone()),
+            }));
+    }
+
+    Ok(Response::Bool(created))
+}
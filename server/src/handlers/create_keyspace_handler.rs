@@ -0,0 +1,32 @@
+//! Handler for the "create keyspace" command.
+
+use crate::events::GlobalEventBus;
+use crate::schema::GlobalSchema;
+use log::info;
+use shared::error::AppResult;
+use shared::protocol::types::{Event, KeyspaceMetadata, Response, SchemaChange, SchemaChangeType};
+
+/// Adds a keyspace to the schema of this node and notifies the registered clients.
+///
+/// Returns `false` if the keyspace already existed.
+pub(crate) fn handle(
+    keyspace: &KeyspaceMetadata,
+    schema: &GlobalSchema,
+    events: &GlobalEventBus,
+) -> AppResult<Response> {
+    let created = schema.lock().unwrap().create_keyspace(keyspace.clone());
+
+    if created {
+        info!("Created keyspace {:?}", keyspace);
+        events
+            .lock()
+            .unwrap()
+            .publish(Event::SchemaChange(SchemaChange {
+                change: SchemaChangeType::Created,
+                keyspace: keyspace.name.clone(),
+                table: None,
+            }));
+    }
+
+    Ok(Response::Bool(created))
+}
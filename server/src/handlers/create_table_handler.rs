@@ -0,0 +1,35 @@
+//! Handler for the "create table" command.
+
+use crate::events::GlobalEventBus;
+use crate::schema::GlobalSchema;
+use log::info;
+use shared::error::AppResult;
+use shared::protocol::types::{Event, Response, SchemaChange, SchemaChangeType, TableMetadata};
+
+/// Adds a table to the schema of this node and notifies the registered clients.
+///
+/// Returns `false` if the table already existed, and fails if its keyspace doesn't exist.
+pub(crate) fn handle(
+    table: &TableMetadata,
+    schema: &GlobalSchema,
+    events: &GlobalEventBus,
+) -> AppResult<Response> {
+    let created = match schema.lock().unwrap().create_table(table.clone()) {
+        Ok(created) => created,
+        Err(error) => return Ok(Response::Error(error)),
+    };
+
+    if created {
+        info!("Created table {:?}", table);
+        events
+            .lock()
+            .unwrap()
+            .publish(Event::SchemaChange(SchemaChange {
+                change: SchemaChangeType::Created,
+                keyspace: table.keyspace.clone(),
+                table: Some(table.name.clone()),
+            }));
+    }
+
+    Ok(Response::Bool(created))
+}
@@ -0,0 +1,37 @@
+//! Handler for the "create user" command.
+
+use crate::auth::GlobalAuthenticator;
+use crate::roles::{GlobalRoles, hash_password};
+use shared::error::AppResult;
+use shared::protocol::types::{CreateUser, ErrorCode, ErrorResponse, Response};
+
+/// Adds a user to the roles table.
+///
+/// The authorizer already made sure the logged in user is a superuser.
+pub(crate) fn handle(
+    create_user: &CreateUser,
+    authenticator: &GlobalAuthenticator,
+    roles: &GlobalRoles,
+) -> AppResult<Response> {
+    if !authenticator.requires_authentication() {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!(
+                "CREATE USER is not supported by the {}",
+                authenticator.name()
+            ),
+        )));
+    }
+
+    let salted_hash = hash_password(&create_user.password);
+    let result =
+        roles
+            .lock()
+            .unwrap()
+            .create(&create_user.name, salted_hash, create_user.superuser);
+
+    match result {
+        Ok(()) => Ok(Response::Bool(true)),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
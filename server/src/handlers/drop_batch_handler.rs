@@ -1,27 +1,26 @@
 //! Handler for the "drop batch" command.
 
 use crate::storage::{GlobalStorage, Storage};
-use shared::consistent_hash_ring::Range;
 use shared::error::AppResult;
-use shared::protocol::types::Response;
+use shared::protocol::types::{Response, ValueCount};
+use shared::token_range::TokenRange;
 
-/// Drops values by the provided range of keys.
-pub(crate) fn handle(ranges: &[Range], storage: &GlobalStorage) -> AppResult<Response> {
+/// Drops values by the provided range of keys, answering with the number of values removed
+/// from each range.
+pub(crate) fn handle(ranges: &[TokenRange], storage: &GlobalStorage) -> AppResult<Response> {
     let mut storage_guard = storage.lock().unwrap();
 
-    let mut count = 0;
-    let mut keys = Vec::new();
+    let mut removed = ValueCount::default();
     for range in ranges {
-        let range_values = storage_guard.get_keys_in_range(range.start, range.end);
-        keys.extend(range_values);
-    }
-
-    for key in keys {
-        let result = storage_guard.remove(key);
-        if result {
-            count += 1;
+        let mut count = 0;
+        for key in storage_guard.get_keys_in_range(range) {
+            if storage_guard.remove(key) {
+                count += 1;
+            }
         }
+        removed.total += count;
+        removed.ranges.push(count);
     }
 
-    Ok(Response::String(format!("Removed: {}", count)))
+    Ok(Response::Count(removed))
 }
@@ -0,0 +1,20 @@
+//! Handler for the "drop rows" command.
+
+use crate::storage::GlobalRowStorage;
+use log::info;
+use shared::error::AppResult;
+use shared::protocol::types::{Response, RowRanges};
+
+/// Removes the rows of a keyspace in some ranges, which other nodes took over.
+pub(crate) fn handle(row_ranges: &RowRanges, rows: &GlobalRowStorage) -> AppResult<Response> {
+    let removed = rows
+        .lock()
+        .unwrap()
+        .drop_ranges(&row_ranges.keyspace, &row_ranges.ranges);
+    info!(
+        "Dropped {} partitions of keyspace {} moved to other nodes",
+        removed, row_ranges.keyspace
+    );
+
+    Ok(Response::Void)
+}
@@ -0,0 +1,39 @@
+//! Handler for the "drop user" command.
+
+use crate::auth::GlobalAuthenticator;
+use crate::roles::GlobalRoles;
+use shared::error::AppResult;
+use shared::protocol::types::{DropUser, ErrorCode, ErrorResponse, Response};
+
+/// Removes a user from the roles table, the default superuser included.
+///
+/// The authorizer already made sure the logged in user is a superuser, which can't drop
+/// itself, so that a superuser is always left.
+pub(crate) fn handle(
+    drop_user: &DropUser,
+    user: Option<&str>,
+    authenticator: &GlobalAuthenticator,
+    roles: &GlobalRoles,
+) -> AppResult<Response> {
+    if !authenticator.requires_authentication() {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("DROP USER is not supported by the {}", authenticator.name()),
+        )));
+    }
+    if user == Some(drop_user.name.as_str()) {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            "Cannot DROP primary role for current login",
+        )));
+    }
+
+    let removed = roles.lock().unwrap().remove(&drop_user.name);
+    if !removed && !drop_user.if_exists {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("{} doesn't exist", drop_user.name),
+        )));
+    }
+    Ok(Response::Bool(true))
+}
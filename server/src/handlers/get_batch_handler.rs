@@ -1,21 +1,90 @@
 //! Handler for the "get batch" command.
 
 use crate::storage::{GlobalStorage, Storage};
-use shared::consistent_hash_ring::Range;
+use crate::tracing;
+use crate::warnings;
 use shared::error::AppResult;
-use shared::protocol::types::Response;
+use shared::protocol::types::{ErrorCode, ErrorResponse, GetBatch, Page, PagingState, Response};
 
-/// Gets a batch of values by the provided range of keys.
-pub(crate) fn handle(ranges: &[Range], storage: &GlobalStorage) -> AppResult<Response> {
+/// Number of values a single request may read before its client is warned.
+const GET_BATCH_WARN_THRESHOLD: usize = 1000;
+
+/// Gets a batch of values by the provided range of keys, a page at a time if the request
+/// has a page size.
+///
+/// The paging state holds the index of the range the page ended in and the key of its last
+/// value, so the next page starts right after it even if values were added in the meantime.
+pub(crate) fn handle(get_batch: &GetBatch, storage: &GlobalStorage) -> AppResult<Response> {
+    let (first_range, mut after) = match &get_batch.paging_state {
+        Some(paging_state) => match decode_paging_state(paging_state) {
+            Some((range, after)) => (range, Some(after)),
+            None => {
+                return Ok(Response::Error(ErrorResponse::new(
+                    ErrorCode::ProtocolError,
+                    "Invalid paging state",
+                )));
+            }
+        },
+        None => (0, None),
+    };
+    if get_batch.page_size == Some(0) {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            "The page size must be positive",
+        )));
+    }
+    let page_size = get_batch.page_size.unwrap_or(usize::MAX);
     let storage_guard = storage.lock().unwrap();
 
     let mut values = Vec::new();
-    for range in ranges {
-        let range_values = storage_guard.get_values(range.start, range.end);
-        values.extend(range_values);
+    let mut paging_state = None;
+    for (index, range) in get_batch.ranges.iter().enumerate().skip(first_range) {
+        let page = storage_guard.get_page(range, after.take(), page_size - values.len());
+        let last_key = page.last().map(|(key, _)| *key);
+        values.extend(page.into_iter().map(|(_, value)| value.clone()));
+
+        if values.len() == page_size
+            && let Some(last_key) = last_key
+        {
+            paging_state = Some(encode_paging_state(index, last_key));
+            break;
+        }
+    }
+
+    tracing::trace(|| {
+        format!(
+            "Read {} values in {} ranges from storage",
+            values.len(),
+            get_batch.ranges.len()
+        )
+    });
+    if values.len() > GET_BATCH_WARN_THRESHOLD {
+        warnings::warn(format!(
+            "Read {} values in {} ranges, exceeding the warning threshold of {}; \
+             page the read or transfer smaller ranges",
+            values.len(),
+            get_batch.ranges.len(),
+            GET_BATCH_WARN_THRESHOLD
+        ));
     }
 
-    let response_array = values.iter().map(|val| val.as_str().to_string()).collect();
+    Ok(Response::Page(Page {
+        values,
+        paging_state,
+    }))
+}
+
+fn encode_paging_state(range: usize, after: u64) -> PagingState {
+    let mut paging_state = (range as u64).to_be_bytes().to_vec();
+    paging_state.extend(after.to_be_bytes());
+    paging_state
+}
 
-    Ok(Response::Array(response_array))
+fn decode_paging_state(paging_state: &[u8]) -> Option<(usize, u64)> {
+    let (range, after) = paging_state.split_first_chunk::<8>()?;
+    let after: [u8; 8] = after.try_into().ok()?;
+    Some((
+        u64::from_be_bytes(*range) as usize,
+        u64::from_be_bytes(after),
+    ))
 }
@@ -1,12 +1,22 @@
 //! Handler for the "get count" command.
 
 use crate::storage::{GlobalStorage, Storage};
+use crate::tracing;
 use shared::error::AppResult;
-use shared::protocol::types::Response;
+use shared::protocol::types::{Response, ValueCount};
+use shared::token_range::TokenRange;
 
-/// Gets the count of values in storage.
-pub(crate) fn handle(storage: &GlobalStorage) -> AppResult<Response> {
+/// Gets the count of values in storage, and of those in each of the given ranges.
+pub(crate) fn handle(ranges: &[TokenRange], storage: &GlobalStorage) -> AppResult<Response> {
     let storage_guard = storage.lock().unwrap();
-    let count = storage_guard.get_count();
-    Ok(Response::String(format!("Count: {}", count)))
+    tracing::trace(|| "Counting values in storage".to_string());
+
+    Ok(Response::Count(ValueCount {
+        total: storage_guard.get_count() as u64,
+        ranges: ranges
+            .iter()
+            .map(|range| storage_guard.get_count_in_range(range) as u64)
+            .collect(),
+        nodes: Vec::new(),
+    }))
 }
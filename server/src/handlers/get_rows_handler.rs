@@ -0,0 +1,29 @@
+//! Handler for the "get rows" command.
+
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use shared::error::AppResult;
+use shared::protocol::types::{Response, RowRanges};
+
+/// Reads the rows of every table of a keyspace stored on this node in some ranges, for another
+/// node to take them over.
+pub(crate) fn handle(
+    row_ranges: &RowRanges,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    let tables: Vec<_> = schema
+        .lock()
+        .unwrap()
+        .tables_of(&row_ranges.keyspace)
+        .into_iter()
+        .cloned()
+        .collect();
+
+    let rows = rows.lock().unwrap();
+    let mutations = tables
+        .iter()
+        .flat_map(|table| rows.read_ranges(table, &row_ranges.ranges))
+        .collect();
+    Ok(Response::Mutations(mutations))
+}
@@ -0,0 +1,13 @@
+//! Handler for the "get trace" command.
+
+use crate::tracing::GlobalTraces;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+use uuid::Uuid;
+
+/// Gets the events this node recorded for a tracing session.
+pub(crate) fn handle(session_id: &Uuid, traces: &GlobalTraces) -> AppResult<Response> {
+    let traces_guard = traces.lock().unwrap();
+
+    Ok(Response::Trace(traces_guard.events(session_id)))
+}
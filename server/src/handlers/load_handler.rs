@@ -0,0 +1,60 @@
+//! Handler for the "load" command.
+
+use crate::storage::{GlobalRowStorage, GlobalStorage, Storage};
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+
+/// Returns the number of bytes of the values and rows this node stores.
+pub(crate) fn handle(storage: &GlobalStorage, rows: &GlobalRowStorage) -> AppResult<Response> {
+    let values = storage.lock().unwrap().get_size();
+    let rows = rows.lock().unwrap().size();
+
+    Ok(Response::Load((values + rows) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coordinator::Coordinator;
+    use crate::cql::executor::Executor;
+    use crate::cql::parser;
+    use crate::handlers::load_handler::handle;
+    use crate::server::NodeState;
+    use crate::storage::Storage;
+    use shared::connection_pool::ConnectionPool;
+    use shared::protocol::types::Response;
+    use std::sync::mpsc;
+
+    fn load(state: &NodeState) -> u64 {
+        match handle(&state.storage, &state.rows).unwrap() {
+            Response::Load(load) => load,
+            response => panic!("Unexpected response {:?}", response),
+        }
+    }
+
+    #[test]
+    fn test_load_counts_values_and_rows() {
+        let state = NodeState::single_node();
+        let (sender, _receiver) = mpsc::channel();
+        let mut coordinator = Coordinator::new(&state, sender, ConnectionPool::new());
+
+        assert_eq!(load(&state), 0);
+
+        state.storage.lock().unwrap().add("apple".to_string());
+        state.storage.lock().unwrap().add("kiwi".to_string());
+
+        assert_eq!(load(&state), 9);
+
+        for query in [
+            "CREATE KEYSPACE shop WITH replication = \
+             {'class': 'SimpleStrategy', 'replication_factor': 1}",
+            "CREATE TABLE shop.users (name text PRIMARY KEY, age int)",
+            "INSERT INTO shop.users (name, age) VALUES ('alice', 30)",
+        ] {
+            Executor::new(&state, &mut coordinator, None, None, &[])
+                .execute(&parser::parse(query).unwrap())
+                .unwrap();
+        }
+
+        assert!(load(&state) > 9);
+    }
+}
@@ -4,7 +4,39 @@
 
 pub(crate) mod add_batch_handler;
 pub(crate) mod add_handler;
+pub(crate) mod add_rows_handler;
+pub(crate) mod alter_user_handler;
+pub(crate) mod batch_handler;
+pub(crate) mod change_topology_handler;
 pub(crate) mod check_handler;
+pub(crate) mod counter_update_handler;
+pub(crate) mod cql_handler;
+pub(crate) mod create_index_handler;
+pub(crate) mod create_keyspace_handler;
+pub(crate) mod create_table_handler;
+pub(crate) mod create_user_handler;
 pub(crate) mod drop_batch_handler;
+pub(crate) mod drop_rows_handler;
+pub(crate) mod drop_user_handler;
 pub(crate) mod get_batch_handler;
 pub(crate) mod get_count;
+pub(crate) mod get_rows_handler;
+pub(crate) mod get_trace_handler;
+pub(crate) mod grant_handler;
+pub(crate) mod load_handler;
+pub(crate) mod mutate_handler;
+pub(crate) mod options_handler;
+pub(crate) mod paxos_commit_handler;
+pub(crate) mod paxos_prepare_handler;
+pub(crate) mod paxos_propose_handler;
+pub(crate) mod prepare_handler;
+pub(crate) mod pull_schema_handler;
+pub(crate) mod read_handler;
+pub(crate) mod read_mutations_handler;
+pub(crate) mod register_handler;
+pub(crate) mod remove_batchlog_handler;
+pub(crate) mod revoke_handler;
+pub(crate) mod schema_version_handler;
+pub(crate) mod startup_handler;
+pub(crate) mod store_batchlog_handler;
+pub(crate) mod tokens_handler;
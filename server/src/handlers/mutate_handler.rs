@@ -0,0 +1,39 @@
+//! Handler for the "mutate" command.
+
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::{ErrorCode, ErrorResponse, Mutation, Response};
+
+/// Applies a write to a partition this node is a replica of.
+pub(crate) fn handle(
+    mutation: &Mutation,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    let Some(table) = schema
+        .lock()
+        .unwrap()
+        .table(&mutation.keyspace, &mutation.table)
+        .cloned()
+    else {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!(
+                "unconfigured table {}.{}",
+                mutation.keyspace, mutation.table
+            ),
+        )));
+    };
+
+    tracing::trace(|| {
+        format!(
+            "Applying mutation of {}.{} to storage",
+            mutation.keyspace, mutation.table
+        )
+    });
+    rows.lock().unwrap().apply(&table, mutation);
+
+    Ok(Response::Void)
+}
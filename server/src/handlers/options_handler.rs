@@ -0,0 +1,18 @@
+//! Handler for the "options" message.
+
+use shared::error::AppResult;
+use shared::protocol::types::{ProtocolVersion, Response};
+use std::collections::HashMap;
+
+/// Lists the startup options supported by the server.
+pub(crate) fn handle() -> AppResult<Response> {
+    let protocol_versions = ProtocolVersion::SUPPORTED
+        .iter()
+        .map(|version| version.to_string())
+        .collect();
+
+    Ok(Response::Supported(HashMap::from([
+        ("CQL_VERSION".to_string(), vec!["3.0.0".to_string()]),
+        ("PROTOCOL_VERSIONS".to_string(), protocol_versions),
+    ])))
+}
@@ -0,0 +1,22 @@
+//! Handler for the "paxos commit" command.
+
+use crate::handlers::mutate_handler;
+use crate::paxos::GlobalPaxos;
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use shared::error::AppResult;
+use shared::protocol::types::{Proposal, Response};
+
+/// Applies the proposal of a decided round of a lightweight transaction.
+///
+/// Committing a proposal more than once is harmless, since its mutation is written at the
+/// time of its ballot.
+pub(crate) fn handle(
+    proposal: &Proposal,
+    paxos: &GlobalPaxos,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    paxos.lock().unwrap().commit(proposal);
+    mutate_handler::handle(&proposal.mutation, schema, rows)
+}
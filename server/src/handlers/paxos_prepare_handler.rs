@@ -0,0 +1,20 @@
+//! Handler for the "paxos prepare" command.
+
+use crate::paxos::GlobalPaxos;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::{PaxosPrepare, Response};
+
+/// Promises to take part in a round of a lightweight transaction on a partition this node is
+/// a replica of, unless it promised a higher ballot before.
+pub(crate) fn handle(prepare: &PaxosPrepare, paxos: &GlobalPaxos) -> AppResult<Response> {
+    let promise = paxos.lock().unwrap().prepare(prepare);
+    tracing::trace(|| {
+        format!(
+            "Paxos prepare of {}.{}: promised {}",
+            prepare.keyspace, prepare.table, promise.promised
+        )
+    });
+
+    Ok(Response::Promise(Box::new(promise)))
+}
@@ -0,0 +1,20 @@
+//! Handler for the "paxos propose" command.
+
+use crate::paxos::GlobalPaxos;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::{Proposal, Response};
+
+/// Accepts the proposal of a round of a lightweight transaction, unless this node promised a
+/// higher ballot since.
+pub(crate) fn handle(proposal: &Proposal, paxos: &GlobalPaxos) -> AppResult<Response> {
+    let accepted = paxos.lock().unwrap().propose(proposal);
+    tracing::trace(|| {
+        format!(
+            "Paxos propose of {}.{}: accepted {}",
+            proposal.mutation.keyspace, proposal.mutation.table, accepted
+        )
+    });
+
+    Ok(Response::Bool(accepted))
+}
@@ -0,0 +1,25 @@
+//! Handler for the "prepare" message.
+
+use crate::prepared_cache::GlobalPreparedCache;
+use crate::schema::GlobalSchema;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+
+/// Prepares a statement and returns its id together with bind and result metadata.
+///
+/// Unqualified table names refer to `keyspace`, the keyspace of the connection.
+pub(crate) fn handle(
+    query: &str,
+    keyspace: Option<&str>,
+    schema: &GlobalSchema,
+    prepared_cache: &GlobalPreparedCache,
+) -> AppResult<Response> {
+    tracing::trace(|| format!("Parsing {}", query));
+    let mut cache_guard = prepared_cache.lock().unwrap();
+
+    match cache_guard.prepare(query, keyspace, &schema.lock().unwrap()) {
+        Ok(metadata) => Ok(Response::Prepared(metadata)),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
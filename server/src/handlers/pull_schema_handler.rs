@@ -0,0 +1,11 @@
+//! Handler for the "pull schema" command.
+
+use crate::schema::GlobalSchema;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+
+/// Returns the keyspaces and tables of this node, for a node with another schema version to
+/// add the ones it misses.
+pub(crate) fn handle(schema: &GlobalSchema) -> AppResult<Response> {
+    Ok(Response::Schema(schema.lock().unwrap().definition()))
+}
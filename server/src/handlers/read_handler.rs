@@ -0,0 +1,130 @@
+//! Handler for the "read" command.
+
+use crate::schema::GlobalSchema;
+use crate::storage::{GlobalRowStorage, StoredRow};
+use crate::tracing;
+use crate::warnings;
+use shared::error::AppResult;
+use shared::protocol::types::{
+    CqlValue, ErrorCode, ErrorResponse, ReadCommand, Response, Rows, TableMetadata,
+};
+
+/// Number of tombstones a read may scan before its client is warned.
+const TOMBSTONE_WARN_THRESHOLD: usize = 1000;
+
+/// Reads the rows of a table stored on this node, with every column of the table in
+/// declaration order.
+///
+/// Rows are in token order, and in clustering order within a partition unless the slice is
+/// reversed. Reads with an index expression fail if this node doesn't know the index yet.
+/// Reads scanning many tombstones, left by deletions, warn their client.
+pub(crate) fn handle(
+    command: &ReadCommand,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    let schema = schema.lock().unwrap();
+    let Some(table) = schema.table(&command.keyspace, &command.table).cloned() else {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("unconfigured table {}.{}", command.keyspace, command.table),
+        )));
+    };
+    if let Some(expression) = &command.index
+        && schema
+            .index_on(&command.keyspace, &command.table, &expression.column)
+            .is_none()
+    {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("No secondary index on column {}", expression.column),
+        )));
+    }
+    drop(schema);
+
+    let (stored_rows, tombstones) = rows.lock().unwrap().read(&table, command);
+    tracing::trace(|| {
+        format!(
+            "Read {} live rows and {} tombstones of {}.{} from storage",
+            stored_rows.len(),
+            tombstones,
+            command.keyspace,
+            command.table
+        )
+    });
+    warn_about_tombstones(command, stored_rows.len(), tombstones);
+
+    let rows = stored_rows
+        .into_iter()
+        .map(|row| column_values(&table, row))
+        .collect();
+
+    Ok(Response::Rows(Rows {
+        columns: table.columns,
+        rows,
+    }))
+}
+
+/// Returns the values of a row for every column of its table, in declaration order.
+pub(crate) fn column_values(table: &TableMetadata, mut row: StoredRow) -> Vec<CqlValue> {
+    table
+        .columns
+        .iter()
+        .map(|column| {
+            let partition_key_index = table
+                .partition_key
+                .iter()
+                .position(|key| *key == column.name);
+            let clustering_key_index = table
+                .clustering_key
+                .iter()
+                .position(|key| *key == column.name);
+            match (partition_key_index, clustering_key_index) {
+                (Some(index), _) => row.partition_key[index].clone(),
+                (_, Some(index)) => row.clustering_key[index].clone(),
+                _ => row.cells.remove(&column.name).unwrap_or(CqlValue::Null),
+            }
+        })
+        .collect()
+}
+
+/// Warns the client about a read that scanned more tombstones than the threshold, which slows
+/// reads down and usually comes from using a table as a queue.
+fn warn_about_tombstones(command: &ReadCommand, live_rows: usize, tombstones: usize) {
+    if tombstones > TOMBSTONE_WARN_THRESHOLD {
+        warnings::warn(format!(
+            "Read {} live rows and {} tombstone cells from {}.{}, exceeding specified threshold \
+             of {}",
+            live_rows, tombstones, command.keyspace, command.table, TOMBSTONE_WARN_THRESHOLD
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::read_handler::{TOMBSTONE_WARN_THRESHOLD, warn_about_tombstones};
+    use crate::warnings;
+    use shared::protocol::types::{ClusteringSlice, ReadCommand};
+
+    #[test]
+    fn test_reads_scanning_many_tombstones_warn() {
+        let command = ReadCommand {
+            keyspace: "shop".to_string(),
+            table: "users".to_string(),
+            partition_key: None,
+            slice: ClusteringSlice::default(),
+            limit: None,
+            index: None,
+        };
+
+        warnings::begin();
+        warn_about_tombstones(&command, 10, TOMBSTONE_WARN_THRESHOLD);
+        assert!(warnings::take().is_empty());
+
+        warnings::begin();
+        warn_about_tombstones(&command, 10, TOMBSTONE_WARN_THRESHOLD + 1);
+        let warnings = warnings::take();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Read 10 live rows and 1001 tombstone cells"));
+    }
+}
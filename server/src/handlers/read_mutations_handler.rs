@@ -0,0 +1,38 @@
+//! Handler for the "read mutations" command.
+
+use crate::schema::GlobalSchema;
+use crate::storage::GlobalRowStorage;
+use crate::tracing;
+use shared::error::AppResult;
+use shared::protocol::types::{ErrorCode, ErrorResponse, ReadCommand, Response};
+
+/// Reads the rows of a table stored on this node as the mutations writing them, deletions
+/// included, for a coordinator to reconcile the copies of several replicas by timestamp.
+pub(crate) fn handle(
+    command: &ReadCommand,
+    schema: &GlobalSchema,
+    rows: &GlobalRowStorage,
+) -> AppResult<Response> {
+    let Some(table) = schema
+        .lock()
+        .unwrap()
+        .table(&command.keyspace, &command.table)
+        .cloned()
+    else {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("unconfigured table {}.{}", command.keyspace, command.table),
+        )));
+    };
+
+    let mutations = rows.lock().unwrap().read_mutations(&table, command);
+    tracing::trace(|| {
+        format!(
+            "Read {} mutations of {}.{} from storage",
+            mutations.len(),
+            command.keyspace,
+            command.table
+        )
+    });
+    Ok(Response::Mutations(mutations))
+}
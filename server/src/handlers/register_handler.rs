@@ -0,0 +1,19 @@
+//! Handler for the "register" message.
+
+use shared::error::AppResult;
+use shared::protocol::types::{ErrorCode, ErrorResponse, EventType, Response};
+
+/// Accepts a registration for events.
+///
+/// The subscription itself is tied to the connection, so it is set up by the server once the
+/// READY answer was sent.
+pub(crate) fn handle(event_types: &[EventType]) -> AppResult<Response> {
+    if event_types.is_empty() {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::ProtocolError,
+            "REGISTER requires at least one event type",
+        )));
+    }
+
+    Ok(Response::Ready)
+}
@@ -0,0 +1,13 @@
+//! Handler for the "remove batchlog" command.
+
+use crate::batchlog::GlobalBatchlog;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+use uuid::Uuid;
+
+/// Removes a batch whose mutations were all applied by its coordinator.
+pub(crate) fn handle(id: &Uuid, batchlog: &GlobalBatchlog) -> AppResult<Response> {
+    let mut batchlog_guard = batchlog.lock().unwrap();
+
+    Ok(Response::Bool(batchlog_guard.remove(id)))
+}
@@ -0,0 +1,32 @@
+//! Handler for the "revoke" command.
+
+use crate::auth::GlobalAuthenticator;
+use crate::roles::GlobalRoles;
+use shared::error::AppResult;
+use shared::protocol::types::{ErrorCode, ErrorResponse, PermissionChange, Response};
+
+/// Revokes permissions on a resource from a role.
+///
+/// The authorizer already made sure the logged in user holds AUTHORIZE on the resource.
+pub(crate) fn handle(
+    change: &PermissionChange,
+    authenticator: &GlobalAuthenticator,
+    roles: &GlobalRoles,
+) -> AppResult<Response> {
+    if !authenticator.requires_authentication() {
+        return Ok(Response::Error(ErrorResponse::new(
+            ErrorCode::Invalid,
+            format!("REVOKE is not supported by the {}", authenticator.name()),
+        )));
+    }
+
+    let result = roles
+        .lock()
+        .unwrap()
+        .revoke(&change.role, &change.permissions, &change.resource);
+
+    match result {
+        Ok(()) => Ok(Response::Bool(true)),
+        Err(error) => Ok(Response::Error(error)),
+    }
+}
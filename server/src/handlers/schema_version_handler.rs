@@ -0,0 +1,10 @@
+//! Handler for the "schema version" command.
+
+use crate::schema::GlobalSchema;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+
+/// Returns the version of this node's schema, for other nodes to compare with theirs.
+pub(crate) fn handle(schema: &GlobalSchema) -> AppResult<Response> {
+    Ok(Response::SchemaVersion(schema.lock().unwrap().version()))
+}
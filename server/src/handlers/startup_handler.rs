@@ -0,0 +1,22 @@
+//! Handler for the "startup" message.
+
+use crate::auth::GlobalAuthenticator;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+use std::collections::HashMap;
+
+/// Accepts the startup options of a new connection.
+///
+/// If the authenticator requires it, the client is asked to authenticate before it can send
+/// queries. Switching to checksummed framing for protocol v5 happens in the server once the
+/// answer is sent.
+pub(crate) fn handle(
+    _options: &HashMap<String, String>,
+    authenticator: &GlobalAuthenticator,
+) -> AppResult<Response> {
+    if authenticator.requires_authentication() {
+        return Ok(Response::Authenticate(authenticator.name().to_string()));
+    }
+
+    Ok(Response::Ready)
+}
@@ -0,0 +1,14 @@
+//! Handler for the "store batchlog" command.
+
+use crate::batchlog::GlobalBatchlog;
+use shared::error::AppResult;
+use shared::protocol::types::{BatchlogEntry, Response};
+
+/// Stores a logged batch on behalf of its coordinator.
+pub(crate) fn handle(entry: &BatchlogEntry, batchlog: &GlobalBatchlog) -> AppResult<Response> {
+    let mut batchlog_guard = batchlog.lock().unwrap();
+
+    batchlog_guard.store(entry.clone());
+
+    Ok(Response::Bool(true))
+}
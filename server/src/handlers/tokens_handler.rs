@@ -0,0 +1,10 @@
+//! Handler for the "tokens" command.
+
+use crate::coordinator::GlobalCluster;
+use shared::error::AppResult;
+use shared::protocol::types::Response;
+
+/// Returns the tokens of every node this node knows, its own included.
+pub(crate) fn handle(cluster: &GlobalCluster) -> AppResult<Response> {
+    Ok(Response::Tokens(cluster.read().unwrap().node_tokens()))
+}
@@ -0,0 +1,98 @@
+//! Requests a node sends other nodes on behalf of a client's request.
+//!
+//! They carry the tracing session and the custom payload of the request handled on the current
+//! thread, so the other nodes trace them in the same session and hand the same payload to their
+//! query handler, and the warnings of the other nodes are passed on to the client. Work moved to another thread, like replication, takes the `RequestContext`
+//! along.
+
+use crate::tracing;
+use crate::tracing::TraceState;
+use crate::warnings;
+use shared::cluster::Node;
+use shared::connection_pool::ConnectionPool;
+use shared::error::AppResult;
+use shared::protocol::frame::{RequestExtras, Tracing};
+use shared::protocol::types::{CustomPayload, Request, Response};
+use std::cell::RefCell;
+
+thread_local! {
+    static CUSTOM_PAYLOAD: RefCell<CustomPayload> = RefCell::new(CustomPayload::new());
+}
+
+/// The tracing session and custom payload of a client's request.
+#[derive(Clone, Default)]
+pub(crate) struct RequestContext {
+    trace_state: Option<TraceState>,
+    custom_payload: CustomPayload,
+}
+
+impl RequestContext {
+    pub(crate) fn new(trace_state: Option<TraceState>, custom_payload: CustomPayload) -> Self {
+        Self {
+            trace_state,
+            custom_payload,
+        }
+    }
+
+    /// Returns the context of the request handled on the current thread.
+    pub(crate) fn current() -> Self {
+        Self {
+            trace_state: tracing::current(),
+            custom_payload: CUSTOM_PAYLOAD.with(|payload| payload.borrow().clone()),
+        }
+    }
+
+    /// Continues the request on the current thread, until `exit` is called.
+    pub(crate) fn enter(self) {
+        if let Some(trace_state) = self.trace_state {
+            tracing::begin(trace_state);
+        }
+        CUSTOM_PAYLOAD.with(|payload| *payload.borrow_mut() = self.custom_payload);
+    }
+}
+
+/// Stops continuing a request on the current thread.
+pub(crate) fn exit() {
+    tracing::end();
+    CUSTOM_PAYLOAD.with(|payload| payload.borrow_mut().clear());
+}
+
+/// Sends a request to another node with the tracing session and custom payload of the request
+/// handled on the current thread, passing on the warnings of its response.
+pub(crate) fn execute(
+    connection_pool: &mut ConnectionPool,
+    node: &Node,
+    request: Request,
+) -> AppResult<Response> {
+    let extras = RequestExtras {
+        tracing: tracing::session_id().map_or(Tracing::Disabled, Tracing::Session),
+        custom_payload: CUSTOM_PAYLOAD.with(|payload| payload.borrow().clone()),
+    };
+
+    connection_pool
+        .execute_with_extras(node, request, extras)
+        .map(|(response, extras)| {
+            extras.warnings.into_iter().for_each(warnings::forward);
+            response
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::internode;
+    use crate::internode::{CUSTOM_PAYLOAD, RequestContext};
+    use shared::protocol::types::CustomPayload;
+
+    #[test]
+    fn test_context_is_current_until_exit() {
+        let custom_payload = CustomPayload::from([("tenant".to_string(), b"acme".to_vec())]);
+
+        RequestContext::new(None, custom_payload.clone()).enter();
+        let current = RequestContext::current();
+        internode::exit();
+
+        assert_eq!(current.custom_payload, custom_payload);
+        assert!(current.trace_state.is_none());
+        assert!(CUSTOM_PAYLOAD.with(|payload| payload.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,50 @@
+//! What a node remembers about itself across restarts.
+//!
+//! A node generates its host id and picks its tokens when it first starts, and saves them to
+//! its data directory. After a restart it keeps owning the same ranges of the ring, even if it
+//! was given another address or other token settings: the other nodes recognize it by its
+//! host id.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct LocalNode {
+    /// The host id of this node, `None` until it was generated.
+    pub(crate) host_id: Option<Uuid>,
+    /// The tokens of this node, empty until it picked them.
+    pub(crate) tokens: Vec<u64>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl LocalNode {
+    /// Loads what this node saved to a file, or starts out empty if it never saved anything.
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let mut local_node = Self::default();
+        if path.exists() {
+            local_node = bincode::deserialize(&fs::read(path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+
+        local_node.path = Some(path.to_path_buf());
+        Ok(local_node)
+    }
+
+    /// Saves this node's state to the file it was loaded from.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        // written to a temporary file first, so a crash never leaves half a file behind
+        let temporary_path = path.with_extension("tmp");
+        let bytes =
+            bincode::serialize(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(&temporary_path, bytes)?;
+        fs::rename(&temporary_path, path)
+    }
+}
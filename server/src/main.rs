@@ -1,10 +1,26 @@
 //! Entry point for the server application.
 
+mod auth;
+mod authorizer;
+mod batchlog;
+mod coordinator;
+mod cql;
+mod events;
+mod failure_detector;
 mod handler_manager;
 mod handlers;
+mod internode;
+mod local_node;
+mod paxos;
+mod prepared_cache;
+mod query_handler;
 mod replicator;
+mod roles;
+mod schema;
 mod server;
 mod storage;
+mod tracing;
+mod warnings;
 
 use server::Server;
 
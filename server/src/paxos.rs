@@ -0,0 +1,183 @@
+//! Replica side of lightweight transactions.
+//!
+//! A lightweight transaction applies a mutation only if a condition on the current row holds,
+//! and is decided with a round of Paxos among the replicas of the row's partition: the
+//! coordinator asks them to promise a ballot, reads the row, proposes the mutation and, once a
+//! majority accepted it, commits it. Each replica remembers, per partition, the highest ballot
+//! it promised, the proposal it accepted last and the proposal it committed last, so that a
+//! later round can finish a proposal whose coordinator failed before committing it.
+//!
+//! The state is kept in memory only, like the rows themselves.
+
+use shared::protocol::types::{Ballot, CqlValue, PaxosPrepare, Promise, Proposal};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A thread-safe, shared Paxos state.
+pub type GlobalPaxos = Arc<Mutex<Paxos>>;
+
+/// Keyspace, table and partition key values of a partition.
+type PartitionKey = (String, String, Vec<String>);
+
+#[derive(Default)]
+struct PaxosState {
+    promised: Option<Ballot>,
+    accepted: Option<Proposal>,
+    committed: Option<Proposal>,
+}
+
+pub struct Paxos {
+    partitions: HashMap<PartitionKey, PaxosState>,
+}
+
+impl Paxos {
+    pub fn new() -> Self {
+        Self {
+            partitions: HashMap::new(),
+        }
+    }
+
+    /// Promises a ballot unless a higher or equal one was promised before.
+    ///
+    /// The promise carries the last accepted and committed proposals either way, so the
+    /// coordinator can finish an interrupted round.
+    pub fn prepare(&mut self, prepare: &PaxosPrepare) -> Promise {
+        let state = self.state(&prepare.keyspace, &prepare.table, &prepare.partition_key);
+        let promised = state
+            .promised
+            .is_none_or(|promised| promised < prepare.ballot);
+        if promised {
+            state.promised = Some(prepare.ballot);
+        }
+
+        Promise {
+            promised,
+            accepted: state.accepted.clone(),
+            committed: state.committed.clone(),
+        }
+    }
+
+    /// Accepts a proposal unless a higher ballot was promised since. Returns `true` if it was
+    /// accepted.
+    pub fn propose(&mut self, proposal: &Proposal) -> bool {
+        let mutation = &proposal.mutation;
+        let state = self.state(&mutation.keyspace, &mutation.table, &mutation.partition_key);
+        if state
+            .promised
+            .is_some_and(|promised| promised > proposal.ballot)
+        {
+            return false;
+        }
+
+        state.promised = Some(proposal.ballot);
+        state.accepted = Some(proposal.clone());
+        true
+    }
+
+    /// Records a committed proposal, which the caller applies to storage.
+    ///
+    /// An accepted proposal of the same or an older round is done with.
+    pub fn commit(&mut self, proposal: &Proposal) {
+        let mutation = &proposal.mutation;
+        let state = self.state(&mutation.keyspace, &mutation.table, &mutation.partition_key);
+        if state
+            .accepted
+            .as_ref()
+            .is_some_and(|accepted| accepted.ballot <= proposal.ballot)
+        {
+            state.accepted = None;
+        }
+        if state
+            .committed
+            .as_ref()
+            .is_none_or(|committed| committed.ballot < proposal.ballot)
+        {
+            state.committed = Some(proposal.clone());
+        }
+    }
+
+    fn state(
+        &mut self,
+        keyspace: &str,
+        table: &str,
+        partition_key: &[CqlValue],
+    ) -> &mut PaxosState {
+        let key = (
+            keyspace.to_string(),
+            table.to_string(),
+            partition_key
+                .iter()
+                .map(|value| value.to_string())
+                .collect(),
+        );
+        self.partitions.entry(key).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paxos::Paxos;
+    use shared::protocol::types::{Ballot, CqlValue, Mutation, PaxosPrepare, Proposal};
+    use uuid::Uuid;
+
+    fn ballot(timestamp: i64) -> Ballot {
+        Ballot {
+            timestamp,
+            id: Uuid::nil(),
+        }
+    }
+
+    fn prepare(timestamp: i64) -> PaxosPrepare {
+        PaxosPrepare {
+            keyspace: "shop".to_string(),
+            table: "users".to_string(),
+            partition_key: vec![CqlValue::Text("alice".to_string())],
+            ballot: ballot(timestamp),
+        }
+    }
+
+    fn proposal(timestamp: i64) -> Proposal {
+        Proposal {
+            ballot: ballot(timestamp),
+            mutation: Mutation {
+                keyspace: "shop".to_string(),
+                table: "users".to_string(),
+                partition_key: vec![CqlValue::Text("alice".to_string())],
+                clustering_key: Vec::new(),
+                cells: vec![("age".to_string(), CqlValue::Int(30))],
+                row_marker: true,
+                row_deletion: false,
+                counters: Vec::new(),
+                timestamp,
+            },
+        }
+    }
+
+    #[test]
+    fn test_promises_only_higher_ballots() {
+        let mut paxos = Paxos::new();
+
+        assert!(paxos.prepare(&prepare(20)).promised);
+        assert!(!paxos.prepare(&prepare(10)).promised);
+        assert!(!paxos.prepare(&prepare(20)).promised);
+        assert!(!paxos.propose(&proposal(10)));
+        assert!(paxos.propose(&proposal(20)));
+    }
+
+    #[test]
+    fn test_promise_returns_accepted_proposal_until_committed() {
+        let mut paxos = Paxos::new();
+        paxos.prepare(&prepare(10));
+        paxos.propose(&proposal(10));
+
+        let promise = paxos.prepare(&prepare(20));
+        assert!(promise.promised);
+        assert_eq!(promise.accepted, Some(proposal(10)));
+        assert_eq!(promise.committed, None);
+
+        paxos.commit(&proposal(10));
+        let promise = paxos.prepare(&prepare(30));
+        assert_eq!(promise.accepted, None);
+        assert_eq!(promise.committed, Some(proposal(10)));
+    }
+}
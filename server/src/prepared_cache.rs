@@ -0,0 +1,469 @@
+//! Server-side cache of prepared statements.
+//!
+//! Statements are keyed by the MD5 digest of their query string, so preparing the same query
+//! twice, on any node, yields the same id. Unqualified table names are qualified with the
+//! keyspace of the connection preparing the statement, which is then part of the digest too.
+//!
+//! The metadata of the bind markers and of the result is taken from the schema of the
+//! statement's table when it is prepared.
+
+use crate::cql::ast::{Assignment, CqlStatement, Operator, Relation, Selection, TableName, Term};
+use crate::cql::parser;
+use crate::schema::Schema;
+use md5::{Digest, Md5};
+use shared::protocol::types::{
+    ColumnSpec, DataType, ErrorCode, ErrorResponse, Execute, PreparedMetadata, StatementId,
+    TableMetadata,
+};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Maximum number of statements kept; the oldest ones are evicted first.
+const PREPARED_CACHE_CAPACITY: usize = 10_000;
+
+/// A thread-safe, shared prepared statement cache.
+pub type GlobalPreparedCache = Arc<Mutex<PreparedCache>>;
+
+/// A statement prepared on this node.
+pub(crate) struct PreparedStatement {
+    statement: CqlStatement,
+    metadata: PreparedMetadata,
+}
+
+pub struct PreparedCache {
+    statements: HashMap<StatementId, PreparedStatement>,
+    /// Statement ids in insertion order, used for eviction.
+    order: VecDeque<StatementId>,
+}
+
+impl PreparedCache {
+    pub fn new() -> Self {
+        Self {
+            statements: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Parses and caches a statement, returning its metadata.
+    ///
+    /// `keyspace` is the keyspace of the connection, which unqualified table names refer to.
+    pub(crate) fn prepare(
+        &mut self,
+        query: &str,
+        keyspace: Option<&str>,
+        schema: &Schema,
+    ) -> Result<PreparedMetadata, ErrorResponse> {
+        let mut statement = parser::parse(query)?;
+        let qualified_with = qualify(&mut statement, keyspace)?;
+        let id = Self::statement_id(qualified_with, query);
+        if let Some(prepared) = self.statements.get(&id) {
+            return Ok(prepared.metadata.clone());
+        }
+
+        let metadata = match data_table(&statement) {
+            Some(table) => {
+                let table = lookup(table, schema)?;
+                PreparedMetadata {
+                    id,
+                    bind_metadata: column_bind_metadata(&statement, &table)?,
+                    pk_indexes: column_pk_indexes(&statement, &table),
+                    result_metadata: column_result_metadata(&statement, &table)?,
+                }
+            }
+            None => PreparedMetadata {
+                id,
+                bind_metadata: bind_metadata(&statement)?,
+                pk_indexes: pk_indexes(&statement),
+                result_metadata: result_metadata(&statement),
+            },
+        };
+
+        if self.order.len() >= PREPARED_CACHE_CAPACITY
+            && let Some(evicted) = self.order.pop_front()
+        {
+            self.statements.remove(&evicted);
+        }
+        self.order.push_back(id);
+        self.statements.insert(
+            id,
+            PreparedStatement {
+                statement,
+                metadata: metadata.clone(),
+            },
+        );
+
+        Ok(metadata)
+    }
+
+    /// Returns the prepared statement of an EXECUTE, once its values were checked against the
+    /// statement's bind markers.
+    ///
+    /// Fails with `ErrorCode::Unprepared` if the statement isn't cached on this node, in which
+    /// case the client is expected to prepare it again.
+    pub(crate) fn statement(&self, execute: &Execute) -> Result<CqlStatement, ErrorResponse> {
+        let prepared = self.statements.get(&execute.id).ok_or_else(|| {
+            ErrorResponse::new(
+                ErrorCode::Unprepared,
+                format!(
+                    "Prepared query with ID {} not found",
+                    Self::format_id(&execute.id)
+                ),
+            )
+        })?;
+
+        let bind_metadata = &prepared.metadata.bind_metadata;
+        if execute.values.len() != bind_metadata.len() {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!(
+                    "There were {} markers(?) in CQL but {} bound variables",
+                    bind_metadata.len(),
+                    execute.values.len()
+                ),
+            ));
+        }
+        Ok(prepared.statement.clone())
+    }
+
+    fn statement_id(keyspace: Option<&str>, query: &str) -> StatementId {
+        let mut digest = Md5::new();
+        if let Some(keyspace) = keyspace {
+            digest.update(keyspace.as_bytes());
+        }
+        digest.update(query.as_bytes());
+        digest.finalize().into()
+    }
+
+    fn format_id(id: &StatementId) -> String {
+        id.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+fn invalid(message: impl Into<String>) -> ErrorResponse {
+    ErrorResponse::new(ErrorCode::Invalid, message)
+}
+
+/// Qualifies the table name of a statement with the keyspace of the connection, returning the
+/// keyspace if the statement refers to it.
+fn qualify<'k>(
+    statement: &mut CqlStatement,
+    keyspace: Option<&'k str>,
+) -> Result<Option<&'k str>, ErrorResponse> {
+    let table = match statement {
+        CqlStatement::CreateTable(create) => &mut create.table,
+        CqlStatement::CreateIndex(create) => &mut create.table,
+        CqlStatement::Insert(insert) => &mut insert.table,
+        CqlStatement::Select(select) => &mut select.table,
+        CqlStatement::Update(update) => &mut update.table,
+        CqlStatement::Delete(delete) => &mut delete.table,
+        _ => return Ok(None),
+    };
+    if table.keyspace.is_some() {
+        return Ok(None);
+    }
+
+    let keyspace = keyspace.ok_or_else(|| {
+        invalid(
+            "No keyspace has been specified. USE a keyspace, or explicitly specify \
+             keyspace.tablename",
+        )
+    })?;
+    table.keyspace = Some(keyspace.to_string());
+    Ok(Some(keyspace))
+}
+
+/// Returns the table whose rows a statement reads or writes.
+fn data_table(statement: &CqlStatement) -> Option<&TableName> {
+    match statement {
+        CqlStatement::Insert(insert) => Some(&insert.table),
+        CqlStatement::Select(select) => Some(&select.table),
+        CqlStatement::Update(update) => Some(&update.table),
+        CqlStatement::Delete(delete) => Some(&delete.table),
+        _ => None,
+    }
+}
+
+fn lookup(table: &TableName, schema: &Schema) -> Result<TableMetadata, ErrorResponse> {
+    schema
+        .table(table.keyspace.as_deref().unwrap_or_default(), &table.name)
+        .cloned()
+        .ok_or_else(|| invalid(format!("unconfigured table {}", table.name)))
+}
+
+fn column(table: &TableMetadata, name: &str) -> Result<ColumnSpec, ErrorResponse> {
+    table
+        .column(name)
+        .cloned()
+        .ok_or_else(|| invalid(format!("Undefined column name {}", name)))
+}
+
+/// Pairs the terms of a statement on a table with the name of the column they are a value of.
+fn column_terms(statement: &CqlStatement) -> Vec<(&str, &Term)> {
+    match statement {
+        CqlStatement::Insert(insert) => insert
+            .values
+            .iter()
+            .map(|(column, term)| (column.as_str(), term))
+            .collect(),
+        CqlStatement::Update(update) => {
+            let mut terms: Vec<(&str, &Term)> = update
+                .assignments
+                .iter()
+                .map(|(column, assignment)| match assignment {
+                    Assignment::Value(term)
+                    | Assignment::Add(term)
+                    | Assignment::Subtract(term) => (column.as_str(), term),
+                })
+                .collect();
+            terms.extend(relation_terms(&update.relations));
+            terms.extend(relation_terms(&update.conditions));
+            terms
+        }
+        CqlStatement::Select(select) => relation_terms(&select.relations),
+        CqlStatement::Delete(delete) => relation_terms(&delete.relations),
+        _ => Vec::new(),
+    }
+}
+
+/// Describes the bind markers of a statement on a table with the columns they are bound to,
+/// in marker order.
+fn column_bind_metadata(
+    statement: &CqlStatement,
+    table: &TableMetadata,
+) -> Result<Vec<ColumnSpec>, ErrorResponse> {
+    let mut markers = Vec::new();
+    for (name, term) in column_terms(statement) {
+        let spec = column(table, name)?;
+        if let Term::Marker(index) = term {
+            markers.push((*index, spec));
+        }
+    }
+    markers.sort_by_key(|(index, _)| *index);
+
+    Ok(markers.into_iter().map(|(_, spec)| spec).collect())
+}
+
+/// Returns the indexes of the bind markers giving the partition key of a statement on a table,
+/// in key order, or none if part of the key isn't bound by a marker.
+fn column_pk_indexes(statement: &CqlStatement, table: &TableMetadata) -> Vec<u16> {
+    let key_terms: Vec<(&str, &Term)> = match statement {
+        CqlStatement::Insert(_) => column_terms(statement),
+        CqlStatement::Select(select) => equal_terms(&select.relations),
+        CqlStatement::Update(update) => equal_terms(&update.relations),
+        CqlStatement::Delete(delete) => equal_terms(&delete.relations),
+        _ => Vec::new(),
+    };
+
+    table
+        .partition_key
+        .iter()
+        .map(|key| {
+            key_terms.iter().find_map(|(column, term)| match term {
+                Term::Marker(index) if column == key => Some(*index as u16),
+                _ => None,
+            })
+        })
+        .collect::<Option<Vec<u16>>>()
+        .unwrap_or_default()
+}
+
+fn relation_terms(relations: &[Relation]) -> Vec<(&str, &Term)> {
+    relations
+        .iter()
+        .map(|relation| (relation.column.as_str(), &relation.term))
+        .collect()
+}
+
+/// Pairs the terms of the EQ relations with their column's name.
+fn equal_terms(relations: &[Relation]) -> Vec<(&str, &Term)> {
+    relations
+        .iter()
+        .filter(|relation| relation.operator == Operator::Equal)
+        .map(|relation| (relation.column.as_str(), &relation.term))
+        .collect()
+}
+
+/// Describes the columns a statement on a table returns.
+fn column_result_metadata(
+    statement: &CqlStatement,
+    table: &TableMetadata,
+) -> Result<Vec<ColumnSpec>, ErrorResponse> {
+    let CqlStatement::Select(select) = statement else {
+        return Ok(Vec::new());
+    };
+
+    match &select.selection {
+        Selection::All => Ok(table.columns.clone()),
+        Selection::Columns(columns) => columns.iter().map(|name| column(table, name)).collect(),
+        Selection::Count => Ok(vec![ColumnSpec::new("count", DataType::BigInt)]),
+    }
+}
+
+/// Describes the bind markers of a statement, in marker order.
+fn bind_metadata(statement: &CqlStatement) -> Result<Vec<ColumnSpec>, ErrorResponse> {
+    let text = |name: &str| ColumnSpec::new(name, DataType::Text);
+    let terms: Vec<(&Term, ColumnSpec)> = match statement {
+        CqlStatement::AddValue(add) => {
+            let mut terms = vec![(&add.value, text("value"))];
+            if let Some(replication_factor) = &add.replication_factor {
+                terms.push((
+                    replication_factor,
+                    ColumnSpec::new("replication_factor", DataType::Int),
+                ));
+            }
+            terms
+        }
+        CqlStatement::CheckValue(value) => vec![(value, text("value"))],
+        CqlStatement::CreateUser(create) => vec![
+            (&create.name, text("name")),
+            (&create.password, text("password")),
+        ],
+        CqlStatement::AlterUser(alter) => {
+            let mut terms = vec![(&alter.name, text("name"))];
+            if let Some(password) = &alter.password {
+                terms.push((password, text("password")));
+            }
+            terms
+        }
+        CqlStatement::DropUser(drop) => vec![(&drop.name, text("name"))],
+        _ => Vec::new(),
+    };
+
+    Ok(terms
+        .into_iter()
+        .filter(|(term, _)| matches!(term, Term::Marker(_)))
+        .map(|(_, spec)| spec)
+        .collect())
+}
+
+/// Returns the indexes of the bind markers forming the partition key.
+fn pk_indexes(statement: &CqlStatement) -> Vec<u16> {
+    match statement {
+        CqlStatement::AddValue(add) => match add.value {
+            Term::Marker(index) => vec![index as u16],
+            _ => Vec::new(),
+        },
+        CqlStatement::CheckValue(Term::Marker(index)) => vec![*index as u16],
+        _ => Vec::new(),
+    }
+}
+
+/// Describes the columns returned by a statement.
+fn result_metadata(statement: &CqlStatement) -> Vec<ColumnSpec> {
+    match statement {
+        CqlStatement::CheckValue(_) => vec![ColumnSpec::new("exists", DataType::Boolean)],
+        CqlStatement::CountValues => vec![ColumnSpec::new("count", DataType::BigInt)],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prepared_cache::PreparedCache;
+    use crate::schema::Schema;
+    use shared::protocol::types::{
+        ClusteringOrder, ColumnSpec, DataType, ErrorCode, KeyspaceMetadata, TableMetadata,
+    };
+
+    fn schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.create_keyspace(KeyspaceMetadata {
+            name: "shop".to_string(),
+            replication_factor: 2,
+        });
+        schema
+            .create_table(TableMetadata {
+                keyspace: "shop".to_string(),
+                name: "orders".to_string(),
+                columns: vec![
+                    ColumnSpec::new("customer", DataType::Text),
+                    ColumnSpec::new("region", DataType::Int),
+                    ColumnSpec::new("id", DataType::Uuid),
+                    ColumnSpec::new("total", DataType::Double),
+                ],
+                partition_key: vec!["customer".to_string(), "region".to_string()],
+                clustering_key: vec!["id".to_string()],
+                clustering_order: vec![ClusteringOrder::Asc],
+            })
+            .unwrap();
+        schema
+    }
+
+    #[test]
+    fn test_prepare_takes_metadata_from_schema() {
+        let mut cache = PreparedCache::new();
+
+        let insert = cache
+            .prepare(
+                "INSERT INTO orders (id, region, customer, total) VALUES (?, ?, ?, 1.5)",
+                Some("shop"),
+                &schema(),
+            )
+            .unwrap();
+        assert_eq!(
+            insert.bind_metadata,
+            vec![
+                ColumnSpec::new("id", DataType::Uuid),
+                ColumnSpec::new("region", DataType::Int),
+                ColumnSpec::new("customer", DataType::Text),
+            ]
+        );
+        assert_eq!(insert.pk_indexes, vec![2, 1]);
+        assert!(insert.result_metadata.is_empty());
+
+        let select = cache
+            .prepare(
+                "SELECT id, total FROM shop.orders WHERE customer = ? AND region = 3",
+                None,
+                &schema(),
+            )
+            .unwrap();
+        assert_eq!(
+            select.bind_metadata,
+            vec![ColumnSpec::new("customer", DataType::Text)]
+        );
+        assert!(select.pk_indexes.is_empty());
+        assert_eq!(
+            select.result_metadata,
+            vec![
+                ColumnSpec::new("id", DataType::Uuid),
+                ColumnSpec::new("total", DataType::Double),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prepare_qualifies_tables_with_connection_keyspace() {
+        let mut cache = PreparedCache::new();
+        let query = "SELECT * FROM orders WHERE customer = ? AND region = ?";
+
+        let error = cache.prepare(query, None, &schema()).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Invalid);
+        let error = cache.prepare(query, Some("other"), &schema()).unwrap_err();
+        assert_eq!(error.code, ErrorCode::Invalid);
+
+        let prepared = cache.prepare(query, Some("shop"), &schema()).unwrap();
+        assert_eq!(
+            prepared.id,
+            PreparedCache::statement_id(Some("shop"), query)
+        );
+        assert_ne!(prepared.id, PreparedCache::statement_id(None, query));
+        assert_eq!(prepared.pk_indexes, vec![0, 1]);
+        assert_eq!(prepared.result_metadata.len(), 4);
+    }
+
+    #[test]
+    fn test_prepare_rejects_unknown_columns() {
+        let mut cache = PreparedCache::new();
+
+        let error = cache
+            .prepare(
+                "UPDATE shop.orders SET discount = ? WHERE customer = ? AND region = ?",
+                None,
+                &schema(),
+            )
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::Invalid);
+        assert_eq!(error.message, "Undefined column name discount");
+    }
+}
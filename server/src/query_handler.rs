@@ -0,0 +1,116 @@
+//! Pluggable handling of custom payloads.
+//!
+//! A request may carry a custom payload, a map of bytes such as a tenant id or request tags.
+//! The query handler is chosen with the `query_handler=` argument and sees the payload of every
+//! request before it is handled, including requests forwarded by other nodes, which carry the
+//! payload of the client's request. After handling, it returns the payload of the response.
+
+use log::info;
+use shared::protocol::types::{CustomPayload, ErrorResponse, Request, Response};
+use std::sync::Arc;
+
+/// A thread-safe, shared query handler.
+pub(crate) type GlobalQueryHandler = Arc<dyn QueryHandler + Send + Sync>;
+
+pub(crate) trait QueryHandler {
+    fn name(&self) -> &'static str;
+    /// Called before a request is handled; an error is returned to the client instead of
+    /// handling the request.
+    fn before_request(
+        &self,
+        request: &Request,
+        user: Option<&str>,
+        custom_payload: &CustomPayload,
+    ) -> Result<(), ErrorResponse>;
+    /// Returns the custom payload of the response to a request.
+    fn after_request(
+        &self,
+        request: &Request,
+        response: &Response,
+        custom_payload: &CustomPayload,
+    ) -> CustomPayload;
+}
+
+/// Creates the query handler with the given name, or `None` if there is no such handler.
+pub(crate) fn from_name(name: &str) -> Option<GlobalQueryHandler> {
+    match name {
+        DefaultQueryHandler::NAME => Some(Arc::new(DefaultQueryHandler)),
+        AuditingQueryHandler::NAME => Some(Arc::new(AuditingQueryHandler)),
+        _ => None,
+    }
+}
+
+/// Ignores custom payloads and returns none.
+pub(crate) struct DefaultQueryHandler;
+
+impl DefaultQueryHandler {
+    pub(crate) const NAME: &'static str = "DefaultQueryHandler";
+}
+
+impl QueryHandler for DefaultQueryHandler {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn before_request(
+        &self,
+        _request: &Request,
+        _user: Option<&str>,
+        _custom_payload: &CustomPayload,
+    ) -> Result<(), ErrorResponse> {
+        Ok(())
+    }
+
+    fn after_request(
+        &self,
+        _request: &Request,
+        _response: &Response,
+        _custom_payload: &CustomPayload,
+    ) -> CustomPayload {
+        CustomPayload::new()
+    }
+}
+
+/// Logs the requests carrying a custom payload with their user, and echoes the payload back.
+pub(crate) struct AuditingQueryHandler;
+
+impl AuditingQueryHandler {
+    pub(crate) const NAME: &'static str = "AuditingQueryHandler";
+}
+
+impl QueryHandler for AuditingQueryHandler {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn before_request(
+        &self,
+        request: &Request,
+        user: Option<&str>,
+        custom_payload: &CustomPayload,
+    ) -> Result<(), ErrorResponse> {
+        if !custom_payload.is_empty() {
+            let mut entries: Vec<String> = custom_payload
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, String::from_utf8_lossy(value)))
+                .collect();
+            entries.sort();
+            info!(
+                "Audit: {:?} request by {} with payload {}",
+                request.opcode(),
+                user.unwrap_or("anonymous"),
+                entries.join(",")
+            );
+        }
+        Ok(())
+    }
+
+    fn after_request(
+        &self,
+        _request: &Request,
+        _response: &Response,
+        custom_payload: &CustomPayload,
+    ) -> CustomPayload {
+        custom_payload.clone()
+    }
+}
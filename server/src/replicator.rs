@@ -1,26 +1,30 @@
+use crate::coordinator::GlobalCluster;
+use crate::internode;
+use crate::internode::RequestContext;
+use crate::tracing;
 use log::info;
-use shared::cluster::{Cluster, Node};
+use shared::cluster::Node;
 use shared::connection_pool::ConnectionPool;
 use shared::protocol::types::{Entry, Request};
-use shared::routing::RoutingStrategy;
 use std::sync::mpsc::Receiver;
 
 pub(crate) struct Replicator {
-    cluster: Cluster,
+    cluster: GlobalCluster,
     receiver: Receiver<ReplicationEntry>,
     connection_pool: ConnectionPool,
     current_host: String,
 }
 
+/// Values to replicate, with the context of the request that wrote them.
 pub(crate) enum ReplicationEntry {
-    Single(Entry),
-    Batch(Vec<Entry>),
+    Single(Entry, RequestContext),
+    Batch(Vec<Entry>, RequestContext),
 }
 
 impl Replicator {
     pub(crate) fn new(
         current_host: String,
-        cluster: Cluster,
+        cluster: GlobalCluster,
         receiver: Receiver<ReplicationEntry>,
         connection_pool: ConnectionPool,
     ) -> Self {
@@ -35,9 +39,16 @@ impl Replicator {
     pub(crate) fn run(&mut self) {
         while let Ok(replication_entry) = self.receiver.recv() {
             match replication_entry {
-                ReplicationEntry::Single(entry) => self.replicate_single_value(entry),
-                ReplicationEntry::Batch(entries) => self.replicate_batch(&entries),
+                ReplicationEntry::Single(entry, context) => {
+                    context.enter();
+                    self.replicate_single_value(entry)
+                }
+                ReplicationEntry::Batch(entries, context) => {
+                    context.enter();
+                    self.replicate_batch(&entries)
+                }
             }
+            internode::exit();
         }
     }
 
@@ -59,19 +70,23 @@ impl Replicator {
 
     fn replicate_value(&mut self, entry: Entry) {
         let current_host = self.current_host.clone().into();
-        let hash =
-            shared::consistent_hash_ring::ConsistentHashRing::calculate_hash(entry.value.as_str());
 
-        let replication_strategy = self.cluster.replication_strategy();
-        let nodes = replication_strategy.get_replica_nodes(
-            hash,
-            &current_host,
-            entry.replication_factor.unwrap(),
-        );
+        let nodes: Vec<Node> = {
+            let cluster = self.cluster.read().unwrap();
+            let replication_strategy = cluster.replication_strategy();
+            replication_strategy
+                .get_key_replica_nodes(
+                    entry.value.as_str(),
+                    &current_host,
+                    entry.replication_factor.unwrap(),
+                )
+                .into_iter()
+                .filter_map(|address| cluster.node(address).cloned())
+                .collect()
+        };
 
-        for node_address in nodes.iter() {
-            let node = Node::new(node_address.to_string());
-            let strategy = RoutingStrategy::Direct(&node);
+        for node in nodes.iter() {
+            let node_address = &node.address;
 
             //since the value is being replicated, we don't want to replicate it again
             let request = Request::Add(Entry {
@@ -80,7 +95,13 @@ impl Replicator {
             });
 
             info!("Sending request to node: {}", node_address);
-            let result = self.connection_pool.execute(strategy, request, None);
+            tracing::trace(|| {
+                format!(
+                    "Sending replica write of {} to {}",
+                    entry.value, node_address
+                )
+            });
+            let result = internode::execute(&mut self.connection_pool, node, request);
 
             info!("{:?}", result);
         }
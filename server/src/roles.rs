@@ -0,0 +1,273 @@
+//! The roles system table.
+//!
+//! Holds the users that can log in, with a bcrypt hash of their password, and the permissions
+//! granted to them. bcrypt salts every hash, so equal passwords never produce equal hashes.
+//! The table is kept by every node and saved to its data directory; CREATE USER, ALTER USER,
+//! DROP USER, GRANT and REVOKE are sent to all of them.
+//!
+//! A node starting without a saved table creates the default superuser, whose password is
+//! meant to be changed with ALTER USER, or which is dropped once another superuser exists.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use shared::protocol::types::{ErrorCode, ErrorResponse, Permission, Resource};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// A thread-safe, shared roles table.
+pub type GlobalRoles = Arc<Mutex<Roles>>;
+
+/// Superuser created on every node with an empty roles table, to create the other users.
+pub(crate) const DEFAULT_SUPERUSER: &str = "cassandra";
+pub(crate) const DEFAULT_SUPERUSER_PASSWORD: &str = "cassandra";
+
+/// Work factor of the password hashes.
+const BCRYPT_COST: u32 = 10;
+
+#[derive(Serialize, Deserialize)]
+struct Role {
+    salted_hash: String,
+    is_superuser: bool,
+    permissions: HashSet<(Resource, Permission)>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Roles {
+    roles: HashMap<String, Role>,
+    /// Where the table is saved; `None` keeps it in memory only.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Roles {
+    /// Creates a roles table holding only the default superuser.
+    pub fn new() -> Self {
+        let mut roles = Self {
+            roles: HashMap::new(),
+            path: None,
+        };
+        roles.roles.insert(
+            DEFAULT_SUPERUSER.to_string(),
+            Role {
+                salted_hash: hash_password(DEFAULT_SUPERUSER_PASSWORD),
+                is_superuser: true,
+                permissions: HashSet::new(),
+            },
+        );
+        roles
+    }
+
+    /// Loads the roles table saved to a file, or creates one holding only the default
+    /// superuser if none was saved yet.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let saved = path.exists();
+        let mut roles = match saved {
+            true => bincode::deserialize(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            false => Self::new(),
+        };
+
+        roles.path = Some(path);
+        if !saved {
+            roles.save();
+        }
+        Ok(roles)
+    }
+
+    /// Adds a user with an already hashed password.
+    pub(crate) fn create(
+        &mut self,
+        name: &str,
+        salted_hash: String,
+        is_superuser: bool,
+    ) -> Result<(), ErrorResponse> {
+        if self.roles.contains_key(name) {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!("{} already exists", name),
+            ));
+        }
+
+        self.roles.insert(
+            name.to_string(),
+            Role {
+                salted_hash,
+                is_superuser,
+                permissions: HashSet::new(),
+            },
+        );
+        self.save();
+        Ok(())
+    }
+
+    /// Changes the password of a user to an already hashed one, or its superuser status;
+    /// what is `None` is left unchanged.
+    pub(crate) fn alter(
+        &mut self,
+        name: &str,
+        salted_hash: Option<String>,
+        is_superuser: Option<bool>,
+    ) -> Result<(), ErrorResponse> {
+        let role = self.role_mut(name)?;
+        if let Some(salted_hash) = salted_hash {
+            role.salted_hash = salted_hash;
+        }
+        if let Some(is_superuser) = is_superuser {
+            role.is_superuser = is_superuser;
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Removes a user along with its permissions, returning `false` if it doesn't exist.
+    pub(crate) fn remove(&mut self, name: &str) -> bool {
+        let removed = self.roles.remove(name).is_some();
+        if removed {
+            self.save();
+        }
+        removed
+    }
+
+    /// Grants permissions on a resource to a role.
+    pub(crate) fn grant(
+        &mut self,
+        role: &str,
+        permissions: &[Permission],
+        resource: &Resource,
+    ) -> Result<(), ErrorResponse> {
+        let role = self.role_mut(role)?;
+        for permission in permissions {
+            role.permissions.insert((resource.clone(), *permission));
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Revokes permissions on a resource from a role.
+    ///
+    /// Permissions granted on a parent resource stay in effect.
+    pub(crate) fn revoke(
+        &mut self,
+        role: &str,
+        permissions: &[Permission],
+        resource: &Resource,
+    ) -> Result<(), ErrorResponse> {
+        let role = self.role_mut(role)?;
+        for permission in permissions {
+            role.permissions.remove(&(resource.clone(), *permission));
+        }
+        self.save();
+        Ok(())
+    }
+
+    /// Returns whether a role holds a permission on a resource or on any of its parents.
+    ///
+    /// Superusers hold every permission.
+    pub(crate) fn has_permission(
+        &self,
+        role: &str,
+        permission: Permission,
+        resource: &Resource,
+    ) -> bool {
+        let Some(role) = self.roles.get(role) else {
+            return false;
+        };
+        if role.is_superuser {
+            return true;
+        }
+
+        let mut resource = Some(resource.clone());
+        while let Some(current) = resource {
+            if role.permissions.contains(&(current.clone(), permission)) {
+                return true;
+            }
+            resource = current.parent();
+        }
+        false
+    }
+
+    fn role_mut(&mut self, name: &str) -> Result<&mut Role, ErrorResponse> {
+        self.roles.get_mut(name).ok_or_else(|| {
+            ErrorResponse::new(ErrorCode::Invalid, format!("Role {} doesn't exist", name))
+        })
+    }
+
+    /// Returns the password hash of a user, if it exists.
+    pub(crate) fn salted_hash(&self, name: &str) -> Option<String> {
+        self.roles.get(name).map(|role| role.salted_hash.clone())
+    }
+
+    pub(crate) fn is_superuser(&self, name: &str) -> bool {
+        self.roles.get(name).is_some_and(|role| role.is_superuser)
+    }
+
+    /// Saves the table to the file it was loaded from, if any.
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // written to a temporary file first, so a crash never leaves half a table behind
+        let temporary_path = path.with_extension("tmp");
+        let result = bincode::serialize(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|bytes| fs::write(&temporary_path, bytes))
+            .and_then(|_| fs::rename(&temporary_path, path));
+        if let Err(e) = result {
+            warn!("Failed to save the roles to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Hashes a password with a random salt.
+///
+/// Hashing is deliberately slow, so it should happen without holding the roles lock.
+pub(crate) fn hash_password(password: &str) -> String {
+    bcrypt::hash(password, BCRYPT_COST).expect("bcrypt cost is valid")
+}
+
+/// Checks a password against a hash created by `hash_password`.
+pub(crate) fn verify_password(password: &str, salted_hash: &str) -> bool {
+    bcrypt::verify(password, salted_hash).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::roles::{DEFAULT_SUPERUSER, Roles, hash_password};
+    use shared::protocol::types::{Permission, Resource};
+
+    #[test]
+    fn test_permissions_are_inherited_from_parents() {
+        let mut roles = Roles::new();
+        let keyspace = Resource::Keyspace("default".to_string());
+        roles.create("alice", hash_password("pw"), false).unwrap();
+
+        assert!(!roles.has_permission("alice", Permission::Select, &keyspace));
+
+        roles
+            .grant("alice", &[Permission::Select], &Resource::AllKeyspaces)
+            .unwrap();
+
+        assert!(roles.has_permission("alice", Permission::Select, &keyspace));
+        assert!(!roles.has_permission("alice", Permission::Modify, &keyspace));
+
+        roles
+            .revoke("alice", &[Permission::Select], &Resource::AllKeyspaces)
+            .unwrap();
+
+        assert!(!roles.has_permission("alice", Permission::Select, &keyspace));
+        assert!(roles.has_permission(DEFAULT_SUPERUSER, Permission::Modify, &keyspace));
+    }
+
+    #[test]
+    fn test_grant_to_unknown_role_fails() {
+        let mut roles = Roles::new();
+
+        let result = roles.grant("bob", &[Permission::Select], &Resource::AllKeyspaces);
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,361 @@
+//! The keyspaces, tables and secondary indexes known to this node.
+//!
+//! A keyspace, table or index is created on the node coordinating its CREATE statement, which
+//! then tells every other node about it. Each node saves its schema to its data directory, so
+//! it survives restarts, and identifies it by a version: the digest of its keyspaces, tables
+//! and indexes. Nodes that missed a change, e.g. because they were down, notice that another
+//! node has a different version and pull the definitions they don't know yet from it.
+
+use crate::coordinator::GlobalCluster;
+use crate::events::GlobalEventBus;
+use crate::handlers::{create_index_handler, create_keyspace_handler, create_table_handler};
+use crate::storage::GlobalRowStorage;
+use log::{info, warn};
+use md5::{Digest, Md5};
+use shared::cluster::Node;
+use shared::connection_pool::ConnectionPool;
+use shared::error::AppResult;
+use shared::protocol::types::{
+    ErrorCode, ErrorResponse, IndexMetadata, KeyspaceMetadata, Request, Response, SchemaDefinition,
+    TableMetadata,
+};
+use shared::routing::RoutingStrategy;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often the schema versions of the other nodes are compared with the one of this node.
+const SCHEMA_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A thread-safe, shared schema.
+pub type GlobalSchema = Arc<Mutex<Schema>>;
+
+pub struct Schema {
+    keyspaces: BTreeMap<String, KeyspaceMetadata>,
+    /// Tables keyed by keyspace and table name.
+    tables: BTreeMap<(String, String), TableMetadata>,
+    /// Secondary indexes keyed by keyspace and index name.
+    indexes: BTreeMap<(String, String), IndexMetadata>,
+    /// File the schema is saved to after every change, if it is persisted.
+    path: Option<PathBuf>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self {
+            keyspaces: BTreeMap::new(),
+            tables: BTreeMap::new(),
+            indexes: BTreeMap::new(),
+            path: None,
+        }
+    }
+
+    /// Loads the schema saved at `path`, or starts with an empty one if there is none yet.
+    /// Every change is saved there.
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let mut schema = Self::new();
+        if path.exists() {
+            let definition: SchemaDefinition = bincode::deserialize(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for keyspace in definition.keyspaces {
+                schema.keyspaces.insert(keyspace.name.clone(), keyspace);
+            }
+            for table in definition.tables {
+                schema
+                    .tables
+                    .insert((table.keyspace.clone(), table.name.clone()), table);
+            }
+            for index in definition.indexes {
+                schema
+                    .indexes
+                    .insert((index.keyspace.clone(), index.name.clone()), index);
+            }
+        }
+
+        schema.path = Some(path);
+        Ok(schema)
+    }
+
+    pub fn keyspace(&self, name: &str) -> Option<&KeyspaceMetadata> {
+        self.keyspaces.get(name)
+    }
+
+    pub fn table(&self, keyspace: &str, name: &str) -> Option<&TableMetadata> {
+        self.tables.get(&(keyspace.to_string(), name.to_string()))
+    }
+
+    /// Returns the tables of a keyspace.
+    pub fn tables_of(&self, keyspace: &str) -> Vec<&TableMetadata> {
+        self.tables
+            .values()
+            .filter(|table| table.keyspace == keyspace)
+            .collect()
+    }
+
+    /// Returns the secondary index on a column of a table, if there is one.
+    pub fn index_on(&self, keyspace: &str, table: &str, column: &str) -> Option<&IndexMetadata> {
+        self.indexes.values().find(|index| {
+            index.keyspace == keyspace && index.table == table && index.column == column
+        })
+    }
+
+    /// Adds a keyspace, returning `false` if it already exists.
+    pub fn create_keyspace(&mut self, keyspace: KeyspaceMetadata) -> bool {
+        if self.keyspaces.contains_key(&keyspace.name) {
+            return false;
+        }
+        self.keyspaces.insert(keyspace.name.clone(), keyspace);
+        self.save();
+        true
+    }
+
+    /// Adds a table, returning `false` if it already exists.
+    ///
+    /// Fails if the keyspace of the table doesn't exist.
+    pub fn create_table(&mut self, table: TableMetadata) -> Result<bool, ErrorResponse> {
+        if !self.keyspaces.contains_key(&table.keyspace) {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!("Keyspace {} does not exist", table.keyspace),
+            ));
+        }
+
+        let key = (table.keyspace.clone(), table.name.clone());
+        if self.tables.contains_key(&key) {
+            return Ok(false);
+        }
+        self.tables.insert(key, table);
+        self.save();
+        Ok(true)
+    }
+
+    /// Adds a secondary index, returning `false` if an index of the same name already exists.
+    ///
+    /// Fails if the table of the index doesn't exist.
+    pub fn create_index(&mut self, index: IndexMetadata) -> Result<bool, ErrorResponse> {
+        if self.table(&index.keyspace, &index.table).is_none() {
+            return Err(ErrorResponse::new(
+                ErrorCode::Invalid,
+                format!("Table {}.{} does not exist", index.keyspace, index.table),
+            ));
+        }
+
+        let key = (index.keyspace.clone(), index.name.clone());
+        if self.indexes.contains_key(&key) {
+            return Ok(false);
+        }
+        self.indexes.insert(key, index);
+        self.save();
+        Ok(true)
+    }
+
+    /// Returns all keyspaces, tables and indexes, ordered by name.
+    pub fn definition(&self) -> SchemaDefinition {
+        SchemaDefinition {
+            keyspaces: self.keyspaces.values().cloned().collect(),
+            tables: self.tables.values().cloned().collect(),
+            indexes: self.indexes.values().cloned().collect(),
+        }
+    }
+
+    /// Returns the MD5 digest of the keyspaces, tables and indexes, which is the same on every
+    /// node knowing the same ones.
+    pub fn version(&self) -> Uuid {
+        let bytes = bincode::serialize(&self.definition()).unwrap_or_default();
+        Uuid::from_bytes(Md5::digest(bytes).into())
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        // written to a temporary file first, so a crash never leaves half a schema behind
+        let temporary_path = path.with_extension("tmp");
+        let result = bincode::serialize(&self.definition())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            .and_then(|bytes| fs::write(&temporary_path, bytes))
+            .and_then(|_| fs::rename(&temporary_path, path));
+        if let Err(e) = result {
+            warn!("Failed to save the schema to {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Pulls the keyspaces, tables and indexes this node misses from nodes with another schema
+/// version.
+pub(crate) struct SchemaPuller {
+    current_host: Node,
+    cluster: GlobalCluster,
+    schema: GlobalSchema,
+    rows: GlobalRowStorage,
+    events: GlobalEventBus,
+    connection_pool: ConnectionPool,
+}
+
+impl SchemaPuller {
+    pub(crate) fn new(
+        current_host: Node,
+        cluster: GlobalCluster,
+        schema: GlobalSchema,
+        rows: GlobalRowStorage,
+        events: GlobalEventBus,
+        connection_pool: ConnectionPool,
+    ) -> Self {
+        Self {
+            current_host,
+            cluster,
+            schema,
+            rows,
+            events,
+            connection_pool,
+        }
+    }
+
+    pub(crate) fn run(&mut self) {
+        loop {
+            thread::sleep(SCHEMA_CHECK_INTERVAL);
+
+            let nodes: Vec<Node> = self
+                .cluster
+                .read()
+                .unwrap()
+                .get_nodes()
+                .iter()
+                .filter(|node| node.address != self.current_host.address)
+                .cloned()
+                .collect();
+
+            for node in nodes {
+                let version = self.schema.lock().unwrap().version();
+                // unreachable nodes are reported by the failure detector
+                if let Ok(Response::SchemaVersion(other_version)) =
+                    self.request(&node, Request::SchemaVersion)
+                    && other_version != version
+                {
+                    self.pull(&node);
+                }
+            }
+        }
+    }
+
+    /// Adds the keyspaces, tables and indexes of another node that this node doesn't know yet.
+    fn pull(&mut self, node: &Node) {
+        let definition = match self.request(node, Request::PullSchema) {
+            Ok(Response::Schema(definition)) => definition,
+            result => {
+                warn!(
+                    "Failed to pull the schema of {}: {:?}",
+                    node.address, result
+                );
+                return;
+            }
+        };
+        info!("Merging the schema of {}", node.address);
+
+        for keyspace in definition.keyspaces.iter() {
+            let _ = create_keyspace_handler::handle(keyspace, &self.schema, &self.events);
+        }
+        for table in definition.tables.iter() {
+            let _ = create_table_handler::handle(table, &self.schema, &self.events);
+
+            let schema = self.schema.lock().unwrap();
+            if schema
+                .table(&table.keyspace, &table.name)
+                .is_some_and(|known_table| known_table != table)
+            {
+                warn!(
+                    "Table {}.{} is defined differently on {}, keeping the definition of this node",
+                    table.keyspace, table.name, node.address
+                );
+            }
+        }
+        for index in definition.indexes.iter() {
+            let _ = create_index_handler::handle(index, &self.schema, &self.rows, &self.events);
+        }
+    }
+
+    fn request(&mut self, node: &Node, request: Request) -> AppResult<Response> {
+        self.connection_pool
+            .execute(RoutingStrategy::Direct(node), request, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::Schema;
+    use shared::protocol::types::{
+        ClusteringOrder, ColumnSpec, DataType, IndexMetadata, KeyspaceMetadata, TableMetadata,
+    };
+    use std::fs;
+
+    fn keyspace() -> KeyspaceMetadata {
+        KeyspaceMetadata {
+            name: "shop".to_string(),
+            replication_factor: 2,
+        }
+    }
+
+    fn table() -> TableMetadata {
+        TableMetadata {
+            keyspace: "shop".to_string(),
+            name: "events".to_string(),
+            columns: vec![
+                ColumnSpec::new("id", DataType::Uuid),
+                ColumnSpec::new("at", DataType::Timestamp),
+                ColumnSpec::new("tags", DataType::Set(Box::new(DataType::Text))),
+            ],
+            partition_key: vec!["id".to_string()],
+            clustering_key: vec!["at".to_string()],
+            clustering_order: vec![ClusteringOrder::Desc],
+        }
+    }
+
+    fn index() -> IndexMetadata {
+        IndexMetadata {
+            keyspace: "shop".to_string(),
+            table: "events".to_string(),
+            name: "events_at_idx".to_string(),
+            column: "at".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_version_depends_only_on_keyspaces_and_tables() {
+        let mut schema = Schema::new();
+        let mut other_schema = Schema::new();
+        assert_eq!(schema.version(), other_schema.version());
+
+        schema.create_keyspace(keyspace());
+        assert_ne!(schema.version(), other_schema.version());
+
+        other_schema.create_keyspace(keyspace());
+        schema.create_table(table()).unwrap();
+        other_schema.create_table(table()).unwrap();
+        assert_eq!(schema.version(), other_schema.version());
+    }
+
+    #[test]
+    fn test_load_restores_saved_schema() {
+        let directory = std::env::temp_dir().join(format!("schema-test-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("schema.bin");
+
+        let mut schema = Schema::load(path.clone()).unwrap();
+        schema.create_keyspace(keyspace());
+        schema.create_table(table()).unwrap();
+        schema.create_index(index()).unwrap();
+
+        let loaded = Schema::load(path).unwrap();
+        assert_eq!(loaded.table("shop", "events"), Some(&table()));
+        assert_eq!(loaded.index_on("shop", "events", "at"), Some(&index()));
+        assert_eq!(loaded.version(), schema.version());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
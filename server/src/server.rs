@@ -3,39 +3,183 @@
 //! This module contains the `Server` struct which manages incoming TCP connections,
 //! dispatches messages to appropriate handlers, and maintains global storage.
 
+use crate::auth;
+use crate::auth::{AllowAllAuthenticator, GlobalAuthenticator, PasswordAuthenticator};
+use crate::batchlog::{Batchlog, BatchlogReplayer, GlobalBatchlog};
+use crate::coordinator::{Coordinator, GlobalCluster};
+use crate::events::{EventBus, GlobalEventBus};
+use crate::failure_detector::FailureDetector;
 use crate::handler_manager::HandlerManager;
+use crate::local_node::LocalNode;
+use crate::paxos::{GlobalPaxos, Paxos};
+use crate::prepared_cache::{GlobalPreparedCache, PreparedCache};
+use crate::query_handler;
+use crate::query_handler::{AuditingQueryHandler, DefaultQueryHandler, GlobalQueryHandler};
 use crate::replicator::{ReplicationEntry, Replicator};
+use crate::roles::{GlobalRoles, Roles};
+use crate::schema::{GlobalSchema, Schema, SchemaPuller};
 use crate::storage;
-use crate::storage::{BTreeStorage, Storage};
+use crate::storage::{BTreeStorage, GlobalRowStorage, RowStorage, Storage};
+use crate::tracing::{GlobalTraces, SystemTraces};
 use log::{info, warn};
+use shared::auth::Credentials;
 use shared::cluster::{Cluster, Node};
 use shared::connection::Connection;
 use shared::connection_pool::ConnectionPool;
-use shared::error::AppResult;
-use std::net::{TcpListener, TcpStream};
+use shared::consistent_hash_ring::{ConsistentHashRing, DEFAULT_NUM_TOKENS};
+use shared::error::{AppResult, Error};
+use shared::partitioner;
+use shared::partitioner::{
+    ByteOrderedPartitioner, GlobalPartitioner, Murmur3Partitioner, RandomPartitioner,
+};
+use shared::protocol::types::{
+    ErrorCode, ErrorResponse, EventType, NodeTokens, ProtocolVersion, Request, Response,
+};
+use shared::routing::RoutingStrategy;
+use shared::stream::Stream;
+use shared::tls::{TlsAcceptor, TlsConnector, TlsSettings};
+use shared::token_allocator;
+use std::fs;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
 use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Mutex, mpsc};
+use std::sync::{Mutex, RwLock, mpsc};
 use std::thread;
+use std::time::Duration;
 use storage::GlobalStorage;
+use uuid::Uuid;
 
 /// The address of the server.
 const LOCALHOST: &str = "localhost";
 
 const NODES_ARG_KEY: &str = "nodes=";
+const DATA_DIR_ARG_KEY: &str = "data_dir=";
+const AUTHENTICATOR_ARG_KEY: &str = "authenticator=";
+const QUERY_HANDLER_ARG_KEY: &str = "query_handler=";
+const PARTITIONER_ARG_KEY: &str = "partitioner=";
+const NUM_TOKENS_ARG_KEY: &str = "num_tokens=";
+const INITIAL_TOKEN_ARG_KEY: &str = "initial_token=";
+const ALLOCATE_TOKENS_ARG_KEY: &str = "allocate_tokens_for_replication_factor=";
+const INTERNODE_USER_ARG_KEY: &str = "internode_user=";
+const INTERNODE_PASSWORD_FILE_ARG_KEY: &str = "internode_password_file=";
+
+/// Environment variable the internode password can be given in instead of a file.
+const INTERNODE_PASSWORD_ENV_VAR: &str = "INTERNODE_PASSWORD";
+const TLS_CERT_ARG_KEY: &str = "tls_cert=";
+const TLS_KEY_ARG_KEY: &str = "tls_key=";
+const TLS_CA_ARG_KEY: &str = "tls_ca=";
+const TLS_REQUIRE_CLIENT_AUTH_ARG_KEY: &str = "tls_require_client_auth=";
+
+/// Directory holding the data directories of the nodes, by port, unless `data_dir=` is given.
+const DEFAULT_DATA_DIR: &str = "data";
+
+/// File in the data directory the schema is saved to.
+const SCHEMA_FILE: &str = "schema.bin";
+
+/// Name of the file in the data directory holding what the node remembers about itself.
+const LOCAL_NODE_FILE: &str = "local.bin";
+
+/// File in the data directory the users and their permissions are saved to.
+const ROLES_FILE: &str = "roles.bin";
+
+/// How often the TLS certificates are checked for changes.
+const CERTIFICATE_RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Node-wide state shared by the connections of a server.
+#[derive(Clone)]
+pub(crate) struct NodeState {
+    pub(crate) current_host: Node,
+    pub(crate) storage: GlobalStorage,
+    pub(crate) schema: GlobalSchema,
+    pub(crate) rows: GlobalRowStorage,
+    pub(crate) paxos: GlobalPaxos,
+    pub(crate) prepared_cache: GlobalPreparedCache,
+    pub(crate) batchlog: GlobalBatchlog,
+    pub(crate) cluster: GlobalCluster,
+    pub(crate) events: GlobalEventBus,
+    pub(crate) roles: GlobalRoles,
+    pub(crate) authenticator: GlobalAuthenticator,
+    pub(crate) query_handler: GlobalQueryHandler,
+    pub(crate) traces: GlobalTraces,
+}
+
+#[cfg(test)]
+impl NodeState {
+    /// State of a node that is alone in its cluster and persists nothing, for tests.
+    pub(crate) fn single_node() -> Self {
+        let partitioner: GlobalPartitioner = std::sync::Arc::new(Murmur3Partitioner);
+        let current_host = Node::new(format!("{}:{}", LOCALHOST, 4000));
+        let cluster = Cluster::new(vec![current_host.clone()], partitioner.clone());
+        Self {
+            current_host,
+            storage: GlobalStorage::new(Mutex::new(BTreeStorage::new(partitioner.clone()))),
+            schema: GlobalSchema::new(Mutex::new(Schema::new())),
+            rows: GlobalRowStorage::new(Mutex::new(RowStorage::new(partitioner))),
+            paxos: GlobalPaxos::new(Mutex::new(Paxos::new())),
+            prepared_cache: GlobalPreparedCache::new(Mutex::new(PreparedCache::new())),
+            batchlog: GlobalBatchlog::new(Mutex::new(Batchlog::new())),
+            cluster: GlobalCluster::new(RwLock::new(cluster)),
+            events: GlobalEventBus::new(Mutex::new(EventBus::new())),
+            authenticator: std::sync::Arc::new(AllowAllAuthenticator),
+            roles: GlobalRoles::new(Mutex::new(Roles::new())),
+            query_handler: query_handler::from_name(DefaultQueryHandler::NAME).unwrap(),
+            traces: GlobalTraces::new(Mutex::new(SystemTraces::new())),
+        }
+    }
+}
 
 /// The main server struct.
 ///
 /// It holds the shared storage and provides methods to start the server and process connections.
 pub(crate) struct Server {
+    partitioner: GlobalPartitioner,
     storage: GlobalStorage,
+    schema: GlobalSchema,
+    rows: GlobalRowStorage,
+    paxos: GlobalPaxos,
+    prepared_cache: GlobalPreparedCache,
+    batchlog: GlobalBatchlog,
+    events: GlobalEventBus,
+    roles: GlobalRoles,
+    traces: GlobalTraces,
+    /// Credentials this node authenticates with when connecting to other nodes.
+    internode_credentials: Option<Credentials>,
+    /// Secures connections from clients and other nodes, if TLS is enabled.
+    tls_acceptor: Option<TlsAcceptor>,
+    /// Secures connections to other nodes, if TLS is enabled.
+    tls_connector: Option<TlsConnector>,
 }
 
 impl Server {
     /// Creates a new `Server` instance with empty storage.
     pub(crate) fn new() -> Self {
-        let storage = GlobalStorage::new(Mutex::new(BTreeStorage::new()));
+        let partitioner = Self::initialize_partitioner();
+        let storage = GlobalStorage::new(Mutex::new(BTreeStorage::new(partitioner.clone())));
+        let schema = GlobalSchema::new(Mutex::new(Schema::new()));
+        let rows = GlobalRowStorage::new(Mutex::new(RowStorage::new(partitioner.clone())));
+        let paxos = GlobalPaxos::new(Mutex::new(Paxos::new()));
+        let prepared_cache = GlobalPreparedCache::new(Mutex::new(PreparedCache::new()));
+        let batchlog = GlobalBatchlog::new(Mutex::new(Batchlog::new()));
+        let events = GlobalEventBus::new(Mutex::new(EventBus::new()));
+        let roles = GlobalRoles::new(Mutex::new(Roles::new()));
+        let traces = GlobalTraces::new(Mutex::new(SystemTraces::new()));
 
-        Self { storage }
+        Self {
+            partitioner,
+            storage,
+            schema,
+            rows,
+            paxos,
+            prepared_cache,
+            batchlog,
+            events,
+            roles,
+            traces,
+            internode_credentials: None,
+            tls_acceptor: None,
+            tls_connector: None,
+        }
     }
 
     /// Starts the server.
@@ -44,24 +188,70 @@ impl Server {
     pub fn start(&mut self) {
         let port = Self::get_port(std::env::args().collect());
 
+        let mut current_host = Node::new(format!("{}:{}", LOCALHOST, port));
         let nodes = Self::initialize_cluster_config();
-        let cluster = Cluster::new(nodes);
+        let cluster =
+            GlobalCluster::new(RwLock::new(Cluster::new(nodes, self.partitioner.clone())));
+        self.initialize_roles(port);
+        let authenticator = self.initialize_authenticator();
+        self.internode_credentials = Self::initialize_internode_credentials(&authenticator);
+        self.initialize_tls();
+        self.initialize_schema(port);
+        self.initialize_local_node(port, &mut current_host, &cluster);
+
+        let state = NodeState {
+            current_host: current_host.clone(),
+            storage: self.storage.clone(),
+            schema: self.schema.clone(),
+            rows: self.rows.clone(),
+            paxos: self.paxos.clone(),
+            prepared_cache: self.prepared_cache.clone(),
+            batchlog: self.batchlog.clone(),
+            cluster: cluster.clone(),
+            events: self.events.clone(),
+            roles: self.roles.clone(),
+            authenticator,
+            query_handler: Self::initialize_query_handler(),
+            traces: self.traces.clone(),
+        };
 
         let (tx, rx): (Sender<ReplicationEntry>, Receiver<ReplicationEntry>) = mpsc::channel();
 
         let mut replicator = Replicator::new(
-            format!("localhost:{}", port).to_string(),
-            cluster,
+            current_host.address.clone(),
+            cluster.clone(),
             rx,
-            ConnectionPool::new(),
+            self.connection_pool(),
         );
         thread::spawn(move || replicator.run());
 
+        let mut batchlog_replayer =
+            BatchlogReplayer::new(self.batchlog.clone(), self.coordinator(&state, &tx));
+        thread::spawn(move || batchlog_replayer.run());
+
+        let mut failure_detector = FailureDetector::new(
+            current_host.clone(),
+            cluster.clone(),
+            self.events.clone(),
+            self.connection_pool(),
+        );
+        thread::spawn(move || failure_detector.run());
+
+        let mut schema_puller = SchemaPuller::new(
+            current_host.clone(),
+            cluster.clone(),
+            self.schema.clone(),
+            self.rows.clone(),
+            self.events.clone(),
+            self.connection_pool(),
+        );
+        thread::spawn(move || schema_puller.run());
+
         let listener_result = TcpListener::bind(format!("{}:{}", LOCALHOST, port));
 
         info!("Server started on port {}", port);
         match listener_result {
-            Ok(listener) => self.start_accepting(listener, tx),
+            Ok(listener) => self.start_accepting(listener, tx, state),
             Err(e) => {
                 warn!("Error: {}", e)
             }
@@ -69,7 +259,12 @@ impl Server {
     }
 
     /// Continuously accepts incoming TCP connections and spawns a new thread for each.
-    fn start_accepting(&self, listener: TcpListener, sender: Sender<ReplicationEntry>) {
+    fn start_accepting(
+        &self,
+        listener: TcpListener,
+        sender: Sender<ReplicationEntry>,
+        state: NodeState,
+    ) {
         loop {
             let incoming_result = listener.accept();
 
@@ -83,13 +278,29 @@ impl Server {
 
             let (stream, client_address) = incoming_result.unwrap();
 
-            let storage = self.storage.clone();
+            let state = state.clone();
+            let coordinator = self.coordinator(&state, &sender);
+            let tls_acceptor = self.tls_acceptor.clone();
 
             let sender = sender.clone();
             thread::spawn(move || {
                 info!("Accepted connection from: {}", client_address);
 
-                let result = Self::handle_connection(stream, storage, sender);
+                // the handshake happens here so that a slow client doesn't block accepting
+                let stream = match tls_acceptor {
+                    Some(tls_acceptor) => match tls_acceptor.accept(stream) {
+                        Ok(stream) => Stream::from(stream),
+                        Err(e) => {
+                            warn!("TLS handshake with {} failed: {}", client_address, e);
+                            return;
+                        }
+                    },
+                    None => Stream::from(stream),
+                };
+
+                let events = state.events.clone();
+                let handler_manager = HandlerManager::new(state, sender, coordinator);
+                let result = Self::handle_connection(stream, handler_manager, events);
 
                 match result {
                     Ok(_) => {}
@@ -103,8 +314,20 @@ impl Server {
         }
     }
 
+    /// Creates a coordinator for a connection or background task of this node.
+    fn coordinator(&self, state: &NodeState, sender: &Sender<ReplicationEntry>) -> Coordinator {
+        Coordinator::new(state, sender.clone(), self.connection_pool())
+    }
+
+    /// Creates a connection pool for talking to the other nodes of the cluster.
+    fn connection_pool(&self) -> ConnectionPool {
+        ConnectionPool::new()
+            .with_credentials(self.internode_credentials.clone())
+            .with_tls(self.tls_connector.clone())
+    }
+
     /// Parses the port number from a command line argument string.
-    fn parse_port(arg: &String) -> i32 {
+    fn parse_port(arg: &str) -> i32 {
         let port_string = arg.split("=").nth(1);
         port_string.unwrap().parse::<i32>().unwrap()
     }
@@ -113,7 +336,7 @@ impl Server {
     fn get_port(args: Vec<String>) -> i32 {
         args.iter()
             .find(|arg| arg.starts_with("port"))
-            .map(Self::parse_port)
+            .map(|arg| Self::parse_port(arg))
             .unwrap_or(4000)
     }
 
@@ -121,28 +344,80 @@ impl Server {
     ///
     /// It initializes handlers and enters a loop to process messages from the client.
     fn handle_connection(
-        stream: TcpStream,
-        storage: GlobalStorage,
-        sender: Sender<ReplicationEntry>,
+        stream: Stream,
+        mut handler_manager: HandlerManager,
+        events: GlobalEventBus,
     ) -> AppResult<()> {
         let mut connection = Connection::new(stream);
-        let handler_manager = HandlerManager::new(storage, sender);
 
         loop {
-            Self::process_message(&mut connection, &handler_manager)?;
+            Self::process_message(&mut connection, &mut handler_manager, &events)?;
         }
     }
 
     /// Receives, processes, and responds to a single message from a connection.
+    ///
+    /// Responses are sent in the protocol version of the request. A request in an unsupported
+    /// version is answered with a protocol error listing the supported versions, after which
+    /// the connection is closed.
     fn process_message(
         connection: &mut Connection,
-        handler_manager: &HandlerManager,
+        handler_manager: &mut HandlerManager,
+        events: &GlobalEventBus,
     ) -> AppResult<()> {
-        let request = connection.receive_request()?;
+        let request = match connection.receive_request() {
+            Ok(request) => request,
+            Err(Error::UnsupportedVersion(version)) => {
+                connection.set_protocol_version(ProtocolVersion::closest(version.0));
+                let error = ErrorResponse::new(ErrorCode::ProtocolError, version.to_string());
+                connection.send_response(&Response::Error(error))?;
+                return Err(Error::UnsupportedVersion(version));
+            }
+            Err(e) => return Err(e),
+        };
 
-        let handler_result = handler_manager.handle(&request)?;
+        let (handler_result, extras) =
+            handler_manager.handle_with_extras(&request, connection.request_extras().clone())?;
 
-        connection.send_response(&handler_result)?;
+        connection.send_response_with_extras(&handler_result, extras)?;
+
+        // STARTUP and its answer are always plain frames; v5 switches to segments right after
+        if matches!(request, Request::Startup(_))
+            && matches!(handler_result, Response::Ready | Response::Authenticate(_))
+            && connection.protocol_version().uses_segments()
+        {
+            connection.enable_checksummed_framing();
+        }
+
+        if let Request::Register(event_types) = &request
+            && handler_result == Response::Ready
+        {
+            Self::forward_events(connection, event_types, events)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pushes the events the connection registered for to its client, from a separate thread.
+    ///
+    /// The thread stops once an event can't be written, i.e. after the client disconnected.
+    fn forward_events(
+        connection: &Connection,
+        event_types: &[EventType],
+        events: &GlobalEventBus,
+    ) -> AppResult<()> {
+        let mut event_writer = connection.event_writer()?;
+        let receiver = events.lock().unwrap().subscribe(event_types);
+        info!("Connection registered for events {:?}", event_types);
+
+        thread::spawn(move || {
+            for event in receiver {
+                if let Err(e) = event_writer.send_event(&event) {
+                    warn!("Failed to push event {:?}: {:?}", event, e);
+                    break;
+                }
+            }
+        });
 
         Ok(())
     }
@@ -154,13 +429,325 @@ impl Server {
             .expect("--nodes argument is required")
             .trim_start_matches(NODES_ARG_KEY)
             .split(",")
-            .map(|s| Node {
-                address: s.to_string(),
-            })
+            .map(Node::from)
             .collect::<Vec<Node>>();
 
         info!("Nodes: {:?}", nodes);
 
         nodes
     }
+
+    /// Creates the authenticator named by the `authenticator=` argument, allowing all
+    /// connections by default.
+    fn initialize_authenticator(&self) -> GlobalAuthenticator {
+        let name = Self::find_arg(AUTHENTICATOR_ARG_KEY)
+            .unwrap_or_else(|| AllowAllAuthenticator::NAME.to_string());
+
+        let authenticator = auth::from_name(&name, &self.roles).unwrap_or_else(|| {
+            panic!(
+                "Unknown authenticator {}, expected {} or {}",
+                name,
+                AllowAllAuthenticator::NAME,
+                PasswordAuthenticator::NAME
+            )
+        });
+        info!("Authenticator: {}", authenticator.name());
+
+        authenticator
+    }
+
+    /// Creates the partitioner named by the `partitioner=` argument, which every node of the
+    /// cluster has to agree on, using the Murmur3 partitioner by default.
+    fn initialize_partitioner() -> GlobalPartitioner {
+        let name = Self::find_arg(PARTITIONER_ARG_KEY)
+            .unwrap_or_else(|| Murmur3Partitioner::NAME.to_string());
+
+        let partitioner = partitioner::from_name(&name).unwrap_or_else(|| {
+            panic!(
+                "Unknown partitioner {}, expected {}, {} or {}",
+                name,
+                Murmur3Partitioner::NAME,
+                RandomPartitioner::NAME,
+                ByteOrderedPartitioner::NAME
+            )
+        });
+        info!("Partitioner: {}", partitioner.name());
+
+        partitioner
+    }
+
+    fn initialize_query_handler() -> GlobalQueryHandler {
+        let name = Self::find_arg(QUERY_HANDLER_ARG_KEY)
+            .unwrap_or_else(|| DefaultQueryHandler::NAME.to_string());
+
+        let query_handler = query_handler::from_name(&name).unwrap_or_else(|| {
+            panic!(
+                "Unknown query handler {}, expected {} or {}",
+                name,
+                DefaultQueryHandler::NAME,
+                AuditingQueryHandler::NAME
+            )
+        });
+        info!("Query handler: {}", query_handler.name());
+
+        query_handler
+    }
+
+    /// Returns the credentials for connecting to other nodes.
+    ///
+    /// The user is given with the `internode_user=` argument and the password, which would be
+    /// visible to every local user as an argument, in the `INTERNODE_PASSWORD` environment
+    /// variable or in the file given with `internode_password_file=`. Both are required if this
+    /// node requires authentication: the default superuser can be altered or dropped, so it
+    /// isn't assumed.
+    fn initialize_internode_credentials(
+        authenticator: &GlobalAuthenticator,
+    ) -> Option<Credentials> {
+        let user = Self::find_arg(INTERNODE_USER_ARG_KEY);
+        let password = Self::internode_password();
+
+        match (user, password) {
+            (Some(user), Some(password)) => Some(Credentials::new(user, password)),
+            (None, None) if authenticator.requires_authentication() => panic!(
+                "{} requires {} and {} or {} for connecting to other nodes",
+                authenticator.name(),
+                INTERNODE_USER_ARG_KEY,
+                INTERNODE_PASSWORD_ENV_VAR,
+                INTERNODE_PASSWORD_FILE_ARG_KEY
+            ),
+            (None, None) => None,
+            _ => panic!(
+                "{} and {} or {} must be given together",
+                INTERNODE_USER_ARG_KEY, INTERNODE_PASSWORD_ENV_VAR, INTERNODE_PASSWORD_FILE_ARG_KEY
+            ),
+        }
+    }
+
+    /// Reads the internode password from the environment or from its file, which must not be
+    /// readable by other users.
+    fn internode_password() -> Option<String> {
+        if let Ok(password) = std::env::var(INTERNODE_PASSWORD_ENV_VAR) {
+            return Some(password);
+        }
+
+        let path = Self::find_arg(INTERNODE_PASSWORD_FILE_ARG_KEY)?;
+        let metadata = fs::metadata(&path)
+            .unwrap_or_else(|e| panic!("Failed to read internode password file {}: {}", path, e));
+        if metadata.permissions().mode() & 0o077 != 0 {
+            panic!(
+                "Internode password file {} must not be accessible by other users",
+                path
+            );
+        }
+
+        let password = fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Failed to read internode password file {}: {}", path, e));
+        Some(password.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Enables TLS if a certificate is given with the `tls_cert=` and `tls_key=` arguments.
+    ///
+    /// Other nodes are verified against the CA given with `tls_ca=`, which clients have to
+    /// present a certificate of as well with `tls_require_client_auth=true`. The node's own
+    /// certificate is presented to other nodes requiring one. Changed certificates are
+    /// reloaded in the background.
+    fn initialize_tls(&mut self) {
+        let Some(cert) = Self::find_arg(TLS_CERT_ARG_KEY) else {
+            return;
+        };
+
+        let settings = TlsSettings {
+            cert: Some(PathBuf::from(cert)),
+            key: Self::find_arg(TLS_KEY_ARG_KEY).map(PathBuf::from),
+            ca: Some(PathBuf::from(
+                Self::find_arg(TLS_CA_ARG_KEY).unwrap_or_else(|| {
+                    panic!("{} is required to verify other nodes", TLS_CA_ARG_KEY)
+                }),
+            )),
+            require_client_auth: Self::find_arg(TLS_REQUIRE_CLIENT_AUTH_ARG_KEY)
+                .is_some_and(|value| value == "true"),
+        };
+
+        let tls_acceptor = TlsAcceptor::new(settings.clone())
+            .unwrap_or_else(|e| panic!("Invalid TLS configuration: {}", e));
+        let tls_connector = TlsConnector::new(settings.clone())
+            .unwrap_or_else(|e| panic!("Invalid TLS configuration: {}", e));
+        info!(
+            "TLS enabled, client certificates {}",
+            match settings.require_client_auth {
+                true => "required",
+                false => "optional",
+            }
+        );
+
+        let (acceptor, connector) = (tls_acceptor.clone(), tls_connector.clone());
+        thread::spawn(move || {
+            loop {
+                thread::sleep(CERTIFICATE_RELOAD_INTERVAL);
+
+                match acceptor
+                    .reload_if_changed()
+                    .and_then(|reloaded| Ok(reloaded | connector.reload_if_changed()?))
+                {
+                    Ok(true) => info!("Reloaded TLS certificates"),
+                    Ok(false) => {}
+                    Err(e) => warn!("Failed to reload TLS certificates: {}", e),
+                }
+            }
+        });
+
+        self.tls_acceptor = Some(tls_acceptor);
+        self.tls_connector = Some(tls_connector);
+    }
+
+    /// Returns the data directory of this node, given with `data_dir=`, creating it if needed.
+    fn data_dir(port: i32) -> PathBuf {
+        let data_dir = Self::find_arg(DATA_DIR_ARG_KEY)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DATA_DIR).join(port.to_string()));
+        std::fs::create_dir_all(&data_dir)
+            .unwrap_or_else(|e| panic!("Cannot create data directory {:?}: {}", data_dir, e));
+        data_dir
+    }
+
+    /// Loads the users saved in the data directory given with `data_dir=`, starting out with
+    /// the default superuser when there are none.
+    fn initialize_roles(&mut self, port: i32) {
+        let path = Self::data_dir(port).join(ROLES_FILE);
+
+        let roles = Roles::load(path.clone())
+            .unwrap_or_else(|e| panic!("Cannot load the roles from {:?}: {}", path, e));
+        *self.roles.lock().unwrap() = roles;
+    }
+
+    /// Loads the schema saved in the data directory given with `data_dir=`, which defaults to
+    /// a directory named after the port.
+    fn initialize_schema(&mut self, port: i32) {
+        let data_dir = Self::data_dir(port);
+
+        let schema = Schema::load(data_dir.join(SCHEMA_FILE))
+            .unwrap_or_else(|e| panic!("Cannot load the schema from {:?}: {}", data_dir, e));
+        info!("Schema version: {}", schema.version());
+
+        // rows aren't persisted, so the indexes start out empty and fill as rows are written
+        let mut rows = self.rows.lock().unwrap();
+        for index in schema.definition().indexes {
+            if let Some(table) = schema.table(&index.keyspace, &index.table) {
+                rows.create_index(table, &index.column);
+            }
+        }
+        *self.schema.lock().unwrap() = schema;
+    }
+
+    /// Gives this node the host id it generated and places it on the ring at the tokens it
+    /// picked when it first started.
+    ///
+    /// A new node takes the tokens given with `initial_token=`, formatted like the tokens of
+    /// the partitioner and separated by commas. Otherwise it picks `num_tokens=` tokens:
+    /// allocated to balance the ring for the replication factor given with
+    /// `allocate_tokens_for_replication_factor=`, or derived from its address by default.
+    fn initialize_local_node(&self, port: i32, current_host: &mut Node, cluster: &GlobalCluster) {
+        let path = Self::data_dir(port).join(LOCAL_NODE_FILE);
+        let mut local_node = LocalNode::load(&path)
+            .unwrap_or_else(|e| panic!("Cannot load the local node from {:?}: {}", path, e));
+
+        let generate_host_id = local_node.host_id.is_none();
+        let host_id = *local_node.host_id.get_or_insert_with(Uuid::new_v4);
+        let pick_tokens = local_node.tokens.is_empty();
+
+        if pick_tokens {
+            local_node.tokens = self.pick_tokens(current_host, cluster);
+        } else if Self::find_arg(INITIAL_TOKEN_ARG_KEY).is_some()
+            || Self::find_arg(NUM_TOKENS_ARG_KEY).is_some()
+        {
+            warn!(
+                "Keeping the tokens picked when this node first started, ignoring token settings"
+            );
+        }
+        if generate_host_id || pick_tokens {
+            local_node
+                .save()
+                .unwrap_or_else(|e| panic!("Cannot save the local node to {:?}: {}", path, e));
+        }
+        info!("Host id: {}", host_id);
+        info!("Tokens: {} tokens", local_node.tokens.len());
+
+        current_host.host_id = Some(host_id);
+        cluster.write().unwrap().learn_node(NodeTokens {
+            address: current_host.address.clone(),
+            host_id: Some(host_id),
+            tokens: local_node.tokens,
+        });
+    }
+
+    fn pick_tokens(&self, current_host: &Node, cluster: &GlobalCluster) -> Vec<u64> {
+        if let Some(initial_token) = Self::find_arg(INITIAL_TOKEN_ARG_KEY) {
+            let partitioner = cluster.read().unwrap().partitioner().clone();
+            return initial_token
+                .split(',')
+                .map(|token| {
+                    partitioner
+                        .parse_token(token.trim())
+                        .unwrap_or_else(|| panic!("Invalid initial token {}", token))
+                })
+                .collect();
+        }
+
+        let num_tokens = Self::find_arg(NUM_TOKENS_ARG_KEY)
+            .map(|num_tokens| {
+                num_tokens
+                    .parse::<usize>()
+                    .expect("num_tokens must be a number")
+            })
+            .unwrap_or(DEFAULT_NUM_TOKENS);
+        let Some(replication_factor) = Self::find_arg(ALLOCATE_TOKENS_ARG_KEY) else {
+            return ConsistentHashRing::default_tokens(&current_host.address, num_tokens);
+        };
+        let replication_factor = replication_factor
+            .parse::<usize>()
+            .expect("allocate_tokens_for_replication_factor must be a number");
+
+        self.learn_tokens(current_host, cluster);
+        let tokens = token_allocator::allocate_tokens(
+            &cluster.read().unwrap().get_ring_entities(),
+            &current_host.address,
+            num_tokens,
+            replication_factor,
+        );
+        info!(
+            "Allocated {} tokens for replication factor {}",
+            tokens.len(),
+            replication_factor
+        );
+        tokens
+    }
+
+    /// Asks the other nodes for their tokens, so that new tokens are allocated around them.
+    ///
+    /// Nodes that can't be reached keep their default tokens.
+    fn learn_tokens(&self, current_host: &Node, cluster: &GlobalCluster) {
+        let nodes = cluster.read().unwrap().get_nodes().to_vec();
+        let mut connection_pool = self.connection_pool();
+        for node in nodes
+            .iter()
+            .filter(|node| node.address != current_host.address)
+        {
+            match connection_pool.execute(RoutingStrategy::Direct(node), Request::Tokens, None) {
+                Ok(Response::Tokens(node_tokens)) => {
+                    for node_tokens in node_tokens {
+                        if node_tokens.address == node.address {
+                            cluster.write().unwrap().learn_node(node_tokens);
+                        }
+                    }
+                }
+                result => warn!("Cannot learn the tokens of {}: {:?}", node.address, result),
+            }
+        }
+    }
+
+    fn find_arg(key: &str) -> Option<String> {
+        std::env::args()
+            .find(|arg| arg.starts_with(key))
+            .map(|arg| arg.trim_start_matches(key).to_string())
+    }
 }
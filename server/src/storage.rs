@@ -1,10 +1,18 @@
 //! Storage abstractions for the server.
 //!
-//! This module defines the `Storage` trait and a thread-safe `HashMap` implementation.
+//! This module defines the `Storage` trait and a thread-safe `HashMap` implementation for the
+//! values of ADD, and the `RowStorage` holding the rows of CQL tables, their counter shards
+//! and their secondary indexes.
 
-use shared::consistent_hash_ring::ConsistentHashRing;
-use std::collections::BTreeMap;
-use std::ops::Bound::Included;
+use shared::partitioner::GlobalPartitioner;
+use shared::protocol::types::{
+    ClusteringBound, ClusteringOrder, ClusteringSlice, CounterShard, CounterUpdate, CqlValue,
+    Mutation, ReadCommand, TableMetadata,
+};
+use shared::token_range::TokenRange;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Bound::{Included, Unbounded};
 use std::sync::{Arc, Mutex};
 
 /// A thread-safe, shared storage type.
@@ -14,42 +22,55 @@ pub type GlobalStorage = Arc<Mutex<BTreeStorage>>;
 pub trait Storage {
     type ValueType;
 
-    fn new() -> Self;
+    /// Creates an empty storage, keying values by the token the partitioner gives them.
+    fn new(partitioner: GlobalPartitioner) -> Self;
     /// Adds a value to the storage. Returns `true` if it was successfully added.
     fn add(&mut self, value: Self::ValueType) -> bool;
     /// Checks if a value exists in the storage.
     fn check(&self, value: &Self::ValueType) -> bool;
     /// Gets the number of elements in the storage.
     fn get_count(&self) -> usize;
+    /// Gets the number of bytes the elements in the storage take.
+    fn get_size(&self) -> usize;
+    /// Gets the number of elements with keys in a range.
+    fn get_count_in_range(&self, range: &TokenRange) -> usize;
 
     fn remove(&mut self, value: u64) -> bool;
 
-    fn get_values(&self, start: u64, end: u64) -> Vec<&Self::ValueType>;
-
-    fn get_keys_in_range(&self, start: u64, end: u64) -> Vec<u64>;
+    fn get_keys_in_range(&self, range: &TokenRange) -> Vec<u64>;
+    /// Gets up to `limit` values with keys in a range, in ring order from the start of the
+    /// range and after the key `after` of the range, with their keys.
+    fn get_page(
+        &self,
+        range: &TokenRange,
+        after: Option<u64>,
+        limit: usize,
+    ) -> Vec<(u64, &Self::ValueType)>;
 }
 
 pub struct BTreeStorage {
     data: BTreeMap<u64, String>,
+    partitioner: GlobalPartitioner,
 }
 
 impl Storage for BTreeStorage {
     type ValueType = String;
 
-    fn new() -> Self {
+    fn new(partitioner: GlobalPartitioner) -> Self {
         Self {
             data: BTreeMap::new(),
+            partitioner,
         }
     }
 
     fn add(&mut self, value: String) -> bool {
-        let hash = ConsistentHashRing::calculate_hash(value.as_str());
+        let hash = self.partitioner.token(value.as_bytes());
         let result = self.data.insert(hash, value);
         result.is_none()
     }
 
     fn check(&self, value: &String) -> bool {
-        let hash = ConsistentHashRing::calculate_hash(value.as_str());
+        let hash = self.partitioner.token(value.as_bytes());
         self.data.contains_key(&hash)
     }
 
@@ -57,21 +78,1266 @@ impl Storage for BTreeStorage {
         self.data.len()
     }
 
+    fn get_size(&self) -> usize {
+        self.data.values().map(|value| value.len()).sum()
+    }
+
+    fn get_count_in_range(&self, range: &TokenRange) -> usize {
+        range
+            .bounds()
+            .into_iter()
+            .map(|bounds| self.data.range(bounds).count())
+            .sum()
+    }
+
     fn remove(&mut self, value: u64) -> bool {
         self.data.remove(&value).is_some()
     }
 
-    fn get_values(&self, start: u64, end: u64) -> Vec<&String> {
-        self.data
-            .range((Included(start), Included(end)))
-            .map(|(_, v)| v)
+    fn get_keys_in_range(&self, range: &TokenRange) -> Vec<u64> {
+        range
+            .bounds()
+            .into_iter()
+            .flat_map(|bounds| self.data.range(bounds).map(|(k, _)| *k))
             .collect()
     }
 
-    fn get_keys_in_range(&self, start: u64, end: u64) -> Vec<u64> {
-        self.data
-            .range((Included(start), Included(end)))
-            .map(|(k, _)| *k)
+    fn get_page(
+        &self,
+        range: &TokenRange,
+        after: Option<u64>,
+        limit: usize,
+    ) -> Vec<(u64, &String)> {
+        let range = match after.map(|after| range.after(after)) {
+            Some(Some(rest)) => rest,
+            Some(None) => return Vec::new(),
+            None => *range,
+        };
+        range
+            .bounds()
+            .into_iter()
+            .flat_map(|bounds| self.data.range(bounds))
+            .take(limit)
+            .map(|(k, v)| (*k, v))
             .collect()
     }
 }
+
+/// A thread-safe, shared row storage.
+pub type GlobalRowStorage = Arc<Mutex<RowStorage>>;
+
+/// Position of a partition within its table: the partition's token, then its key values to
+/// tell apart partitions whose tokens collide.
+type PartitionPosition = (u64, Vec<String>);
+
+/// The rows of CQL tables, ordered by partition token within each table and by clustering key
+/// within each partition.
+///
+/// Every cell remembers when it was written, so mutations can be applied in any order and
+/// more than once: the most recent write of a cell wins, and a deletion hides everything
+/// written before it. Counters are kept as shards instead, merged by their clocks.
+///
+/// Secondary indexes are local to the node: they index the rows the node stores, and are kept
+/// up to date as mutations are applied, however the rows arrive.
+pub struct RowStorage {
+    tables: HashMap<(String, String), BTreeMap<PartitionPosition, Partition>>,
+    /// Secondary indexes by table and indexed column.
+    indexes: HashMap<(String, String), HashMap<String, SecondaryIndex>>,
+    /// Gives partitions their tokens.
+    partitioner: GlobalPartitioner,
+}
+
+/// The positions of the live rows of a table by the text of their value in the indexed
+/// column, in token and clustering order.
+type SecondaryIndex = BTreeMap<String, BTreeSet<(PartitionPosition, ClusteringKey)>>;
+
+struct Partition {
+    partition_key: Vec<CqlValue>,
+    rows: BTreeMap<ClusteringKey, Row>,
+}
+
+/// The clustering key of a row, or a bound of a slice of rows, ordered by the clustering
+/// order of its table.
+#[derive(Debug, Clone)]
+struct ClusteringKey {
+    values: Vec<CqlValue>,
+    order: Vec<ClusteringOrder>,
+    edge: Edge,
+}
+
+/// Where a key sorts relative to the keys starting with its values: bounds sort right before
+/// or after all of them, so that a slice can be read with a single range of the partition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Edge {
+    Before,
+    Row,
+    After,
+}
+
+/// A row as stored, including the writes and deletions that decide which cells are live.
+#[derive(Debug, Clone)]
+struct Row {
+    cells: HashMap<String, Cell>,
+    /// Time of the latest INSERT, which keeps the row alive even without cells.
+    marker: Option<i64>,
+    /// Time of the latest deletion of the whole row.
+    deleted_at: Option<i64>,
+    /// The shards of each counter column by the replica owning them.
+    counters: HashMap<String, HashMap<String, CounterShard>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cell {
+    /// Null for a deleted cell.
+    value: CqlValue,
+    timestamp: i64,
+}
+
+/// A live row as read from storage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRow {
+    pub partition_key: Vec<CqlValue>,
+    pub clustering_key: Vec<CqlValue>,
+    /// The live cells of the row by column name; the primary key columns are not included.
+    pub cells: HashMap<String, CqlValue>,
+}
+
+impl RowStorage {
+    pub fn new(partitioner: GlobalPartitioner) -> Self {
+        Self {
+            tables: HashMap::new(),
+            indexes: HashMap::new(),
+            partitioner,
+        }
+    }
+
+    /// Applies a mutation of a table, keeping the most recent write of every cell and
+    /// updating the indexes of the table.
+    pub fn apply(&mut self, table: &TableMetadata, mutation: &Mutation) {
+        let table_key = (mutation.keyspace.clone(), mutation.table.clone());
+        let position = self.position(&mutation.partition_key);
+        let clustering_key = ClusteringKey {
+            values: mutation.clustering_key.clone(),
+            order: table.clustering_order.clone(),
+            edge: Edge::Row,
+        };
+        let row = self
+            .tables
+            .entry(table_key.clone())
+            .or_default()
+            .entry(position.clone())
+            .or_insert_with(|| Partition {
+                partition_key: mutation.partition_key.clone(),
+                rows: BTreeMap::new(),
+            })
+            .rows
+            .entry(clustering_key.clone())
+            .or_insert_with(|| Row {
+                cells: HashMap::new(),
+                marker: None,
+                deleted_at: None,
+                counters: HashMap::new(),
+            });
+
+        let Some(indexes) = self.indexes.get_mut(&table_key) else {
+            row.apply(mutation);
+            return;
+        };
+        let indexed_values: Vec<(String, Option<String>)> = indexes
+            .keys()
+            .map(|column| (column.clone(), row.value(table, &clustering_key, column)))
+            .collect();
+        row.apply(mutation);
+
+        let row_position = (position, clustering_key);
+        for (column, old_value) in indexed_values {
+            let new_value = row.value(table, &row_position.1, &column);
+            if new_value == old_value {
+                continue;
+            }
+            let index = indexes.get_mut(&column).unwrap();
+            if let Some(old_value) = old_value
+                && let Some(positions) = index.get_mut(&old_value)
+            {
+                positions.remove(&row_position);
+                if positions.is_empty() {
+                    index.remove(&old_value);
+                }
+            }
+            if let Some(new_value) = new_value {
+                index
+                    .entry(new_value)
+                    .or_default()
+                    .insert(row_position.clone());
+            }
+        }
+    }
+
+    /// Applies increments of counters as the replica leading them: the replica's shard of
+    /// each counter gets the increment and a higher clock.
+    ///
+    /// Returns the mutation writing the new shards, already applied here, for the other
+    /// replicas to apply.
+    pub fn increment(
+        &mut self,
+        table: &TableMetadata,
+        update: &CounterUpdate,
+        replica: &str,
+        timestamp: i64,
+    ) -> Mutation {
+        let row = self
+            .tables
+            .get(&(update.keyspace.clone(), update.table.clone()))
+            .and_then(|partitions| partitions.get(&self.position(&update.partition_key)))
+            .and_then(|partition| {
+                partition.rows.get(&ClusteringKey {
+                    values: update.clustering_key.clone(),
+                    order: table.clustering_order.clone(),
+                    edge: Edge::Row,
+                })
+            });
+
+        let counters = update
+            .deltas
+            .iter()
+            .map(|(column, delta)| {
+                let shard = row
+                    .and_then(|row| row.counters.get(column))
+                    .and_then(|shards| shards.get(replica));
+                let shard = CounterShard {
+                    replica: replica.to_string(),
+                    clock: shard.map_or(0, |shard| shard.clock) + 1,
+                    count: shard.map_or(0, |shard| shard.count) + delta,
+                };
+                (column.clone(), shard)
+            })
+            .collect();
+        let mutation = Mutation {
+            keyspace: update.keyspace.clone(),
+            table: update.table.clone(),
+            partition_key: update.partition_key.clone(),
+            clustering_key: update.clustering_key.clone(),
+            cells: Vec::new(),
+            row_marker: false,
+            row_deletion: false,
+            counters,
+            timestamp,
+        };
+
+        self.apply(table, &mutation);
+        mutation
+    }
+
+    /// Indexes a column of a table, building the index from the rows stored so far. Rows
+    /// applied later are indexed as they are applied.
+    pub fn create_index(&mut self, table: &TableMetadata, column: &str) {
+        let table_key = (table.keyspace.clone(), table.name.clone());
+
+        let mut index = SecondaryIndex::new();
+        for (position, partition) in self.tables.get(&table_key).into_iter().flatten() {
+            for (clustering_key, row) in partition.rows.iter() {
+                if let Some(value) = row.value(table, clustering_key, column) {
+                    index
+                        .entry(value)
+                        .or_default()
+                        .insert((position.clone(), clustering_key.clone()));
+                }
+            }
+        }
+        self.indexes
+            .entry(table_key)
+            .or_default()
+            .insert(column.to_string(), index);
+    }
+
+    /// Reads the live rows of a table, of one partition or of all partitions in token order,
+    /// along with the number of tombstones scanned on the way.
+    ///
+    /// Only the rows in the command's slice of each partition are read, up to its limit. With
+    /// an index expression, the rows are looked up in the index on its column instead, which
+    /// must have been created.
+    pub fn read(&self, table: &TableMetadata, command: &ReadCommand) -> (Vec<StoredRow>, usize) {
+        let table_key = (table.keyspace.clone(), table.name.clone());
+        let Some(partitions) = self.tables.get(&table_key) else {
+            return (Vec::new(), 0);
+        };
+        let mut tombstones = 0;
+
+        if let Some(expression) = &command.index {
+            let partition_position = command
+                .partition_key
+                .as_deref()
+                .map(|partition_key| self.position(partition_key));
+            let rows = self
+                .indexes
+                .get(&table_key)
+                .and_then(|indexes| indexes.get(&expression.column))
+                .and_then(|index| index.get(&expression.value.to_string()))
+                .into_iter()
+                .flatten()
+                .filter(|(position, _)| {
+                    partition_position
+                        .as_ref()
+                        .is_none_or(|partition_position| partition_position == position)
+                })
+                .filter_map(|(position, clustering_key)| {
+                    let partition = partitions.get(position)?;
+                    let row = partition.rows.get(clustering_key)?;
+                    tombstones += row.tombstones();
+                    row.live(&partition.partition_key, clustering_key)
+                })
+                .take(command.limit.unwrap_or(usize::MAX))
+                .collect();
+            return (rows, tombstones);
+        }
+
+        let partitions: Vec<&Partition> = match &command.partition_key {
+            Some(partition_key) => partitions
+                .get(&self.position(partition_key))
+                .into_iter()
+                .collect(),
+            None => partitions.values().collect(),
+        };
+        let rows = partitions
+            .into_iter()
+            .flat_map(|partition| {
+                partition
+                    .slice(table, &command.slice)
+                    .map(move |(clustering_key, row)| (partition, clustering_key, row))
+            })
+            .filter_map(|(partition, clustering_key, row)| {
+                tombstones += row.tombstones();
+                row.live(&partition.partition_key, clustering_key)
+            })
+            .take(command.limit.unwrap_or(usize::MAX))
+            .collect();
+        (rows, tombstones)
+    }
+
+    /// Returns the rows of a table in the command's partition and slice as the mutations
+    /// writing them, with their timestamps and deletions, so that another node can merge its
+    /// copy of the rows with them. The limit and index expression of the command are ignored.
+    pub fn read_mutations(&self, table: &TableMetadata, command: &ReadCommand) -> Vec<Mutation> {
+        let Some(partitions) = self
+            .tables
+            .get(&(table.keyspace.clone(), table.name.clone()))
+        else {
+            return Vec::new();
+        };
+
+        let partitions: Vec<&Partition> = match &command.partition_key {
+            Some(partition_key) => partitions
+                .get(&self.position(partition_key))
+                .into_iter()
+                .collect(),
+            None => partitions.values().collect(),
+        };
+        partitions
+            .into_iter()
+            .flat_map(|partition| {
+                partition
+                    .slice(table, &command.slice)
+                    .flat_map(move |(clustering_key, row)| {
+                        row.mutations(table, &partition.partition_key, clustering_key)
+                    })
+            })
+            .collect()
+    }
+
+    /// Returns the rows of a table in some ranges of the ring as the mutations writing them,
+    /// deletions included, so that another node can take the rows over.
+    pub fn read_ranges(&self, table: &TableMetadata, ranges: &[TokenRange]) -> Vec<Mutation> {
+        let Some(partitions) = self
+            .tables
+            .get(&(table.keyspace.clone(), table.name.clone()))
+        else {
+            return Vec::new();
+        };
+
+        partitions
+            .iter()
+            .filter(|((token, _), _)| ranges.iter().any(|range| range.contains(*token)))
+            .flat_map(|(_, partition)| {
+                partition
+                    .rows
+                    .iter()
+                    .flat_map(move |(clustering_key, row)| {
+                        row.mutations(table, &partition.partition_key, clustering_key)
+                    })
+            })
+            .collect()
+    }
+
+    /// Removes the partitions of every table of a keyspace in some ranges of the ring, along
+    /// with their index entries, once other nodes took them over.
+    ///
+    /// Returns the number of partitions removed.
+    pub fn drop_ranges(&mut self, keyspace: &str, ranges: &[TokenRange]) -> usize {
+        let in_ranges =
+            |position: &PartitionPosition| ranges.iter().any(|range| range.contains(position.0));
+
+        let mut removed = 0;
+        for ((table_keyspace, _), partitions) in self.tables.iter_mut() {
+            if table_keyspace == keyspace {
+                let count = partitions.len();
+                partitions.retain(|position, _| !in_ranges(position));
+                removed += count - partitions.len();
+            }
+        }
+        for ((table_keyspace, _), indexes) in self.indexes.iter_mut() {
+            if table_keyspace != keyspace {
+                continue;
+            }
+            for index in indexes.values_mut() {
+                for positions in index.values_mut() {
+                    positions.retain(|(position, _)| !in_ranges(position));
+                }
+                index.retain(|_, positions| !positions.is_empty());
+            }
+        }
+        removed
+    }
+
+    /// Estimates the bytes the rows of every table take: their keys, cells and counter
+    /// shards, deleted ones included until they are overwritten.
+    pub fn size(&self) -> usize {
+        self.tables
+            .values()
+            .flat_map(|partitions| partitions.values())
+            .map(Partition::size)
+            .sum()
+    }
+
+    fn position(&self, partition_key: &[CqlValue]) -> PartitionPosition {
+        (
+            self.partitioner.partition_key_token(partition_key),
+            partition_key
+                .iter()
+                .map(|value| value.to_string())
+                .collect(),
+        )
+    }
+}
+
+impl Partition {
+    fn size(&self) -> usize {
+        let rows: usize = self
+            .rows
+            .iter()
+            .map(|(clustering_key, row)| values_size(&clustering_key.values) + row.size())
+            .sum();
+        values_size(&self.partition_key) + rows
+    }
+
+    /// Returns the rows of a slice of the partition, deleted ones included, in the slice's
+    /// order.
+    fn slice<'p>(
+        &'p self,
+        table: &TableMetadata,
+        slice: &ClusteringSlice,
+    ) -> Box<dyn Iterator<Item = (&'p ClusteringKey, &'p Row)> + 'p> {
+        let bound = |bound: &Option<ClusteringBound>, edge_if_inclusive, edge_if_exclusive| {
+            bound.as_ref().map(|bound| ClusteringKey {
+                values: bound.prefix.clone(),
+                order: table.clustering_order.clone(),
+                edge: match bound.inclusive {
+                    true => edge_if_inclusive,
+                    false => edge_if_exclusive,
+                },
+            })
+        };
+        let start = bound(&slice.start, Edge::Before, Edge::After);
+        let end = bound(&slice.end, Edge::After, Edge::Before);
+        // bounds never equal a row, so whether they are included doesn't matter
+        if let (Some(start), Some(end)) = (&start, &end)
+            && start > end
+        {
+            return Box::new(std::iter::empty());
+        }
+
+        let rows = self.rows.range((
+            start.map_or(Unbounded, Included),
+            end.map_or(Unbounded, Included),
+        ));
+        match slice.reversed {
+            true => Box::new(rows.rev()),
+            false => Box::new(rows),
+        }
+    }
+}
+
+impl Ord for ClusteringKey {
+    /// Compares the values both keys have in clustering order; a key starting with all values
+    /// of a shorter one sorts before or after it as the edge of the shorter one says.
+    fn cmp(&self, other: &Self) -> Ordering {
+        for ((value, other_value), order) in self.values.iter().zip(other.values.iter()).zip(
+            self.order
+                .iter()
+                .chain(std::iter::repeat(&ClusteringOrder::Asc)),
+        ) {
+            let ordering = value.partial_cmp(other_value).unwrap_or(Ordering::Equal);
+            let ordering = match order {
+                ClusteringOrder::Asc => ordering,
+                ClusteringOrder::Desc => ordering.reverse(),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+
+        match self.values.len().cmp(&other.values.len()) {
+            Ordering::Equal => self.edge.cmp(&other.edge),
+            Ordering::Less if self.edge == Edge::After => Ordering::Greater,
+            Ordering::Less => Ordering::Less,
+            Ordering::Greater if other.edge == Edge::After => Ordering::Less,
+            Ordering::Greater => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for ClusteringKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ClusteringKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ClusteringKey {}
+
+impl Row {
+    fn size(&self) -> usize {
+        let cells: usize = self
+            .cells
+            .iter()
+            .map(|(column, cell)| column.len() + values_size([&cell.value]) + size_of::<i64>())
+            .sum();
+        let counters: usize = self
+            .counters
+            .iter()
+            .flat_map(|(column, shards)| shards.values().map(move |shard| (column, shard)))
+            .map(|(column, shard)| column.len() + shard.replica.len() + 2 * size_of::<u64>())
+            .sum();
+        cells + counters
+    }
+
+    /// Returns the number of deletions the row keeps: that of the whole row and those of its
+    /// cells.
+    fn tombstones(&self) -> usize {
+        let deleted_cells = self
+            .cells
+            .values()
+            .filter(|cell| cell.value == CqlValue::Null)
+            .count();
+        usize::from(self.deleted_at.is_some()) + deleted_cells
+    }
+
+    fn is_live(&self) -> bool {
+        self.marker.is_some()
+            || !self.counters.is_empty()
+            || self.cells.values().any(|cell| cell.value != CqlValue::Null)
+    }
+
+    /// Returns the text of the row's value in a regular or clustering column, as kept by a
+    /// secondary index on the column, or `None` if the row has no live value there.
+    fn value(
+        &self,
+        table: &TableMetadata,
+        clustering_key: &ClusteringKey,
+        column: &str,
+    ) -> Option<String> {
+        if let Some(index) = table.clustering_key.iter().position(|key| key == column) {
+            return self
+                .is_live()
+                .then(|| clustering_key.values[index].to_string());
+        }
+        self.cells
+            .get(column)
+            .filter(|cell| cell.value != CqlValue::Null)
+            .map(|cell| cell.value.to_string())
+    }
+
+    /// Applies a mutation to the row, keeping the most recent write of every cell and the
+    /// counter shards with the highest clocks.
+    fn apply(&mut self, mutation: &Mutation) {
+        let timestamp = mutation.timestamp;
+
+        if mutation.row_deletion
+            && self
+                .deleted_at
+                .is_none_or(|deleted_at| deleted_at < timestamp)
+        {
+            self.deleted_at = Some(timestamp);
+            self.cells.retain(|_, cell| cell.timestamp > timestamp);
+            self.marker = self.marker.filter(|marker| *marker > timestamp);
+        }
+        if self
+            .deleted_at
+            .is_some_and(|deleted_at| deleted_at >= timestamp)
+        {
+            return;
+        }
+
+        if mutation.row_marker && self.marker.is_none_or(|marker| marker < timestamp) {
+            self.marker = Some(timestamp);
+        }
+        for (column, value) in mutation.cells.iter() {
+            if self
+                .cells
+                .get(column)
+                .is_none_or(|cell| cell.timestamp <= timestamp)
+            {
+                self.cells.insert(
+                    column.clone(),
+                    Cell {
+                        value: value.clone(),
+                        timestamp,
+                    },
+                );
+            }
+        }
+        for (column, shard) in mutation.counters.iter() {
+            let shards = self.counters.entry(column.clone()).or_default();
+            if shards
+                .get(&shard.replica)
+                .is_none_or(|known| known.clock < shard.clock)
+            {
+                shards.insert(shard.replica.clone(), shard.clone());
+            }
+        }
+    }
+
+    /// Returns the mutations that recreate the row when applied, in any order: its deletion,
+    /// its marker, its cells grouped by timestamp and its counter shards.
+    fn mutations(
+        &self,
+        table: &TableMetadata,
+        partition_key: &[CqlValue],
+        clustering_key: &ClusteringKey,
+    ) -> Vec<Mutation> {
+        let mutation = |timestamp| Mutation {
+            keyspace: table.keyspace.clone(),
+            table: table.name.clone(),
+            partition_key: partition_key.to_vec(),
+            clustering_key: clustering_key.values.clone(),
+            cells: Vec::new(),
+            row_marker: false,
+            row_deletion: false,
+            counters: Vec::new(),
+            timestamp,
+        };
+
+        let mut mutations = Vec::new();
+        if let Some(deleted_at) = self.deleted_at {
+            mutations.push(Mutation {
+                row_deletion: true,
+                ..mutation(deleted_at)
+            });
+        }
+        if let Some(marker) = self.marker {
+            mutations.push(Mutation {
+                row_marker: true,
+                ..mutation(marker)
+            });
+        }
+        let mut cells: BTreeMap<i64, Vec<(String, CqlValue)>> = BTreeMap::new();
+        for (column, cell) in self.cells.iter() {
+            cells
+                .entry(cell.timestamp)
+                .or_default()
+                .push((column.clone(), cell.value.clone()));
+        }
+        for (timestamp, cells) in cells {
+            mutations.push(Mutation {
+                cells,
+                ..mutation(timestamp)
+            });
+        }
+        if !self.counters.is_empty() {
+            // shards are merged by clock, and counter rows are never deleted
+            mutations.push(Mutation {
+                counters: self
+                    .counters
+                    .iter()
+                    .flat_map(|(column, shards)| {
+                        shards.values().map(|shard| (column.clone(), shard.clone()))
+                    })
+                    .collect(),
+                ..mutation(i64::MAX)
+            });
+        }
+        mutations
+    }
+
+    /// Returns the live cells of the row, or `None` if the row doesn't exist anymore.
+    fn live(
+        &self,
+        partition_key: &[CqlValue],
+        clustering_key: &ClusteringKey,
+    ) -> Option<StoredRow> {
+        if !self.is_live() {
+            return None;
+        }
+        let counters = self.counters.iter().map(|(column, shards)| {
+            let count = shards.values().map(|shard| shard.count).sum();
+            (column.clone(), CqlValue::BigInt(count))
+        });
+        let cells: HashMap<String, CqlValue> = self
+            .cells
+            .iter()
+            .filter(|(_, cell)| cell.value != CqlValue::Null)
+            .map(|(column, cell)| (column.clone(), cell.value.clone()))
+            .chain(counters)
+            .collect();
+
+        Some(StoredRow {
+            partition_key: partition_key.to_vec(),
+            clustering_key: clustering_key.values.clone(),
+            cells,
+        })
+    }
+}
+
+/// Returns the bytes the values take when encoded.
+fn values_size<'v>(values: impl IntoIterator<Item = &'v CqlValue>) -> usize {
+    values
+        .into_iter()
+        .map(|value| bincode::serialized_size(value).unwrap_or(0) as usize)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{BTreeStorage, RowStorage, Storage};
+    use shared::partitioner::{Murmur3Partitioner, Partitioner};
+    use shared::protocol::types::{
+        ClusteringBound, ClusteringOrder, ClusteringSlice, ColumnSpec, CounterUpdate, CqlValue,
+        DataType, IndexExpression, Mutation, ReadCommand, TableMetadata,
+    };
+    use shared::token_range::TokenRange;
+    use std::sync::Arc;
+
+    fn table(clustering_order: Vec<ClusteringOrder>) -> TableMetadata {
+        TableMetadata {
+            keyspace: "shop".to_string(),
+            name: "users".to_string(),
+            columns: vec![
+                ColumnSpec::new("name", DataType::Text),
+                ColumnSpec::new("day", DataType::Int),
+                ColumnSpec::new("order", DataType::Int),
+                ColumnSpec::new("age", DataType::Int),
+            ],
+            partition_key: vec!["name".to_string()],
+            clustering_key: ["day", "order"][..clustering_order.len()]
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+            clustering_order,
+        }
+    }
+
+    fn mutation(cells: Vec<(&str, CqlValue)>, timestamp: i64) -> Mutation {
+        Mutation {
+            keyspace: "shop".to_string(),
+            table: "users".to_string(),
+            partition_key: vec![CqlValue::Text("alice".to_string())],
+            clustering_key: Vec::new(),
+            cells: cells
+                .into_iter()
+                .map(|(column, value)| (column.to_string(), value))
+                .collect(),
+            row_marker: false,
+            row_deletion: false,
+            counters: Vec::new(),
+            timestamp,
+        }
+    }
+
+    fn command(name: &str, slice: ClusteringSlice) -> ReadCommand {
+        ReadCommand {
+            keyspace: "shop".to_string(),
+            table: "users".to_string(),
+            partition_key: Some(vec![CqlValue::Text(name.to_string())]),
+            slice,
+            limit: None,
+            index: None,
+        }
+    }
+
+    fn read_age(storage: &RowStorage) -> Option<CqlValue> {
+        storage
+            .read(
+                &table(Vec::new()),
+                &command("alice", ClusteringSlice::default()),
+            )
+            .0
+            .first()
+            .and_then(|row| row.cells.get("age").cloned())
+    }
+
+    /// Stores the rows (day, order) of alice's partition, inserted in a shuffled order.
+    fn storage_with_orders(table: &TableMetadata) -> RowStorage {
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        for (day, order) in [(2, 1), (1, 2), (3, 1), (1, 1), (2, 2), (10, 1)] {
+            storage.apply(
+                table,
+                &Mutation {
+                    clustering_key: vec![CqlValue::Int(day), CqlValue::Int(order)]
+                        [..table.clustering_key.len()]
+                        .to_vec(),
+                    row_marker: true,
+                    ..mutation(Vec::new(), 10)
+                },
+            );
+        }
+        storage
+    }
+
+    fn clustering_keys(
+        storage: &RowStorage,
+        table: &TableMetadata,
+        slice: ClusteringSlice,
+    ) -> Vec<Vec<i32>> {
+        storage
+            .read(table, &command("alice", slice))
+            .0
+            .into_iter()
+            .map(|row| {
+                row.clustering_key
+                    .iter()
+                    .map(|value| match value {
+                        CqlValue::Int(value) => *value,
+                        value => panic!("Unexpected clustering value {:?}", value),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn bound(prefix: &[i32], inclusive: bool) -> Option<ClusteringBound> {
+        Some(ClusteringBound {
+            prefix: prefix.iter().map(|value| CqlValue::Int(*value)).collect(),
+            inclusive,
+        })
+    }
+
+    #[test]
+    fn test_most_recent_write_wins_in_any_order() {
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+
+        storage.apply(
+            &table(Vec::new()),
+            &mutation(vec![("age", CqlValue::Int(31))], 20),
+        );
+        storage.apply(
+            &table(Vec::new()),
+            &mutation(vec![("age", CqlValue::Int(30))], 10),
+        );
+
+        assert_eq!(read_age(&storage), Some(CqlValue::Int(31)));
+    }
+
+    #[test]
+    fn test_size_grows_with_stored_data() {
+        let mut values = BTreeStorage::new(Arc::new(Murmur3Partitioner));
+        values.add("apple".to_string());
+        values.add("kiwi".to_string());
+        assert_eq!(values.get_size(), 9);
+
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        assert_eq!(storage.size(), 0);
+        storage.apply(
+            &table(Vec::new()),
+            &mutation(vec![("age", CqlValue::Int(30))], 10),
+        );
+        let size = storage.size();
+        assert!(size > 0);
+
+        // an overwritten cell takes no more space
+        storage.apply(
+            &table(Vec::new()),
+            &mutation(vec![("age", CqlValue::Int(31))], 20),
+        );
+        assert_eq!(storage.size(), size);
+        storage.apply(
+            &table(Vec::new()),
+            &mutation(vec![("name", CqlValue::Text("Alice".to_string()))], 30),
+        );
+        assert!(storage.size() > size);
+    }
+
+    #[test]
+    fn test_counter_shards_merge_without_double_counting() {
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        let table = table(Vec::new());
+        let update = |delta: i64| CounterUpdate {
+            keyspace: "shop".to_string(),
+            table: "users".to_string(),
+            partition_key: vec![CqlValue::Text("alice".to_string())],
+            clustering_key: Vec::new(),
+            deltas: vec![("age".to_string(), delta)],
+        };
+
+        let first = storage.increment(&table, &update(5), "10.0.0.1", 10);
+        storage.increment(&table, &update(-2), "10.0.0.1", 20);
+        // a replayed shard is older than the one it was replaced with
+        storage.apply(&table, &first);
+        assert_eq!(read_age(&storage), Some(CqlValue::BigInt(3)));
+
+        let mut other_replica = RowStorage::new(Arc::new(Murmur3Partitioner));
+        let shard = other_replica.increment(&table, &update(10), "10.0.0.2", 30);
+        storage.apply(&table, &shard);
+        storage.apply(&table, &shard);
+        assert_eq!(read_age(&storage), Some(CqlValue::BigInt(13)));
+    }
+
+    #[test]
+    fn test_deletion_hides_older_writes_only() {
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        let table = table(Vec::new());
+        let deletion = Mutation {
+            row_deletion: true,
+            ..mutation(Vec::new(), 20)
+        };
+
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(30))], 10));
+        storage.apply(&table, &deletion);
+        assert_eq!(read_age(&storage), None);
+
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(31))], 15));
+        assert_eq!(read_age(&storage), None);
+
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(32))], 30));
+        assert_eq!(read_age(&storage), Some(CqlValue::Int(32)));
+    }
+
+    #[test]
+    fn test_read_counts_scanned_tombstones() {
+        let table = table(vec![ClusteringOrder::Asc, ClusteringOrder::Asc]);
+        let mut storage = storage_with_orders(&table);
+        let delete = |day: i32, order: i32, cells: Vec<(&str, CqlValue)>| Mutation {
+            clustering_key: vec![CqlValue::Int(day), CqlValue::Int(order)],
+            row_deletion: cells.is_empty(),
+            ..mutation(cells, 20)
+        };
+        storage.apply(&table, &delete(1, 1, Vec::new()));
+        storage.apply(&table, &delete(2, 1, Vec::new()));
+        storage.apply(&table, &delete(3, 1, vec![("age", CqlValue::Null)]));
+
+        let (rows, tombstones) =
+            storage.read(&table, &command("alice", ClusteringSlice::default()));
+        assert_eq!(rows.len(), 4);
+        assert_eq!(tombstones, 3);
+
+        // the scan stops at the limit, before the later tombstones
+        let mut command = command("alice", ClusteringSlice::default());
+        command.limit = Some(1);
+        let (rows, tombstones) = storage.read(&table, &command);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(tombstones, 1);
+    }
+
+    #[test]
+    fn test_read_mutations_reconcile_copies_by_timestamp() {
+        let table = table(Vec::new());
+        let insert = Mutation {
+            row_marker: true,
+            ..mutation(vec![("age", CqlValue::Int(30))], 10)
+        };
+        let copy = |age: CqlValue, timestamp: i64| {
+            let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+            storage.apply(&table, &insert);
+            storage.apply(&table, &mutation(vec![("age", age)], timestamp));
+            storage
+        };
+        let deleted = || copy(CqlValue::Null, 30);
+        let updated = || copy(CqlValue::Int(31), 20);
+
+        let command = command("alice", ClusteringSlice::default());
+        for (mut storage, other) in [(deleted(), updated()), (updated(), deleted())] {
+            for mutation in other.read_mutations(&table, &command) {
+                storage.apply(&table, &mutation);
+            }
+            let (rows, tombstones) = storage.read(&table, &command);
+            assert_eq!(rows.len(), 1);
+            assert!(rows[0].cells.is_empty());
+            assert_eq!(tombstones, 1);
+        }
+    }
+
+    #[test]
+    fn test_row_marker_keeps_row_without_cells() {
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        let insert = Mutation {
+            row_marker: true,
+            ..mutation(vec![("age", CqlValue::Null)], 10)
+        };
+
+        storage.apply(&table(Vec::new()), &insert);
+
+        let (rows, _) = storage.read(
+            &table(Vec::new()),
+            &command("alice", ClusteringSlice::default()),
+        );
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].cells.is_empty());
+        assert!(
+            storage
+                .read(
+                    &table(Vec::new()),
+                    &command("bob", ClusteringSlice::default())
+                )
+                .0
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_rows_are_ordered_by_typed_clustering_key() {
+        let table = table(vec![ClusteringOrder::Asc, ClusteringOrder::Asc]);
+        let storage = storage_with_orders(&table);
+
+        assert_eq!(
+            clustering_keys(&storage, &table, ClusteringSlice::default()),
+            vec![
+                vec![1, 1],
+                vec![1, 2],
+                vec![2, 1],
+                vec![2, 2],
+                vec![3, 1],
+                vec![10, 1]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_slice_between_prefixes() {
+        let table = table(vec![ClusteringOrder::Asc, ClusteringOrder::Asc]);
+        let storage = storage_with_orders(&table);
+
+        // day > 1 AND day <= 3
+        let slice = ClusteringSlice {
+            start: bound(&[1], false),
+            end: bound(&[3], true),
+            reversed: false,
+        };
+        assert_eq!(
+            clustering_keys(&storage, &table, slice.clone()),
+            vec![vec![2, 1], vec![2, 2], vec![3, 1]]
+        );
+        assert_eq!(
+            clustering_keys(
+                &storage,
+                &table,
+                ClusteringSlice {
+                    reversed: true,
+                    ..slice
+                }
+            ),
+            vec![vec![3, 1], vec![2, 2], vec![2, 1]]
+        );
+
+        // day = 2 AND order >= 2
+        let slice = ClusteringSlice {
+            start: bound(&[2, 2], true),
+            end: bound(&[2], true),
+            reversed: false,
+        };
+        assert_eq!(clustering_keys(&storage, &table, slice), vec![vec![2, 2]]);
+
+        // day > 3 AND day < 2
+        let slice = ClusteringSlice {
+            start: bound(&[3], false),
+            end: bound(&[2], false),
+            reversed: false,
+        };
+        assert!(clustering_keys(&storage, &table, slice).is_empty());
+    }
+
+    #[test]
+    fn test_descending_clustering_order_and_limit() {
+        let table = table(vec![ClusteringOrder::Desc]);
+        let storage = storage_with_orders(&table);
+
+        // day < 10, which starts the slice for a descending column
+        let mut command = command(
+            "alice",
+            ClusteringSlice {
+                start: bound(&[10], false),
+                end: None,
+                reversed: false,
+            },
+        );
+        command.limit = Some(2);
+        let (rows, _) = storage.read(&table, &command);
+
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.clustering_key.clone())
+                .collect::<Vec<_>>(),
+            vec![vec![CqlValue::Int(3)], vec![CqlValue::Int(2)]]
+        );
+    }
+
+    #[test]
+    fn test_index_follows_writes_and_deletions() {
+        let table = table(Vec::new());
+        let mut storage = RowStorage::new(Arc::new(Murmur3Partitioner));
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(30))], 10));
+        storage.create_index(&table, "age");
+        let bob = Mutation {
+            partition_key: vec![CqlValue::Text("bob".to_string())],
+            ..mutation(vec![("age", CqlValue::Int(30))], 10)
+        };
+        storage.apply(&table, &bob);
+        let read_age = |storage: &RowStorage, age: i32| -> Vec<CqlValue> {
+            let command = ReadCommand {
+                partition_key: None,
+                index: Some(IndexExpression {
+                    column: "age".to_string(),
+                    value: CqlValue::Int(age),
+                }),
+                ..command("alice", ClusteringSlice::default())
+            };
+            let mut names: Vec<CqlValue> = storage
+                .read(&table, &command)
+                .0
+                .into_iter()
+                .map(|row| row.partition_key[0].clone())
+                .collect();
+            names.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            names
+        };
+        let name = |name: &str| CqlValue::Text(name.to_string());
+
+        // rows stored before the index was created are indexed as well
+        assert_eq!(read_age(&storage, 30), vec![name("alice"), name("bob")]);
+
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(31))], 20));
+        assert_eq!(read_age(&storage, 30), vec![name("bob")]);
+        assert_eq!(read_age(&storage, 31), vec![name("alice")]);
+
+        // an older write doesn't change the indexed value
+        storage.apply(&table, &mutation(vec![("age", CqlValue::Int(30))], 15));
+        assert_eq!(read_age(&storage, 31), vec![name("alice")]);
+
+        storage.apply(
+            &table,
+            &Mutation {
+                row_deletion: true,
+                ..mutation(Vec::new(), 30)
+            },
+        );
+        assert!(read_age(&storage, 31).is_empty());
+    }
+
+    #[test]
+    fn test_rows_move_between_nodes_by_range() {
+        let table = table(Vec::new());
+        let partitioner = Arc::new(Murmur3Partitioner);
+        let mut source = RowStorage::new(partitioner.clone());
+        source.create_index(&table, "age");
+        source.apply(&table, &mutation(vec![("age", CqlValue::Int(30))], 10));
+        let bob = Mutation {
+            partition_key: vec![CqlValue::Text("bob".to_string())],
+            ..mutation(vec![("age", CqlValue::Int(30))], 10)
+        };
+        source.apply(&table, &bob);
+        let alice_token = partitioner.partition_key_token(&[CqlValue::Text("alice".to_string())]);
+        let alice_range = TokenRange::new(alice_token.wrapping_sub(1), alice_token);
+        let names = |storage: &RowStorage| -> Vec<CqlValue> {
+            let command = ReadCommand {
+                partition_key: None,
+                index: Some(IndexExpression {
+                    column: "age".to_string(),
+                    value: CqlValue::Int(30),
+                }),
+                ..command("alice", ClusteringSlice::default())
+            };
+            let (rows, _) = storage.read(&table, &command);
+            rows.into_iter()
+                .map(|row| row.partition_key[0].clone())
+                .collect()
+        };
+
+        // the receiving node indexes the rows as it applies them
+        let mut target = RowStorage::new(partitioner.clone());
+        target.create_index(&table, "age");
+        for mutation in source.read_ranges(&table, &[alice_range]) {
+            target.apply(&table, &mutation);
+        }
+        assert_eq!(names(&target), vec![CqlValue::Text("alice".to_string())]);
+
+        assert_eq!(source.drop_ranges("shop", &[alice_range]), 1);
+        assert_eq!(source.drop_ranges("other", &[TokenRange::full()]), 0);
+        assert_eq!(names(&source), vec![CqlValue::Text("bob".to_string())]);
+        assert!(source.read_ranges(&table, &[alice_range]).is_empty());
+    }
+
+    #[test]
+    fn test_pages_resume_after_last_key() {
+        let mut storage = BTreeStorage::new(Arc::new(Murmur3Partitioner));
+        for value in ["a", "b", "c", "d", "e"] {
+            storage.add(value.to_string());
+        }
+
+        let mut values = Vec::new();
+        let mut after = None;
+        loop {
+            let page = storage.get_page(&TokenRange::full(), after, 2);
+            assert!(page.len() <= 2);
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            after = Some(*last);
+            values.extend(page.into_iter().map(|(_, value)| value.clone()));
+        }
+
+        assert_eq!(values.len(), 5);
+        assert_eq!(
+            values,
+            storage
+                .get_page(&TokenRange::full(), None, usize::MAX)
+                .into_iter()
+                .map(|(_, value)| value.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(
+            storage
+                .get_page(&TokenRange::new(0, 10), Some(u64::MAX), 2)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_wrapping_ranges_read_past_the_end_of_the_ring() {
+        let mut storage = BTreeStorage::new(Arc::new(Murmur3Partitioner));
+        for value in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            storage.add(value.to_string());
+        }
+        let keys = storage.get_keys_in_range(&TokenRange::full());
+        assert_eq!(keys.len(), 8);
+
+        // from after the third key around the end of the ring to the second key
+        let range = TokenRange::new(keys[2], keys[1]);
+        let expected: Vec<u64> = keys[3..].iter().chain(&keys[..2]).copied().collect();
+        assert_eq!(storage.get_keys_in_range(&range), expected);
+        assert_eq!(storage.get_count_in_range(&range), 7);
+
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let page = storage.get_page(&range, after, 3);
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            after = Some(*last);
+            paged.extend(page.into_iter().map(|(key, _)| key));
+        }
+        assert_eq!(paged, expected);
+    }
+}
@@ -0,0 +1,176 @@
+//! Request tracing.
+//!
+//! A client sets the TRACING flag on a request to have the nodes handling it record what they
+//! do: parsing, routing, replica sends, storage reads and replication. Every node records the
+//! events in its own `system_traces`, and the coordinator returns the id of the tracing
+//! session with the response. Requests the coordinator forwards carry the session, so the
+//! events of all nodes end up under the same session; a client reads the whole trace by asking
+//! every node for it.
+//!
+//! The traced session is per thread, since every connection is served by its own thread:
+//! `trace` does nothing unless a session was started on the current thread.
+
+use shared::protocol::types::TraceEvent;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+use uuid::Uuid;
+
+/// Number of sessions kept in `system_traces`; older sessions are dropped first.
+const MAX_TRACE_SESSIONS: usize = 1000;
+
+/// A thread-safe, shared `system_traces`.
+pub type GlobalTraces = Arc<Mutex<SystemTraces>>;
+
+/// The trace events recorded on this node, grouped by session.
+pub struct SystemTraces {
+    sessions: HashMap<Uuid, Vec<TraceEvent>>,
+    /// Session ids in the order they were first recorded.
+    order: VecDeque<Uuid>,
+}
+
+impl SystemTraces {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records an event, dropping the oldest session if too many are kept.
+    pub fn record(&mut self, event: TraceEvent) {
+        if !self.sessions.contains_key(&event.session_id) {
+            if self.order.len() == MAX_TRACE_SESSIONS
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.sessions.remove(&oldest);
+            }
+            self.order.push_back(event.session_id);
+        }
+
+        self.sessions
+            .entry(event.session_id)
+            .or_default()
+            .push(event);
+    }
+
+    /// Returns the events recorded for a session.
+    pub fn events(&self, session_id: &Uuid) -> Vec<TraceEvent> {
+        self.sessions.get(session_id).cloned().unwrap_or_default()
+    }
+}
+
+/// A tracing session as seen by one node.
+#[derive(Clone)]
+pub(crate) struct TraceState {
+    session_id: Uuid,
+    /// Address of this node.
+    source: String,
+    started_at: Instant,
+    traces: GlobalTraces,
+}
+
+impl TraceState {
+    pub(crate) fn new(session_id: Uuid, source: String, traces: GlobalTraces) -> Self {
+        Self {
+            session_id,
+            source,
+            started_at: Instant::now(),
+            traces,
+        }
+    }
+
+    pub(crate) fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    fn trace(&self, activity: String) {
+        self.traces.lock().unwrap().record(TraceEvent {
+            session_id: self.session_id,
+            activity,
+            source: self.source.clone(),
+            source_elapsed: self.started_at.elapsed().as_micros() as u64,
+            timestamp: SystemTime::now(),
+        });
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceState>> = const { RefCell::new(None) };
+}
+
+/// Traces what the current thread does in the given session, until `end` is called.
+pub(crate) fn begin(state: TraceState) {
+    CURRENT.with(|current| *current.borrow_mut() = Some(state));
+}
+
+/// Stops tracing the current thread.
+pub(crate) fn end() {
+    CURRENT.with(|current| *current.borrow_mut() = None);
+}
+
+/// Returns the session traced on the current thread, e.g. to continue it on another thread.
+pub(crate) fn current() -> Option<TraceState> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Returns the id of the session traced on the current thread, to forward it to other nodes.
+pub(crate) fn session_id() -> Option<Uuid> {
+    CURRENT.with(|current| current.borrow().as_ref().map(TraceState::session_id))
+}
+
+/// Records an event in the session traced on the current thread, if any.
+///
+/// The activity is only formatted if the thread is traced.
+pub(crate) fn trace(activity: impl FnOnce() -> String) {
+    CURRENT.with(|current| {
+        if let Some(state) = current.borrow().as_ref() {
+            state.trace(activity());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tracing;
+    use crate::tracing::{GlobalTraces, MAX_TRACE_SESSIONS, SystemTraces, TraceState};
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_trace_records_only_while_traced() {
+        let traces = GlobalTraces::new(Mutex::new(SystemTraces::new()));
+        let session_id = Uuid::new_v4();
+
+        tracing::trace(|| "untraced".to_string());
+        tracing::begin(TraceState::new(
+            session_id,
+            "localhost:3000".to_string(),
+            traces.clone(),
+        ));
+        tracing::trace(|| "traced".to_string());
+        tracing::end();
+        tracing::trace(|| "untraced".to_string());
+
+        let events = traces.lock().unwrap().events(&session_id);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].activity, "traced");
+        assert_eq!(events[0].source, "localhost:3000");
+    }
+
+    #[test]
+    fn test_record_drops_oldest_sessions() {
+        let traces = GlobalTraces::new(Mutex::new(SystemTraces::new()));
+        let session_ids: Vec<Uuid> = (0..=MAX_TRACE_SESSIONS).map(|_| Uuid::new_v4()).collect();
+
+        for session_id in session_ids.iter() {
+            let state = TraceState::new(*session_id, "localhost:3000".to_string(), traces.clone());
+            state.trace("event".to_string());
+        }
+
+        let traces = traces.lock().unwrap();
+        assert!(traces.events(&session_ids[0]).is_empty());
+        assert_eq!(traces.events(&session_ids[MAX_TRACE_SESSIONS]).len(), 1);
+    }
+}
@@ -0,0 +1,67 @@
+//! Warnings returned to clients.
+//!
+//! Handlers warn about requests that work but point at a bad access pattern, such as oversized
+//! batches, before the pattern causes an outage. The warnings are collected while a request is
+//! handled and sent back with its response under the WARNING flag.
+//!
+//! Like tracing, collecting is per thread: `warn` only logs unless collecting was started on
+//! the current thread, e.g. for background tasks that have no client to warn. Warnings of the
+//! other nodes handling part of a request, like the replicas of a read, are passed on to its
+//! client too.
+
+use log::warn;
+use std::cell::RefCell;
+
+thread_local! {
+    static WARNINGS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Starts collecting the warnings of a request on the current thread.
+pub(crate) fn begin() {
+    WARNINGS.with(|warnings| *warnings.borrow_mut() = Some(Vec::new()));
+}
+
+/// Stops collecting and returns the warnings collected since `begin`.
+pub(crate) fn take() -> Vec<String> {
+    WARNINGS.with(|warnings| warnings.borrow_mut().take().unwrap_or_default())
+}
+
+/// Warns the client of the request handled on the current thread.
+pub(crate) fn warn(message: String) {
+    warn!("{}", message);
+    forward(message);
+}
+
+/// Passes on a warning another node returned while handling part of the request handled on
+/// the current thread; that node logged it already. Warnings several replicas returned alike
+/// are passed on once.
+pub(crate) fn forward(message: String) {
+    WARNINGS.with(|warnings| {
+        if let Some(warnings) = warnings.borrow_mut().as_mut()
+            && !warnings.contains(&message)
+        {
+            warnings.push(message);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::warnings;
+
+    #[test]
+    fn test_take_returns_warnings_since_begin() {
+        warnings::warn("before".to_string());
+        warnings::begin();
+        warnings::warn("during".to_string());
+
+        warnings::forward("during".to_string());
+        warnings::forward("from a replica".to_string());
+
+        assert_eq!(
+            warnings::take(),
+            vec!["during".to_string(), "from a replica".to_string()]
+        );
+        assert!(warnings::take().is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+//! Credentials and the SASL PLAIN mechanism used to authenticate connections.
+
+use std::fmt;
+
+/// A username and password to authenticate with.
+#[derive(Clone, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Encodes the credentials as a SASL PLAIN response: an empty authorization id, the
+    /// username and the password, separated by NUL bytes.
+    pub fn to_sasl_plain(&self) -> Vec<u8> {
+        let mut token = Vec::with_capacity(self.username.len() + self.password.len() + 2);
+        token.push(0);
+        token.extend_from_slice(self.username.as_bytes());
+        token.push(0);
+        token.extend_from_slice(self.password.as_bytes());
+        token
+    }
+
+    /// Decodes a SASL PLAIN response, returning `None` if it is malformed.
+    pub fn from_sasl_plain(token: &[u8]) -> Option<Credentials> {
+        let mut parts = token.split(|byte| *byte == 0);
+        let _authorization_id = parts.next()?;
+        let username = String::from_utf8(parts.next()?.to_vec()).ok()?;
+        let password = String::from_utf8(parts.next()?.to_vec()).ok()?;
+
+        if parts.next().is_some() || username.is_empty() {
+            return None;
+        }
+
+        Some(Credentials { username, password })
+    }
+}
+
+impl fmt::Debug for Credentials {
+    /// Keeps the password out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
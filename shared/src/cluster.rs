@@ -1,38 +1,53 @@
 use crate::consistent_hash_ring::ConsistentHashRing;
+use crate::partitioner::GlobalPartitioner;
+use crate::protocol::types::{NodeTokens, TopologyChange, TopologyChangeType};
 use crate::replication::ReplicationStrategy;
 pub(crate) use crate::routing::{Router, RoutingStrategy};
+use crate::token_range::TokenRange;
+use std::collections::HashMap;
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub address: String,
+    /// Identifies the node across address changes; unknown until the node tells it.
+    pub host_id: Option<Uuid>,
 }
 
 impl Node {
     pub fn new(address: String) -> Node {
-        Node { address }
+        Node {
+            address,
+            host_id: None,
+        }
     }
 }
 
-impl Into<Node> for String {
-    fn into(self) -> Node {
-        Node::new(self)
+impl From<String> for Node {
+    fn from(val: String) -> Self {
+        Node::new(val)
     }
 }
 
-impl Into<Node> for &str {
-    fn into(self) -> Node {
-        Node::new(self.to_string())
+impl From<&str> for Node {
+    fn from(val: &str) -> Self {
+        Node::new(val.to_string())
     }
 }
 
 pub struct Cluster {
     ring: ConsistentHashRing,
+    /// Addresses nodes moved to before their host id was known, by host id. A node is moved
+    /// once its host id is learned.
+    pending_moves: HashMap<Uuid, String>,
 }
 
 impl Cluster {
-    pub fn new(nodes: Vec<Node>) -> Cluster {
+    /// Creates a cluster of the given nodes, placing keys on the ring with the partitioner.
+    pub fn new(nodes: Vec<Node>, partitioner: GlobalPartitioner) -> Cluster {
         Cluster {
-            ring: ConsistentHashRing::new(nodes),
+            ring: ConsistentHashRing::new(nodes, partitioner),
+            pending_moves: HashMap::new(),
         }
     }
 
@@ -44,6 +59,10 @@ impl Cluster {
         ReplicationStrategy::new(&self.ring)
     }
 
+    pub fn partitioner(&self) -> &GlobalPartitioner {
+        self.ring.partitioner()
+    }
+
     pub fn drop_node(&mut self, node: &Node) {
         self.ring.drop_node(node);
     }
@@ -52,7 +71,133 @@ impl Cluster {
         self.ring.add_node(node);
     }
 
+    pub fn get_nodes(&self) -> &[Node] {
+        self.ring.get_nodes()
+    }
+
+    /// Returns the node reached at the given address, with its host id if it is known.
+    pub fn node(&self, address: &str) -> Option<&Node> {
+        self.get_nodes().iter().find(|node| node.address == address)
+    }
+
+    pub fn contains_node(&self, address: &str) -> bool {
+        self.node(address).is_some()
+    }
+
+    /// Adds, drops or moves the node of a topology change.
+    ///
+    /// Returns `false` if the cluster already reflected the change, so that the same change
+    /// received from several nodes is applied only once. A node that moved is only known by
+    /// its host id, so until the host id was learned the move is kept and applied then.
+    pub fn apply_topology_change(&mut self, change: &TopologyChange) -> bool {
+        let contains_node = self.contains_node(&change.address);
+        match (change.change, change.host_id) {
+            (TopologyChangeType::NewNode, host_id) if !contains_node => self.add_node(Node {
+                address: change.address.clone(),
+                host_id,
+            }),
+            (TopologyChangeType::NewNode, Some(host_id)) => {
+                return self.set_host_id(&change.address, host_id);
+            }
+            (TopologyChangeType::RemovedNode, _) if contains_node => {
+                self.drop_node(&Node::new(change.address.clone()))
+            }
+            (TopologyChangeType::MovedNode, Some(host_id)) => {
+                if self.ring.address(&host_id).is_some() {
+                    return self.ring.move_node(host_id, &change.address).is_some();
+                }
+                let previous = self.pending_moves.insert(host_id, change.address.clone());
+                return previous.as_ref() != Some(&change.address);
+            }
+            _ => return false,
+        }
+        true
+    }
+
     pub fn get_ring_entities(&self) -> Vec<(u64, &str)> {
         self.ring.get_entities()
     }
+
+    /// Returns the ranges of the ring, each with the address of the node owning it.
+    pub fn token_ranges(&self) -> Vec<(TokenRange, &str)> {
+        self.ring.token_ranges()
+    }
+
+    /// Returns the ranges of the ring a node stores for a replication factor, adjacent ones
+    /// merged: those it owns and those it is one of the next distinct nodes of.
+    pub fn replicated_ranges(&self, address: &str, replication_factor: usize) -> Vec<TokenRange> {
+        let replication = self.replication_strategy();
+        TokenRange::merge(
+            self.token_ranges()
+                .into_iter()
+                .filter(|(range, owner)| {
+                    *owner == address
+                        || replication
+                            .get_replica_nodes(
+                                range.end,
+                                &Node::from(*owner),
+                                replication_factor.saturating_sub(1),
+                            )
+                            .contains(&address)
+                })
+                .map(|(range, _)| range),
+        )
+    }
+
+    /// Returns the share of the ring every node owns, between 0 and 1.
+    pub fn ownership(&self) -> HashMap<String, f64> {
+        self.ring.ownership()
+    }
+
+    /// Returns the share of the ring every node replicates for a replication factor.
+    pub fn effective_ownership(&self, replication_factor: usize) -> HashMap<String, f64> {
+        self.ring.effective_ownership(replication_factor)
+    }
+
+    /// Replaces the tokens of a node, returning `false` if it already had them.
+    pub fn set_tokens(&mut self, address: &str, tokens: Vec<u64>) -> bool {
+        self.ring.set_tokens(address, tokens)
+    }
+
+    /// Sets the host id of the node with the given address, returning `false` if it already
+    /// had it. A node that moved before its host id was known is moved now.
+    pub fn set_host_id(&mut self, address: &str, host_id: Uuid) -> bool {
+        if !self.ring.set_host_id(address, host_id) {
+            return false;
+        }
+        if let Some(new_address) = self.pending_moves.remove(&host_id) {
+            self.ring.move_node(host_id, &new_address);
+        }
+        true
+    }
+
+    /// Applies what a node told about itself: its host id and the tokens it picked.
+    ///
+    /// Returns `false` if the cluster already knew both.
+    pub fn learn_node(&mut self, node_tokens: NodeTokens) -> bool {
+        let NodeTokens {
+            address,
+            host_id,
+            tokens,
+        } = node_tokens;
+
+        let learned_host_id = host_id.is_some_and(|host_id| self.set_host_id(&address, host_id));
+        // the node may have been moved on learning its host id
+        let address = host_id
+            .and_then(|host_id| self.ring.address(&host_id))
+            .map_or(address, str::to_string);
+        self.set_tokens(&address, tokens) || learned_host_id
+    }
+
+    /// Returns the host id and tokens of every node, as placed on the ring.
+    pub fn node_tokens(&self) -> Vec<NodeTokens> {
+        self.get_nodes()
+            .iter()
+            .map(|node| NodeTokens {
+                address: node.address.clone(),
+                host_id: node.host_id,
+                tokens: self.ring.tokens(&node.address),
+            })
+            .collect()
+    }
 }
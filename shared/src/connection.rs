@@ -1,59 +1,279 @@
 //! Connection management for the server.
 //!
 //! This module provides the `Connection` struct which handles reading from and writing to
-//! a TCP stream using a custom protocol.
+//! a TCP or TLS stream using a custom protocol.
 
+use crate::auth::Credentials;
 use crate::error::{AppResult, Error};
+use crate::protocol::frame::{RequestExtras, ResponseExtras};
 use crate::protocol::protocol_reader::ProtocolReader;
 use crate::protocol::protocol_writer::ProtocolWriter;
-use crate::protocol::types::{Request, Response};
-use std::net::TcpStream;
+use crate::protocol::segment::Framing;
+use crate::protocol::types::{
+    Event, EventType, ProtocolVersion, Request, Response, UnsupportedVersion,
+};
+use crate::stream::Stream;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// CQL version announced in the STARTUP message.
+const CQL_VERSION: &str = "3.0.0";
 
 /// Represents a connection to a client.
 ///
-/// It uses buffered readers and writers for efficient I/O operations over a `TcpStream`.
+/// It uses buffered readers and writers for efficient I/O operations over a `Stream`.
+/// The reader is kept for the lifetime of the connection so that no buffered bytes are lost
+/// between messages.
 pub struct Connection {
-    stream: TcpStream,
+    reader: ProtocolReader<Stream>,
+    version: ProtocolVersion,
+    framing: Framing,
+    /// Credentials to answer an AUTHENTICATE with during startup.
+    credentials: Option<Credentials>,
+    /// Serializes writes of this connection and of its `EventWriter`s, so that a pushed event
+    /// never interleaves with a response.
+    write_lock: Arc<Mutex<()>>,
+    /// Tracing and custom payload of the last request.
+    request_extras: RequestExtras,
+}
+
+/// Pushes events to a client on a connection that registered for them.
+///
+/// It writes to a clone of the connection's stream, so events can be sent from another thread
+/// while the connection keeps serving requests.
+pub struct EventWriter {
+    stream: Stream,
+    version: ProtocolVersion,
+    framing: Framing,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl EventWriter {
+    /// Sends an event to the client.
+    pub fn send_event(&mut self, event: &Event) -> AppResult<()> {
+        let _guard = self.write_lock.lock().unwrap();
+        ProtocolWriter::new(&mut self.stream)
+            .with_version(self.version)
+            .with_framing(self.framing)
+            .send_event(event)
+            .map_err(|err| Error::ConnectionError(Some(err)))
+    }
 }
 
 impl Connection {
-    /// Creates a new `Connection` from a `TcpStream`.
-    pub fn new(stream: TcpStream) -> Self {
-        Self { stream }
+    /// Creates a new `Connection` from a `TcpStream` or an established TLS stream.
+    pub fn new(stream: impl Into<Stream>) -> Self {
+        Self {
+            reader: ProtocolReader::new(stream.into()),
+            version: ProtocolVersion::default(),
+            framing: Framing::Plain,
+            credentials: None,
+            write_lock: Arc::new(Mutex::new(())),
+            request_extras: RequestExtras::default(),
+        }
+    }
+
+    /// Sets the protocol version used for outgoing messages.
+    pub fn with_protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the credentials used if the server requires authentication.
+    pub fn with_credentials(mut self, credentials: Option<Credentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Changes the protocol version used for outgoing messages.
+    pub fn set_protocol_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Returns the protocol version of the connection.
+    ///
+    /// On the server this is the version of the last request, so responses are sent back in
+    /// the client's version.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Wraps all subsequent frames into checksummed segments.
+    pub fn enable_checksummed_framing(&mut self) {
+        self.framing = Framing::Checksummed;
+        self.reader.set_framing(Framing::Checksummed);
+    }
+
+    /// Starts the connection up, switching to checksummed framing for protocol v5.
+    ///
+    /// If the server requires authentication, the credentials are sent with SASL PLAIN.
+    pub fn startup(&mut self) -> AppResult<()> {
+        let options = HashMap::from([("CQL_VERSION".to_string(), CQL_VERSION.to_string())]);
+
+        let response = self.send_request_with_response(&Request::Startup(options))?;
+        if matches!(response, Response::Ready | Response::Authenticate(_))
+            && self.version.uses_segments()
+        {
+            self.enable_checksummed_framing();
+        }
+
+        match response {
+            Response::Ready => Ok(()),
+            Response::Authenticate(authenticator) => self.authenticate(&authenticator),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    fn authenticate(&mut self, authenticator: &str) -> AppResult<()> {
+        let token = match &self.credentials {
+            Some(credentials) => credentials.to_sasl_plain(),
+            None => {
+                return Err(Error::AuthenticationFailed(format!(
+                    "Server requires authentication with {} but no credentials were provided",
+                    authenticator
+                )));
+            }
+        };
+
+        match self.send_request_with_response(&Request::AuthResponse(token))? {
+            Response::AuthSuccess(_) => Ok(()),
+            Response::Error(error) => Err(Error::AuthenticationFailed(error.message)),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Registers the connection for events of the given types.
+    ///
+    /// Afterwards the server pushes matching events, which are read with `receive_event`.
+    pub fn register(&mut self, event_types: Vec<EventType>) -> AppResult<()> {
+        match self.send_request_with_response(&Request::Register(event_types))? {
+            Response::Ready => Ok(()),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Returns a writer pushing events to the client of this connection.
+    pub fn event_writer(&self) -> AppResult<EventWriter> {
+        let stream = self
+            .reader
+            .get_ref()
+            .try_clone()
+            .map_err(|err| Error::ConnectionError(Some(err)))?;
+
+        Ok(EventWriter {
+            stream,
+            version: self.version,
+            framing: self.framing,
+            write_lock: self.write_lock.clone(),
+        })
+    }
+
+    /// Waits for the next event pushed by the server.
+    pub fn receive_event(&mut self) -> AppResult<Event> {
+        match self.receive_response()? {
+            Response::Event(event) => Ok(event),
+            response => Err(Error::UnexpectedResponse(response)),
+        }
     }
 
     /// Receives a request from the client.
     pub fn receive_request(&mut self) -> AppResult<Request> {
-        let mut reader = ProtocolReader::new(&self.stream);
-        reader
-            .receive_request()
-            .map_err(|err| Error::ConnectionError(Some(err)))
+        let frame = self.reader.receive_frame().map_err(Self::map_error)?;
+        self.version = frame.version.protocol;
+        self.request_extras = frame.request_extras();
+
+        bincode::deserialize(&frame.body).map_err(|_| Error::ParseError)
+    }
+
+    /// Returns the tracing and custom payload of the last request.
+    pub fn request_extras(&self) -> &RequestExtras {
+        &self.request_extras
     }
 
     /// Receives a response from the server.
     pub fn receive_response(&mut self) -> AppResult<Response> {
-        let mut reader = ProtocolReader::new(&self.stream);
-        reader
-            .receive_response()
-            .map_err(|err| Error::ConnectionError(Some(err)))
+        self.receive_response_with_extras()
+            .map(|(response, _)| response)
+    }
+
+    /// Receives a response from the server together with its tracing session id, warnings and
+    /// custom payload.
+    fn receive_response_with_extras(&mut self) -> AppResult<(Response, ResponseExtras)> {
+        let frame = self.reader.receive_frame().map_err(Self::map_error)?;
+
+        let response = bincode::deserialize(&frame.body).map_err(|_| Error::ParseError)?;
+        Ok((response, frame.response_extras()))
     }
 
     /// Sends a response to the client.
     pub fn send_response(&mut self, value: &Response) -> AppResult<()> {
-        let mut writer = ProtocolWriter::new(&self.stream);
+        let write_lock = self.write_lock.clone();
+        let _guard = write_lock.lock().unwrap();
+        let mut writer = self.writer();
         writer
             .send_response(value)
             .map_err(|err| Error::ConnectionError(Some(err)))
     }
 
+    /// Sends a response to the client together with its tracing session id, warnings and
+    /// custom payload.
+    pub fn send_response_with_extras(
+        &mut self,
+        value: &Response,
+        extras: ResponseExtras,
+    ) -> AppResult<()> {
+        let write_lock = self.write_lock.clone();
+        let _guard = write_lock.lock().unwrap();
+        let mut writer = self.writer();
+        writer
+            .send_response_with_extras(value, extras)
+            .map_err(|err| Error::ConnectionError(Some(err)))
+    }
+
     /// Sends a request to the server and waits for a response.
     pub fn send_request_with_response(&mut self, request: &Request) -> AppResult<Response> {
         {
-            let mut writer = ProtocolWriter::new(&self.stream);
+            let mut writer = self.writer();
             writer
                 .send_request(request)
                 .map_err(|err| Error::ConnectionError(Some(err)))?;
         }
         self.receive_response()
     }
+
+    /// Sends a request with its tracing flag and custom payload to the server, and waits for
+    /// the response together with its extras.
+    pub fn send_request_with_extras(
+        &mut self,
+        request: &Request,
+        extras: RequestExtras,
+    ) -> AppResult<(Response, ResponseExtras)> {
+        {
+            let mut writer = self.writer();
+            writer
+                .send_request_with_extras(request, extras)
+                .map_err(|err| Error::ConnectionError(Some(err)))?;
+        }
+        self.receive_response_with_extras()
+    }
+
+    fn writer(&mut self) -> ProtocolWriter<&mut Stream> {
+        ProtocolWriter::new(self.reader.get_mut())
+            .with_version(self.version)
+            .with_framing(self.framing)
+    }
+
+    /// Maps a read error, surfacing unsupported protocol versions.
+    fn map_error(err: io::Error) -> Error {
+        let unsupported_version = err
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UnsupportedVersion>())
+            .copied();
+
+        match unsupported_version {
+            Some(version) => Error::UnsupportedVersion(version),
+            None => Error::ConnectionError(Some(err)),
+        }
+    }
 }
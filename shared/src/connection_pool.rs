@@ -1,9 +1,13 @@
-use crate::cluster::RoutingStrategy;
+use crate::auth::Credentials;
+use crate::cluster::{Node, RoutingStrategy};
 use crate::connection::Connection;
 use crate::error::AppResult;
-use crate::protocol::types::{Request, Response};
+use crate::protocol::frame::{RequestExtras, ResponseExtras, Tracing};
+use crate::protocol::types::{CustomPayload, ProtocolVersion, Request, Response, Rows, ValueCount};
+use crate::stream::Stream;
+use crate::tls::TlsConnector;
 use std::collections::HashMap;
-use std::net::TcpStream;
+use uuid::Uuid;
 
 /// A connection pool that manages persistent TCP connections to cluster nodes.
 ///
@@ -18,6 +22,22 @@ use std::net::TcpStream;
 /// - **Fanout**: Broadcasts request to multiple nodes and merges responses
 pub struct ConnectionPool {
     connections: HashMap<String, Connection>,
+    protocol_version: ProtocolVersion,
+    credentials: Option<Credentials>,
+    tls: Option<TlsConnector>,
+    /// Whether requests sent to a single node are traced.
+    tracing: bool,
+    /// Custom payload sent with every request.
+    custom_payload: CustomPayload,
+    /// Tracing session id, warnings and custom payload of the last response; for fanout
+    /// requests the warnings and payloads of all nodes.
+    extras: ResponseExtras,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ConnectionPool {
@@ -25,9 +45,60 @@ impl ConnectionPool {
     pub fn new() -> Self {
         Self {
             connections: HashMap::new(),
+            protocol_version: ProtocolVersion::default(),
+            credentials: None,
+            tls: None,
+            tracing: false,
+            custom_payload: CustomPayload::new(),
+            extras: ResponseExtras::default(),
         }
     }
 
+    /// Sets the protocol version new connections are started up with.
+    pub fn with_protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets the credentials new connections authenticate with, if the nodes require it.
+    pub fn with_credentials(mut self, credentials: Option<Credentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Sets the connector new connections are secured with, or `None` for plaintext.
+    pub fn with_tls(mut self, tls: Option<TlsConnector>) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Turns tracing of requests sent to a single node on or off.
+    ///
+    /// Fanout requests are never traced, as each node would start its own session.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
+    /// Sets the custom payload sent with every request, e.g. a tenant id.
+    pub fn set_custom_payload(&mut self, custom_payload: CustomPayload) {
+        self.custom_payload = custom_payload;
+    }
+
+    /// Returns the tracing session of the last traced request.
+    pub fn tracing_id(&self) -> Option<Uuid> {
+        self.extras.tracing_id
+    }
+
+    /// Returns the warnings the nodes attached to the responses of the last request.
+    pub fn warnings(&self) -> &[String] {
+        &self.extras.warnings
+    }
+
+    /// Returns the custom payload the nodes attached to the responses of the last request.
+    pub fn response_payload(&self) -> &CustomPayload {
+        &self.extras.custom_payload
+    }
+
     /// Executes a request based on the provided routing strategy.
     ///
     /// This method handles three types of routing strategies:
@@ -50,10 +121,20 @@ impl ConnectionPool {
         request: Request,
         exclude_node: Option<&str>,
     ) -> AppResult<Response> {
+        self.extras = ResponseExtras::default();
+        let mut extras = RequestExtras {
+            tracing: Tracing::Disabled,
+            custom_payload: self.custom_payload.clone(),
+        };
         match strategy {
             RoutingStrategy::Direct(node_addr) => {
-                let connection = self.get_or_create_connection(node_addr.address.as_str())?;
-                connection.send_request_with_response(&request)
+                if self.tracing {
+                    extras.tracing = Tracing::NewSession;
+                }
+                let (response, response_extras) =
+                    self.execute_with_extras(node_addr, request, extras)?;
+                self.extras = response_extras;
+                Ok(response)
             }
 
             RoutingStrategy::Fanout(nodes) => {
@@ -63,9 +144,13 @@ impl ConnectionPool {
                     if exclude_node.is_some() && node_addr.address == exclude_node.unwrap() {
                         continue;
                     }
-                    let connection = self.get_or_create_connection(node_addr.address.as_str())?;
-                    let response = connection.send_request_with_response(&request)?;
-                    all_responses.push(response.clone());
+                    let (response, response_extras) =
+                        self.execute_with_extras(node_addr, request.clone(), extras.clone())?;
+                    self.extras.warnings.extend(response_extras.warnings);
+                    self.extras
+                        .custom_payload
+                        .extend(response_extras.custom_payload);
+                    all_responses.push((node_addr.address.clone(), response.clone()));
                     println!("{}: Response {:?}", node_addr.address, response);
                 }
 
@@ -74,14 +159,41 @@ impl ConnectionPool {
         }
     }
 
+    /// Sends a request with the given tracing and custom payload to a node, returning the
+    /// response together with its extras.
+    ///
+    /// A node forwarding a request passes the tracing session and custom payload of the
+    /// client's request, so the other node traces it in the same session and sees the same
+    /// payload. The connection is dropped if the request failed, so that the next request
+    /// reconnects.
+    pub fn execute_with_extras(
+        &mut self,
+        node: &Node,
+        request: Request,
+        extras: RequestExtras,
+    ) -> AppResult<(Response, ResponseExtras)> {
+        let node_addr = node.address.as_str();
+        let result = self
+            .get_or_create_connection(node_addr)
+            .and_then(|connection| connection.send_request_with_extras(&request, extras));
+
+        if result.is_err() {
+            self.connections.remove(node_addr);
+        }
+        result
+    }
+
     /// Get existing connection or create new one
     fn get_or_create_connection(&mut self, node_addr: &str) -> AppResult<&mut Connection> {
         // Check if connection exists
         if !self.connections.contains_key(node_addr) {
-            // Create new connection
-            let stream = TcpStream::connect(node_addr)
+            // Create new connection and negotiate the protocol with STARTUP
+            let stream = Stream::connect(node_addr, self.tls.as_ref())
                 .map_err(|e| crate::error::Error::ConnectionError(Some(e)))?;
-            let connection = Connection::new(stream);
+            let mut connection = Connection::new(stream)
+                .with_protocol_version(self.protocol_version)
+                .with_credentials(self.credentials.clone());
+            connection.startup()?;
             self.connections.insert(node_addr.to_string(), connection);
         }
 
@@ -90,17 +202,103 @@ impl ConnectionPool {
     }
 
     /// Merge multiple responses into one
-    fn merge_responses(responses: Vec<Response>) -> AppResult<Response> {
+    ///
+    /// Counts are summed, keeping the count of every node, and rows are concatenated. If
+    /// every node answered with `Void`, so does the merged response. If any node answered
+    /// with an error, that error is returned instead.
+    fn merge_responses(responses: Vec<(String, Response)>) -> AppResult<Response> {
         let mut merged = Vec::new();
+        let mut merged_count: Option<ValueCount> = None;
+        let mut merged_rows: Option<Rows> = None;
+        let mut void = false;
 
-        for response in responses {
+        for (node_addr, response) in responses {
             match response {
+                Response::Count(count) => {
+                    let merged_count = merged_count.get_or_insert_with(|| ValueCount {
+                        ranges: vec![0; count.ranges.len()],
+                        ..ValueCount::default()
+                    });
+                    merged_count.total += count.total;
+                    for (merged_range, range) in merged_count.ranges.iter_mut().zip(&count.ranges) {
+                        *merged_range += range;
+                    }
+                    merged_count.nodes.push((node_addr, count));
+                }
                 Response::Array(values) => merged.extend(values),
-                Response::String(s) => merged.push(s),
+                Response::Page(page) => merged.extend(page.values),
+                Response::Rows(rows) => match &mut merged_rows {
+                    Some(merged_rows) => merged_rows.rows.extend(rows.rows),
+                    None => merged_rows = Some(rows),
+                },
+                Response::Void => void = true,
                 Response::Bool(_) => {} // Skip bools in merge
+                Response::Error(error) => return Ok(Response::Error(error)),
+                Response::Ready
+                | Response::Supported(_)
+                | Response::Prepared(_)
+                | Response::Event(_)
+                | Response::Trace(_)
+                | Response::SetKeyspace(_)
+                | Response::SchemaChange(_)
+                | Response::SchemaVersion(_)
+                | Response::Schema(_)
+                | Response::Tokens(_)
+                | Response::Load(_)
+                | Response::Promise(_)
+                | Response::Mutations(_)
+                | Response::Authenticate(_)
+                | Response::AuthChallenge(_)
+                | Response::AuthSuccess(_) => {}
             }
         }
 
+        if let Some(count) = merged_count {
+            return Ok(Response::Count(count));
+        }
+        if let Some(rows) = merged_rows {
+            return Ok(Response::Rows(rows));
+        }
+        if void && merged.is_empty() {
+            return Ok(Response::Void);
+        }
         Ok(Response::Array(merged))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::connection_pool::ConnectionPool;
+    use crate::protocol::types::{Response, ValueCount};
+
+    fn count(total: u64, ranges: Vec<u64>) -> Response {
+        Response::Count(ValueCount {
+            total,
+            ranges,
+            nodes: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_merge_responses_sums_counts_by_node() {
+        let merged = ConnectionPool::merge_responses(vec![
+            ("127.0.0.1:3000".to_string(), count(10, vec![2, 3])),
+            ("127.0.0.1:4000".to_string(), count(5, vec![0, 4])),
+        ])
+        .unwrap();
+
+        let Response::Count(merged) = merged else {
+            panic!("Expected a count, got {:?}", merged);
+        };
+        assert_eq!(merged.total, 15);
+        assert_eq!(merged.ranges, vec![2, 7]);
+        assert_eq!(
+            merged
+                .nodes
+                .iter()
+                .map(|(address, count)| (address.as_str(), count.total))
+                .collect::<Vec<_>>(),
+            vec![("127.0.0.1:3000", 10), ("127.0.0.1:4000", 5)]
+        );
+    }
+}
@@ -1,103 +1,168 @@
 pub use crate::cluster::Node;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use crate::partitioner::GlobalPartitioner;
+use crate::token_allocator;
+use crate::token_range::TokenRange;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
 
-/// Number of virtual nodes created per physical node.
+/// Number of tokens (virtual nodes) of a node, unless configured otherwise.
 ///
 /// Virtual nodes improve data distribution by creating multiple points
 /// on the hash ring for each physical node, reducing hot spots.
-const VIRTUAL_PARTITION_COUNT: usize = 256;
+pub const DEFAULT_NUM_TOKENS: usize = 256;
 
 /// A consistent hashing ring for distributing keys across nodes.
 ///
 /// This implementation uses virtual nodes to ensure even distribution
 /// of data across the cluster. Each physical node is assigned multiple positions
-/// on the ring, determined by hashing the node address with a replica index.
+/// on the ring, its tokens. Until the tokens a node picked are known, it is placed at its
+/// default tokens, determined by hashing the node address with a replica index.
 ///
 /// # How it works
 ///
-/// 1. Each node gets 10 virtual tokens placed at hash positions on the ring
-/// 2. Keys are hashed and mapped to the next token clockwise on the ring
+/// 1. Each node gets its tokens placed at positions on the ring
+/// 2. Keys are turned into tokens by the partitioner and mapped to the next token clockwise
+///    on the ring
 /// 3. The node owning that token is responsible for storing the key
-#[derive(Debug)]
+///
+/// Nodes are reached at their address, which can change: the ring places nodes by host id,
+/// so a node that moves keeps its tokens and stays the same node. Until the host id of a node
+/// is known, it is placed under a provisional one.
 pub struct ConsistentHashRing {
-    /// Maps hash values (tokens) to node indices
-    ring: BTreeMap<u64, String>,
+    /// Maps hash values (tokens) to the host ids of the nodes owning them
+    ring: BTreeMap<u64, Uuid>,
+    /// Maps host ids to the addresses the nodes are reached at
+    addresses: HashMap<Uuid, String>,
     /// All nodes in the cluster
     nodes: Vec<Node>,
-}
-
-/// Represents a range of hash values on the consistent hash ring.
-///
-/// A range is defined by a start hash (exclusive) and end hash (inclusive).
-/// When `start > end`, this represents a wraparound range that spans from
-/// start to u64::MAX and then from 0 to end.
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-pub struct Range {
-    /// Start of the range (exclusive)
-    pub start: u64,
-    /// End of the range (inclusive)
-    pub end: u64,
+    /// Gives keys their tokens
+    partitioner: GlobalPartitioner,
 }
 
 impl ConsistentHashRing {
     /// Creates a new consistent hash ring with the given nodes.
     ///
     /// Each node is assigned multiple virtual partitions to ensure even distribution.
-    pub fn new(nodes: Vec<Node>) -> ConsistentHashRing {
-        let mut ring = BTreeMap::new();
+    pub fn new(nodes: Vec<Node>, partitioner: GlobalPartitioner) -> ConsistentHashRing {
+        let mut ring = ConsistentHashRing {
+            ring: BTreeMap::new(),
+            addresses: HashMap::new(),
+            nodes: Vec::new(),
+            partitioner,
+        };
 
-        for node in nodes.iter() {
-            Self::insert_virtual_nodes(&mut ring, &node.address);
+        for node in nodes {
+            ring.add_node(node);
         }
+        ring
+    }
 
-        ConsistentHashRing { ring, nodes }
+    /// Returns the default tokens of a node, derived from its address.
+    pub fn default_tokens(node_address: &str, num_tokens: usize) -> Vec<u64> {
+        (0..num_tokens)
+            .map(|i| {
+                let key = format!("{}:vnode:{}", node_address, i);
+                Self::calculate_hash_with_seed(&key, i as u32)
+            })
+            .collect()
     }
 
-    /// Inserts virtual nodes for a given node address into the ring.
-    fn insert_virtual_nodes(ring: &mut BTreeMap<u64, String>, node_address: &str) {
-        for i in 0..VIRTUAL_PARTITION_COUNT {
-            let key = format!("{}:vnode:{}", node_address, i);
-            let hash = Self::calculate_hash_with_seed(&key, i as u32);
-            ring.insert(hash, node_address.to_string());
+    /// Inserts the tokens of a given host id into the ring, skipping tokens another node
+    /// already owns.
+    fn insert_tokens(ring: &mut BTreeMap<u64, Uuid>, host_id: Uuid, tokens: Vec<u64>) {
+        for token in tokens {
+            ring.entry(token).or_insert(host_id);
         }
     }
 
-    /// Removes virtual nodes for a given node address from the ring.
-    fn remove_virtual_nodes(ring: &mut BTreeMap<u64, String>, node_address: &str) {
-        for i in 0..VIRTUAL_PARTITION_COUNT {
-            let key = format!("{}:vnode:{}", node_address, i);
-            let hash = Self::calculate_hash_with_seed(&key, i as u32);
-            ring.remove(&hash);
-        }
+    /// Removes the tokens of a given host id from the ring.
+    fn remove_tokens(ring: &mut BTreeMap<u64, Uuid>, host_id: Uuid) {
+        ring.retain(|_, owner| *owner != host_id);
     }
 
-    /// Returns the node responsible for a given hash value.
-    pub fn get_node(&self, hash: u64) -> &Node {
-        let selected_node = self
+    /// Returns the node responsible for a given hash value, or `None` if the ring is empty.
+    pub fn get_node(&self, hash: u64) -> Option<&Node> {
+        let host_id = self
             .ring
             .range(hash..)
             .next()
             .or_else(|| self.ring.iter().next())
-            .map(|(_, address)| address)
-            .unwrap();
+            .map(|(_, host_id)| host_id)?;
 
-        self.nodes
-            .iter()
-            .find(|n| &n.address == selected_node)
-            .unwrap()
+        let address = self.address(host_id)?;
+        self.nodes.iter().find(|n| n.address == address)
     }
 
+    /// Returns the tokens of the ring with the address of the node owning each, in ring order.
     pub fn get_entities(&self) -> Vec<(u64, &str)> {
         self.ring
             .iter()
-            .map(|(hash, node_address)| (*hash, node_address.as_str()))
+            .map(|(hash, host_id)| (*hash, self.address(host_id).unwrap()))
             .collect()
     }
 
-    pub fn calculate_hash(value: &str) -> u64 {
-        let hash = murmur3::murmur3_x64_128(&mut std::io::Cursor::new(value), 0).unwrap();
-        hash as u64
+    /// Returns the tokens of the ring with the host id of the node owning each, in ring order.
+    pub fn get_host_entities(&self) -> Vec<(u64, Uuid)> {
+        self.ring
+            .iter()
+            .map(|(hash, host_id)| (*hash, *host_id))
+            .collect()
+    }
+
+    /// Returns the address the node with the given host id is reached at.
+    pub fn address(&self, host_id: &Uuid) -> Option<&str> {
+        self.addresses.get(host_id).map(String::as_str)
+    }
+
+    /// Returns the host id the node with the given address is placed under, which is
+    /// provisional until the node told its own.
+    pub fn host_id(&self, node_address: &str) -> Option<Uuid> {
+        self.addresses
+            .iter()
+            .find(|(_, address)| *address == node_address)
+            .map(|(host_id, _)| *host_id)
+    }
+
+    /// Returns the ranges of the ring, each ending at a token and starting at the previous
+    /// one, with the address of the node owning the token, in ring order.
+    pub fn token_ranges(&self) -> Vec<(TokenRange, &str)> {
+        let entities = self.get_entities();
+        entities
+            .iter()
+            .enumerate()
+            .map(|(i, (token, node_address))| {
+                let previous = entities[(i + entities.len() - 1) % entities.len()].0;
+                (TokenRange::new(previous, *token), *node_address)
+            })
+            .collect()
+    }
+
+    /// Returns the share of the ring every node owns, between 0 and 1.
+    pub fn ownership(&self) -> HashMap<String, f64> {
+        self.effective_ownership(1)
+    }
+
+    /// Returns the share of the ring every node replicates for a replication factor, between
+    /// 0 and 1: the ranges it owns and those it is one of the next distinct nodes of.
+    pub fn effective_ownership(&self, replication_factor: usize) -> HashMap<String, f64> {
+        let ownership =
+            token_allocator::replicated_ownership(&self.get_host_entities(), replication_factor);
+        self.addresses
+            .iter()
+            .map(|(host_id, address)| {
+                let share = ownership.get(host_id).copied().unwrap_or(0.0);
+                (address.clone(), share)
+            })
+            .collect()
+    }
+
+    /// Returns the token of a key, as given by the partitioner of the ring.
+    pub fn token(&self, key: &str) -> u64 {
+        self.partitioner.token(key.as_bytes())
+    }
+
+    pub fn partitioner(&self) -> &GlobalPartitioner {
+        &self.partitioner
     }
 
     pub fn calculate_hash_with_seed(value: &str, seed: u32) -> u64 {
@@ -105,17 +170,118 @@ impl ConsistentHashRing {
         hash as u64
     }
 
-    pub fn add_node(&mut self, node: Node) {
-        let node_address = node.address.clone();
-        Self::insert_virtual_nodes(&mut self.ring, &node_address);
+    /// Adds a node at its default tokens, under its host id unless another node has it.
+    pub fn add_node(&mut self, mut node: Node) {
+        // the node only becomes the other one once it is known to have moved
+        if node
+            .host_id
+            .is_some_and(|host_id| self.addresses.contains_key(&host_id))
+        {
+            node.host_id = None;
+        }
+        let host_id = node.host_id.unwrap_or_else(Uuid::new_v4);
+        Self::insert_tokens(
+            &mut self.ring,
+            host_id,
+            Self::default_tokens(&node.address, DEFAULT_NUM_TOKENS),
+        );
+        self.addresses.insert(host_id, node.address.clone());
         self.nodes.push(node);
     }
 
     pub fn drop_node(&mut self, node: &Node) {
-        Self::remove_virtual_nodes(&mut self.ring, &node.address);
+        if let Some(host_id) = self.host_id(&node.address) {
+            Self::remove_tokens(&mut self.ring, host_id);
+            self.addresses.remove(&host_id);
+        }
         self.nodes.retain(|n| n.address != node.address);
     }
 
+    /// Returns the tokens of a node, in ring order.
+    pub fn tokens(&self, node_address: &str) -> Vec<u64> {
+        let Some(host_id) = self.host_id(node_address) else {
+            return Vec::new();
+        };
+        self.ring
+            .iter()
+            .filter(|(_, owner)| **owner == host_id)
+            .map(|(token, _)| *token)
+            .collect()
+    }
+
+    /// Replaces the tokens of a node of the ring.
+    ///
+    /// Returns `false` if the node already had these tokens, or isn't part of the ring. A node
+    /// keeps its tokens if given none, so that the ring is never left empty.
+    pub fn set_tokens(&mut self, node_address: &str, mut tokens: Vec<u64>) -> bool {
+        tokens.sort();
+        tokens.dedup();
+        let Some(host_id) = self.host_id(node_address) else {
+            return false;
+        };
+        if tokens.is_empty() || self.tokens(node_address) == tokens {
+            return false;
+        }
+
+        Self::remove_tokens(&mut self.ring, host_id);
+        Self::insert_tokens(&mut self.ring, host_id, tokens);
+        true
+    }
+
+    /// Sets the host id of the node with the given address, placing its tokens under it.
+    ///
+    /// Returns `false` if the node already had this host id, isn't part of the ring, or the
+    /// host id is another node's: that node only becomes this one once it is known to have
+    /// moved.
+    pub fn set_host_id(&mut self, node_address: &str, host_id: Uuid) -> bool {
+        let Some(previous_host_id) = self.host_id(node_address) else {
+            return false;
+        };
+        if self.addresses.contains_key(&host_id) {
+            return false;
+        }
+
+        for owner in self.ring.values_mut() {
+            if *owner == previous_host_id {
+                *owner = host_id;
+            }
+        }
+        self.addresses.remove(&previous_host_id);
+        self.addresses.insert(host_id, node_address.to_string());
+        for node in self.nodes.iter_mut() {
+            if node.address == node_address {
+                node.host_id = Some(host_id);
+            }
+        }
+        true
+    }
+
+    /// Moves the node with the given host id to a new address, where it keeps its tokens.
+    ///
+    /// A node that was already placed at the new address is replaced. Returns the previous
+    /// address of the node, or `None` if no node has this host id or it didn't move.
+    pub fn move_node(&mut self, host_id: Uuid, new_address: &str) -> Option<String> {
+        let node = self.nodes.iter().find(|n| n.host_id == Some(host_id))?;
+        if node.address == new_address {
+            return None;
+        }
+        let previous_address = node.address.clone();
+
+        if let Some(replaced) = self.host_id(new_address) {
+            Self::remove_tokens(&mut self.ring, replaced);
+            self.addresses.remove(&replaced);
+            self.nodes.retain(|n| n.address != new_address);
+        }
+        self.addresses.insert(host_id, new_address.to_string());
+        for node in self.nodes.iter_mut() {
+            if node.address == previous_address {
+                node.address = new_address.to_string();
+            }
+        }
+
+        Some(previous_address)
+    }
+
     pub fn get_nodes(&self) -> &[Node] {
         &self.nodes
     }
@@ -124,37 +290,34 @@ impl ConsistentHashRing {
 #[cfg(test)]
 mod tests {
     use crate::consistent_hash_ring::{ConsistentHashRing, Node};
+    use crate::partitioner::Murmur3Partitioner;
+    use crate::token_range::TokenRange;
+    use std::sync::Arc;
+    use uuid::Uuid;
 
     #[test]
     fn test_hash() {
         let ip = "127.0.0.1:3000";
-        let hash = ConsistentHashRing::calculate_hash(ip);
-        assert_eq!(hash, 2784727742823359555);
+        let ring = ConsistentHashRing::new(Vec::new(), Arc::new(Murmur3Partitioner));
+        let hash = ring.token(ip);
+        assert_eq!(ring.partitioner().format_token(hash), "2784727742823359555");
     }
 
     #[test]
     fn test_add_node_to_the_ring() {
         let nodes_ips = vec![
-            Node {
-                address: "127.0.0.1:3000".to_string(),
-            },
-            Node {
-                address: "127.0.0.1:3001".to_string(),
-            },
-            Node {
-                address: "127.0.0.1:3002".to_string(),
-            },
+            Node::from("127.0.0.1:3000"),
+            Node::from("127.0.0.1:3001"),
+            Node::from("127.0.0.1:3002"),
         ];
 
-        let new_node = Node {
-            address: "127.0.0.1:3003".to_string(),
-        };
+        let new_node = Node::from("127.0.0.1:3003");
 
-        let mut ring = ConsistentHashRing::new(nodes_ips);
+        let mut ring = ConsistentHashRing::new(nodes_ips, Arc::new(Murmur3Partitioner));
 
-        let value_hash = ConsistentHashRing::calculate_hash("test_key1");
-        let node_hash = ring.get_node(value_hash);
-        assert_eq!(node_hash.address, "127.0.0.1:3002");
+        let value_hash = ring.token("test_key1");
+        let node_hash = ring.get_node(value_hash).unwrap();
+        assert_eq!(node_hash.address, "127.0.0.1:3000");
 
         ring.add_node(new_node);
     }
@@ -162,30 +325,127 @@ mod tests {
     #[test]
     fn test_get_ring_() {
         let nodes_ips = vec![
-            Node {
-                address: "localhost:3000".to_string(),
-            },
-            Node {
-                address: "localhost:4000".to_string(),
-            },
-            Node {
-                address: "localhost:5001".to_string(),
-            },
-            Node {
-                address: "localhost:6001".to_string(),
-            },
+            Node::from("localhost:3000"),
+            Node::from("localhost:4000"),
+            Node::from("localhost:5001"),
+            Node::from("localhost:6001"),
         ];
 
-        let new_node = Node {
-            address: "localhost:7001".to_string(),
-        };
+        let new_node = Node::from("localhost:7001");
 
-        let mut ring = ConsistentHashRing::new(nodes_ips);
+        let mut ring = ConsistentHashRing::new(nodes_ips, Arc::new(Murmur3Partitioner));
 
-        let value_hash = ConsistentHashRing::calculate_hash("test_key1");
-        let node_hash = ring.get_node(value_hash);
-        assert_eq!(node_hash.address, "localhost:4000");
+        let value_hash = ring.token("test_key1");
+        let node_hash = ring.get_node(value_hash).unwrap();
+        assert_eq!(node_hash.address, "localhost:6001");
 
         ring.add_node(new_node);
     }
+
+    #[test]
+    fn test_set_tokens_replaces_default_tokens() {
+        let nodes = vec![Node::from("localhost:3000"), Node::from("localhost:4000")];
+        let mut ring = ConsistentHashRing::new(nodes, Arc::new(Murmur3Partitioner));
+
+        assert!(ring.set_tokens("localhost:3000", vec![300, 100]));
+        assert!(ring.set_tokens("localhost:4000", vec![200]));
+        assert!(!ring.set_tokens("localhost:4000", vec![200]));
+        assert!(!ring.set_tokens("localhost:5001", vec![400]));
+
+        assert_eq!(ring.tokens("localhost:3000"), vec![100, 300]);
+        assert_eq!(ring.get_node(150).unwrap().address, "localhost:4000");
+        assert_eq!(ring.get_node(250).unwrap().address, "localhost:3000");
+        // past the last token the ring wraps around to the first one
+        assert_eq!(ring.get_node(301).unwrap().address, "localhost:3000");
+
+        ring.drop_node(&Node::from("localhost:3000"));
+        ring.drop_node(&Node::from("localhost:4000"));
+        assert!(ring.get_node(150).is_none());
+    }
+
+    #[test]
+    fn test_ownership_follows_range_sizes() {
+        let nodes = vec![
+            Node::from("localhost:3000"),
+            Node::from("localhost:4000"),
+            Node::from("localhost:5001"),
+        ];
+        let mut ring = ConsistentHashRing::new(nodes, Arc::new(Murmur3Partitioner));
+        let quarter = 1 << 62;
+        ring.set_tokens("localhost:3000", vec![quarter]);
+        ring.set_tokens("localhost:4000", vec![quarter * 2]);
+        ring.set_tokens("localhost:5001", vec![0]);
+
+        let ranges = ring.token_ranges();
+        assert_eq!(ranges.len(), 3);
+        // the first range wraps around from the last token
+        assert_eq!(ranges[0].0.start, quarter * 2);
+        assert_eq!(ranges[0].0.size(), 2 * quarter as u128);
+        assert_eq!(ranges[1].0.size(), quarter as u128);
+
+        let ownership = ring.ownership();
+        assert_eq!(ownership["localhost:5001"], 0.5);
+        assert_eq!(ownership["localhost:3000"], 0.25);
+
+        let ownership = ring.effective_ownership(2);
+        assert_eq!(ownership["localhost:5001"], 0.75);
+        assert_eq!(ownership["localhost:3000"], 0.75);
+        assert_eq!(ownership["localhost:4000"], 0.5);
+    }
+
+    #[test]
+    fn test_move_node_keeps_tokens() {
+        let nodes = vec![Node::from("localhost:3000"), Node::from("localhost:4000")];
+        let mut ring = ConsistentHashRing::new(nodes, Arc::new(Murmur3Partitioner));
+        let host_id = Uuid::new_v4();
+        ring.set_tokens("localhost:3000", vec![100, 300]);
+        assert!(ring.set_host_id("localhost:3000", host_id));
+        assert!(!ring.set_host_id("localhost:3000", host_id));
+
+        // the new address was already placed, at its default tokens
+        ring.add_node(Node::from("localhost:3001"));
+        assert_eq!(
+            ring.move_node(host_id, "localhost:3001"),
+            Some("localhost:3000".to_string())
+        );
+        assert_eq!(ring.move_node(host_id, "localhost:3001"), None);
+
+        assert_eq!(ring.get_nodes().len(), 2);
+        assert_eq!(ring.tokens("localhost:3001"), vec![100, 300]);
+        assert!(ring.tokens("localhost:3000").is_empty());
+        assert_eq!(ring.get_node(250).unwrap().address, "localhost:3001");
+    }
+
+    #[test]
+    fn test_moved_node_keeps_its_share_of_the_ring() {
+        let nodes = vec![
+            Node::from("localhost:3000"),
+            Node::from("localhost:4000"),
+            Node::from("localhost:5001"),
+        ];
+        let mut ring = ConsistentHashRing::new(nodes, Arc::new(Murmur3Partitioner));
+        let host_id = Uuid::new_v4();
+        ring.set_tokens("localhost:3000", vec![100]);
+        ring.set_tokens("localhost:4000", vec![200]);
+        ring.set_tokens("localhost:5001", vec![300]);
+        assert!(ring.set_host_id("localhost:4000", host_id));
+        // another node can't take the host id of a known node
+        assert!(!ring.set_host_id("localhost:5001", host_id));
+        let ownership = ring.effective_ownership(2);
+
+        ring.move_node(host_id, "localhost:4001");
+
+        assert_eq!(ring.host_id("localhost:4001"), Some(host_id));
+        assert_eq!(ring.address(&host_id), Some("localhost:4001"));
+        let moved_ownership = ring.effective_ownership(2);
+        assert_eq!(moved_ownership.len(), 3);
+        assert_eq!(
+            moved_ownership["localhost:4001"],
+            ownership["localhost:4000"]
+        );
+        assert_eq!(
+            ring.token_ranges()[1],
+            (TokenRange::new(100, 200), "localhost:4001")
+        );
+    }
 }
@@ -1,5 +1,7 @@
 //! Error types for the application.
 
+use crate::protocol::types::{Response, UnsupportedVersion};
+
 /// Error variants that can occur during application execution.
 #[derive(Debug)]
 pub enum Error {
@@ -11,6 +13,12 @@ pub enum Error {
     ConnectionError(Option<std::io::Error>),
     /// The received command is not recognized.
     UnknownCommand(),
+    /// The peer speaks a protocol version that isn't supported.
+    UnsupportedVersion(UnsupportedVersion),
+    /// The server answered with an unexpected message.
+    UnexpectedResponse(Response),
+    /// The server rejected the credentials, or required some that weren't given.
+    AuthenticationFailed(String),
 }
 
 /// A specialized Result type for application operations.
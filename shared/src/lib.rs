@@ -1,8 +1,15 @@
+pub mod auth;
 pub mod cluster;
 pub mod connection;
 pub mod connection_pool;
 pub mod consistent_hash_ring;
 pub mod error;
+pub mod paging;
+pub mod partitioner;
 pub mod protocol;
 pub mod replication;
 pub mod routing;
+pub mod stream;
+pub mod tls;
+pub mod token_allocator;
+pub mod token_range;
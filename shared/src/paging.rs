@@ -0,0 +1,85 @@
+//! Reading the values of ranges from nodes a page at a time.
+
+use crate::cluster::Node;
+use crate::connection_pool::ConnectionPool;
+use crate::error::{AppResult, Error};
+use crate::protocol::types::{GetBatch, PagingState, Request, Response};
+use crate::routing::RoutingStrategy;
+use crate::token_range::TokenRange;
+use std::collections::VecDeque;
+
+/// The values of some ranges stored on some nodes, read node by node with GET_BATCH.
+///
+/// A page is only requested once the values of the previous one were consumed, so the values
+/// never have to fit in memory at once. Iteration stops at the first error.
+pub struct Pages<'a> {
+    connection_pool: &'a mut ConnectionPool,
+    nodes: VecDeque<Node>,
+    ranges: Vec<TokenRange>,
+    page_size: usize,
+    /// Where to continue reading on the first node; `None` if it wasn't read yet.
+    paging_state: Option<PagingState>,
+    values: VecDeque<String>,
+    failed: bool,
+}
+
+impl<'a> Pages<'a> {
+    pub fn new(
+        connection_pool: &'a mut ConnectionPool,
+        nodes: Vec<Node>,
+        ranges: Vec<TokenRange>,
+        page_size: usize,
+    ) -> Self {
+        Self {
+            connection_pool,
+            nodes: nodes.into(),
+            ranges,
+            page_size,
+            paging_state: None,
+            values: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    /// Requests the next page of the first node, moving on to the next node after its last
+    /// page.
+    fn fetch(&mut self) -> AppResult<()> {
+        let Some(node) = self.nodes.front() else {
+            return Ok(());
+        };
+        let request = Request::GetBatch(GetBatch {
+            ranges: self.ranges.clone(),
+            page_size: Some(self.page_size),
+            paging_state: self.paging_state.take(),
+        });
+
+        match self
+            .connection_pool
+            .execute(RoutingStrategy::Direct(node), request, None)?
+        {
+            Response::Page(page) => {
+                self.values.extend(page.values);
+                self.paging_state = page.paging_state;
+                if self.paging_state.is_none() {
+                    self.nodes.pop_front();
+                }
+                Ok(())
+            }
+            response => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+}
+
+impl Iterator for Pages<'_> {
+    type Item = AppResult<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.values.is_empty() && !self.nodes.is_empty() && !self.failed {
+            if let Err(error) = self.fetch() {
+                self.failed = true;
+                return Some(Err(error));
+            }
+        }
+        self.values.pop_front().map(Ok)
+    }
+}
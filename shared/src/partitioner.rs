@@ -0,0 +1,363 @@
+//! Partitioners, which turn partition keys into tokens placing them on the ring.
+//!
+//! Every partitioner orders its tokens differently, so tokens are kept as `u64` positions on
+//! the ring, ordered like the partitioner's own tokens. Every node and client of a cluster
+//! has to use the same partitioner, chosen with the `partitioner=` argument, for the nodes
+//! owning a key to agree.
+
+use crate::protocol::types::CqlValue;
+use md5::{Digest, Md5};
+use std::sync::Arc;
+
+/// A thread-safe, shared partitioner.
+pub type GlobalPartitioner = Arc<dyn Partitioner + Send + Sync>;
+
+pub trait Partitioner {
+    fn name(&self) -> &'static str;
+    /// Returns the position on the ring of the token of a key.
+    fn token(&self, key: &[u8]) -> u64;
+    /// Formats a position on the ring as a token of this partitioner.
+    fn format_token(&self, token: u64) -> String;
+    /// Parses a token of this partitioner into its position on the ring, or returns `None` if
+    /// it isn't a valid token.
+    fn parse_token(&self, token: &str) -> Option<u64>;
+
+    /// Returns the position on the ring of the partition with the given partition key values.
+    ///
+    /// Like Cassandra, a single value is hashed as it is serialized, while the values of a
+    /// composite key are each serialized prefixed with their 16-bit length and followed by a
+    /// zero byte.
+    fn partition_key_token(&self, partition_key: &[CqlValue]) -> u64 {
+        if let [value] = partition_key {
+            return self.token(&value.serialize());
+        }
+
+        let mut key = Vec::new();
+        for value in partition_key {
+            let bytes = value.serialize();
+            key.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+            key.extend_from_slice(&bytes);
+            key.push(0);
+        }
+        self.token(&key)
+    }
+}
+
+/// Creates the partitioner with the given name, or `None` if there is no such partitioner.
+pub fn from_name(name: &str) -> Option<GlobalPartitioner> {
+    match name {
+        Murmur3Partitioner::NAME => Some(Arc::new(Murmur3Partitioner)),
+        RandomPartitioner::NAME => Some(Arc::new(RandomPartitioner)),
+        ByteOrderedPartitioner::NAME => Some(Arc::new(ByteOrderedPartitioner)),
+        _ => None,
+    }
+}
+
+/// Hashes keys with MurmurHash3 into signed 64-bit tokens, like Cassandra does.
+pub struct Murmur3Partitioner;
+
+impl Murmur3Partitioner {
+    pub const NAME: &'static str = "Murmur3Partitioner";
+
+    /// Returns the token Cassandra gives to a key: the first half of its x64 128-bit hash,
+    /// with the minimum reserved for the empty key.
+    pub fn long_token(key: &[u8]) -> i64 {
+        if key.is_empty() {
+            return i64::MIN;
+        }
+        match murmur3_x64_128_h1(key) as i64 {
+            i64::MIN => i64::MAX,
+            token => token,
+        }
+    }
+}
+
+impl Partitioner for Murmur3Partitioner {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn token(&self, key: &[u8]) -> u64 {
+        // flipping the sign bit keeps negative tokens before positive ones
+        (Self::long_token(key) as u64) ^ (1 << 63)
+    }
+
+    fn format_token(&self, token: u64) -> String {
+        ((token ^ (1 << 63)) as i64).to_string()
+    }
+
+    fn parse_token(&self, token: &str) -> Option<u64> {
+        token
+            .parse::<i64>()
+            .ok()
+            .map(|token| (token as u64) ^ (1 << 63))
+    }
+}
+
+/// Hashes keys with MD5 into tokens between 0 and 2^127.
+///
+/// The ring keeps the 64 most significant bits of the tokens.
+pub struct RandomPartitioner;
+
+impl RandomPartitioner {
+    pub const NAME: &'static str = "RandomPartitioner";
+
+    /// Returns the absolute value of the MD5 digest of a key, read as a signed big-endian
+    /// 128-bit integer.
+    pub fn big_integer_token(key: &[u8]) -> u128 {
+        let digest: [u8; 16] = Md5::digest(key).into();
+        i128::from_be_bytes(digest).unsigned_abs()
+    }
+}
+
+impl Partitioner for RandomPartitioner {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn token(&self, key: &[u8]) -> u64 {
+        if key.is_empty() {
+            return 0;
+        }
+        u64::try_from(Self::big_integer_token(key) >> 63).unwrap_or(u64::MAX)
+    }
+
+    fn format_token(&self, token: u64) -> String {
+        ((token as u128) << 63).to_string()
+    }
+
+    fn parse_token(&self, token: &str) -> Option<u64> {
+        match token.parse::<u128>().ok()? {
+            token if token > 1 << 127 => None,
+            token => Some(u64::try_from(token >> 63).unwrap_or(u64::MAX)),
+        }
+    }
+}
+
+/// Orders keys by their bytes, which keeps ranges of keys together on the ring but spreads
+/// them unevenly.
+///
+/// The ring keeps the first 8 bytes of the keys.
+pub struct ByteOrderedPartitioner;
+
+impl ByteOrderedPartitioner {
+    pub const NAME: &'static str = "ByteOrderedPartitioner";
+}
+
+impl Partitioner for ByteOrderedPartitioner {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn token(&self, key: &[u8]) -> u64 {
+        let mut bytes = [0; 8];
+        let len = key.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&key[..len]);
+        u64::from_be_bytes(bytes)
+    }
+
+    fn format_token(&self, token: u64) -> String {
+        let bytes = token.to_be_bytes();
+        let len = bytes
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |i| i + 1);
+        bytes[..len]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn parse_token(&self, token: &str) -> Option<u64> {
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return None;
+        }
+        let key = (0..token.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&token[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(self.token(&key))
+    }
+}
+
+const MURMUR3_C1: u64 = 0x87c3_7b91_1142_53d5;
+const MURMUR3_C2: u64 = 0x4cf5_ad43_2745_937f;
+
+/// Returns the first half of the MurmurHash3 x64 128-bit hash of a key, seeded with 0.
+///
+/// Like Cassandra, the bytes of the last, partial block are sign-extended, so keys with such
+/// bytes of 0x80 or more hash differently than with the reference implementation.
+fn murmur3_x64_128_h1(key: &[u8]) -> u64 {
+    let mut h1: u64 = 0;
+    let mut h2: u64 = 0;
+
+    let mut blocks = key.chunks_exact(16);
+    for block in blocks.by_ref() {
+        let k1 = u64::from_le_bytes(block[..8].try_into().unwrap());
+        let k2 = u64::from_le_bytes(block[8..].try_into().unwrap());
+
+        h1 ^= mix_k1(k1);
+        h1 = h1.rotate_left(27).wrapping_add(h2);
+        h1 = h1.wrapping_mul(5).wrapping_add(0x52dc_e729);
+
+        h2 ^= mix_k2(k2);
+        h2 = h2.rotate_left(31).wrapping_add(h1);
+        h2 = h2.wrapping_mul(5).wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    let mut k1: u64 = 0;
+    let mut k2: u64 = 0;
+    for (i, byte) in tail.iter().enumerate() {
+        let byte = *byte as i8 as i64 as u64;
+        match i {
+            0..8 => k1 ^= byte << (8 * i),
+            _ => k2 ^= byte << (8 * (i - 8)),
+        }
+    }
+    if tail.len() > 8 {
+        h2 ^= mix_k2(k2);
+    }
+    if !tail.is_empty() {
+        h1 ^= mix_k1(k1);
+    }
+
+    h1 ^= key.len() as u64;
+    h2 ^= key.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    h1 = fmix64(h1);
+    h2 = fmix64(h2);
+    h1.wrapping_add(h2)
+}
+
+fn mix_k1(k1: u64) -> u64 {
+    k1.wrapping_mul(MURMUR3_C1)
+        .rotate_left(31)
+        .wrapping_mul(MURMUR3_C2)
+}
+
+fn mix_k2(k2: u64) -> u64 {
+    k2.wrapping_mul(MURMUR3_C2)
+        .rotate_left(33)
+        .wrapping_mul(MURMUR3_C1)
+}
+
+fn fmix64(mut k: u64) -> u64 {
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    k ^= k >> 33;
+    k = k.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    k ^= k >> 33;
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partitioner::{
+        ByteOrderedPartitioner, Murmur3Partitioner, Partitioner, RandomPartitioner, from_name,
+    };
+    use crate::protocol::types::CqlValue;
+
+    #[test]
+    fn test_murmur3_tokens_match_cassandra() {
+        // the token Cassandra gives to the int partition key 1
+        assert_eq!(
+            Murmur3Partitioner::long_token(&1i32.to_be_bytes()),
+            -4069959284402364209
+        );
+        assert_eq!(Murmur3Partitioner::long_token(b""), i64::MIN);
+    }
+
+    #[test]
+    fn test_murmur3_matches_reference_hash_for_ascii_keys() {
+        for key in ["127.0.0.1:3000", "test_key1", "a key longer than one block"] {
+            let hash = murmur3::murmur3_x64_128(&mut std::io::Cursor::new(key), 0).unwrap();
+            assert_eq!(
+                Murmur3Partitioner::long_token(key.as_bytes()),
+                hash as u64 as i64
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokens_keep_the_order_of_the_partitioner() {
+        let partitioner = Murmur3Partitioner;
+        let (negative, positive) = (1i32.to_be_bytes(), *b"1");
+        let (negative, positive) = (negative.as_slice(), positive.as_slice());
+        assert!(Murmur3Partitioner::long_token(negative) < 0);
+        assert!(Murmur3Partitioner::long_token(positive) > 0);
+        assert!(partitioner.token(negative) < partitioner.token(positive));
+        assert_eq!(
+            partitioner.format_token(partitioner.token(negative)),
+            Murmur3Partitioner::long_token(negative).to_string()
+        );
+
+        let partitioner = ByteOrderedPartitioner;
+        assert!(partitioner.token(b"apple") < partitioner.token(b"banana"));
+        assert_eq!(partitioner.format_token(partitioner.token(b"ab")), "6162");
+    }
+
+    #[test]
+    fn test_parse_token_reverses_format_token() {
+        for name in [
+            Murmur3Partitioner::NAME,
+            RandomPartitioner::NAME,
+            ByteOrderedPartitioner::NAME,
+        ] {
+            let partitioner = from_name(name).unwrap();
+            let token = partitioner.token(b"key");
+            assert_eq!(
+                partitioner.parse_token(&partitioner.format_token(token)),
+                Some(token)
+            );
+        }
+        assert_eq!(
+            Murmur3Partitioner.parse_token("-9223372036854775808"),
+            Some(0)
+        );
+        assert_eq!(Murmur3Partitioner.parse_token("token"), None);
+        assert_eq!(ByteOrderedPartitioner.parse_token("6"), None);
+        assert!(from_name("OrderPreservingPartitioner").is_none());
+    }
+
+    #[test]
+    fn test_random_tokens_are_positive_md5_digests() {
+        let partitioner = RandomPartitioner;
+        let token = RandomPartitioner::big_integer_token(b"key");
+        assert!(token <= 1 << 127);
+        assert_eq!(partitioner.token(b"key"), (token >> 63) as u64);
+    }
+
+    #[test]
+    fn test_partition_key_token_matches_cassandra() {
+        // tokens Cassandra gives to single column partition keys
+        let partitioner = from_name(Murmur3Partitioner::NAME).unwrap();
+        for (key, token) in [
+            (CqlValue::Int(1), "-4069959284402364209"),
+            (CqlValue::Text("123".to_string()), "-7468325962851647638"),
+            (CqlValue::Blob(b"123".to_vec()), "-7468325962851647638"),
+            (
+                CqlValue::Text("9223372036854775807".to_string()),
+                "7162290910810015547",
+            ),
+            (CqlValue::BigInt(-72340172838076674), "-8927430733708461935"),
+            (CqlValue::BigInt(1157442765409226768), "1446172840243228796"),
+            (
+                CqlValue::Blob(b"\x00\xff\x10\xfa\x99".repeat(10)),
+                "5837342703291459765",
+            ),
+        ] {
+            assert_eq!(
+                partitioner.format_token(partitioner.partition_key_token(&[key])),
+                token
+            );
+        }
+
+        // composite keys serialize every value with its length and a zero byte
+        assert_eq!(
+            partitioner.partition_key_token(&[CqlValue::Text("a".to_string()), CqlValue::Int(1)]),
+            partitioner.token(&[0, 1, b'a', 0, 0, 4, 0, 0, 0, 1, 0])
+        );
+    }
+}
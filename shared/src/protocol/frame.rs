@@ -1,9 +1,56 @@
 //! Frame management for the protocol
 //!
 //! This represents a frame in the protocol, which is used to read and write data to and from
+//!
+//! Depending on the header flags, the body on the wire is prefixed with extra fields, in this
+//! order:
+//! - `Flags::TRACING` (responses only): a 16 byte tracing session id
+//! - `Flags::WARNING` (responses only): a `[string list]` of warnings
+//! - `Flags::CUSTOM_PAYLOAD`: a `[bytes map]` custom payload
 
+use crate::protocol::primitives::{
+    read_bytes_map, read_string_list, write_bytes_map, write_string_list,
+};
+use crate::protocol::types::{CustomPayload, TRACE_SESSION_PAYLOAD_KEY};
 pub use crate::protocol::types::{Flags, Opcode, Version};
+use std::collections::HashMap;
 use std::io::Read;
+use uuid::Uuid;
+
+/// Size of the frame header in bytes.
+const HEADER_LENGTH: usize = 9;
+
+/// Stream id of events pushed by the server, `-1` as a signed short.
+pub const EVENT_STREAM: u16 = 0xFFFF;
+
+/// Tracing asked for by a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tracing {
+    /// The request isn't traced.
+    #[default]
+    Disabled,
+    /// A client asked to trace the request in a new session.
+    NewSession,
+    /// Another node forwarded the request as part of a session.
+    Session(Uuid),
+}
+
+/// The flag-implied fields a request carries besides its body.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestExtras {
+    pub tracing: Tracing,
+    pub custom_payload: CustomPayload,
+}
+
+/// The flag-implied fields a response carries besides its body.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseExtras {
+    /// Tracing session id, present on responses to traced requests.
+    pub tracing_id: Option<Uuid>,
+    /// Warnings the server attached to the response.
+    pub warnings: Vec<String>,
+    pub custom_payload: CustomPayload,
+}
 
 #[derive(Debug, Clone)]
 pub struct Frame {
@@ -11,14 +58,21 @@ pub struct Frame {
     pub flags: Flags,
     pub stream: u16,
     pub opcode: Opcode,
+    /// Length of `body`, not counting the flag-implied fields.
     pub length: u32,
+    /// Tracing session id, present on responses to traced requests.
+    pub tracing_id: Option<Uuid>,
+    /// Warnings attached to a response.
+    pub warnings: Vec<String>,
+    /// Custom payload carried by either a request or a response.
+    pub custom_payload: HashMap<String, Vec<u8>>,
     pub body: Vec<u8>,
 }
 
 impl Frame {
     /// Creates a new frame with the given parameters.
     ///
-    /// Flags and stream are optional, defaulting to None and 0 respectively.
+    /// Flags and stream are optional, defaulting to empty flags and 0 respectively.
     pub fn new(
         version: Version,
         flags: Option<Flags>,
@@ -27,7 +81,7 @@ impl Frame {
         length: u32,
         body: Vec<u8>,
     ) -> Self {
-        let flags = flags.unwrap_or(Flags::None);
+        let flags = flags.unwrap_or_default();
         let stream = stream.unwrap_or(0);
 
         Self {
@@ -36,27 +90,135 @@ impl Frame {
             stream,
             opcode,
             length,
+            tracing_id: None,
+            warnings: Vec::new(),
+            custom_payload: HashMap::new(),
             body,
         }
     }
 
+    /// Sets the tracing session id and the `TRACING` flag.
+    pub fn with_tracing_id(mut self, tracing_id: Uuid) -> Self {
+        self.flags.insert(Flags::TRACING);
+        self.tracing_id = Some(tracing_id);
+        self
+    }
+
+    /// Sets the warnings and the `WARNING` flag, unless there are no warnings.
+    pub fn with_warnings(mut self, warnings: Vec<String>) -> Self {
+        self.flags.set(Flags::WARNING, !warnings.is_empty());
+        self.warnings = warnings;
+        self
+    }
+
+    /// Sets the custom payload and the `CUSTOM_PAYLOAD` flag, unless the payload is empty.
+    pub fn with_custom_payload(mut self, custom_payload: HashMap<String, Vec<u8>>) -> Self {
+        self.flags
+            .set(Flags::CUSTOM_PAYLOAD, !custom_payload.is_empty());
+        self.custom_payload = custom_payload;
+        self
+    }
+
+    /// Sets the flag-implied fields of a request.
+    ///
+    /// The session of a forwarded traced request travels in the custom payload.
+    pub fn with_request_extras(mut self, extras: RequestExtras) -> Self {
+        let mut custom_payload = extras.custom_payload;
+        match extras.tracing {
+            Tracing::Disabled => {}
+            Tracing::NewSession => self.flags.insert(Flags::TRACING),
+            Tracing::Session(session_id) => {
+                self.flags.insert(Flags::TRACING);
+                custom_payload.insert(
+                    TRACE_SESSION_PAYLOAD_KEY.to_string(),
+                    session_id.as_bytes().to_vec(),
+                );
+            }
+        }
+        self.with_custom_payload(custom_payload)
+    }
+
+    /// Returns the flag-implied fields of a request.
+    pub fn request_extras(&self) -> RequestExtras {
+        let mut custom_payload = self.custom_payload.clone();
+        let session_id = custom_payload
+            .remove(TRACE_SESSION_PAYLOAD_KEY)
+            .and_then(|bytes| Uuid::from_slice(&bytes).ok());
+
+        let tracing = match (self.flags.contains(Flags::TRACING), session_id) {
+            (false, _) => Tracing::Disabled,
+            (true, None) => Tracing::NewSession,
+            (true, Some(session_id)) => Tracing::Session(session_id),
+        };
+        RequestExtras {
+            tracing,
+            custom_payload,
+        }
+    }
+
+    /// Sets the flag-implied fields of a response.
+    pub fn with_response_extras(self, extras: ResponseExtras) -> Self {
+        let frame = match extras.tracing_id {
+            Some(tracing_id) => self.with_tracing_id(tracing_id),
+            None => self,
+        };
+        frame
+            .with_warnings(extras.warnings)
+            .with_custom_payload(extras.custom_payload)
+    }
+
+    /// Returns the flag-implied fields of a response.
+    pub fn response_extras(&self) -> ResponseExtras {
+        ResponseExtras {
+            tracing_id: self.tracing_id,
+            warnings: self.warnings.clone(),
+            custom_payload: self.custom_payload.clone(),
+        }
+    }
+
+    /// Returns whether this frame travels from the server to the client.
+    fn is_response(&self) -> bool {
+        self.version.is_response()
+    }
+
     /// Encodes the frame into a vector of bytes.
+    ///
+    /// The length in the header covers the flag-implied fields as well as the body.
     pub fn encode(self) -> Result<Vec<u8>, std::io::Error> {
-        let mut bytes = Vec::with_capacity(9 + self.body.len());
-        bytes.push(self.version as u8);
-        bytes.push(self.flags as u8);
+        let mut extras = Vec::new();
+        if self.is_response() && self.flags.contains(Flags::TRACING) {
+            extras.extend_from_slice(self.tracing_id.unwrap_or_default().as_bytes());
+        }
+        if self.is_response() && self.flags.contains(Flags::WARNING) {
+            write_string_list(&mut extras, &self.warnings);
+        }
+        if self.flags.contains(Flags::CUSTOM_PAYLOAD) {
+            write_bytes_map(&mut extras, &self.custom_payload);
+        }
+
+        let length = (extras.len() + self.body.len()) as u32;
+
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + length as usize);
+        bytes.push(self.version.into());
+        bytes.push(self.flags.bits());
         bytes.extend_from_slice(&self.stream.to_be_bytes());
         bytes.push(self.opcode as u8);
-        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(&extras);
         bytes.extend_from_slice(&self.body);
         Ok(bytes)
     }
 
     /// Decodes a frame from a stream.
+    ///
+    /// A frame of an unsupported protocol version fails with an `InvalidData` error wrapping
+    /// [`UnsupportedVersion`](crate::protocol::types::UnsupportedVersion); the rest of that
+    /// frame is left unread.
     pub fn decode(stream: &mut impl Read) -> Result<Frame, std::io::Error> {
         let mut version_byte = [0u8; 1];
         stream.read_exact(&mut version_byte)?;
-        let version = Version::from(version_byte[0]);
+        let version = Version::try_from(version_byte[0])
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
         let mut flags_byte = [0u8; 1];
         stream.read_exact(&mut flags_byte)?;
@@ -74,16 +236,27 @@ impl Frame {
         stream.read_exact(&mut length_bytes)?;
         let length = u32::from_be_bytes(length_bytes);
 
-        let mut body = vec![0u8; length as usize];
-        stream.read_exact(&mut body)?;
+        let mut content = vec![0u8; length as usize];
+        stream.read_exact(&mut content)?;
 
-        Ok(Frame {
-            version,
-            flags,
-            stream: stream_id,
-            opcode,
-            length,
-            body,
-        })
+        let mut frame = Frame::new(version, Some(flags), Some(stream_id), opcode, 0, Vec::new());
+        let mut reader = content.as_slice();
+
+        if frame.is_response() && flags.contains(Flags::TRACING) {
+            let mut tracing_id = [0u8; 16];
+            reader.read_exact(&mut tracing_id)?;
+            frame.tracing_id = Some(Uuid::from_bytes(tracing_id));
+        }
+        if frame.is_response() && flags.contains(Flags::WARNING) {
+            frame.warnings = read_string_list(&mut reader)?;
+        }
+        if flags.contains(Flags::CUSTOM_PAYLOAD) {
+            frame.custom_payload = read_bytes_map(&mut reader)?;
+        }
+
+        frame.body = reader.to_vec();
+        frame.length = frame.body.len() as u32;
+
+        Ok(frame)
     }
 }
@@ -1,4 +1,6 @@
 pub mod frame;
+pub mod primitives;
 pub mod protocol_reader;
 pub mod protocol_writer;
+pub mod segment;
 pub mod types;
@@ -0,0 +1,104 @@
+//! Encoding of the primitive notations used by the native protocol.
+//!
+//! All integers are big-endian. The notations follow the protocol spec:
+//! - `[string]`: a `[short]` length followed by UTF-8 bytes
+//! - `[string list]`: a `[short]` count followed by that many `[string]`
+//! - `[bytes]`: an `[int]` length followed by the bytes; a negative length means null
+//! - `[bytes map]`: a `[short]` count followed by `[string]` keys and `[bytes]` values
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+
+/// Writes a `[short]`.
+pub fn write_short(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Reads a `[short]`.
+pub fn read_short(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+/// Writes an `[int]`.
+pub fn write_int(buffer: &mut Vec<u8>, value: i32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Reads an `[int]`.
+pub fn read_int(reader: &mut impl Read) -> io::Result<i32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_be_bytes(bytes))
+}
+
+/// Writes a `[string]`.
+pub fn write_string(buffer: &mut Vec<u8>, value: &str) {
+    write_short(buffer, value.len() as u16);
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+/// Reads a `[string]`.
+pub fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_short(reader)?;
+    let mut bytes = vec![0u8; length as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes a `[string list]`.
+pub fn write_string_list(buffer: &mut Vec<u8>, values: &[String]) {
+    write_short(buffer, values.len() as u16);
+    for value in values {
+        write_string(buffer, value);
+    }
+}
+
+/// Reads a `[string list]`.
+pub fn read_string_list(reader: &mut impl Read) -> io::Result<Vec<String>> {
+    let count = read_short(reader)?;
+    (0..count).map(|_| read_string(reader)).collect()
+}
+
+/// Writes a `[bytes]`.
+pub fn write_bytes(buffer: &mut Vec<u8>, value: &[u8]) {
+    write_int(buffer, value.len() as i32);
+    buffer.extend_from_slice(value);
+}
+
+/// Reads a `[bytes]`. A null value is returned as an empty vector.
+pub fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let length = read_int(reader)?;
+    if length < 0 {
+        return Ok(Vec::new());
+    }
+    let mut bytes = vec![0u8; length as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Writes a `[bytes map]`. Keys are written in sorted order so encoding is deterministic.
+pub fn write_bytes_map(buffer: &mut Vec<u8>, map: &HashMap<String, Vec<u8>>) {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+
+    write_short(buffer, keys.len() as u16);
+    for key in keys {
+        write_string(buffer, key);
+        write_bytes(buffer, &map[key]);
+    }
+}
+
+/// Reads a `[bytes map]`.
+pub fn read_bytes_map(reader: &mut impl Read) -> io::Result<HashMap<String, Vec<u8>>> {
+    let count = read_short(reader)?;
+    let mut map = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let key = read_string(reader)?;
+        let value = read_bytes(reader)?;
+        map.insert(key, value);
+    }
+    Ok(map)
+}
@@ -1,33 +1,92 @@
 //! Wrapper over TCP stream for reading and decoding protocol frames
 
 use crate::protocol::frame::Frame;
+use crate::protocol::segment::{Framing, Segment};
 use crate::protocol::types::{Request, Response};
 use io::Read;
 use std::io;
 use std::io::BufReader;
 
+/// Size of the frame header in bytes; the body length is stored in its last four bytes.
+const FRAME_HEADER_LENGTH: usize = 9;
+
 pub struct ProtocolReader<T: Read> {
     reader: BufReader<T>,
+    framing: Framing,
+    /// Segment payload that hasn't been decoded into frames yet.
+    pending: Vec<u8>,
 }
 
 impl<T: Read> ProtocolReader<T> {
     pub fn new(reader: T) -> Self {
         let reader = BufReader::new(reader);
-        Self { reader }
+        Self {
+            reader,
+            framing: Framing::Plain,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Switches how subsequent frames are read from the stream.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        self.reader.get_ref()
+    }
+
+    /// Returns the underlying stream, e.g. to write replies on the same connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.reader.get_mut()
+    }
+
+    /// Receives the next frame, unwrapping it from segments if needed.
+    pub fn receive_frame(&mut self) -> Result<Frame, io::Error> {
+        match self.framing {
+            Framing::Plain => Frame::decode(&mut self.reader),
+            Framing::Checksummed => self.receive_segmented_frame(),
+        }
     }
 
     pub fn receive_response(&mut self) -> Result<Response, io::Error> {
-        let frame = Frame::decode(&mut self.reader)?;
+        let frame = self.receive_frame()?;
         let response: Response = bincode::deserialize(&frame.body)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         Ok(response)
     }
 
     pub fn receive_request(&mut self) -> Result<Request, io::Error> {
-        let frame = Frame::decode(&mut self.reader)?;
+        let frame = self.receive_frame()?;
         let response: Request = bincode::deserialize(&frame.body)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
         Ok(response)
     }
+
+    /// Reads segments until a complete frame is buffered and decodes it.
+    fn receive_segmented_frame(&mut self) -> Result<Frame, io::Error> {
+        loop {
+            if let Some(frame_length) = Self::buffered_frame_length(&self.pending)
+                && self.pending.len() >= frame_length
+            {
+                let frame = Frame::decode(&mut &self.pending[..frame_length])?;
+                self.pending.drain(..frame_length);
+                return Ok(frame);
+            }
+
+            let segment = Segment::decode(&mut self.reader)?;
+            self.pending.extend_from_slice(&segment.payload);
+        }
+    }
+
+    /// Returns the encoded length of the first buffered frame, once its header is available.
+    fn buffered_frame_length(buffer: &[u8]) -> Option<usize> {
+        if buffer.len() < FRAME_HEADER_LENGTH {
+            return None;
+        }
+        let body_length = u32::from_be_bytes([buffer[5], buffer[6], buffer[7], buffer[8]]);
+        Some(FRAME_HEADER_LENGTH + body_length as usize)
+    }
 }
@@ -1,50 +1,110 @@
 //! Wrapper over TCP stream for encoding data into protocol frames and write to the stream
 
-use crate::protocol::frame::Frame;
-use crate::protocol::types::{Opcode, Request, Response, Version};
+use crate::protocol::frame::{EVENT_STREAM, Frame, RequestExtras, ResponseExtras};
+use crate::protocol::segment;
+use crate::protocol::segment::Framing;
+use crate::protocol::types::{Event, ProtocolVersion, Request, Response, Version};
 use std::io::{BufWriter, Write};
 
 pub struct ProtocolWriter<T: Write> {
     writer: BufWriter<T>,
+    version: ProtocolVersion,
+    framing: Framing,
 }
 
-impl<'a, T: Write> ProtocolWriter<T> {
+impl<T: Write> ProtocolWriter<T> {
     pub fn new(writer: T) -> Self {
         let writer = BufWriter::new(writer);
-        Self { writer }
+        Self {
+            writer,
+            version: ProtocolVersion::default(),
+            framing: Framing::Plain,
+        }
+    }
+
+    /// Sets the protocol version written into frame headers.
+    pub fn with_version(mut self, version: ProtocolVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets how frames are laid out on the stream.
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
     }
 
     pub fn send_request(&mut self, request: &Request) -> Result<(), std::io::Error> {
+        self.send_request_with_extras(request, RequestExtras::default())
+    }
+
+    /// Sends a request together with its tracing flag and custom payload.
+    pub fn send_request_with_extras(
+        &mut self,
+        request: &Request,
+        extras: RequestExtras,
+    ) -> Result<(), std::io::Error> {
         let request_bytes = bincode::serialize(request).unwrap();
 
         let frame = Frame::new(
-            Version::Request,
+            Version::request(self.version),
             None,
             None,
-            Opcode::Query,
+            request.opcode(),
             request_bytes.len() as u32,
             request_bytes,
-        );
-        let frame_bytes = frame.encode()?;
-        self.writer.write_all(&frame_bytes)?;
-        self.writer.flush()?;
-
-        Ok(())
+        )
+        .with_request_extras(extras);
+        self.send_frame(frame)
     }
 
     pub fn send_response(&mut self, request: &Response) -> Result<(), std::io::Error> {
-        let request_bytes = bincode::serialize(request).unwrap();
+        self.send_response_with_extras(request, ResponseExtras::default())
+    }
+
+    /// Sends a response together with its tracing session id, warnings and custom payload.
+    pub fn send_response_with_extras(
+        &mut self,
+        response: &Response,
+        extras: ResponseExtras,
+    ) -> Result<(), std::io::Error> {
+        let response_bytes = bincode::serialize(response).unwrap();
 
         let frame = Frame::new(
-            Version::Response,
+            Version::response(self.version),
             None,
             None,
-            Opcode::Query,
-            request_bytes.len() as u32,
-            request_bytes,
+            response.opcode(),
+            response_bytes.len() as u32,
+            response_bytes,
+        )
+        .with_response_extras(extras);
+        self.send_frame(frame)
+    }
+
+    /// Pushes an event to a registered client on the reserved event stream.
+    pub fn send_event(&mut self, event: &Event) -> Result<(), std::io::Error> {
+        let response = Response::Event(event.clone());
+        let response_bytes = bincode::serialize(&response).unwrap();
+
+        let frame = Frame::new(
+            Version::response(self.version),
+            None,
+            Some(EVENT_STREAM),
+            response.opcode(),
+            response_bytes.len() as u32,
+            response_bytes,
         );
+        self.send_frame(frame)
+    }
+
+    /// Encodes and writes a frame, wrapping it into segments if needed.
+    pub fn send_frame(&mut self, frame: Frame) -> Result<(), std::io::Error> {
         let frame_bytes = frame.encode()?;
-        self.writer.write_all(&frame_bytes)?;
+        match self.framing {
+            Framing::Plain => self.writer.write_all(&frame_bytes)?,
+            Framing::Checksummed => segment::write_frame(&mut self.writer, &frame_bytes)?,
+        }
         self.writer.flush()?;
 
         Ok(())
@@ -0,0 +1,148 @@
+//! Checksummed segment framing introduced by protocol v5.
+//!
+//! Once a v5 connection has been started up, frames are no longer written to the stream
+//! directly but wrapped into segments:
+//!
+//! ```text
+//! +----------------------------+----------------+-----------------+------------------+
+//! | header (3 bytes, LE)       | CRC24 (3, LE)  | payload         | CRC32 (4, LE)    |
+//! | 17 bits payload length     | of the header  | up to 128KiB-1  | of the payload   |
+//! | 1 bit self-contained flag  |                |                 |                  |
+//! +----------------------------+----------------+-----------------+------------------+
+//! ```
+//!
+//! A self-contained segment holds one or more complete frames. A frame that doesn't fit
+//! into a single segment is split across several segments that are not self-contained.
+
+use std::io;
+use std::io::{Read, Write};
+
+/// Largest payload a single segment can carry.
+pub const MAX_PAYLOAD_LENGTH: usize = (1 << 17) - 1;
+
+/// Length of the segment header including its CRC24.
+const HEADER_LENGTH: usize = 6;
+
+const SELF_CONTAINED_BIT: u32 = 1 << 17;
+
+const CRC24_INIT: u32 = 0x875060;
+const CRC24_POLY: u32 = 0x1974F0B;
+
+/// Bytes mixed into every payload CRC32, as done by Cassandra.
+const CRC32_INITIAL_BYTES: [u8; 4] = [0xFA, 0x2D, 0x55, 0xCA];
+
+/// How frames are laid out on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Framing {
+    /// Frames are written to the stream as they are.
+    #[default]
+    Plain,
+    /// Frames are wrapped into checksummed segments (protocol v5).
+    Checksummed,
+}
+
+/// A single segment of a v5 connection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub payload: Vec<u8>,
+    pub self_contained: bool,
+}
+
+impl Segment {
+    /// Encodes the segment into a vector of bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut header = self.payload.len() as u32;
+        if self.self_contained {
+            header |= SELF_CONTAINED_BIT;
+        }
+        let header_bytes = &header.to_le_bytes()[..3];
+        let header_crc = crc24(header_bytes);
+
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.payload.len() + 4);
+        bytes.extend_from_slice(header_bytes);
+        bytes.extend_from_slice(&header_crc.to_le_bytes()[..3]);
+        bytes.extend_from_slice(&self.payload);
+        bytes.extend_from_slice(&crc32(&self.payload).to_le_bytes());
+        bytes
+    }
+
+    /// Decodes a segment from a stream, verifying both checksums.
+    pub fn decode(stream: &mut impl Read) -> io::Result<Segment> {
+        let mut header_bytes = [0u8; HEADER_LENGTH];
+        stream.read_exact(&mut header_bytes)?;
+
+        let header = u32::from_le_bytes([header_bytes[0], header_bytes[1], header_bytes[2], 0]);
+        let expected_header_crc =
+            u32::from_le_bytes([header_bytes[3], header_bytes[4], header_bytes[5], 0]);
+        if crc24(&header_bytes[..3]) != expected_header_crc {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Segment header CRC24 mismatch",
+            ));
+        }
+
+        let length = (header & MAX_PAYLOAD_LENGTH as u32) as usize;
+        let self_contained = header & SELF_CONTAINED_BIT != 0;
+
+        let mut payload = vec![0u8; length];
+        stream.read_exact(&mut payload)?;
+
+        let mut crc_bytes = [0u8; 4];
+        stream.read_exact(&mut crc_bytes)?;
+        if crc32(&payload) != u32::from_le_bytes(crc_bytes) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Segment payload CRC32 mismatch",
+            ));
+        }
+
+        Ok(Segment {
+            payload,
+            self_contained,
+        })
+    }
+}
+
+/// Writes an encoded frame as one self-contained segment, or as several segments that are
+/// not self-contained if the frame is larger than [`MAX_PAYLOAD_LENGTH`].
+pub fn write_frame(writer: &mut impl Write, frame_bytes: &[u8]) -> io::Result<()> {
+    if frame_bytes.len() <= MAX_PAYLOAD_LENGTH {
+        let segment = Segment {
+            payload: frame_bytes.to_vec(),
+            self_contained: true,
+        };
+        return writer.write_all(&segment.encode());
+    }
+
+    for chunk in frame_bytes.chunks(MAX_PAYLOAD_LENGTH) {
+        let segment = Segment {
+            payload: chunk.to_vec(),
+            self_contained: false,
+        };
+        writer.write_all(&segment.encode())?;
+    }
+    Ok(())
+}
+
+/// CRC24 over up to 8 bytes, matching Cassandra's segment header checksum.
+fn crc24(bytes: &[u8]) -> u32 {
+    let mut crc = CRC24_INIT;
+    for byte in bytes {
+        crc ^= (*byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= CRC24_POLY;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+/// CRC32 of a segment payload.
+fn crc32(payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&CRC32_INITIAL_BYTES);
+    hasher.update(payload);
+    hasher.finalize()
+}
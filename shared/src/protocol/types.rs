@@ -1,23 +1,176 @@
-use crate::consistent_hash_ring::Range;
+use crate::token_range::TokenRange;
+use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-#[repr(u8)]
-pub enum Flags {
-    None = 0x00,
-    Compression = 0x01,
-    Tracing = 0x02,
-    CustomPayload = 0x04,
-    Warning = 0x08,
+bitflags! {
+    /// Frame header flags.
+    ///
+    /// The protocol defines flags as a bitmask, so a single frame can carry any
+    /// combination of them. Each flag (except compression) implies extra fields
+    /// at the start of the frame body, see [`crate::protocol::frame::Frame`].
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+    pub struct Flags: u8 {
+        /// The frame body is compressed.
+        const COMPRESSION = 0x01;
+        /// Requests tracing; on a response the body starts with the tracing id.
+        const TRACING = 0x02;
+        /// The body starts with a `[bytes map]` custom payload.
+        const CUSTOM_PAYLOAD = 0x04;
+        /// The response body starts with a `[string list]` of warnings.
+        const WARNING = 0x08;
+    }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+/// Native protocol versions understood by this implementation.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
 #[repr(u8)]
-pub enum Version {
-    Request = 0x04,
-    Response = 0x84,
+pub enum ProtocolVersion {
+    V3 = 0x03,
+    #[default]
+    V4 = 0x04,
+    /// Uses checksummed segment framing once the connection has been started up.
+    V5 = 0x05,
+}
+
+impl ProtocolVersion {
+    /// All supported versions, oldest first.
+    pub const SUPPORTED: [ProtocolVersion; 3] = [
+        ProtocolVersion::V3,
+        ProtocolVersion::V4,
+        ProtocolVersion::V5,
+    ];
+
+    /// Returns the supported version closest to the requested raw version.
+    ///
+    /// Used to pick a version for replying to a peer speaking an unsupported version.
+    pub fn closest(version: u8) -> ProtocolVersion {
+        let version = version & !RESPONSE_DIRECTION_BIT;
+        Self::SUPPORTED
+            .into_iter()
+            .rev()
+            .find(|supported| *supported as u8 <= version)
+            .unwrap_or(ProtocolVersion::V3)
+    }
+
+    /// Returns whether frames of this version are wrapped in checksummed segments.
+    pub fn uses_segments(&self) -> bool {
+        *self >= ProtocolVersion::V5
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/v{}", *self as u8, *self as u8)
+    }
+}
+
+impl TryFrom<u8> for ProtocolVersion {
+    type Error = UnsupportedVersion;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        match version {
+            0x03 => Ok(ProtocolVersion::V3),
+            0x04 => Ok(ProtocolVersion::V4),
+            0x05 => Ok(ProtocolVersion::V5),
+            _ => Err(UnsupportedVersion(version)),
+        }
+    }
 }
 
+/// Bit of the version byte that marks a frame as a response.
+const RESPONSE_DIRECTION_BIT: u8 = 0x80;
+
+/// The direction a frame travels in.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+/// The version byte of a frame header: the protocol version plus the direction bit.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Version {
+    pub protocol: ProtocolVersion,
+    pub direction: Direction,
+}
+
+impl Version {
+    /// Version byte of a request in the given protocol version.
+    pub fn request(protocol: ProtocolVersion) -> Self {
+        Self {
+            protocol,
+            direction: Direction::Request,
+        }
+    }
+
+    /// Version byte of a response in the given protocol version.
+    pub fn response(protocol: ProtocolVersion) -> Self {
+        Self {
+            protocol,
+            direction: Direction::Response,
+        }
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.direction == Direction::Response
+    }
+}
+
+impl From<Version> for u8 {
+    fn from(version: Version) -> Self {
+        match version.direction {
+            Direction::Request => version.protocol as u8,
+            Direction::Response => version.protocol as u8 | RESPONSE_DIRECTION_BIT,
+        }
+    }
+}
+
+impl TryFrom<u8> for Version {
+    type Error = UnsupportedVersion;
+
+    fn try_from(version: u8) -> Result<Self, Self::Error> {
+        let protocol = ProtocolVersion::try_from(version & !RESPONSE_DIRECTION_BIT)
+            .map_err(|_| UnsupportedVersion(version))?;
+        let direction = if version & RESPONSE_DIRECTION_BIT == 0 {
+            Direction::Request
+        } else {
+            Direction::Response
+        };
+
+        Ok(Self {
+            protocol,
+            direction,
+        })
+    }
+}
+
+/// A frame was received with a protocol version this implementation doesn't speak.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedVersion(pub u8);
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let supported = ProtocolVersion::SUPPORTED
+            .iter()
+            .map(|version| version.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(
+            f,
+            "Invalid or unsupported protocol version ({}); supported versions are ({})",
+            self.0 & !RESPONSE_DIRECTION_BIT,
+            supported
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 #[repr(u8)]
 pub enum Opcode {
@@ -63,37 +216,777 @@ impl From<u8> for Opcode {
     }
 }
 
-impl From<u8> for Version {
-    fn from(version: u8) -> Self {
-        match version {
-            0x04 => Version::Request,
-            0x84 => Version::Response,
-            _ => panic!("Invalid version"),
-        }
-    }
-}
-
 impl From<u8> for Flags {
-    fn from(version: u8) -> Self {
-        match version {
-            0x00 => Flags::None,
-            0x01 => Flags::Compression,
-            0x02 => Flags::Tracing,
-            0x04 => Flags::CustomPayload,
-            0x08 => Flags::Warning,
-            _ => panic!("Invalid flags"),
-        }
+    /// Unknown bits are ignored, as required by the protocol.
+    fn from(flags: u8) -> Self {
+        Flags::from_bits_truncate(flags)
     }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Request {
+    /// Initializes the connection; answered with `Response::Ready`.
+    Startup(HashMap<String, String>),
+    /// Asks which startup options the server supports.
+    Options,
+    /// Executes a CQL statement.
+    Query(Query),
+    /// Prepares a statement; answered with `Response::Prepared`.
+    Prepare(String),
+    /// Executes a previously prepared statement with bound values.
+    Execute(Execute),
+    /// Executes several modification statements as one batch.
+    Batch(Batch),
+    /// Stores a logged batch on a batchlog replica before it is applied.
+    StoreBatchlog(BatchlogEntry),
+    /// Removes a batch from the batchlog once all its mutations were applied.
+    RemoveBatchlog(Uuid),
+    /// Subscribes the connection to events; answered with `Response::Ready`, after which
+    /// matching events are pushed as `Response::Event`.
+    Register(Vec<EventType>),
+    /// Tells a node that another node joined or left the cluster.
+    ChangeTopology(TopologyChange),
+    /// Answers an AUTHENTICATE or AUTH_CHALLENGE with a SASL token.
+    AuthResponse(Vec<u8>),
+    /// Creates a user that can log in with a password; only allowed for superusers.
+    CreateUser(CreateUser),
+    /// Changes the password or superuser status of a user; only allowed for superusers.
+    AlterUser(AlterUser),
+    /// Removes a user; only allowed for superusers.
+    DropUser(DropUser),
+    /// Grants permissions on a resource to a role.
+    Grant(PermissionChange),
+    /// Revokes permissions on a resource from a role.
+    Revoke(PermissionChange),
+    /// Reads the events a node recorded for a tracing session; answered with
+    /// `Response::Trace`.
+    GetTrace(Uuid),
+    /// Applies a write to a partition the node is a replica of.
+    Mutate(Mutation),
+    /// Asks a replica to lead an increment of counter columns, which it turns into its shards
+    /// of the counters and writes to every replica.
+    CounterUpdate(CounterUpdate),
+    /// Reads rows of a table stored on the node; answered with `Response::Rows`.
+    Read(ReadCommand),
+    /// Reads rows of a table stored on the node as the mutations writing them, deletions
+    /// included; answered with `Response::Mutations`.
+    ReadMutations(ReadCommand),
+    /// Tells a node about a keyspace created on another node.
+    CreateKeyspace(KeyspaceMetadata),
+    /// Asks a replica to promise taking part in a round of a lightweight transaction;
+    /// answered with `Response::Promise`.
+    PaxosPrepare(PaxosPrepare),
+    /// Asks a replica to accept the proposal of a round it promised; answered with
+    /// `Response::Bool`.
+    PaxosPropose(Proposal),
+    /// Tells a replica to apply an accepted proposal.
+    PaxosCommit(Proposal),
+    /// Tells a node about a table created on another node.
+    CreateTable(TableMetadata),
+    /// Tells a node about a secondary index created on another node.
+    CreateIndex(IndexMetadata),
+    /// Asks a node for its schema version; answered with `Response::SchemaVersion`.
+    SchemaVersion,
+    /// Asks a node for its keyspaces and tables; answered with `Response::Schema`.
+    PullSchema,
+    /// Asks a node for the tokens of every node it knows, its own included; answered with
+    /// `Response::Tokens`.
+    Tokens,
+    /// Asks a node how much data it stores; answered with `Response::Load`.
+    Load,
     Add(Entry),
     Check(String),
-    Count,
-    GetBatch(Vec<Range>),
-    DropBatch(Vec<Range>),
+    /// Counts the values a node stores, and separately those in each of the given ranges;
+    /// answered with `Response::Count`.
+    Count(Vec<TokenRange>),
+    /// Reads the values a node stores in some ranges; answered with `Response::Page`.
+    GetBatch(GetBatch),
+    DropBatch(Vec<TokenRange>),
     AddBatch(Vec<Entry>),
+    /// Reads the rows of a keyspace a node stores in some ranges as the mutations writing
+    /// them, deletions included; answered with `Response::Mutations`.
+    GetRows(RowRanges),
+    /// Applies rows streamed from another node, indexing them as they are applied.
+    AddRows(Vec<Mutation>),
+    /// Removes the rows of a keyspace a node stores in some ranges.
+    DropRows(RowRanges),
+}
+
+/// The rows of a keyspace in some ranges of the ring, which move between nodes when the
+/// cluster is rebalanced.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RowRanges {
+    pub keyspace: String,
+    pub ranges: Vec<TokenRange>,
+}
+
+/// Reads the values of some ranges, in range order and by hash within a range.
+///
+/// With a page size, at most that many values are returned, along with a paging state to
+/// pass in the next request to read the values after them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct GetBatch {
+    pub ranges: Vec<TokenRange>,
+    /// Maximum number of values to return; all values of the ranges if `None`.
+    pub page_size: Option<usize>,
+    /// Where the previous page ended, as returned with it.
+    pub paging_state: Option<PagingState>,
+}
+
+impl GetBatch {
+    /// Reads all values of the ranges at once.
+    pub fn new(ranges: Vec<TokenRange>) -> Self {
+        Self {
+            ranges,
+            page_size: None,
+            paging_state: None,
+        }
+    }
+}
+
+/// Where a paged read stopped. Opaque to clients, which just hand it back to the node that
+/// returned it.
+pub type PagingState = Vec<u8>;
+
+/// A page of values read by a GET_BATCH.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Page {
+    pub values: Vec<String>,
+    /// Where to continue reading; `None` once there are no more values.
+    pub paging_state: Option<PagingState>,
+}
+
+/// The number of values stored, in total and in some ranges.
+///
+/// A node answers with its own counts. Merging the answers of a fanout sums them into cluster
+/// totals, which count a replicated value once per replica, and keeps the answer of every
+/// node.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ValueCount {
+    pub total: u64,
+    /// The number of values in each requested range, in request order.
+    pub ranges: Vec<u64>,
+    /// The counts of every node answering a fanout, by address; empty in a node's answer.
+    pub nodes: Vec<(String, ValueCount)>,
+}
+
+/// Body of a QUERY message.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Query {
+    pub query: String,
+    /// Values for the bind markers, in marker order.
+    pub values: Vec<CqlValue>,
+    /// Keyspace of unqualified table names, overriding the keyspace set with USE.
+    pub keyspace: Option<String>,
+}
+
+impl Query {
+    pub fn new(query: impl Into<String>) -> Self {
+        Self {
+            query: query.into(),
+            values: Vec::new(),
+            keyspace: None,
+        }
+    }
+}
+
+/// Identifier of a prepared statement: the MD5 digest of its query string.
+pub type StatementId = [u8; 16];
+
+/// Body of an EXECUTE message.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Execute {
+    pub id: StatementId,
+    /// Values for the bind markers, in marker order.
+    pub values: Vec<CqlValue>,
+}
+
+/// Body of a BATCH message.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Batch {
+    pub batch_type: BatchType,
+    pub statements: Vec<BatchStatement>,
+}
+
+/// How the statements of a batch are applied.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BatchType {
+    /// Written to the batchlog first, so a batch spanning several partitions is eventually
+    /// applied completely even if the coordinator crashes half way.
+    Logged,
+    /// Applied partition by partition without any atomicity guarantee.
+    Unlogged,
+    /// Only counter updates, which are not idempotent and therefore never replayed.
+    Counter,
+}
+
+impl fmt::Display for BatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchType::Logged => write!(f, "LOGGED"),
+            BatchType::Unlogged => write!(f, "UNLOGGED"),
+            BatchType::Counter => write!(f, "COUNTER"),
+        }
+    }
+}
+
+/// A statement of a batch, either given as a query string or as a prepared statement id.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum BatchStatement {
+    Query(String, Vec<CqlValue>),
+    Prepared(StatementId, Vec<CqlValue>),
+}
+
+/// A logged batch as stored in the batchlog: the mutations it still has to apply.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct BatchlogEntry {
+    pub id: Uuid,
+    /// Values the batch adds.
+    pub mutations: Vec<Entry>,
+    /// Rows of CQL tables the batch writes.
+    pub row_mutations: Vec<Mutation>,
+}
+
+/// A write to one partition of a table, applied by every replica of the partition.
+///
+/// Cells are written at the mutation's timestamp, so replicas receiving writes in different
+/// orders end up with the same row: the most recent write of each cell wins.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Mutation {
+    pub keyspace: String,
+    pub table: String,
+    /// Values of the partition key columns, in key order.
+    pub partition_key: Vec<CqlValue>,
+    /// Values of the clustering columns, identifying the row within its partition.
+    pub clustering_key: Vec<CqlValue>,
+    /// Columns to write; a null value deletes the cell.
+    pub cells: Vec<(String, CqlValue)>,
+    /// Whether the row exists even without any cells, as after an INSERT.
+    pub row_marker: bool,
+    /// Whether the whole row is deleted before the cells are written.
+    pub row_deletion: bool,
+    /// Shards of counter columns, merged with the shards the row already has.
+    pub counters: Vec<(String, CounterShard)>,
+    /// Microseconds since the epoch, assigned by the coordinator.
+    pub timestamp: i64,
+}
+
+/// The part of a counter a replica is responsible for: the sum of the increments it led.
+///
+/// Only the replica owning a shard changes it, raising the clock every time, so replicas
+/// merge shards by keeping the one with the highest clock. Applying a shard again, or in a
+/// different order, therefore never counts an increment twice. The value of a counter is the
+/// sum of its shards.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CounterShard {
+    /// Host id of the replica owning the shard.
+    pub replica: String,
+    pub clock: u64,
+    pub count: i64,
+}
+
+/// Increments of the counter columns of a row.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CounterUpdate {
+    pub keyspace: String,
+    pub table: String,
+    pub partition_key: Vec<CqlValue>,
+    pub clustering_key: Vec<CqlValue>,
+    /// Amounts added to each counter column; negative to decrement.
+    pub deltas: Vec<(String, i64)>,
+}
+
+/// Orders the rounds of a lightweight transaction: replicas only take part in the round with
+/// the highest ballot they have seen.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash)]
+pub struct Ballot {
+    /// Microseconds since the epoch, which is also the write time of the proposed mutation.
+    pub timestamp: i64,
+    /// Tells apart ballots of coordinators starting a round at the same time.
+    pub id: Uuid,
+}
+
+/// The first phase of a lightweight transaction: asks a replica of a partition to promise
+/// not to take part in rounds with a lower ballot.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PaxosPrepare {
+    pub keyspace: String,
+    pub table: String,
+    pub partition_key: Vec<CqlValue>,
+    pub ballot: Ballot,
+}
+
+/// A replica's answer to a `PaxosPrepare`.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Promise {
+    /// Whether the replica promised, i.e. hasn't promised a higher ballot before.
+    pub promised: bool,
+    /// The proposal the replica accepted last, which may not have been committed yet.
+    pub accepted: Option<Proposal>,
+    /// The proposal the replica committed last.
+    pub committed: Option<Proposal>,
+}
+
+/// A mutation proposed, or committed, in the round of a ballot.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Proposal {
+    pub ballot: Ballot,
+    pub mutation: Mutation,
+}
+
+/// Asks a replica for the rows of a table, either of one partition or of all partitions it
+/// stores.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ReadCommand {
+    pub keyspace: String,
+    pub table: String,
+    pub partition_key: Option<Vec<CqlValue>>,
+    /// The rows to read from each partition.
+    pub slice: ClusteringSlice,
+    /// Maximum number of rows to return.
+    pub limit: Option<usize>,
+    /// Reads only the rows found in a secondary index instead of whole partitions.
+    pub index: Option<IndexExpression>,
+}
+
+/// Restricts a read to the rows whose indexed column has a value.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexExpression {
+    pub column: String,
+    pub value: CqlValue,
+}
+
+/// The rows of a partition between two clustering key prefixes.
+///
+/// Bounds are in the clustering order of the table, so for a descending clustering column
+/// the start bound holds the greater value.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct ClusteringSlice {
+    /// Where the slice starts; unbounded if `None`.
+    pub start: Option<ClusteringBound>,
+    /// Where the slice ends; unbounded if `None`.
+    pub end: Option<ClusteringBound>,
+    /// Whether the rows are returned in the reverse of the clustering order.
+    pub reversed: bool,
+}
+
+/// A bound of a `ClusteringSlice`: the values of the first clustering columns, and whether
+/// the rows starting with them are part of the slice.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ClusteringBound {
+    pub prefix: Vec<CqlValue>,
+    pub inclusive: bool,
+}
+
+/// How the rows of a partition are ordered by a clustering column.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ClusteringOrder {
+    Asc,
+    Desc,
+}
+
+impl ClusteringOrder {
+    pub fn reverse(self) -> Self {
+        match self {
+            ClusteringOrder::Asc => ClusteringOrder::Desc,
+            ClusteringOrder::Desc => ClusteringOrder::Asc,
+        }
+    }
+}
+
+/// A keyspace: a namespace of tables sharing a replication factor.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct KeyspaceMetadata {
+    pub name: String,
+    /// Number of nodes storing every partition of the keyspace's tables.
+    pub replication_factor: usize,
+}
+
+/// A secondary index on a regular or clustering column of a table, which every node keeps for
+/// the rows it stores.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexMetadata {
+    pub keyspace: String,
+    pub table: String,
+    /// Name of the index, unique within its keyspace.
+    pub name: String,
+    pub column: String,
+}
+
+/// A table: its typed columns and the columns forming its primary key.
+///
+/// The partition key decides which nodes store a row; the clustering columns tell apart the
+/// rows of a partition.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TableMetadata {
+    pub keyspace: String,
+    pub name: String,
+    /// All columns, in declaration order.
+    pub columns: Vec<ColumnSpec>,
+    /// Names of the partition key columns, in key order.
+    pub partition_key: Vec<String>,
+    /// Names of the clustering columns, in key order.
+    pub clustering_key: Vec<String>,
+    /// How rows are ordered by each clustering column.
+    pub clustering_order: Vec<ClusteringOrder>,
+}
+
+impl TableMetadata {
+    pub fn column(&self, name: &str) -> Option<&ColumnSpec> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    pub fn is_partition_key(&self, name: &str) -> bool {
+        self.partition_key.iter().any(|column| column == name)
+    }
+
+    pub fn is_clustering_key(&self, name: &str) -> bool {
+        self.clustering_key.iter().any(|column| column == name)
+    }
+
+    pub fn is_primary_key(&self, name: &str) -> bool {
+        self.is_partition_key(name) || self.is_clustering_key(name)
+    }
+
+    /// Returns whether the table's regular columns are counters, which only UPDATE can change.
+    pub fn is_counter_table(&self) -> bool {
+        self.columns
+            .iter()
+            .any(|column| column.data_type == DataType::Counter)
+    }
+}
+
+/// The keyspaces, tables and indexes of a node, as exchanged between nodes to agree on a
+/// schema.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct SchemaDefinition {
+    pub keyspaces: Vec<KeyspaceMetadata>,
+    pub tables: Vec<TableMetadata>,
+    pub indexes: Vec<IndexMetadata>,
+}
+
+/// The result of a SELECT: the selected columns and a row of values for each of them.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct Rows {
+    pub columns: Vec<ColumnSpec>,
+    pub rows: Vec<Vec<CqlValue>>,
+}
+
+/// A custom payload: opaque values keyed by name, such as a tenant id or request tags, that a
+/// request hands to the server's query handler and a response hands back to the client.
+pub type CustomPayload = HashMap<String, Vec<u8>>;
+
+/// Key of the custom payload entry carrying the tracing session of a traced request that a
+/// node forwards to another node, so that both record their events under the same session.
+pub const TRACE_SESSION_PAYLOAD_KEY: &str = "trace_session";
+
+/// Something a node did while handling a traced request.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TraceEvent {
+    pub session_id: Uuid,
+    pub activity: String,
+    /// Address of the node that recorded the event.
+    pub source: String,
+    /// Microseconds since the session started on the source node.
+    pub source_elapsed: u64,
+    pub timestamp: SystemTime,
+}
+
+/// Types of columns and bind markers.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum DataType {
+    Text,
+    Int,
+    BigInt,
+    Uuid,
+    Timestamp,
+    Blob,
+    Boolean,
+    Double,
+    /// A 64-bit integer changed only by increments, which replicas merge without losing any.
+    Counter,
+    List(Box<DataType>),
+    Set(Box<DataType>),
+    Map(Box<DataType>, Box<DataType>),
+}
+
+impl DataType {
+    pub fn is_collection(&self) -> bool {
+        matches!(
+            self,
+            DataType::List(_) | DataType::Set(_) | DataType::Map(_, _)
+        )
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Text => write!(f, "text"),
+            DataType::Int => write!(f, "int"),
+            DataType::BigInt => write!(f, "bigint"),
+            DataType::Uuid => write!(f, "uuid"),
+            DataType::Timestamp => write!(f, "timestamp"),
+            DataType::Blob => write!(f, "blob"),
+            DataType::Boolean => write!(f, "boolean"),
+            DataType::Double => write!(f, "double"),
+            DataType::Counter => write!(f, "counter"),
+            DataType::List(element) => write!(f, "list<{}>", element),
+            DataType::Set(element) => write!(f, "set<{}>", element),
+            DataType::Map(key, value) => write!(f, "map<{}, {}>", key, value),
+        }
+    }
+}
+
+/// A typed value bound to a statement or returned in a result.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum CqlValue {
+    Null,
+    Text(String),
+    Int(i32),
+    BigInt(i64),
+    Uuid(Uuid),
+    /// Milliseconds since the epoch.
+    Timestamp(i64),
+    Blob(Vec<u8>),
+    Boolean(bool),
+    Double(f64),
+    List(Vec<CqlValue>),
+    /// Distinct elements in ascending order.
+    Set(Vec<CqlValue>),
+    /// Entries with distinct keys in ascending key order.
+    Map(Vec<(CqlValue, CqlValue)>),
+}
+
+impl CqlValue {
+    /// Returns whether the value can be bound to a marker of the given type.
+    pub fn is_of_type(&self, data_type: &DataType) -> bool {
+        match (self, data_type) {
+            (CqlValue::Null, _)
+            | (CqlValue::Text(_), DataType::Text)
+            | (CqlValue::Int(_), DataType::Int)
+            | (CqlValue::BigInt(_), DataType::BigInt | DataType::Counter)
+            | (CqlValue::Uuid(_), DataType::Uuid)
+            | (CqlValue::Timestamp(_), DataType::Timestamp)
+            | (CqlValue::Blob(_), DataType::Blob)
+            | (CqlValue::Boolean(_), DataType::Boolean)
+            | (CqlValue::Double(_), DataType::Double) => true,
+            (CqlValue::List(elements), DataType::List(element_type))
+            | (CqlValue::Set(elements), DataType::Set(element_type)) => elements
+                .iter()
+                .all(|element| *element != CqlValue::Null && element.is_of_type(element_type)),
+            (CqlValue::Map(entries), DataType::Map(key_type, value_type)) => {
+                entries.iter().all(|(key, value)| {
+                    *key != CqlValue::Null
+                        && *value != CqlValue::Null
+                        && key.is_of_type(key_type)
+                        && value.is_of_type(value_type)
+                })
+            }
+            _ => false,
+        }
+    }
+
+    /// Serializes the value the way CQL does: numbers in big-endian, text as UTF-8, a uuid as
+    /// its 16 bytes, and a collection as its element count followed by every element, each
+    /// prefixed with its length. Null serializes to no bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            CqlValue::Null => Vec::new(),
+            CqlValue::Text(value) => value.as_bytes().to_vec(),
+            CqlValue::Int(value) => value.to_be_bytes().to_vec(),
+            CqlValue::BigInt(value) | CqlValue::Timestamp(value) => value.to_be_bytes().to_vec(),
+            CqlValue::Uuid(value) => value.as_bytes().to_vec(),
+            CqlValue::Blob(bytes) => bytes.clone(),
+            CqlValue::Boolean(value) => vec![u8::from(*value)],
+            CqlValue::Double(value) => value.to_be_bytes().to_vec(),
+            CqlValue::List(elements) | CqlValue::Set(elements) => {
+                let mut bytes = (elements.len() as i32).to_be_bytes().to_vec();
+                elements
+                    .iter()
+                    .for_each(|element| serialize_element(&mut bytes, element));
+                bytes
+            }
+            CqlValue::Map(entries) => {
+                let mut bytes = (entries.len() as i32).to_be_bytes().to_vec();
+                for (key, value) in entries {
+                    serialize_element(&mut bytes, key);
+                    serialize_element(&mut bytes, value);
+                }
+                bytes
+            }
+        }
+    }
+}
+
+/// Appends an element of a collection to its serialization, prefixed with its length.
+fn serialize_element(bytes: &mut Vec<u8>, element: &CqlValue) {
+    let element = element.serialize();
+    bytes.extend_from_slice(&(element.len() as i32).to_be_bytes());
+    bytes.extend_from_slice(&element);
+}
+
+impl fmt::Display for CqlValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CqlValue::Null => write!(f, "null"),
+            CqlValue::Text(value) => write!(f, "{}", value),
+            CqlValue::Int(value) => write!(f, "{}", value),
+            CqlValue::BigInt(value) => write!(f, "{}", value),
+            CqlValue::Uuid(value) => write!(f, "{}", value),
+            CqlValue::Timestamp(millis) => write_timestamp(f, *millis),
+            CqlValue::Blob(bytes) => {
+                write!(f, "0x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+            }
+            CqlValue::Boolean(value) => write!(f, "{}", value),
+            CqlValue::Double(value) => write!(f, "{}", value),
+            CqlValue::List(elements) => write_elements(f, "[", elements, "]"),
+            CqlValue::Set(elements) => write_elements(f, "{", elements, "}"),
+            CqlValue::Map(entries) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write_element(f, key)?;
+                    write!(f, ": ")?;
+                    write_element(f, value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl PartialOrd for CqlValue {
+    /// Compares two values of the same type; values of different types, and null, are not
+    /// comparable.
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (CqlValue::Text(left), CqlValue::Text(right)) => Some(left.cmp(right)),
+            (CqlValue::Int(left), CqlValue::Int(right)) => Some(left.cmp(right)),
+            (CqlValue::BigInt(left), CqlValue::BigInt(right)) => Some(left.cmp(right)),
+            (CqlValue::Uuid(left), CqlValue::Uuid(right)) => Some(left.cmp(right)),
+            (CqlValue::Timestamp(left), CqlValue::Timestamp(right)) => Some(left.cmp(right)),
+            (CqlValue::Blob(left), CqlValue::Blob(right)) => Some(left.cmp(right)),
+            (CqlValue::Boolean(left), CqlValue::Boolean(right)) => Some(left.cmp(right)),
+            (CqlValue::Double(left), CqlValue::Double(right)) => left.partial_cmp(right),
+            (CqlValue::List(left), CqlValue::List(right))
+            | (CqlValue::Set(left), CqlValue::Set(right)) => {
+                compare_elements(left.iter(), right.iter(), left.len().cmp(&right.len()))
+            }
+            (CqlValue::Map(left), CqlValue::Map(right)) => compare_elements(
+                left.iter().flat_map(|(key, value)| [key, value]),
+                right.iter().flat_map(|(key, value)| [key, value]),
+                left.len().cmp(&right.len()),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Compares collections element by element, and by size if one starts with the other.
+fn compare_elements<'v>(
+    left: impl Iterator<Item = &'v CqlValue>,
+    right: impl Iterator<Item = &'v CqlValue>,
+    by_size: Ordering,
+) -> Option<Ordering> {
+    for (left, right) in left.zip(right) {
+        match left.partial_cmp(right)? {
+            Ordering::Equal => continue,
+            ordering => return Some(ordering),
+        }
+    }
+    Some(by_size)
+}
+
+fn write_elements(
+    f: &mut fmt::Formatter<'_>,
+    open: &str,
+    elements: &[CqlValue],
+    close: &str,
+) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (index, element) in elements.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write_element(f, element)?;
+    }
+    write!(f, "{}", close)
+}
+
+/// Writes an element of a collection, quoting text as in a CQL literal.
+fn write_element(f: &mut fmt::Formatter<'_>, element: &CqlValue) -> fmt::Result {
+    match element {
+        CqlValue::Text(value) => write!(f, "'{}'", value.replace('\'', "''")),
+        element => write!(f, "{}", element),
+    }
+}
+
+/// Writes a timestamp as a UTC date and time, e.g. `2024-03-01 12:30:00.000Z`.
+fn write_timestamp(f: &mut fmt::Formatter<'_>, millis: i64) -> fmt::Result {
+    let days = millis.div_euclid(MILLIS_PER_DAY);
+    let millis_of_day = millis.rem_euclid(MILLIS_PER_DAY);
+
+    // civil date of a day count, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    write!(
+        f,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        millis_of_day / 3_600_000,
+        millis_of_day / 60_000 % 60,
+        millis_of_day / 1000 % 60,
+        millis_of_day % 1000
+    )
+}
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Describes a bind marker or a result column.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ColumnSpec {
+    pub name: String,
+    pub data_type: DataType,
+}
+
+impl ColumnSpec {
+    pub fn new(name: impl Into<String>, data_type: DataType) -> Self {
+        Self {
+            name: name.into(),
+            data_type,
+        }
+    }
+}
+
+/// Result of a PREPARE message.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PreparedMetadata {
+    pub id: StatementId,
+    /// One entry per bind marker, in marker order.
+    pub bind_metadata: Vec<ColumnSpec>,
+    /// Indexes of the bind markers forming the partition key, used for routing.
+    pub pk_indexes: Vec<u16>,
+    /// Columns returned when the statement is executed.
+    pub result_metadata: Vec<ColumnSpec>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -102,9 +995,327 @@ pub struct Entry {
     pub replication_factor: Option<usize>,
 }
 
+/// Body of a CREATE USER statement.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct CreateUser {
+    pub name: String,
+    pub password: String,
+    pub superuser: bool,
+}
+
+impl fmt::Debug for CreateUser {
+    /// Keeps the password out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateUser")
+            .field("name", &self.name)
+            .field("password", &"***")
+            .field("superuser", &self.superuser)
+            .finish()
+    }
+}
+
+/// Body of an ALTER USER statement; what is `None` is left unchanged.
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct AlterUser {
+    pub name: String,
+    pub password: Option<String>,
+    pub superuser: Option<bool>,
+}
+
+impl fmt::Debug for AlterUser {
+    /// Keeps the password out of logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlterUser")
+            .field("name", &self.name)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .field("superuser", &self.superuser)
+            .finish()
+    }
+}
+
+/// Body of a DROP USER statement.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DropUser {
+    pub name: String,
+    /// Whether dropping a user that doesn't exist succeeds.
+    pub if_exists: bool,
+}
+
+/// An operation a role can be allowed to perform on a resource.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Permission {
+    /// Creating keyspaces and tables.
+    Create,
+    /// Reading data, e.g. CHECK and COUNT.
+    Select,
+    /// Writing data, e.g. ADD and BATCH.
+    Modify,
+    /// Granting and revoking permissions on the resource.
+    Authorize,
+}
+
+impl Permission {
+    /// The permissions granted by `GRANT ALL PERMISSIONS`.
+    pub const ALL: [Permission; 4] = [
+        Permission::Create,
+        Permission::Select,
+        Permission::Modify,
+        Permission::Authorize,
+    ];
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Permission::Create => write!(f, "CREATE"),
+            Permission::Select => write!(f, "SELECT"),
+            Permission::Modify => write!(f, "MODIFY"),
+            Permission::Authorize => write!(f, "AUTHORIZE"),
+        }
+    }
+}
+
+/// Something permissions are granted on. Permissions on a resource apply to its children too.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
+pub enum Resource {
+    AllKeyspaces,
+    Keyspace(String),
+}
+
+impl Resource {
+    /// Returns the resource whose permissions also apply to this one.
+    pub fn parent(&self) -> Option<Resource> {
+        match self {
+            Resource::AllKeyspaces => None,
+            Resource::Keyspace(_) => Some(Resource::AllKeyspaces),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::AllKeyspaces => write!(f, "<all keyspaces>"),
+            Resource::Keyspace(keyspace) => write!(f, "<keyspace {}>", keyspace),
+        }
+    }
+}
+
+/// Body of a GRANT or REVOKE statement.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PermissionChange {
+    pub permissions: Vec<Permission>,
+    pub resource: Resource,
+    pub role: String,
+}
+
+/// Kinds of events a connection can register for.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum EventType {
+    TopologyChange,
+    StatusChange,
+    SchemaChange,
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventType::TopologyChange => write!(f, "TOPOLOGY_CHANGE"),
+            EventType::StatusChange => write!(f, "STATUS_CHANGE"),
+            EventType::SchemaChange => write!(f, "SCHEMA_CHANGE"),
+        }
+    }
+}
+
+/// An event pushed by a node to the connections registered for its type.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum Event {
+    TopologyChange(TopologyChange),
+    StatusChange(StatusChange),
+    SchemaChange(SchemaChange),
+}
+
+impl Event {
+    pub fn event_type(&self) -> EventType {
+        match self {
+            Event::TopologyChange(_) => EventType::TopologyChange,
+            Event::StatusChange(_) => EventType::StatusChange,
+            Event::SchemaChange(_) => EventType::SchemaChange,
+        }
+    }
+}
+
+/// The tokens a node owns on the ring.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct NodeTokens {
+    pub address: String,
+    /// Host id of the node, unless it isn't known yet.
+    pub host_id: Option<Uuid>,
+    pub tokens: Vec<u64>,
+}
+
+/// A node joined, left or moved to another address.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct TopologyChange {
+    pub change: TopologyChangeType,
+    /// Address of the node, the new one for a node that moved.
+    pub address: String,
+    /// Host id of the node, which identifies a node that moved.
+    pub host_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum TopologyChangeType {
+    NewNode,
+    RemovedNode,
+    /// The node with the host id of the change moved to its address, keeping its tokens.
+    MovedNode,
+}
+
+/// A node of the cluster became reachable or unreachable.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StatusChange {
+    pub change: StatusChangeType,
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum StatusChangeType {
+    Up,
+    Down,
+}
+
+/// A keyspace or table was created, altered or dropped.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SchemaChange {
+    pub change: SchemaChangeType,
+    pub keyspace: String,
+    /// The changed table, or `None` if the keyspace itself changed.
+    pub table: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SchemaChangeType {
+    Created,
+    Updated,
+    Dropped,
+}
+
+impl Request {
+    /// Returns the opcode of the frame carrying this request.
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Request::Startup(_) => Opcode::Startup,
+            Request::Options => Opcode::Options,
+            Request::Query(_) => Opcode::Query,
+            Request::Prepare(_) => Opcode::Prepare,
+            Request::Execute(_) => Opcode::Execute,
+            Request::Batch(_) => Opcode::Batch,
+            Request::Register(_) => Opcode::Register,
+            Request::AuthResponse(_) => Opcode::AuthResponse,
+            _ => Opcode::Query,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Response {
-    String(String),
+    /// Values read by a GET_BATCH, merged from every node.
     Array(Vec<String>),
+    /// The outcome of a CHECK, or of an internode request that succeeds or fails.
     Bool(bool),
+    /// The connection is ready to accept queries.
+    Ready,
+    /// Startup options supported by the server, keyed by option name.
+    Supported(HashMap<String, Vec<String>>),
+    Prepared(PreparedMetadata),
+    /// Authentication is required; carries the name of the server's authenticator.
+    Authenticate(String),
+    /// A SASL challenge the client has to answer with another AUTH_RESPONSE.
+    AuthChallenge(Vec<u8>),
+    /// Authentication succeeded, with an optional final SASL token.
+    AuthSuccess(Option<Vec<u8>>),
+    /// An event pushed to a registered connection, on stream `EVENT_STREAM`.
+    Event(Event),
+    /// The events a node recorded for a tracing session, in the order they happened.
+    Trace(Vec<TraceEvent>),
+    /// A statement without a result was executed.
+    Void,
+    /// Rows read by a SELECT.
+    Rows(Rows),
+    /// The keyspace of the connection was changed with USE.
+    SetKeyspace(String),
+    /// A statement created, altered or dropped a keyspace or table.
+    SchemaChange(SchemaChange),
+    /// The digest of a node's schema, which nodes with the same schema agree on.
+    SchemaVersion(Uuid),
+    /// The keyspaces and tables of a node.
+    Schema(SchemaDefinition),
+    /// The tokens of the nodes a node knows.
+    Tokens(Vec<NodeTokens>),
+    /// The number of bytes of data a node stores, values and rows together.
+    Load(u64),
+    /// A page of values read by a GET_BATCH.
+    Page(Page),
+    /// The number of values counted by a COUNT, or removed by a DROP_BATCH.
+    Count(ValueCount),
+    /// A replica's answer to a `Request::PaxosPrepare`.
+    Promise(Box<Promise>),
+    /// Rows as the mutations writing them.
+    Mutations(Vec<Mutation>),
+    Error(ErrorResponse),
+}
+
+impl Response {
+    /// Returns the opcode of the frame carrying this response.
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Response::Ready => Opcode::Ready,
+            Response::Supported(_) => Opcode::Supported,
+            Response::Error(_) => Opcode::Error,
+            Response::Event(_) => Opcode::Event,
+            Response::Authenticate(_) => Opcode::Authenticate,
+            Response::AuthChallenge(_) => Opcode::AuthChallenge,
+            Response::AuthSuccess(_) => Opcode::AuthSuccess,
+            _ => Opcode::Result,
+        }
+    }
+}
+
+/// Error codes defined by the native protocol.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[repr(u32)]
+pub enum ErrorCode {
+    ServerError = 0x0000,
+    ProtocolError = 0x000A,
+    /// Authentication failed, e.g. because of a wrong username or password.
+    BadCredentials = 0x0100,
+    /// Not enough replicas are alive to perform the operation.
+    Unavailable = 0x1000,
+    /// A write wasn't acknowledged by all the nodes it was sent to.
+    WriteTimeout = 0x1100,
+    SyntaxError = 0x2000,
+    /// The logged in user isn't allowed to perform the request.
+    Unauthorized = 0x2100,
+    Invalid = 0x2200,
+    /// The keyspace or table to create already exists.
+    AlreadyExists = 0x2400,
+    /// The executed statement id is not in the server's prepared cache.
+    Unprepared = 0x2500,
+}
+
+/// Body of an ERROR message.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorResponse {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use crate::cluster::Node;
 use crate::consistent_hash_ring::ConsistentHashRing;
-use std::collections::HashSet;
+use uuid::Uuid;
 
 pub struct ReplicationStrategy<'a> {
     ring: &'a ConsistentHashRing,
@@ -13,8 +13,9 @@ impl<'a> ReplicationStrategy<'a> {
 
     /// Returns the node addresses where data should be replicated.
     ///
-    /// Finds the next N nodes on the ring (clockwise) after the given hash position,
-    /// excluding the original node.
+    /// Finds the next N distinct nodes on the ring (clockwise) after the given hash position,
+    /// excluding the original node. Nodes are told apart by host id, so a node that moved is
+    /// still a single replica.
     ///
     /// # Arguments
     /// * `value_hash` - The hash of the value to replicate
@@ -26,14 +27,14 @@ impl<'a> ReplicationStrategy<'a> {
         exclude_node: &Node,
         replication_factor: usize,
     ) -> Vec<&str> {
-        let mut nodes = self.ring.get_entities();
-        nodes.sort_by(|(hash1, _), (hash2, _)| hash1.cmp(hash2));
+        let mut nodes = self.ring.get_host_entities();
+        nodes.sort_by_key(|(hash1, _)| *hash1);
 
         // Filter out the excluded node
-        let filtered_nodes: Vec<(u64, &str)> = nodes
-            .iter()
-            .filter(|(_, node_address)| *node_address != exclude_node.address)
-            .map(|(hash, node_address)| (*hash, *node_address))
+        let excluded = self.ring.host_id(&exclude_node.address);
+        let filtered_nodes: Vec<(u64, Uuid)> = nodes
+            .into_iter()
+            .filter(|(_, host_id)| Some(*host_id) != excluded)
             .collect();
 
         // Find position on the ring after the value hash
@@ -42,15 +43,100 @@ impl<'a> ReplicationStrategy<'a> {
             .position(|(hash, _)| *hash > value_hash)
             .unwrap_or(0);
 
-        // Select next N nodes (wrapping around if necessary)
-        let target_nodes = filtered_nodes
+        // Select next N distinct nodes (wrapping around if necessary), skipping further
+        // virtual nodes of already selected nodes
+        let mut target_nodes: Vec<Uuid> = Vec::new();
+        for (_, host_id) in filtered_nodes
             .iter()
             .cycle()
             .skip(position)
-            .take(replication_factor)
-            .map(|(_, node_address)| *node_address)
-            .collect::<HashSet<&str>>();
+            .take(filtered_nodes.len())
+        {
+            if target_nodes.len() == replication_factor {
+                break;
+            }
+            if !target_nodes.contains(host_id) {
+                target_nodes.push(*host_id);
+            }
+        }
+
+        target_nodes
+            .iter()
+            .filter_map(|host_id| self.ring.address(host_id))
+            .collect()
+    }
+
+    /// Returns the node addresses where a key should be replicated, placing the key on the
+    /// ring with the ring's partitioner.
+    pub fn get_key_replica_nodes(
+        &self,
+        key: &str,
+        exclude_node: &Node,
+        replication_factor: usize,
+    ) -> Vec<&str> {
+        self.get_replica_nodes(self.ring.token(key), exclude_node, replication_factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cluster::Node;
+    use crate::consistent_hash_ring::ConsistentHashRing;
+    use crate::partitioner::Murmur3Partitioner;
+    use crate::replication::ReplicationStrategy;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_replica_nodes_are_distinct() {
+        let nodes = vec![
+            Node::new("localhost:3000".to_string()),
+            Node::new("localhost:4000".to_string()),
+            Node::new("localhost:5001".to_string()),
+        ];
+        let ring = ConsistentHashRing::new(nodes.clone(), Arc::new(Murmur3Partitioner));
+        let strategy = ReplicationStrategy::new(&ring);
+
+        let replicas = strategy.get_replica_nodes(42, &nodes[0], 2);
+
+        assert_eq!(replicas.len(), 2);
+        assert!(replicas.contains(&"localhost:4000"));
+        assert!(replicas.contains(&"localhost:5001"));
+    }
+
+    #[test]
+    fn test_replica_nodes_are_limited_by_cluster_size() {
+        let nodes = vec![
+            Node::new("localhost:3000".to_string()),
+            Node::new("localhost:4000".to_string()),
+        ];
+        let ring = ConsistentHashRing::new(nodes.clone(), Arc::new(Murmur3Partitioner));
+        let strategy = ReplicationStrategy::new(&ring);
+
+        let replicas = strategy.get_replica_nodes(42, &nodes[0], 3);
+
+        assert_eq!(replicas, vec!["localhost:4000"]);
+    }
+
+    #[test]
+    fn test_moved_node_stays_a_replica() {
+        let nodes = vec![
+            Node::new("localhost:3000".to_string()),
+            Node::new("localhost:4000".to_string()),
+            Node::new("localhost:5001".to_string()),
+        ];
+        let mut ring = ConsistentHashRing::new(nodes.clone(), Arc::new(Murmur3Partitioner));
+        let host_id = Uuid::new_v4();
+        ring.set_host_id("localhost:4000", host_id);
+        let replicas: Vec<String> = ReplicationStrategy::new(&ring)
+            .get_replica_nodes(42, &nodes[0], 2)
+            .iter()
+            .map(|address| address.replace("localhost:4000", "localhost:4001"))
+            .collect();
+
+        ring.move_node(host_id, "localhost:4001");
 
-        target_nodes.into_iter().collect()
+        let strategy = ReplicationStrategy::new(&ring);
+        assert_eq!(strategy.get_replica_nodes(42, &nodes[0], 2), replicas);
     }
 }
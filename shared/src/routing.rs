@@ -1,6 +1,6 @@
 use crate::cluster::Node;
 use crate::consistent_hash_ring::ConsistentHashRing;
-use crate::protocol::types::Request;
+use crate::protocol::types::{BatchStatement, CqlValue, Execute, PreparedMetadata, Query, Request};
 
 #[derive(Debug)]
 pub enum RoutingStrategy<'a> {
@@ -20,16 +20,74 @@ impl<'a> Router<'a> {
     pub fn route_request(&self, request: &Request) -> RoutingStrategy<'a> {
         match request {
             Request::Add(val) => {
-                let hash = ConsistentHashRing::calculate_hash(val.value.as_str());
-                let node = self.ring.get_node(hash);
-                RoutingStrategy::Direct(node)
+                let hash = self.ring.token(val.value.as_str());
+                self.to_owner(hash)
             }
             Request::Check(val) => {
-                let hash = ConsistentHashRing::calculate_hash(val.as_str());
-                let node = self.ring.get_node(hash);
-                RoutingStrategy::Direct(node)
+                let hash = self.ring.token(val.as_str());
+                self.to_owner(hash)
+            }
+            Request::Prepare(query) | Request::Query(Query { query, .. }) => {
+                // any node can prepare or coordinate a statement, hashing the query just
+                // spreads the load
+                let hash = self.ring.token(query.as_str());
+                self.to_owner(hash)
+            }
+            Request::Batch(batch) => {
+                // any node can coordinate a batch, it routes the mutations itself; hashing the
+                // values of the first statement spreads batches over the cluster, and sends a
+                // batch of ADDs to the node owning its first value
+                let hash = match batch.statements.first() {
+                    Some(BatchStatement::Query(query, values)) if values.is_empty() => {
+                        self.ring.token(query.as_str())
+                    }
+                    Some(
+                        BatchStatement::Query(_, values) | BatchStatement::Prepared(_, values),
+                    ) => self.ring.partitioner().partition_key_token(values),
+                    None => 0,
+                };
+                self.to_owner(hash)
             }
             _ => RoutingStrategy::Fanout(self.ring.get_nodes()),
         }
     }
+
+    /// Returns the node owning the given position on the ring, or `None` if the ring is empty.
+    pub fn owner(&self, hash: u64) -> Option<&'a Node> {
+        self.ring.get_node(hash)
+    }
+
+    /// Routes a request to the node owning the given position on the ring; a ring without
+    /// nodes routes it to none.
+    fn to_owner(&self, hash: u64) -> RoutingStrategy<'a> {
+        match self.ring.get_node(hash) {
+            Some(node) => RoutingStrategy::Direct(node),
+            None => RoutingStrategy::Fanout(&[]),
+        }
+    }
+
+    /// Routes an EXECUTE to the node owning its partition key.
+    ///
+    /// The partition key is made of the bound values at the statement's `pk_indexes`.
+    /// Statements without a partition key can be coordinated by any node, hashing the
+    /// statement id sends every execution of a statement to the node that prepared it.
+    pub fn route_execute(
+        &self,
+        execute: &Execute,
+        metadata: &PreparedMetadata,
+    ) -> RoutingStrategy<'a> {
+        if metadata.pk_indexes.is_empty() {
+            let hash = self.ring.partitioner().token(&execute.id);
+            return self.to_owner(hash);
+        }
+
+        let key: Vec<CqlValue> = metadata
+            .pk_indexes
+            .iter()
+            .filter_map(|index| execute.values.get(*index as usize))
+            .cloned()
+            .collect();
+
+        self.to_owner(self.ring.partitioner().partition_key_token(&key))
+    }
 }
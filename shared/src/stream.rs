@@ -0,0 +1,67 @@
+//! The byte stream a `Connection` runs over: plain TCP or TLS.
+
+use crate::tls::{TlsConnector, TlsStream};
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(TlsStream),
+}
+
+impl Stream {
+    /// Connects to a node, with TLS if a connector is given.
+    pub fn connect(address: &str, tls: Option<&TlsConnector>) -> io::Result<Stream> {
+        let socket = TcpStream::connect(address)?;
+        match tls {
+            Some(connector) => Ok(Stream::Tls(connector.connect(socket, address)?)),
+            None => Ok(Stream::Plain(socket)),
+        }
+    }
+
+    /// Returns another handle to the same stream, e.g. to write from another thread.
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Plain(socket) => Ok(Stream::Plain(socket.try_clone()?)),
+            Stream::Tls(stream) => Ok(Stream::Tls(stream.clone())),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(socket: TcpStream) -> Self {
+        Stream::Plain(socket)
+    }
+}
+
+impl From<TlsStream> for Stream {
+    fn from(stream: TlsStream) -> Self {
+        Stream::Tls(stream)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
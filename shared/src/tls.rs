@@ -0,0 +1,328 @@
+//! TLS for client-to-node and node-to-node connections.
+//!
+//! TLS is optional and configured with PEM files: a node presents its certificate chain to
+//! clients and other nodes, and verifies their certificates against a CA bundle. Requiring
+//! client certificates turns on mutual TLS.
+//!
+//! `TlsAcceptor` and `TlsConnector` reload their PEM files when they change, so renewed
+//! certificates are picked up without a restart. Connections established before a reload keep
+//! their session.
+
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// Size of the chunks of TLS records read from the socket at once.
+///
+/// Records are only read once the decrypted data was consumed, so keeping the chunks small
+/// keeps rustls' buffer of decrypted data from overflowing.
+const RECORD_CHUNK_SIZE: usize = 4096;
+
+/// Paths of the PEM files making up a TLS configuration.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// Certificate chain presented to peers.
+    pub cert: Option<PathBuf>,
+    /// Private key of the certificate.
+    pub key: Option<PathBuf>,
+    /// CA certificates the certificates of peers are verified against.
+    pub ca: Option<PathBuf>,
+    /// Whether a node rejects clients that don't present a certificate signed by `ca`.
+    pub require_client_auth: bool,
+}
+
+impl TlsSettings {
+    fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.cert, &self.key, &self.ca].into_iter().flatten()
+    }
+
+    /// Returns the latest modification time of the PEM files.
+    fn modified(&self) -> io::Result<Option<SystemTime>> {
+        let mut modified = None;
+        for file in self.files() {
+            modified = modified.max(Some(std::fs::metadata(file)?.modified()?));
+        }
+        Ok(modified)
+    }
+
+    fn certificate_chain(
+        &self,
+    ) -> io::Result<Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>> {
+        let (Some(cert), Some(key)) = (&self.cert, &self.key) else {
+            return Ok(None);
+        };
+
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| invalid_data(format!("Cannot read {}: {}", cert.display(), e)))?;
+        let key = PrivateKeyDer::from_pem_file(key)
+            .map_err(|e| invalid_data(format!("Cannot read {}: {}", key.display(), e)))?;
+
+        Ok(Some((certs, key)))
+    }
+
+    fn root_certificates(&self) -> io::Result<Option<Arc<RootCertStore>>> {
+        let Some(ca) = &self.ca else {
+            return Ok(None);
+        };
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(ca)
+            .map_err(|e| invalid_data(format!("Cannot read {}: {}", ca.display(), e)))?
+        {
+            let cert =
+                cert.map_err(|e| invalid_data(format!("Cannot read {}: {}", ca.display(), e)))?;
+            roots.add(cert).map_err(invalid_data)?;
+        }
+
+        Ok(Some(Arc::new(roots)))
+    }
+}
+
+/// A configuration built from `TlsSettings`, rebuilt when the PEM files change.
+struct ReloadableConfig<T> {
+    settings: TlsSettings,
+    build: fn(&TlsSettings) -> io::Result<T>,
+    config: RwLock<Arc<T>>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl<T> ReloadableConfig<T> {
+    fn new(settings: TlsSettings, build: fn(&TlsSettings) -> io::Result<T>) -> io::Result<Self> {
+        let modified = settings.modified()?;
+        let config = build(&settings)?;
+
+        Ok(Self {
+            settings,
+            build,
+            config: RwLock::new(Arc::new(config)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    fn current(&self) -> Arc<T> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Rebuilds the configuration if a PEM file changed since the last build.
+    ///
+    /// If the new files are invalid, the previous configuration stays in use.
+    fn reload_if_changed(&self) -> io::Result<bool> {
+        let modified = self.settings.modified()?;
+        let mut last_modified = self.modified.lock().unwrap();
+        if modified == *last_modified {
+            return Ok(false);
+        }
+
+        let config = (self.build)(&self.settings)?;
+        *self.config.write().unwrap() = Arc::new(config);
+        *last_modified = modified;
+        Ok(true)
+    }
+}
+
+/// Accepts TLS connections on a node.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ReloadableConfig<ServerConfig>>,
+}
+
+impl TlsAcceptor {
+    /// Creates an acceptor presenting the certificate of the settings.
+    ///
+    /// If the settings have a CA, clients may present a certificate signed by it; with
+    /// `require_client_auth` they have to.
+    pub fn new(settings: TlsSettings) -> io::Result<Self> {
+        Ok(Self {
+            config: Arc::new(ReloadableConfig::new(settings, Self::build)?),
+        })
+    }
+
+    /// Performs the server side of the TLS handshake.
+    pub fn accept(&self, socket: TcpStream) -> io::Result<TlsStream> {
+        let session = ServerConnection::new(self.config.current()).map_err(invalid_data)?;
+        TlsStream::new(session.into(), socket)
+    }
+
+    /// Reloads the PEM files if they changed, returning whether they did.
+    pub fn reload_if_changed(&self) -> io::Result<bool> {
+        self.config.reload_if_changed()
+    }
+
+    fn build(settings: &TlsSettings) -> io::Result<ServerConfig> {
+        let provider = provider();
+        let (certs, key) = settings.certificate_chain()?.ok_or_else(|| {
+            invalid_data("A TLS certificate and key are required to accept connections")
+        })?;
+
+        let client_verifier = match settings.root_certificates()? {
+            Some(roots) => {
+                let builder = WebPkiClientVerifier::builder_with_provider(roots, provider.clone());
+                let builder = match settings.require_client_auth {
+                    true => builder,
+                    false => builder.allow_unauthenticated(),
+                };
+                builder.build().map_err(invalid_data)?
+            }
+            None if settings.require_client_auth => {
+                return Err(invalid_data(
+                    "A CA is required to verify client certificates",
+                ));
+            }
+            None => WebPkiClientVerifier::no_client_auth(),
+        };
+
+        ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_data)?
+            .with_client_cert_verifier(client_verifier)
+            .with_single_cert(certs, key)
+            .map_err(invalid_data)
+    }
+}
+
+/// Opens TLS connections to nodes.
+#[derive(Clone)]
+pub struct TlsConnector {
+    config: Arc<ReloadableConfig<ClientConfig>>,
+}
+
+impl TlsConnector {
+    /// Creates a connector verifying nodes against the CA of the settings.
+    ///
+    /// If the settings have a certificate, it is presented to nodes requiring mutual TLS.
+    pub fn new(settings: TlsSettings) -> io::Result<Self> {
+        Ok(Self {
+            config: Arc::new(ReloadableConfig::new(settings, Self::build)?),
+        })
+    }
+
+    /// Performs the client side of the TLS handshake with the node at `address`.
+    ///
+    /// The host part of the address is the name the node's certificate is verified for.
+    pub fn connect(&self, socket: TcpStream, address: &str) -> io::Result<TlsStream> {
+        let host = address
+            .rsplit_once(':')
+            .map_or(address, |(host, _port)| host);
+        let server_name = ServerName::try_from(host.to_string()).map_err(invalid_data)?;
+
+        let session =
+            ClientConnection::new(self.config.current(), server_name).map_err(invalid_data)?;
+        TlsStream::new(session.into(), socket)
+    }
+
+    /// Reloads the PEM files if they changed, returning whether they did.
+    pub fn reload_if_changed(&self) -> io::Result<bool> {
+        self.config.reload_if_changed()
+    }
+
+    fn build(settings: &TlsSettings) -> io::Result<ClientConfig> {
+        let roots = settings
+            .root_certificates()?
+            .ok_or_else(|| invalid_data("A CA is required to verify the certificates of nodes"))?;
+
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid_data)?
+            .with_root_certificates(roots);
+
+        match settings.certificate_chain()? {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs, key)
+                .map_err(invalid_data),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+/// An established TLS connection.
+///
+/// Clones share the TLS session, so one clone can write while another one is blocked reading:
+/// the session is only locked while records are processed, not while waiting for them.
+#[derive(Clone)]
+pub struct TlsStream {
+    session: Arc<Mutex<rustls::Connection>>,
+    socket: Arc<TcpStream>,
+}
+
+impl TlsStream {
+    /// Completes the handshake of a new session.
+    fn new(mut session: rustls::Connection, mut socket: TcpStream) -> io::Result<Self> {
+        while session.is_handshaking() {
+            session.complete_io(&mut socket)?;
+        }
+
+        Ok(Self {
+            session: Arc::new(Mutex::new(session)),
+            socket: Arc::new(socket),
+        })
+    }
+
+    /// Writes the records rustls has queued, e.g. encrypted data or alerts.
+    fn write_records(&self, session: &mut rustls::Connection) -> io::Result<()> {
+        while session.wants_write() {
+            session.write_tls(&mut &*self.socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            {
+                let mut session = self.session.lock().unwrap();
+                match session.reader().read(buf) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    result => return result,
+                }
+            }
+
+            let mut records = [0; RECORD_CHUNK_SIZE];
+            let length = (&*self.socket).read(&mut records)?;
+            if length == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            let mut session = self.session.lock().unwrap();
+            let mut records = &records[..length];
+            while !records.is_empty() {
+                session.read_tls(&mut records)?;
+                session.process_new_packets().map_err(invalid_data)?;
+            }
+            self.write_records(&mut session)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        let length = session.writer().write(buf)?;
+        self.write_records(&mut session)?;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        session.writer().flush()?;
+        self.write_records(&mut session)?;
+        (&*self.socket).flush()
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
@@ -0,0 +1,192 @@
+//! Allocation of the tokens of a joining node.
+//!
+//! Tokens derived from node addresses land anywhere on the ring, so some nodes end up owning
+//! more of it than others. The allocator places the tokens of a new node one at a time, each
+//! in the middle of one of the largest ranges: the one leaving the shares of the ring the nodes
+//! replicate closest to each other, for the replication factor the keyspaces of the cluster
+//! are expected to use.
+
+use crate::token_range::TokenRange;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Number of the largest ranges tried as the place of each new token.
+const CANDIDATE_RANGES: usize = 32;
+
+/// Number of positions on the ring, which every `u64` is one of.
+const RING_SIZE: f64 = 18_446_744_073_709_551_616.0;
+
+/// Returns the share of the ring every node replicates, between 0 and 1, for a replication
+/// factor: the range ending at a token is stored by the node owning the token and the next
+/// distinct nodes on the ring.
+///
+/// The tokens are given with their node, its host id or address, sorted by token.
+pub fn replicated_ownership<N: Copy + Eq + Hash>(
+    tokens: &[(u64, N)],
+    replication_factor: usize,
+) -> HashMap<N, f64> {
+    ownership_by_node(tokens, replication_factor)
+}
+
+/// Picks `num_tokens` tokens for a node joining a ring with the given tokens, sorted by
+/// token, so that the nodes replicate shares of the ring as even as possible.
+///
+/// The first node of a cluster spreads its tokens evenly over the ring.
+pub fn allocate_tokens<'a>(
+    tokens: &[(u64, &'a str)],
+    node_address: &'a str,
+    num_tokens: usize,
+    replication_factor: usize,
+) -> Vec<u64> {
+    let mut ring: Vec<(u64, &str)> = tokens
+        .iter()
+        .filter(|(_, address)| *address != node_address)
+        .copied()
+        .collect();
+    if ring.is_empty() {
+        let step = u64::MAX / num_tokens.max(1) as u64;
+        return (0..num_tokens as u64).map(|i| i * step).collect();
+    }
+
+    let mut allocated = Vec::new();
+    for _ in 0..num_tokens {
+        let mut ranges: Vec<TokenRange> = ring
+            .iter()
+            .enumerate()
+            .map(|(i, (token, _))| {
+                TokenRange::new(ring[(i + ring.len() - 1) % ring.len()].0, *token)
+            })
+            .collect();
+        ranges.sort_by_key(|range| Reverse(range.size()));
+
+        let best = ranges
+            .iter()
+            .take(CANDIDATE_RANGES)
+            .map(|range| range.start.wrapping_add((range.size() / 2) as u64))
+            .filter(|candidate| {
+                ring.binary_search_by_key(candidate, |(token, _)| *token)
+                    .is_err()
+            })
+            .map(|candidate| {
+                let mut candidate_ring = ring.clone();
+                insert_token(&mut candidate_ring, candidate, node_address);
+                (candidate, imbalance(&candidate_ring, replication_factor))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        let Some((token, _)) = best else {
+            break;
+        };
+        insert_token(&mut ring, token, node_address);
+        allocated.push(token);
+    }
+    allocated
+}
+
+fn insert_token<'a>(ring: &mut Vec<(u64, &'a str)>, token: u64, node_address: &'a str) {
+    let position = ring.partition_point(|(other, _)| *other < token);
+    ring.insert(position, (token, node_address));
+}
+
+/// Returns the sum of the squared differences between the share of the ring each node
+/// replicates and the mean share.
+fn imbalance(tokens: &[(u64, &str)], replication_factor: usize) -> f64 {
+    let ownership = ownership_by_node(tokens, replication_factor);
+    let mean = ownership.values().sum::<f64>() / ownership.len() as f64;
+    ownership.values().map(|share| (share - mean).powi(2)).sum()
+}
+
+fn ownership_by_node<N: Copy + Eq + Hash>(
+    tokens: &[(u64, N)],
+    replication_factor: usize,
+) -> HashMap<N, f64> {
+    let mut ownership: HashMap<N, f64> = tokens.iter().map(|(_, node)| (*node, 0.0)).collect();
+    let replica_count = replication_factor.min(ownership.len());
+
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        let previous = tokens[(i + tokens.len() - 1) % tokens.len()].0;
+        let share = TokenRange::new(previous, *token).size() as f64 / RING_SIZE;
+
+        let mut replicas: Vec<N> = Vec::new();
+        for k in 0..tokens.len() {
+            if replicas.len() == replica_count {
+                break;
+            }
+            let node = tokens[(i + k) % tokens.len()].1;
+            if !replicas.contains(&node) {
+                replicas.push(node);
+            }
+        }
+        for replica in replicas {
+            *ownership.get_mut(&replica).unwrap() += share;
+        }
+    }
+    ownership
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consistent_hash_ring::ConsistentHashRing;
+    use crate::token_allocator::{allocate_tokens, imbalance, replicated_ownership};
+
+    fn default_ring(addresses: &[&'static str], num_tokens: usize) -> Vec<(u64, &'static str)> {
+        let mut tokens: Vec<(u64, &str)> = addresses
+            .iter()
+            .flat_map(|address| {
+                ConsistentHashRing::default_tokens(address, num_tokens)
+                    .into_iter()
+                    .map(move |token| (token, *address))
+            })
+            .collect();
+        tokens.sort();
+        tokens
+    }
+
+    #[test]
+    fn test_replicated_ownership_adds_up_to_replication_factor() {
+        let tokens = default_ring(&["localhost:3000", "localhost:4000", "localhost:5001"], 16);
+
+        for replication_factor in 1..=3 {
+            let ownership = replicated_ownership(&tokens, replication_factor);
+            let total: f64 = ownership.values().sum();
+            assert!((total - replication_factor as f64).abs() < 1e-9);
+        }
+        let ownership = replicated_ownership(&tokens, 3);
+        assert!((ownership["localhost:4000"] - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_allocated_tokens_balance_ownership() {
+        let addresses = ["localhost:3000", "localhost:4000", "localhost:5001"];
+        let tokens = default_ring(&addresses, 16);
+
+        let allocated = allocate_tokens(&tokens, "localhost:6001", 16, 2);
+        assert_eq!(allocated.len(), 16);
+
+        let mut with_allocated = tokens.clone();
+        with_allocated.extend(allocated.iter().map(|token| (*token, "localhost:6001")));
+        with_allocated.sort();
+        let with_default = default_ring(
+            &[
+                "localhost:3000",
+                "localhost:4000",
+                "localhost:5001",
+                "localhost:6001",
+            ],
+            16,
+        );
+
+        assert!(imbalance(&with_allocated, 2) < imbalance(&with_default, 2));
+    }
+
+    #[test]
+    fn test_first_node_spreads_tokens_evenly() {
+        let allocated = allocate_tokens(&[], "localhost:3000", 4, 3);
+
+        assert_eq!(
+            allocated,
+            vec![0, u64::MAX / 4, u64::MAX / 4 * 2, u64::MAX / 4 * 3]
+        );
+    }
+}
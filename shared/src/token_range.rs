@@ -0,0 +1,264 @@
+//! Ranges of tokens on the ring.
+//!
+//! A range holds the tokens after its start, up to and including its end, going clockwise
+//! around the ring: a range starting after its end wraps around past the largest token. The
+//! ranges of a ring are those ending at each token and starting at the previous one, so a
+//! range is owned by the node owning its end.
+
+use serde::{Deserialize, Serialize};
+use std::ops::Bound::{self, Excluded, Included, Unbounded};
+
+/// A range of tokens, from `start` (exclusive) to `end` (inclusive).
+///
+/// When `start >= end`, the range wraps around: it spans from `start` to `u64::MAX` and then
+/// from 0 to `end`. A range ending where it starts covers the whole ring.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct TokenRange {
+    /// Start of the range (exclusive)
+    pub start: u64,
+    /// End of the range (inclusive)
+    pub end: u64,
+}
+
+/// Part of a range that doesn't wrap around, from `.0` (exclusive) to `.1` (inclusive), where
+/// -1 stands for the start of the ring so that token 0 can be included.
+type Interval = (i128, i128);
+
+impl TokenRange {
+    pub fn new(start: u64, end: u64) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the range covering every token of the ring.
+    pub fn full() -> Self {
+        Self::new(u64::MAX, u64::MAX)
+    }
+
+    /// Returns whether the range covers every token of the ring.
+    pub fn is_full(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns whether the range wraps around past the largest token.
+    pub fn is_wrapping(&self) -> bool {
+        self.start >= self.end
+    }
+
+    pub fn contains(&self, token: u64) -> bool {
+        match self.is_wrapping() {
+            true => token > self.start || token <= self.end,
+            false => token > self.start && token <= self.end,
+        }
+    }
+
+    /// Returns the number of tokens in the range.
+    pub fn size(&self) -> u128 {
+        match self.end.wrapping_sub(self.start) {
+            0 => 1 << 64,
+            size => size as u128,
+        }
+    }
+
+    /// Returns the bounds of the parts of the range that don't wrap around, in ring order
+    /// from the start of the range, for reading the range from a map ordered by token.
+    pub fn bounds(&self) -> Vec<(Bound<u64>, Bound<u64>)> {
+        match self.is_wrapping() {
+            true => vec![
+                (Excluded(self.start), Unbounded),
+                (Unbounded, Included(self.end)),
+            ],
+            false => vec![(Excluded(self.start), Included(self.end))],
+        }
+    }
+
+    /// Returns the rest of the range after a token of it, which is empty if the token is the
+    /// end of the range or isn't part of it.
+    pub fn after(&self, token: u64) -> Option<TokenRange> {
+        match token != self.end && self.contains(token) {
+            true => Some(Self::new(token, self.end)),
+            false => None,
+        }
+    }
+
+    /// Splits the range into at most `parts` contiguous ranges of about the same size, in
+    /// ring order.
+    pub fn split(&self, parts: usize) -> Vec<TokenRange> {
+        let parts = (parts.max(1) as u128).min(self.size());
+        let boundaries: Vec<u64> = (0..=parts)
+            .map(|i| self.start.wrapping_add((self.size() * i / parts) as u64))
+            .collect();
+
+        boundaries
+            .windows(2)
+            .map(|bounds| Self::new(bounds[0], bounds[1]))
+            .collect()
+    }
+
+    /// Returns the tokens of some ranges as the fewest ranges, ordered by token.
+    pub fn merge(ranges: impl IntoIterator<Item = TokenRange>) -> Vec<TokenRange> {
+        Self::from_intervals(
+            ranges
+                .into_iter()
+                .flat_map(|range| range.intervals())
+                .collect(),
+        )
+    }
+
+    /// Returns the tokens in both ranges, as the fewest ranges.
+    pub fn intersect(&self, other: &TokenRange) -> Vec<TokenRange> {
+        let mut intervals = Vec::new();
+        for (start, end) in self.intervals() {
+            for (other_start, other_end) in other.intervals() {
+                intervals.push((start.max(other_start), end.min(other_end)));
+            }
+        }
+        Self::from_intervals(intervals)
+    }
+
+    /// Returns the tokens of this range that aren't in the other one, as the fewest ranges.
+    pub fn subtract(&self, other: &TokenRange) -> Vec<TokenRange> {
+        let mut intervals = self.intervals();
+        for (other_start, other_end) in other.intervals() {
+            intervals = intervals
+                .into_iter()
+                .flat_map(|(start, end)| {
+                    [(start, end.min(other_start)), (start.max(other_end), end)]
+                })
+                .collect();
+        }
+        Self::from_intervals(intervals)
+    }
+
+    fn intervals(&self) -> Vec<Interval> {
+        let (start, end) = (self.start as i128, self.end as i128);
+        match self.is_wrapping() {
+            true => vec![(start, u64::MAX as i128), (-1, end)],
+            false => vec![(start, end)],
+        }
+    }
+
+    /// Turns intervals into the fewest ranges covering them, joining the interval reaching
+    /// the end of the ring with the one at its start into a range wrapping around.
+    fn from_intervals(mut intervals: Vec<Interval>) -> Vec<TokenRange> {
+        intervals.retain(|(start, end)| start < end);
+        intervals.sort();
+
+        let mut merged: Vec<Interval> = Vec::new();
+        for (start, end) in intervals {
+            match merged.last_mut() {
+                Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        if merged.len() > 1 && merged[0].0 == -1 && merged[merged.len() - 1].1 == u64::MAX as i128 {
+            let (_, end) = merged.remove(0);
+            merged.last_mut().unwrap().1 = end;
+        }
+
+        merged
+            .into_iter()
+            .map(|(start, end)| match start {
+                // from the start of the ring, which wraps around from the largest token
+                -1 => Self::new(u64::MAX, end as u64),
+                start => Self::new(start as u64, end as u64),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token_range::TokenRange;
+
+    const MAX: u64 = u64::MAX;
+
+    #[test]
+    fn test_wrapping_range_contains_both_ends_of_the_ring() {
+        let range = TokenRange::new(100, 10);
+
+        assert!(range.is_wrapping());
+        assert!(range.contains(MAX));
+        assert!(range.contains(0));
+        assert!(range.contains(10));
+        assert!(!range.contains(100));
+        assert!(!range.contains(50));
+        assert_eq!(range.size(), (MAX - 100) as u128 + 11);
+
+        assert!(TokenRange::full().contains(0));
+        assert!(TokenRange::full().contains(MAX));
+        assert_eq!(TokenRange::new(7, 7).size(), 1 << 64);
+    }
+
+    #[test]
+    fn test_after_resumes_within_the_range() {
+        let range = TokenRange::new(100, 10);
+
+        assert_eq!(range.after(200), Some(TokenRange::new(200, 10)));
+        assert_eq!(range.after(5), Some(TokenRange::new(5, 10)));
+        assert_eq!(range.after(10), None);
+        assert_eq!(range.after(50), None);
+    }
+
+    #[test]
+    fn test_split_covers_the_range() {
+        let range = TokenRange::new(MAX - 9, 10);
+        let parts = range.split(4);
+
+        assert_eq!(parts.len(), 4);
+        assert_eq!(parts[0].start, range.start);
+        assert_eq!(parts[3].end, range.end);
+        assert_eq!(parts.iter().map(TokenRange::size).sum::<u128>(), 20);
+        assert_eq!(TokenRange::merge(parts), vec![range]);
+
+        assert_eq!(TokenRange::new(0, 2).split(5).len(), 2);
+        assert_eq!(
+            TokenRange::full().split(2)[0],
+            TokenRange::new(MAX, MAX / 2)
+        );
+    }
+
+    #[test]
+    fn test_merge_joins_adjacent_and_overlapping_ranges() {
+        let merged = TokenRange::merge([
+            TokenRange::new(20, 30),
+            TokenRange::new(0, 10),
+            TokenRange::new(10, 15),
+            TokenRange::new(25, 40),
+        ]);
+        assert_eq!(
+            merged,
+            vec![TokenRange::new(0, 15), TokenRange::new(20, 40)]
+        );
+
+        // the ranges at both ends of the ring form a single wrapping range
+        let merged = TokenRange::merge([TokenRange::new(MAX - 10, MAX), TokenRange::new(MAX, 5)]);
+        assert_eq!(merged, vec![TokenRange::new(MAX - 10, 5)]);
+
+        let merged = TokenRange::merge([TokenRange::new(0, 10), TokenRange::new(10, 0)]);
+        assert_eq!(merged, vec![TokenRange::full()]);
+    }
+
+    #[test]
+    fn test_intersect_and_subtract_wrapping_ranges() {
+        let range = TokenRange::new(100, 10);
+
+        assert_eq!(
+            range.intersect(&TokenRange::new(0, 200)),
+            vec![TokenRange::new(0, 10), TokenRange::new(100, 200)]
+        );
+        assert!(range.intersect(&TokenRange::new(20, 50)).is_empty());
+        assert_eq!(range.intersect(&TokenRange::full()), vec![range]);
+
+        assert_eq!(
+            range.subtract(&TokenRange::new(MAX - 1, 5)),
+            vec![TokenRange::new(5, 10), TokenRange::new(100, MAX - 1)]
+        );
+        assert_eq!(range.subtract(&TokenRange::new(20, 50)), vec![range]);
+        assert!(range.subtract(&TokenRange::full()).is_empty());
+        assert_eq!(
+            TokenRange::full().subtract(&range),
+            vec![TokenRange::new(10, 100)]
+        );
+    }
+}
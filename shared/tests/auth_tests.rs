@@ -0,0 +1,30 @@
+#![cfg(test)]
+
+use shared::auth::Credentials;
+
+#[test]
+fn sasl_plain_token_should_round_trip() {
+    let credentials = Credentials::new("alice", "secret");
+
+    let token = credentials.to_sasl_plain();
+
+    assert_eq!(token, b"\0alice\0secret".to_vec());
+    assert_eq!(Credentials::from_sasl_plain(&token), Some(credentials));
+}
+
+#[test]
+fn from_sasl_plain_should_reject_malformed_tokens() {
+    assert_eq!(Credentials::from_sasl_plain(b"alice"), None);
+    assert_eq!(Credentials::from_sasl_plain(b"\0\0secret"), None);
+    assert_eq!(
+        Credentials::from_sasl_plain(b"\0alice\0secret\0extra"),
+        None
+    );
+}
+
+#[test]
+fn debug_should_hide_password() {
+    let credentials = Credentials::new("alice", "secret");
+
+    assert!(!format!("{:?}", credentials).contains("secret"));
+}
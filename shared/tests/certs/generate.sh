@@ -0,0 +1,21 @@
+#!/bin/sh
+# Regenerates the certificates used by tls_tests.rs: a CA, a node certificate for localhost and
+# a client certificate, all signed by the CA.
+set -e
+cd "$(dirname "$0")"
+
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes -days 36500 \
+    -subj "/CN=crate test CA" -keyout ca.key -out ca.pem
+
+for name in node client; do
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -nodes \
+        -subj "/CN=$name" -keyout "$name.key" -out "$name.csr"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial -days 36500 \
+        -extfile /dev/stdin -out "$name.pem" <<EXT
+subjectAltName = DNS:localhost, IP:127.0.0.1
+extendedKeyUsage = serverAuth, clientAuth
+EXT
+    rm "$name.csr"
+done
+
+rm ca.key ca.srl
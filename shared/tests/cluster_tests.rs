@@ -0,0 +1,188 @@
+#![cfg(test)]
+
+use shared::cluster::{Cluster, Node};
+use shared::partitioner::Murmur3Partitioner;
+use shared::protocol::types::{NodeTokens, TopologyChange, TopologyChangeType};
+use shared::token_range::TokenRange;
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn topology_change(change: TopologyChangeType, address: &str) -> TopologyChange {
+    TopologyChange {
+        change,
+        address: address.to_string(),
+        host_id: None,
+    }
+}
+
+#[test]
+fn apply_topology_change_should_add_and_drop_nodes() {
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:3000")],
+        Arc::new(Murmur3Partitioner),
+    );
+
+    let added = cluster.apply_topology_change(&topology_change(
+        TopologyChangeType::NewNode,
+        "localhost:4000",
+    ));
+
+    assert!(added);
+    assert!(cluster.contains_node("localhost:4000"));
+
+    let dropped = cluster.apply_topology_change(&topology_change(
+        TopologyChangeType::RemovedNode,
+        "localhost:3000",
+    ));
+
+    assert!(dropped);
+    assert!(!cluster.contains_node("localhost:3000"));
+    assert_eq!(cluster.get_nodes().len(), 1);
+}
+
+#[test]
+fn apply_topology_change_should_ignore_known_changes() {
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:3000")],
+        Arc::new(Murmur3Partitioner),
+    );
+
+    let added = cluster.apply_topology_change(&topology_change(
+        TopologyChangeType::NewNode,
+        "localhost:3000",
+    ));
+    let dropped = cluster.apply_topology_change(&topology_change(
+        TopologyChangeType::RemovedNode,
+        "localhost:4000",
+    ));
+
+    assert!(!added);
+    assert!(!dropped);
+    assert_eq!(cluster.get_nodes().len(), 1);
+}
+
+#[test]
+fn moved_node_should_keep_its_host_id_and_tokens() {
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:3000"), Node::from("localhost:4000")],
+        Arc::new(Murmur3Partitioner),
+    );
+    let host_id = Uuid::new_v4();
+    let moved = TopologyChange {
+        host_id: Some(host_id),
+        ..topology_change(TopologyChangeType::MovedNode, "localhost:4001")
+    };
+
+    assert!(cluster.learn_node(NodeTokens {
+        address: "localhost:4000".to_string(),
+        host_id: Some(host_id),
+        tokens: vec![100, 200],
+    }));
+    assert!(cluster.apply_topology_change(&moved));
+    assert!(!cluster.apply_topology_change(&moved));
+
+    assert!(!cluster.contains_node("localhost:4000"));
+    let node_tokens = cluster.node_tokens();
+    let moved_node = node_tokens
+        .iter()
+        .find(|node| node.address == "localhost:4001")
+        .unwrap();
+    assert_eq!(moved_node.host_id, Some(host_id));
+    assert_eq!(moved_node.tokens, vec![100, 200]);
+}
+
+#[test]
+fn node_moved_before_its_host_id_is_known_should_move_once_it_is() {
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:3000"), Node::from("localhost:4000")],
+        Arc::new(Murmur3Partitioner),
+    );
+    let host_id = Uuid::new_v4();
+    let moved = TopologyChange {
+        host_id: Some(host_id),
+        ..topology_change(TopologyChangeType::MovedNode, "localhost:4001")
+    };
+
+    // the node can't be recognized yet, the move is kept until it can
+    assert!(cluster.apply_topology_change(&moved));
+    assert!(!cluster.apply_topology_change(&moved));
+    assert!(cluster.contains_node("localhost:4000"));
+
+    assert!(cluster.learn_node(NodeTokens {
+        address: "localhost:4000".to_string(),
+        host_id: Some(host_id),
+        tokens: vec![100, 200],
+    }));
+    assert!(!cluster.contains_node("localhost:4000"));
+    let node = cluster.node("localhost:4001").unwrap();
+    assert_eq!(node.host_id, Some(host_id));
+    assert_eq!(
+        cluster
+            .node_tokens()
+            .into_iter()
+            .find(|node| node.address == "localhost:4001")
+            .unwrap()
+            .tokens,
+        vec![100, 200]
+    );
+}
+
+#[test]
+fn new_node_should_keep_its_host_id() {
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:3000")],
+        Arc::new(Murmur3Partitioner),
+    );
+    let host_id = Uuid::new_v4();
+    let new_node = |address: &str| TopologyChange {
+        host_id: Some(host_id),
+        ..topology_change(TopologyChangeType::NewNode, address)
+    };
+
+    assert!(cluster.apply_topology_change(&new_node("localhost:4000")));
+    assert!(!cluster.apply_topology_change(&new_node("localhost:4000")));
+    assert_eq!(
+        cluster.node("localhost:4000").unwrap().host_id,
+        Some(host_id)
+    );
+
+    // a known node learns its host id from the change
+    let mut cluster = Cluster::new(
+        vec![Node::from("localhost:4000")],
+        Arc::new(Murmur3Partitioner),
+    );
+    assert!(cluster.apply_topology_change(&new_node("localhost:4000")));
+    assert_eq!(
+        cluster.node("localhost:4000").unwrap().host_id,
+        Some(host_id)
+    );
+}
+
+#[test]
+fn replicated_ranges_should_follow_the_replication_factor() {
+    let mut cluster = Cluster::new(
+        vec![
+            Node::from("localhost:3000"),
+            Node::from("localhost:4000"),
+            Node::from("localhost:5001"),
+        ],
+        Arc::new(Murmur3Partitioner),
+    );
+    cluster.set_tokens("localhost:3000", vec![100]);
+    cluster.set_tokens("localhost:4000", vec![200]);
+    cluster.set_tokens("localhost:5001", vec![300]);
+
+    assert_eq!(
+        cluster.replicated_ranges("localhost:4000", 1),
+        vec![TokenRange::new(100, 200)]
+    );
+    // also the range of the node before it
+    assert_eq!(
+        cluster.replicated_ranges("localhost:4000", 2),
+        vec![TokenRange::new(300, 200)]
+    );
+    assert_eq!(
+        cluster.replicated_ranges("localhost:4000", 3),
+        vec![TokenRange::full()]
+    );
+}
@@ -2,7 +2,10 @@
 
 use io::Cursor;
 use shared::protocol::frame::{Flags, Frame, Opcode, Version};
+use shared::protocol::types::{ProtocolVersion, UnsupportedVersion};
+use std::collections::HashMap;
 use std::io;
+use uuid::Uuid;
 
 #[test]
 fn new_should_create_frame() {
@@ -10,7 +13,7 @@ fn new_should_create_frame() {
     let expected = actual.clone();
 
     let frame = Frame::new(
-        Version::Request,
+        Version::request(ProtocolVersion::V4),
         None,
         None,
         Opcode::Query,
@@ -18,8 +21,8 @@ fn new_should_create_frame() {
         actual,
     );
 
-    assert_eq!(frame.version, Version::Request);
-    assert_eq!(frame.flags, Flags::None);
+    assert_eq!(frame.version, Version::request(ProtocolVersion::V4));
+    assert_eq!(frame.flags, Flags::empty());
     assert_eq!(frame.stream, 0);
     assert_eq!(frame.opcode, Opcode::Query);
     assert_eq!(frame.length, expected.len() as u32);
@@ -31,8 +34,8 @@ fn encode_should_return_bytes() {
     let body = vec![0u8; 1];
 
     let frame = Frame::new(
-        Version::Request,
-        Some(Flags::CustomPayload),
+        Version::request(ProtocolVersion::V4),
+        Some(Flags::CUSTOM_PAYLOAD),
         Some(1234),
         Opcode::Query,
         body.len() as u32,
@@ -40,7 +43,7 @@ fn encode_should_return_bytes() {
     );
     let encoded_frame_result = frame.encode();
 
-    assert_eq!(encoded_frame_result.is_ok(), true);
+    assert!(encoded_frame_result.is_ok());
 
     let encoded_frame = encoded_frame_result.unwrap();
     assert_eq!(encoded_frame[0], 0x04);
@@ -57,9 +60,11 @@ fn encode_should_return_bytes() {
             encoded_frame[7],
             encoded_frame[8]
         ]),
-        1
+        3
     );
-    assert_eq!(encoded_frame[9..10], vec![0u8; 1]);
+    // empty custom payload map followed by the body
+    assert_eq!(encoded_frame[9..11], vec![0u8; 2]);
+    assert_eq!(encoded_frame[11..12], vec![0u8; 1]);
 }
 
 #[test]
@@ -67,8 +72,8 @@ fn decode_should_return_bytes() {
     let body = "test data".as_bytes().to_vec();
     let expected_body = body.clone();
     let frame = Frame::new(
-        Version::Request,
-        Some(Flags::CustomPayload),
+        Version::request(ProtocolVersion::V4),
+        Some(Flags::CUSTOM_PAYLOAD),
         Some(1234),
         Opcode::Query,
         body.len() as u32,
@@ -76,18 +81,98 @@ fn decode_should_return_bytes() {
     );
     let encoded_frame_result = frame.clone().encode();
 
-    assert_eq!(encoded_frame_result.is_ok(), true);
+    assert!(encoded_frame_result.is_ok());
 
     let mut cursor = Cursor::new(encoded_frame_result.unwrap());
 
     let decoded_frame_result = Frame::decode(&mut cursor);
-    assert_eq!(decoded_frame_result.is_ok(), true);
+    assert!(decoded_frame_result.is_ok());
 
     let decoded_frame = decoded_frame_result.unwrap();
-    assert_eq!(decoded_frame.version, Version::Request);
-    assert_eq!(decoded_frame.flags, Flags::CustomPayload);
+    assert_eq!(decoded_frame.version, Version::request(ProtocolVersion::V4));
+    assert_eq!(decoded_frame.flags, Flags::CUSTOM_PAYLOAD);
     assert_eq!(decoded_frame.stream, 1234);
     assert_eq!(decoded_frame.opcode, Opcode::Query);
     assert_eq!(decoded_frame.length, expected_body.len() as u32);
     assert_eq!(decoded_frame.body, expected_body);
 }
+
+#[test]
+fn decode_should_accept_combined_flags() {
+    let mut bytes = vec![0x04, 0x03, 0x00, 0x01, 0x07];
+    bytes.extend_from_slice(&1u32.to_be_bytes());
+    bytes.push(0xFF);
+
+    let decoded_frame = Frame::decode(&mut Cursor::new(bytes)).unwrap();
+
+    assert_eq!(decoded_frame.flags, Flags::COMPRESSION | Flags::TRACING);
+    assert!(decoded_frame.tracing_id.is_none());
+    assert_eq!(decoded_frame.body, vec![0xFF]);
+}
+
+#[test]
+fn decode_should_return_flag_fields_of_response() {
+    let body = "test data".as_bytes().to_vec();
+    let tracing_id = Uuid::from_u128(0x1234);
+    let custom_payload = HashMap::from([("tenant".to_string(), b"acme".to_vec())]);
+    let warnings = vec!["Batch too large".to_string()];
+
+    let frame = Frame::new(
+        Version::response(ProtocolVersion::V4),
+        None,
+        Some(7),
+        Opcode::Result,
+        body.len() as u32,
+        body.clone(),
+    )
+    .with_tracing_id(tracing_id)
+    .with_warnings(warnings.clone())
+    .with_custom_payload(custom_payload.clone());
+
+    let encoded_frame = frame.encode().unwrap();
+    let decoded_frame = Frame::decode(&mut Cursor::new(encoded_frame)).unwrap();
+
+    assert_eq!(
+        decoded_frame.flags,
+        Flags::TRACING | Flags::WARNING | Flags::CUSTOM_PAYLOAD
+    );
+    assert_eq!(decoded_frame.tracing_id, Some(tracing_id));
+    assert_eq!(decoded_frame.warnings, warnings);
+    assert_eq!(decoded_frame.custom_payload, custom_payload);
+    assert_eq!(decoded_frame.length, body.len() as u32);
+    assert_eq!(decoded_frame.body, body);
+}
+
+#[test]
+fn encode_should_set_direction_bit_of_response() {
+    let frame = Frame::new(
+        Version::response(ProtocolVersion::V5),
+        None,
+        None,
+        Opcode::Result,
+        0,
+        Vec::new(),
+    );
+
+    let encoded_frame = frame.encode().unwrap();
+
+    assert_eq!(encoded_frame[0], 0x85);
+}
+
+#[test]
+fn decode_should_reject_unsupported_version() {
+    let mut bytes = vec![0x02, 0x00, 0x00, 0x00, 0x07];
+    bytes.extend_from_slice(&0u32.to_be_bytes());
+
+    let error = Frame::decode(&mut Cursor::new(bytes)).unwrap_err();
+    let unsupported_version = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<UnsupportedVersion>())
+        .copied();
+
+    assert_eq!(unsupported_version, Some(UnsupportedVersion(0x02)));
+    assert_eq!(
+        unsupported_version.unwrap().to_string(),
+        "Invalid or unsupported protocol version (2); supported versions are (3/v3, 4/v4, 5/v5)"
+    );
+}
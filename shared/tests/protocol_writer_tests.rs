@@ -1,6 +1,6 @@
 #![cfg(test)]
 
-use shared::protocol::frame::Frame;
+use shared::protocol::frame::{EVENT_STREAM, Frame, RequestExtras, ResponseExtras, Tracing};
 use shared::protocol::protocol_writer::ProtocolWriter;
 use shared::protocol::types::*;
 use std::io::Cursor;
@@ -32,3 +32,105 @@ fn test_send_request() {
         })
     );
 }
+
+#[test]
+fn test_send_event() {
+    let cursor = Cursor::new(Vec::<u8>::new());
+    let mut protocol_writer = ProtocolWriter::new(cursor);
+
+    let event = Event::TopologyChange(TopologyChange {
+        change: TopologyChangeType::NewNode,
+        address: "localhost:6000".to_string(),
+        host_id: None,
+    });
+    protocol_writer.send_event(&event).unwrap();
+
+    let mut cursor = protocol_writer.into_inner().unwrap();
+    cursor.set_position(0);
+    let frame = Frame::decode(&mut cursor).unwrap();
+
+    assert_eq!(frame.opcode, Opcode::Event);
+    assert_eq!(frame.stream, EVENT_STREAM);
+    assert!(frame.version.is_response());
+
+    let response: Response = bincode::deserialize(&frame.body).unwrap();
+
+    assert_eq!(response, Response::Event(event));
+}
+
+#[test]
+fn test_send_request_with_extras_forwards_session_and_payload() {
+    let cursor = Cursor::new(Vec::<u8>::new());
+    let mut protocol_writer = ProtocolWriter::new(cursor);
+    let session_id = uuid::Uuid::new_v4();
+    let extras = RequestExtras {
+        tracing: Tracing::Session(session_id),
+        custom_payload: CustomPayload::from([("tenant".to_string(), b"acme".to_vec())]),
+    };
+
+    protocol_writer
+        .send_request_with_extras(&Request::Count(Vec::new()), extras.clone())
+        .unwrap();
+
+    let mut cursor = protocol_writer.into_inner().unwrap();
+    cursor.set_position(0);
+    let frame = Frame::decode(&mut cursor).unwrap();
+
+    assert!(frame.flags.contains(Flags::TRACING | Flags::CUSTOM_PAYLOAD));
+    assert_eq!(
+        frame.custom_payload.get(TRACE_SESSION_PAYLOAD_KEY),
+        Some(&session_id.as_bytes().to_vec())
+    );
+    assert_eq!(frame.request_extras(), extras);
+    assert_eq!(
+        bincode::deserialize::<Request>(&frame.body).unwrap(),
+        Request::Count(Vec::new())
+    );
+}
+
+#[test]
+fn test_send_response_with_extras() {
+    let cursor = Cursor::new(Vec::<u8>::new());
+    let mut protocol_writer = ProtocolWriter::new(cursor);
+    let extras = ResponseExtras {
+        tracing_id: Some(uuid::Uuid::new_v4()),
+        warnings: vec!["Batch is too big".to_string()],
+        custom_payload: CustomPayload::from([("request_tag".to_string(), b"audit".to_vec())]),
+    };
+
+    protocol_writer
+        .send_response_with_extras(&Response::Bool(true), extras.clone())
+        .unwrap();
+
+    let mut cursor = protocol_writer.into_inner().unwrap();
+    cursor.set_position(0);
+    let frame = Frame::decode(&mut cursor).unwrap();
+
+    assert!(
+        frame
+            .flags
+            .contains(Flags::TRACING | Flags::WARNING | Flags::CUSTOM_PAYLOAD)
+    );
+    assert_eq!(frame.response_extras(), extras);
+    assert_eq!(
+        bincode::deserialize::<Response>(&frame.body).unwrap(),
+        Response::Bool(true)
+    );
+}
+
+#[test]
+fn test_send_response_without_extras_sets_no_flags() {
+    let cursor = Cursor::new(Vec::<u8>::new());
+    let mut protocol_writer = ProtocolWriter::new(cursor);
+
+    protocol_writer
+        .send_response(&Response::Bool(true))
+        .unwrap();
+
+    let mut cursor = protocol_writer.into_inner().unwrap();
+    cursor.set_position(0);
+    let frame = Frame::decode(&mut cursor).unwrap();
+
+    assert!(frame.flags.is_empty());
+    assert_eq!(frame.response_extras(), ResponseExtras::default());
+}
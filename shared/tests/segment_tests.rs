@@ -0,0 +1,73 @@
+#![cfg(test)]
+
+use shared::protocol::protocol_reader::ProtocolReader;
+use shared::protocol::protocol_writer::ProtocolWriter;
+use shared::protocol::segment::{Framing, MAX_PAYLOAD_LENGTH, Segment};
+use shared::protocol::types::{Entry, ProtocolVersion, Request};
+use std::io::Cursor;
+
+#[test]
+fn decode_should_return_encoded_segment() {
+    let segment = Segment {
+        payload: b"frame bytes".to_vec(),
+        self_contained: true,
+    };
+
+    let decoded = Segment::decode(&mut Cursor::new(segment.encode())).unwrap();
+
+    assert_eq!(decoded, segment);
+}
+
+#[test]
+fn decode_should_reject_corrupted_header() {
+    let segment = Segment {
+        payload: b"frame bytes".to_vec(),
+        self_contained: true,
+    };
+    let mut bytes = segment.encode();
+    bytes[0] ^= 0x01;
+
+    assert!(Segment::decode(&mut Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn decode_should_reject_corrupted_payload() {
+    let segment = Segment {
+        payload: b"frame bytes".to_vec(),
+        self_contained: true,
+    };
+    let mut bytes = segment.encode();
+    bytes[7] ^= 0x01;
+
+    assert!(Segment::decode(&mut Cursor::new(bytes)).is_err());
+}
+
+#[test]
+fn reader_should_reassemble_frame_split_across_segments() {
+    let value = "x".repeat(MAX_PAYLOAD_LENGTH * 2);
+    let request = Request::Add(Entry {
+        value,
+        replication_factor: None,
+    });
+
+    let mut writer = ProtocolWriter::new(Cursor::new(Vec::<u8>::new()))
+        .with_version(ProtocolVersion::V5)
+        .with_framing(Framing::Checksummed);
+    writer.send_request(&request).unwrap();
+    writer.send_request(&Request::Count(Vec::new())).unwrap();
+
+    let mut cursor = writer.into_inner().unwrap();
+    cursor.set_position(0);
+
+    let mut first_segment = cursor.clone();
+    assert!(!Segment::decode(&mut first_segment).unwrap().self_contained);
+
+    let mut reader = ProtocolReader::new(cursor);
+    reader.set_framing(Framing::Checksummed);
+
+    assert_eq!(reader.receive_request().unwrap(), request);
+    assert_eq!(
+        reader.receive_request().unwrap(),
+        Request::Count(Vec::new())
+    );
+}
@@ -0,0 +1,133 @@
+#![cfg(test)]
+
+use shared::tls::{TlsAcceptor, TlsConnector, TlsSettings};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+fn cert(name: &str) -> Option<PathBuf> {
+    Some(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/certs")
+            .join(name),
+    )
+}
+
+fn node_settings(require_client_auth: bool) -> TlsSettings {
+    TlsSettings {
+        cert: cert("node.pem"),
+        key: cert("node.key"),
+        ca: cert("ca.pem"),
+        require_client_auth,
+    }
+}
+
+fn client_settings(with_certificate: bool) -> TlsSettings {
+    TlsSettings {
+        cert: cert("client.pem").filter(|_| with_certificate),
+        key: cert("client.key").filter(|_| with_certificate),
+        ca: cert("ca.pem"),
+        require_client_auth: false,
+    }
+}
+
+/// Connects to an echo server accepting one connection, returning whether the node accepted
+/// the handshake and the echoed message.
+fn echo(node: TlsSettings, client: TlsSettings) -> (bool, Option<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("localhost:{}", listener.local_addr().unwrap().port());
+    let acceptor = TlsAcceptor::new(node).unwrap();
+
+    let server = thread::spawn(move || {
+        let (socket, _) = listener.accept().unwrap();
+        let mut stream = acceptor.accept(socket).ok()?;
+        let mut message = [0; 5];
+        stream.read_exact(&mut message).ok()?;
+        stream.write_all(&message).ok()?;
+        stream.flush().ok()
+    });
+
+    let socket = TcpStream::connect(&address).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let echoed = TlsConnector::new(client)
+        .unwrap()
+        .connect(socket, &address)
+        .and_then(|mut stream| {
+            stream.write_all(b"hello")?;
+            stream.flush()?;
+            let mut echoed = vec![0; 5];
+            stream.read_exact(&mut echoed)?;
+            Ok(echoed)
+        })
+        .ok();
+
+    (server.join().unwrap().is_some(), echoed)
+}
+
+#[test]
+fn data_should_round_trip_over_tls() {
+    let (accepted, echoed) = echo(node_settings(false), client_settings(false));
+
+    assert!(accepted);
+    assert_eq!(echoed, Some(b"hello".to_vec()));
+}
+
+#[test]
+fn mutual_tls_should_accept_clients_with_certificate() {
+    let (accepted, echoed) = echo(node_settings(true), client_settings(true));
+
+    assert!(accepted);
+    assert_eq!(echoed, Some(b"hello".to_vec()));
+}
+
+#[test]
+fn mutual_tls_should_reject_clients_without_certificate() {
+    let (accepted, echoed) = echo(node_settings(true), client_settings(false));
+
+    assert!(!accepted);
+    assert_eq!(echoed, None);
+}
+
+#[test]
+fn connector_should_require_ca() {
+    let settings = TlsSettings {
+        ca: None,
+        ..client_settings(true)
+    };
+
+    assert!(TlsConnector::new(settings).is_err());
+}
+
+#[test]
+fn acceptor_should_reload_changed_certificates() {
+    let directory = std::env::temp_dir().join(format!("tls_reload_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    for name in ["node.pem", "node.key", "ca.pem"] {
+        std::fs::copy(cert(name).unwrap(), directory.join(name)).unwrap();
+    }
+    let settings = TlsSettings {
+        cert: Some(directory.join("node.pem")),
+        key: Some(directory.join("node.key")),
+        ca: Some(directory.join("ca.pem")),
+        require_client_auth: false,
+    };
+    let acceptor = TlsAcceptor::new(settings).unwrap();
+
+    assert!(!acceptor.reload_if_changed().unwrap());
+
+    std::fs::File::options()
+        .append(true)
+        .open(directory.join("node.pem"))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+
+    assert!(acceptor.reload_if_changed().unwrap());
+    assert!(!acceptor.reload_if_changed().unwrap());
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -1,12 +1,23 @@
 mod rebalancer;
 
 use crate::rebalancer::{RebalanceAction, Rebalancer};
+use shared::auth::Credentials;
 use shared::cluster::Cluster;
 use shared::consistent_hash_ring::Node;
+use shared::partitioner;
+use shared::partitioner::{GlobalPartitioner, Murmur3Partitioner};
 use shared::protocol::types::{Entry, Request};
+use shared::tls::{TlsConnector, TlsSettings};
+use std::path::PathBuf;
 use text_colorizer::Colorize;
 
 const NODES_ARG_KEY: &str = "nodes=";
+const USER_ARG_KEY: &str = "user=";
+const PASSWORD_ARG_KEY: &str = "password=";
+const TLS_CA_ARG_KEY: &str = "tls_ca=";
+const TLS_CERT_ARG_KEY: &str = "tls_cert=";
+const TLS_KEY_ARG_KEY: &str = "tls_key=";
+const PARTITIONER_ARG_KEY: &str = "partitioner=";
 
 const LOG_INFO: &str = "INFO";
 const LOG_VERBOSE: &str = "VERBOSE";
@@ -14,8 +25,11 @@ const LOG_VERBOSE: &str = "VERBOSE";
 fn main() {
     let nodes = initialize_cluster_config();
 
-    let cluster = Cluster::new(nodes.clone());
-    let mut rebalancer = Rebalancer::new(cluster);
+    let cluster = Cluster::new(nodes.clone(), initialize_partitioner());
+    let mut rebalancer = Rebalancer::new(cluster, initialize_credentials(), initialize_tls());
+    for node in nodes.iter() {
+        rebalancer.learn_tokens(node);
+    }
     println!(
         "{}: {}",
         LOG_INFO.bright_green(),
@@ -34,12 +48,7 @@ fn main() {
         _ = rebalancer.connection_pool.execute(strategy, request, None);
     }
 
-    let count_request = Request::Count;
-    let router = rebalancer.cluster.router();
-    let strategy = router.route_request(&count_request);
-    let _ = rebalancer
-        .connection_pool
-        .execute(strategy, count_request, None);
+    rebalancer.count(Vec::new());
 
     println!(
         "{}: {}",
@@ -51,11 +60,26 @@ fn main() {
         println!(
             "{}: {}",
             LOG_INFO.bright_green(),
-            "Enter command (add/drop):".green().bold()
+            "Enter command (add/drop/status):".green().bold()
         );
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).unwrap();
 
+        if input.trim() == "status" {
+            println!(
+                "{}: {}",
+                LOG_INFO.bright_green(),
+                "Enter the replication factor to compute ownership for, or nothing"
+                    .green()
+                    .bold()
+            );
+            let mut replication_factor = String::new();
+            std::io::stdin().read_line(&mut replication_factor).unwrap();
+
+            rebalancer.status(replication_factor.trim().parse().ok());
+            continue;
+        }
+
         println!(
             "{}: {}",
             LOG_INFO.bright_green(),
@@ -67,9 +91,7 @@ fn main() {
         let mut node = String::new();
         std::io::stdin().read_line(&mut node).unwrap();
 
-        let new_node = Node {
-            address: node.trim().to_string(),
-        };
+        let new_node = Node::from(node.trim());
 
         match input.trim() {
             "add" => {
@@ -84,15 +106,51 @@ fn main() {
         }
     }
 
+    /// Uses the partitioner of the cluster, given with `partitioner=`, Murmur3 by default.
+    fn initialize_partitioner() -> GlobalPartitioner {
+        let name =
+            find_arg(PARTITIONER_ARG_KEY).unwrap_or_else(|| Murmur3Partitioner::NAME.to_string());
+        partitioner::from_name(&name).unwrap_or_else(|| panic!("Unknown partitioner {}", name))
+    }
+
+    fn initialize_credentials() -> Option<Credentials> {
+        match (find_arg(USER_ARG_KEY), find_arg(PASSWORD_ARG_KEY)) {
+            (Some(user), Some(password)) => Some(Credentials::new(user, password)),
+            (None, None) => None,
+            _ => panic!("user and password must be given together"),
+        }
+    }
+
+    /// Connects over TLS if a CA is given; nodes requiring client certificates also need
+    /// `tls_cert=` and `tls_key=`.
+    fn initialize_tls() -> Option<TlsConnector> {
+        let ca = find_arg(TLS_CA_ARG_KEY)?;
+        let settings = TlsSettings {
+            cert: find_arg(TLS_CERT_ARG_KEY).map(PathBuf::from),
+            key: find_arg(TLS_KEY_ARG_KEY).map(PathBuf::from),
+            ca: Some(PathBuf::from(ca)),
+            require_client_auth: false,
+        };
+
+        Some(
+            TlsConnector::new(settings)
+                .unwrap_or_else(|e| panic!("Invalid TLS configuration: {}", e)),
+        )
+    }
+
+    fn find_arg(key: &str) -> Option<String> {
+        std::env::args()
+            .find(|arg| arg.starts_with(key))
+            .map(|arg| arg.trim_start_matches(key).to_string())
+    }
+
     fn initialize_cluster_config() -> Vec<Node> {
         let nodes = std::env::args()
             .find(|arg| arg.starts_with(NODES_ARG_KEY))
             .expect("--nodes argument is required")
             .trim_start_matches(NODES_ARG_KEY)
             .split(",")
-            .map(|s| Node {
-                address: s.to_string(),
-            })
+            .map(Node::from)
             .collect::<Vec<Node>>();
 
         println!("Nodes: {:?}", nodes);
@@ -1,9 +1,24 @@
 use crate::LOG_VERBOSE;
+use shared::auth::Credentials;
 use shared::cluster::{Cluster, Node};
 use shared::connection_pool::ConnectionPool;
-use shared::consistent_hash_ring::Range;
-use shared::protocol::types::{Entry, Request, Response};
+use shared::error::AppResult;
+use shared::paging::Pages;
+use shared::protocol::types::{
+    Entry, KeyspaceMetadata, Request, Response, RowRanges, TopologyChange, TopologyChangeType,
+    ValueCount,
+};
 use shared::routing::RoutingStrategy;
+use shared::tls::TlsConnector;
+use shared::token_range::TokenRange;
+use std::collections::HashMap;
+
+/// Number of values moved at a time, so that nodes with many values can be rebalanced without
+/// holding all of them in memory.
+const REBALANCE_PAGE_SIZE: usize = 500;
+
+/// The ranges of the ring every node stores rows of, by keyspace and node address.
+type ReplicatedRanges = HashMap<String, HashMap<String, Vec<TokenRange>>>;
 
 pub enum RebalanceAction {
     AddNode,
@@ -12,13 +27,24 @@ pub enum RebalanceAction {
 
 pub struct Rebalancer {
     pub connection_pool: ConnectionPool,
+    /// Reads the values to move while `connection_pool` writes them elsewhere.
+    read_connection_pool: ConnectionPool,
     pub cluster: Cluster,
 }
 
 impl Rebalancer {
-    pub fn new(cluster: Cluster) -> Self {
+    pub fn new(
+        cluster: Cluster,
+        credentials: Option<Credentials>,
+        tls: Option<TlsConnector>,
+    ) -> Self {
         Self {
-            connection_pool: ConnectionPool::new(),
+            connection_pool: ConnectionPool::new()
+                .with_credentials(credentials.clone())
+                .with_tls(tls.clone()),
+            read_connection_pool: ConnectionPool::new()
+                .with_credentials(credentials)
+                .with_tls(tls),
             cluster,
         }
     }
@@ -33,116 +59,125 @@ impl Rebalancer {
     }
 
     fn add_new_node(&mut self, node_address: String) {
-        let node = Node {
-            address: node_address.clone(),
-        };
+        let keyspaces = self.keyspaces();
+        let replicated_before = self.replicated_ranges(&keyspaces);
+        let node = Node::new(node_address.clone());
         self.cluster.add_node(node.clone());
+        self.learn_tokens(&node);
+        let replicated_after = self.replicated_ranges(&keyspaces);
 
-        let mut entities = self.cluster.get_ring_entities();
-        entities.sort_by_key(|&(hash, _)| hash);
-
-        let ranges = Self::get_ranges(entities, node_address.as_str());
-        let mut rebalanced_items = Vec::new();
-
-        let request = Request::GetBatch(ranges.clone());
-        let router = self.cluster.router();
-        let routing_strategy = router.route_request(&request);
+        let ranges = self.get_ranges(&node);
+        let other_nodes = self
+            .cluster
+            .get_nodes()
+            .iter()
+            .filter(|other_node| other_node.address != node.address)
+            .cloned()
+            .collect();
 
-        let result =
-            self.connection_pool
-                .execute(routing_strategy, request, Some(node.address.as_str()));
-
-        match result {
-            Ok(Response::Array(value)) => {
-                rebalanced_items = value
+        let mut values = Pages::new(
+            &mut self.read_connection_pool,
+            other_nodes,
+            ranges.clone(),
+            REBALANCE_PAGE_SIZE,
+        );
+        loop {
+            let rebalanced_items: Vec<Entry> = match Self::next_page(&mut values) {
+                Ok(page) if page.is_empty() => break,
+                Ok(page) => page
                     .into_iter()
                     .map(|val| Entry {
                         value: val,
                         replication_factor: Some(1),
                     })
-                    .collect();
-            }
-            Err(_) => {}
-            _ => {}
-        }
+                    .collect(),
+                Err(e) => {
+                    println!("Failed to get batch: {:?}", e);
+                    break;
+                }
+            };
+            let count = rebalanced_items.len();
 
-        let strategy = RoutingStrategy::Direct(&node);
-        let result =
-            self.connection_pool
-                .execute(strategy, Request::AddBatch(rebalanced_items), None);
+            let strategy = RoutingStrategy::Direct(&node);
+            let result =
+                self.connection_pool
+                    .execute(strategy, Request::AddBatch(rebalanced_items), None);
 
-        match result {
-            Ok(_) => {
-                println!("Added batch successfully to new node: {}", node_address);
-            }
-            Err(e) => {
-                println!("Failed to add batch: {:?}", e);
+            match result {
+                Ok(_) => {
+                    println!(
+                        "Added batch of {} values successfully to new node: {}",
+                        count, node_address
+                    );
+                }
+                Err(e) => {
+                    println!("Failed to add batch: {:?}", e);
+                }
             }
         }
 
-        let request = Request::DropBatch(ranges.clone());
-        let router = self.cluster.router();
-        let routing_strategy = router.route_request(&request);
-        let response = self
-            .connection_pool
-            .execute(routing_strategy, request, Some(node.address.as_str()))
-            .unwrap();
-
-        match response {
-            Response::Array(value) => {
-                println!(
-                    "{}: {}",
-                    LOG_VERBOSE,
-                    format!("[{}] Received data: {:?}", node.address, value)
-                );
-            }
-            _ => {
-                println!(
-                    "{}: {}",
-                    LOG_VERBOSE,
-                    format!("Received data: {:?}", response)
-                );
+        if self.holds_moved_values(&node, &ranges) {
+            let request = Request::DropBatch(ranges.clone());
+            let router = self.cluster.router();
+            let routing_strategy = router.route_request(&request);
+            let response = self
+                .connection_pool
+                .execute(routing_strategy, request, Some(node.address.as_str()))
+                .unwrap();
+
+            match response {
+                Response::Count(removed) => {
+                    for (address, node_removed) in &removed.nodes {
+                        println!(
+                            "{}: [{}] Removed {} moved values",
+                            LOG_VERBOSE, address, node_removed.total
+                        );
+                    }
+                }
+                _ => {
+                    println!("{}: Received data: {:?}", LOG_VERBOSE, response);
+                }
             }
+        } else {
+            println!(
+                "Not all moved values reached {}, keeping them on the other nodes",
+                node.address
+            );
         }
 
-        println!(
-            "{}: {}",
-            LOG_VERBOSE,
-            format!("Rebalanced with new node {}", node.address)
-        );
+        self.move_rows(&replicated_before, &replicated_after);
 
-        let count_request = Request::Count;
-        let router = self.cluster.router();
-        let strategy = router.route_request(&count_request);
-        let _ = self.connection_pool.execute(strategy, count_request, None);
+        println!("{}: Rebalanced with new node {}", LOG_VERBOSE, node.address);
+
+        self.announce_topology_change(TopologyChangeType::NewNode, node.address.clone());
+
+        self.count(Vec::new());
     }
 
     fn remove_node(&mut self, node_address: String) {
-        let node = Node {
-            address: node_address,
-        };
+        let keyspaces = self.keyspaces();
+        let replicated_before = self.replicated_ranges(&keyspaces);
+        let node = Node::new(node_address);
         self.cluster.drop_node(&node);
+        let replicated_after = self.replicated_ranges(&keyspaces);
 
-        let mut dropped_items = Vec::new();
-
-        let request = Request::GetBatch(vec![Range {
-            start: 0,
-            end: u64::MAX,
-        }]);
-        let routing_strategy = RoutingStrategy::Direct(&node);
-        let result = self
-            .connection_pool
-            .execute(routing_strategy, request, None);
-
-        match result {
-            Ok(Response::Array(value)) => {
-                dropped_items = value;
-            }
-            Err(_) => {}
-            _ => {}
-        }
-
+        let all_values = vec![TokenRange::full()];
+        let dropped_items = Pages::new(
+            &mut self.read_connection_pool,
+            vec![node.clone()],
+            all_values,
+            REBALANCE_PAGE_SIZE,
+        );
+        let mut failures = 0;
         for item in dropped_items {
+            let item = match item {
+                Ok(item) => item,
+                Err(e) => {
+                    println!("Failed to get batch: {:?}", e);
+                    failures += 1;
+                    break;
+                }
+            };
             let request = Request::Add(Entry {
                 value: item,
                 replication_factor: None,
@@ -152,65 +187,402 @@ impl Rebalancer {
             let result = self.connection_pool.execute(strategy, request, None);
 
             match result {
-                Ok(Response::String(val)) => {
-                    println!("{}", val);
+                Ok(Response::Void) => {}
+                Ok(response) => {
+                    println!("Failed to add: {:?}", response);
+                    failures += 1;
                 }
-                Ok(_) => {}
                 Err(e) => {
                     println!("Failed to add: {:?}", e);
+                    failures += 1;
                 }
             }
         }
 
-        let drop_request = Request::GetBatch(vec![Range {
-            start: 0,
-            end: u64::MAX,
-        }]);
-        let routing_strategy = RoutingStrategy::Direct(&node);
-        let result = self
-            .connection_pool
-            .execute(routing_strategy, drop_request, None);
-        match result {
-            Ok(_) => {
+        // the values are only dropped once all of them were read and added to the other
+        // nodes, so that a failure never loses values that weren't copied anywhere
+        if failures == 0 {
+            let drop_request = Request::DropBatch(vec![TokenRange::full()]);
+            let routing_strategy = RoutingStrategy::Direct(&node);
+            let result = self
+                .connection_pool
+                .execute(routing_strategy, drop_request, None);
+            if result.is_ok() {
                 println!("Dropped all items from node: {}", &node.address);
             }
-            _ => {}
+        } else {
+            println!(
+                "Not all values of {} were moved ({} failures), keeping them on it",
+                node.address, failures
+            );
         }
 
-        println!(
-            "{}: {}",
-            LOG_VERBOSE,
-            format!("Rebalanced with new node {}", node.address)
-        );
+        self.move_rows(&replicated_before, &replicated_after);
 
-        let count_request = Request::Count;
-        let router = self.cluster.router();
-        let strategy = router.route_request(&count_request);
-        let _ = self.connection_pool.execute(strategy, count_request, None);
+        println!("{}: Rebalanced with new node {}", LOG_VERBOSE, node.address);
+
+        self.announce_topology_change(TopologyChangeType::RemovedNode, node.address.clone());
+
+        self.count(Vec::new());
     }
 
-    fn get_ranges(entities: Vec<(u64, &str)>, look_up_node: &str) -> Vec<Range> {
-        let mut ranges = Vec::new();
+    /// Streams the rows of the ranges a node gained to it from every node that stored them
+    /// before, so that their copies are reconciled, then drops the rows of the ranges nodes
+    /// lost.
+    ///
+    /// Rows are only dropped once every gained range was streamed, so that a failed stream
+    /// leaves them where they were.
+    fn move_rows(&mut self, before: &ReplicatedRanges, after: &ReplicatedRanges) {
+        let mut streamed = true;
+        for (keyspace, nodes) in after {
+            for (address, ranges) in nodes {
+                let gained = subtract(ranges, replicated(before, keyspace, address));
+                if gained.is_empty() {
+                    continue;
+                }
+                for (source, source_ranges) in before.get(keyspace).into_iter().flatten() {
+                    let moved = intersect(&gained, source_ranges);
+                    if source != address && !moved.is_empty() {
+                        streamed &= self.stream_rows(keyspace, source, address, moved);
+                    }
+                }
+            }
+        }
+        if !streamed {
+            println!("Not all moved rows were streamed, keeping them on the other nodes");
+            return;
+        }
+
+        for (keyspace, nodes) in before {
+            for (address, ranges) in nodes {
+                let lost = subtract(ranges, replicated(after, keyspace, address));
+                if !lost.is_empty() {
+                    self.drop_rows(keyspace, address, lost);
+                }
+            }
+        }
+    }
 
-        for i in 0..entities.len() {
-            let (current_hash, current_node) = entities[i];
+    /// Copies the rows of a keyspace in some ranges from one node to another, which indexes
+    /// them as it applies them.
+    fn stream_rows(
+        &mut self,
+        keyspace: &str,
+        source: &str,
+        target: &str,
+        ranges: Vec<TokenRange>,
+    ) -> bool {
+        let request = Request::GetRows(RowRanges {
+            keyspace: keyspace.to_string(),
+            ranges,
+        });
+        let result = self.read_connection_pool.execute(
+            RoutingStrategy::Direct(&Node::from(source)),
+            request,
+            None,
+        );
+        let mutations = match result {
+            Ok(Response::Mutations(mutations)) => mutations,
+            result => {
+                println!("Failed to get rows from {}: {:?}", source, result);
+                return false;
+            }
+        };
+        if mutations.is_empty() {
+            return true;
+        }
 
-            //if it's not the newly added node then skip
-            if current_node != look_up_node {
-                continue;
+        let count = mutations.len();
+        let result = self.connection_pool.execute(
+            RoutingStrategy::Direct(&Node::from(target)),
+            Request::AddRows(mutations),
+            None,
+        );
+        match result {
+            Ok(Response::Void) => {
+                println!(
+                    "{}: Streamed {} mutations of keyspace {} from {} to {}",
+                    LOG_VERBOSE, count, keyspace, source, target
+                );
+                true
+            }
+            result => {
+                println!("Failed to add rows to {}: {:?}", target, result);
+                false
             }
+        }
+    }
+
+    fn drop_rows(&mut self, keyspace: &str, address: &str, ranges: Vec<TokenRange>) {
+        let request = Request::DropRows(RowRanges {
+            keyspace: keyspace.to_string(),
+            ranges,
+        });
+        let result = self.connection_pool.execute(
+            RoutingStrategy::Direct(&Node::from(address)),
+            request,
+            None,
+        );
+        match result {
+            Ok(Response::Void) => println!(
+                "{}: [{}] Removed the moved rows of keyspace {}",
+                LOG_VERBOSE, address, keyspace
+            ),
+            result => println!("Failed to drop rows from {}: {:?}", address, result),
+        }
+    }
 
-            // get previous node hash in the ring that will serve as a starting point
-            // since if hash of the value will be greater then the hash of the node in the ring
-            // it will then take the next node in the ring
-            let previous_hash = if i == 0 { 0 } else { entities[i - 1].0 };
+    /// Returns the keyspaces of the cluster, as told by the first node that can be reached.
+    fn keyspaces(&mut self) -> Vec<KeyspaceMetadata> {
+        for node in self.cluster.get_nodes().to_vec() {
+            let result = self.connection_pool.execute(
+                RoutingStrategy::Direct(&node),
+                Request::PullSchema,
+                None,
+            );
+            if let Ok(Response::Schema(schema)) = result {
+                return schema.keyspaces;
+            }
+        }
+        println!("Failed to read the keyspaces of the cluster, moving no rows");
+        Vec::new()
+    }
 
-            ranges.push(Range {
-                start: previous_hash,
-                end: current_hash,
+    /// Returns the ranges every node of the cluster stores rows of, for the replication
+    /// factor of each keyspace.
+    fn replicated_ranges(&self, keyspaces: &[KeyspaceMetadata]) -> ReplicatedRanges {
+        keyspaces
+            .iter()
+            .map(|keyspace| {
+                let nodes = self
+                    .cluster
+                    .get_nodes()
+                    .iter()
+                    .map(|node| {
+                        let ranges = self
+                            .cluster
+                            .replicated_ranges(&node.address, keyspace.replication_factor);
+                        (node.address.clone(), ranges)
+                    })
+                    .collect();
+                (keyspace.name.clone(), nodes)
             })
+            .collect()
+    }
+
+    /// Learns the host id of a node and places it at the tokens it picked; it keeps its
+    /// default tokens if it can't tell.
+    pub fn learn_tokens(&mut self, node: &Node) {
+        let result =
+            self.connection_pool
+                .execute(RoutingStrategy::Direct(node), Request::Tokens, None);
+
+        match result {
+            Ok(Response::Tokens(node_tokens)) => {
+                for node_tokens in node_tokens {
+                    if node_tokens.address == node.address {
+                        self.cluster.learn_node(node_tokens);
+                    }
+                }
+            }
+            result => println!(
+                "Failed to learn the tokens of {}: {:?}",
+                node.address, result
+            ),
+        }
+    }
+
+    /// Prints the state, load, number of tokens, share of the ring and host id of every node,
+    /// like `nodetool status`. The shares are those replicated for a replication factor if one
+    /// is given, or those owned otherwise.
+    pub fn status(&mut self, replication_factor: Option<usize>) {
+        let nodes = self.cluster.get_nodes().to_vec();
+        let mut loads = HashMap::new();
+        for node in nodes.iter() {
+            let load = self.load(node);
+            if load.is_some() {
+                self.learn_tokens(node);
+            }
+            loads.insert(node.address.clone(), load);
+        }
+        let ownership = match replication_factor {
+            Some(replication_factor) => self.cluster.effective_ownership(replication_factor),
+            None => self.cluster.ownership(),
+        };
+
+        println!("Status=Up/Down");
+        println!(
+            "{:<3} {:<22} {:>12} {:>7} {:>17}  Host ID",
+            "--",
+            "Address",
+            "Load",
+            "Tokens",
+            match replication_factor {
+                Some(_) => "Owns (effective)",
+                None => "Owns",
+            }
+        );
+        for node in self.cluster.node_tokens() {
+            let load = loads.get(&node.address).copied().flatten();
+            println!(
+                "{:<3} {:<22} {:>12} {:>7} {:>16.1}%  {}",
+                match load {
+                    Some(_) => "UN",
+                    None => "DN",
+                },
+                node.address,
+                load.map_or_else(|| "?".to_string(), format_bytes),
+                node.tokens.len(),
+                ownership.get(&node.address).copied().unwrap_or(0.0) * 100.0,
+                node.host_id
+                    .map_or_else(|| "?".to_string(), |host_id| host_id.to_string())
+            );
+        }
+    }
+
+    /// Returns the number of bytes of data a node stores, or `None` if it can't be reached.
+    fn load(&mut self, node: &Node) -> Option<u64> {
+        let result =
+            self.connection_pool
+                .execute(RoutingStrategy::Direct(node), Request::Load, None);
+
+        match result {
+            Ok(Response::Load(load)) => Some(load),
+            result => {
+                println!("Failed to get the load of {}: {:?}", node.address, result);
+                None
+            }
+        }
+    }
+
+    /// Counts the values of every node, and those in some ranges, printing each node's count.
+    pub fn count(&mut self, ranges: Vec<TokenRange>) -> Option<ValueCount> {
+        let request = Request::Count(ranges);
+        let router = self.cluster.router();
+        let strategy = router.route_request(&request);
+
+        match self.connection_pool.execute(strategy, request, None) {
+            Ok(Response::Count(count)) => {
+                for (address, node_count) in count.nodes.iter() {
+                    println!("{}: [{}] {} values", LOG_VERBOSE, address, node_count.total);
+                }
+                println!("{}: {} values in total", LOG_VERBOSE, count.total);
+                Some(count)
+            }
+            result => {
+                println!("Failed to count values: {:?}", result);
+                None
+            }
         }
+    }
 
+    /// Checks that a new node has at least as many values in the ranges moved to it as any
+    /// other node, which it has once every value of the ranges was copied to it.
+    fn holds_moved_values(&mut self, node: &Node, ranges: &[TokenRange]) -> bool {
+        let Some(count) = self.count(ranges.to_vec()) else {
+            return false;
+        };
+        let in_ranges = |node_count: &ValueCount| node_count.ranges.iter().sum::<u64>();
+
+        let moved = count
+            .nodes
+            .iter()
+            .filter(|(address, _)| *address != node.address)
+            .map(|(_, node_count)| in_ranges(node_count))
+            .max()
+            .unwrap_or(0);
+        let held = count
+            .nodes
+            .iter()
+            .find(|(address, _)| *address == node.address)
+            .map_or(0, |(_, node_count)| in_ranges(node_count));
+        println!(
+            "{}: {} holds {} values of the moved ranges, other nodes at most {}",
+            LOG_VERBOSE, node.address, held, moved
+        );
+        held >= moved
+    }
+
+    /// Tells every node of the rebalanced cluster about the change, so that they update their
+    /// rings and notify their registered clients.
+    fn announce_topology_change(&mut self, change: TopologyChangeType, address: String) {
+        let request = Request::ChangeTopology(TopologyChange {
+            change,
+            address,
+            host_id: None,
+        });
+        let router = self.cluster.router();
+        let strategy = router.route_request(&request);
+
+        if let Err(e) = self.connection_pool.execute(strategy, request, None) {
+            println!("Failed to announce topology change: {:?}", e);
+        }
+    }
+
+    /// Takes the next page of values, which is empty once all values were taken.
+    fn next_page(values: &mut Pages) -> AppResult<Vec<String>> {
+        values.by_ref().take(REBALANCE_PAGE_SIZE).collect()
+    }
+
+    /// Returns the ranges of the ring a node owns, adjacent ones merged; the range of its
+    /// first token wraps around from the last token of the ring.
+    fn get_ranges(&self, node: &Node) -> Vec<TokenRange> {
+        TokenRange::merge(
+            self.cluster
+                .token_ranges()
+                .into_iter()
+                .filter(|(_, address)| *address == node.address)
+                .map(|(range, _)| range),
+        )
+    }
+}
+
+/// Returns the ranges a node stores rows of for a keyspace, which are none if it wasn't part
+/// of the cluster.
+fn replicated<'r>(ranges: &'r ReplicatedRanges, keyspace: &str, address: &str) -> &'r [TokenRange] {
+    ranges
+        .get(keyspace)
+        .and_then(|nodes| nodes.get(address))
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Returns the tokens of some ranges that are in any of the others, as the fewest ranges.
+fn intersect(ranges: &[TokenRange], others: &[TokenRange]) -> Vec<TokenRange> {
+    TokenRange::merge(
         ranges
+            .iter()
+            .flat_map(|range| others.iter().flat_map(|other| range.intersect(other))),
+    )
+}
+
+/// Returns the tokens of some ranges that aren't in any of the others, as the fewest ranges.
+fn subtract(ranges: &[TokenRange], others: &[TokenRange]) -> Vec<TokenRange> {
+    let mut rest = ranges.to_vec();
+    for other in others {
+        rest = rest
+            .iter()
+            .flat_map(|range| range.subtract(other))
+            .collect();
+    }
+    TokenRange::merge(rest)
+}
+
+/// Formats a number of bytes in the largest binary unit it is at least one of.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = None;
+    for next_unit in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = Some(next_unit);
+    }
+
+    match unit {
+        Some(unit) => format!("{:.2} {}", size, unit),
+        None => format!("{} bytes", bytes),
     }
 }